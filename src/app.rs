@@ -1,15 +1,42 @@
-use crate::db::{self, ContactHistory, Customer};
+use crate::audit::AuditPanel;
+use crate::currency::{self, CurrencyStore};
+use crate::custom_fields::{self, CustomFieldStore};
+use crate::customer_detail::{CustomerAction, CustomerDetailView};
+use crate::dashboard::DashboardView;
+use crate::deals::DealsView;
+use crate::dunning::DunningView;
+use crate::duplicates::DuplicatesView;
+use crate::auth::{self, LoginView};
+use crate::banking::BankingView;
+use crate::db::{self, ContactHistory, Customer, SaveResult};
+use crate::invoices::InvoicesView;
+use crate::merge::{MergeAction, MergeDialog, Mergeable};
+use crate::pricing::{PriceListStore, PriceListsView};
+use crate::quotes::QuotesView;
+use crate::recurring::RecurringView;
+use crate::relations::{self, RelationGraphView, RelationStore};
+use crate::reports::ReportsView;
+use crate::sepa;
+use crate::settings::SettingsView;
+use crate::stock::InventoryView;
+use crate::tags::{self, TagStore};
+use crate::tasks::{self, TasksView};
+use crate::tax::TaxRateStore;
 use crate::ui;
 use chrono::{NaiveDate, Utc};
 
 use eframe::egui;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::config;
 use std::env;
 use std::path::PathBuf;
 
+/// Custom field values of one customer, by field id.
+type CustomValues = HashMap<i32, String>;
+
 pub struct CrmApp {
     current_view: View,
     customers: Arc<Mutex<Vec<Customer>>>,
@@ -20,6 +47,38 @@ pub struct CrmApp {
     search_results: Vec<Customer>,
     selected_customer: Option<Customer>,
     new_contact_history: ContactHistory,
+    // Customer Contact form: the record as loaded and the user's edits to it
+    customer_loaded: Option<Customer>,
+    customer_edit: Option<Customer>,
+    customer_merge: Arc<Mutex<Option<MergeDialog<Customer>>>>,
+    customer_status: Arc<Mutex<String>>,
+    customer_audit: AuditPanel,
+    invoices_view: InvoicesView,
+    login: LoginView,
+    settings_view: SettingsView,
+    tags: TagStore,
+    custom_fields: CustomFieldStore,
+    /// Custom values of the customer in the Customer Contact form: customer
+    /// id, values as loaded, values being edited.
+    custom_values_edit: Option<(i32, CustomValues, CustomValues)>,
+    relations: RelationStore,
+    relation_graph: RelationGraphView,
+    duplicates_view: DuplicatesView,
+    deals_view: DealsView,
+    quotes_view: QuotesView,
+    dunning_view: DunningView,
+    recurring_view: RecurringView,
+    tax_rates: TaxRateStore,
+    price_lists: PriceListStore,
+    price_lists_view: PriceListsView,
+    currencies: CurrencyStore,
+    reports_view: ReportsView,
+    banking_view: BankingView,
+    inventory_view: InventoryView,
+    dashboard_view: DashboardView,
+    customer_detail: CustomerDetailView,
+    tasks_view: TasksView,
+    customer_list: ui::CustomerListState,
 }
 
 // Menüpunkte
@@ -32,6 +91,18 @@ pub enum View {
     Settings,
     CustomerContact,
     CustomerSearch, // Neuer Menüpunkt
+    RelationGraph,
+    Duplicates,
+    Deals,
+    Quotes,
+    Dunning,
+    Recurring,
+    Reports,
+    Banking,
+    Inventory,
+    PriceLists,
+    CustomerDetail,
+    Tasks,
 }
 
 impl Default for CrmApp {
@@ -46,42 +117,116 @@ impl Default for CrmApp {
             search_results: Vec::new(),
             selected_customer: None,
             new_contact_history: ContactHistory::default(),
+            customer_loaded: None,
+            customer_edit: None,
+            customer_merge: Arc::new(Mutex::new(None)),
+            customer_status: Arc::new(Mutex::new(String::new())),
+            customer_audit: AuditPanel::default(),
+            invoices_view: InvoicesView::default(),
+            login: LoginView::default(),
+            settings_view: SettingsView::default(),
+            tags: TagStore::default(),
+            custom_fields: CustomFieldStore::default(),
+            custom_values_edit: None,
+            relations: RelationStore::default(),
+            relation_graph: RelationGraphView::default(),
+            duplicates_view: DuplicatesView::default(),
+            deals_view: DealsView::default(),
+            quotes_view: QuotesView::default(),
+            dunning_view: DunningView::default(),
+            recurring_view: RecurringView::default(),
+            tax_rates: TaxRateStore::default(),
+            price_lists: PriceListStore::default(),
+            price_lists_view: PriceListsView::default(),
+            currencies: CurrencyStore::default(),
+            reports_view: ReportsView::default(),
+            banking_view: BankingView::default(),
+            inventory_view: InventoryView::default(),
+            dashboard_view: DashboardView::default(),
+            customer_detail: CustomerDetailView::default(),
+            tasks_view: TasksView::default(),
+            customer_list: ui::CustomerListState::default(),
         }
     }
 }
 
 impl CrmApp {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        let app = Self {
-            current_view: View::Main,
-            customers: Arc::new(Mutex::new(Vec::new())),
-            customer_contact_window_open: false,
-            active_customer_index: 0,
-            contact_history_cache: Arc::new(Mutex::new(HashMap::new())),
-            search_query: String::new(),
-            search_results: Vec::new(),
-            selected_customer: None,
-            new_contact_history: ContactHistory::default(),
-        };
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let app = Self::default();
 
         // Load the database configuration
         let config_path = PathBuf::from(format!(
             "{}/.config/zugangsdaten.ini",
             env::var("HOME").unwrap()
         ));
-        let db_config =
-            config::load_db_config(&config_path).expect("Failed to load database configuration");
+        // Without a configuration the login screen leads to the setup wizard.
+        if let Ok(db_config) = config::load_db_config(&config_path) {
+            // Load customers asynchronously
+            let customers = Arc::clone(&app.customers);
+            tokio::spawn(async move {
+                if let Ok(loaded_customers) = db::get_customers(&db_config).await {
+                    *customers.lock().unwrap() = loaded_customers;
+                }
+            });
+        }
+
+        app.start_change_listener(cc.egui_ctx.clone());
+
+        app
+    }
 
-        // Load customers asynchronously
-        let customers = Arc::clone(&app.customers);
-        let db_config_clone = db_config.clone();
+    /// Keeps the local caches in sync with changes made by other clients.
+    /// Reconnects after a short pause whenever the listener connection drops.
+    fn start_change_listener(&self, ctx: egui::Context) {
+        let targets = ChangeTargets {
+            customers: Arc::clone(&self.customers),
+            history_cache: Arc::clone(&self.contact_history_cache),
+            invoices_stale: Arc::clone(&self.invoices_view.stale),
+            tags_stale: Arc::clone(&self.tags.stale),
+            custom_fields_stale: Arc::clone(&self.custom_fields.stale),
+            relations_stale: Arc::clone(&self.relations.stale),
+            deals_stale: Arc::clone(&self.deals_view.stale),
+            quotes_stale: Arc::clone(&self.quotes_view.stale),
+            dunning_stale: Arc::clone(&self.dunning_view.stale),
+            recurring_stale: Arc::clone(&self.recurring_view.stale),
+            tax_rates_stale: Arc::clone(&self.tax_rates.stale),
+            price_lists_stale: Arc::clone(&self.price_lists.stale),
+            currencies_stale: Arc::clone(&self.currencies.stale),
+            reports_stale: Arc::clone(&self.reports_view.stale),
+            banking_stale: Arc::clone(&self.banking_view.stale),
+            inventory_stale: Arc::clone(&self.inventory_view.stale),
+            dashboard_stale: Arc::clone(&self.dashboard_view.stale),
+            customer_detail_stale: Arc::clone(&self.customer_detail.stale),
+            tasks_stale: Arc::clone(&self.tasks_view.stale),
+        };
         tokio::spawn(async move {
-            if let Ok(loaded_customers) = db::get_customers(&db_config_clone).await {
-                *customers.lock().unwrap() = loaded_customers;
+            // Set once a connection has been lost; changes made while the
+            // listener was away were never announced.
+            let mut missed_changes = false;
+            loop {
+                if let Some(config) = db::get_config() {
+                    let result = db::listen_for_changes(
+                        &config,
+                        || {
+                            if missed_changes {
+                                reload_everything(&targets, &config);
+                                ctx.request_repaint();
+                            }
+                        },
+                        |change| {
+                            apply_change(&change, &targets, &config);
+                            ctx.request_repaint();
+                        },
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        eprintln!("Change listener error: {}", e);
+                    }
+                    missed_changes = true;
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
             }
         });
-
-        app
     }
 
     fn render_customer_search(&mut self, ui: &mut egui::Ui) {
@@ -144,7 +289,7 @@ impl CrmApp {
                 });
             });
     
-            if ui.button("Save").clicked() {
+            if auth::can_edit() && ui.button("Save").clicked() {
                 if !self.new_contact_history.contact_type.is_empty() && 
                    !self.new_contact_history.notes.is_empty() &&
                    !self.new_contact_history.contact_outcome.is_empty() {
@@ -164,7 +309,8 @@ impl CrmApp {
         self.search_results = customers
             .iter()
             .filter(|c| c.contact_name.to_lowercase().contains(&search_query) ||
-                        c.company_name.to_lowercase().contains(&search_query))
+                        c.company_name.to_lowercase().contains(&search_query) ||
+                        self.custom_fields.matches(c.customer_id, &search_query))
             .cloned()
             .collect();
     
@@ -174,7 +320,10 @@ impl CrmApp {
 
     fn save_contact_history(&mut self) -> bool {
         if let Some(config) = db::get_config() {
-            let new_history = self.new_contact_history.clone();
+            let mut new_history = self.new_contact_history.clone();
+            if let Some(user) = auth::current_user() {
+                new_history.created_by = user.username;
+            }
             tokio::spawn(async move {
                 match db::add_contact_history(&config, &new_history).await {
                     Ok(_) => {
@@ -230,6 +379,8 @@ impl CrmApp {
             ui.label("No customer records available.");
             return;
         }
+        // Another client may have deleted customers since the last frame.
+        self.active_customer_index = self.active_customer_index.min(customer_count - 1);
 
         let customer_id = {
             let customers = self.customers.lock().unwrap();
@@ -255,7 +406,9 @@ impl CrmApp {
         ui.add_space(20.0);
 
         // Customer form fields
-        let mut customer = self.customers.lock().unwrap()[self.active_customer_index].clone();
+        let stored = self.customers.lock().unwrap()[self.active_customer_index].clone();
+        self.sync_customer_edit(stored);
+        let customer = self.customer_edit.as_mut().unwrap();
 
         ui.horizontal(|ui| {
             ui.label("Company Name:");
@@ -264,44 +417,110 @@ impl CrmApp {
 
         ui.horizontal(|ui| {
             ui.label("Contact Name:");
-            ui.text_edit_singleline(&mut customer.contact_name.to_string());
+            ui.text_edit_singleline(&mut customer.contact_name);
         });
 
         ui.horizontal(|ui| {
             ui.label("Email:");
-            ui.text_edit_singleline(&mut customer.email.to_string());
+            ui.text_edit_singleline(&mut customer.email);
         });
 
         ui.horizontal(|ui| {
             ui.label("Phone:");
-            ui.text_edit_singleline(&mut customer.phone.to_string());
+            ui.text_edit_singleline(&mut customer.phone);
         });
 
         ui.horizontal(|ui| {
             ui.label("Address:");
-            ui.text_edit_multiline(&mut customer.address.to_string());
+            ui.text_edit_multiline(&mut customer.address);
         });
 
         ui.horizontal(|ui| {
             ui.label("City:");
-            ui.text_edit_singleline(&mut customer.city.to_string());
+            ui.text_edit_singleline(&mut customer.city);
         });
 
         ui.horizontal(|ui| {
             ui.label("Postal Code:");
-            ui.text_edit_singleline(&mut customer.postal_code.to_string());
+            ui.text_edit_singleline(&mut customer.postal_code);
         });
 
         ui.horizontal(|ui| {
             ui.label("Country:");
-            ui.text_edit_singleline(&mut customer.country.to_string());
+            ui.text_edit_singleline(&mut customer.country);
+        });
+
+        self.currencies.refresh_if_stale();
+        ui.horizontal(|ui| {
+            ui.label("Currency:");
+            currency::currency_combo(ui, "customer_currency", &mut customer.currency, &self.currencies);
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("VAT ID:");
+            ui.text_edit_singleline(&mut customer.vat_id);
         });
 
+        ui.horizontal(|ui| {
+            ui.label("Buyer Reference:");
+            ui.text_edit_singleline(&mut customer.buyer_reference);
+        });
+        sepa::render_bank_fields(ui, "customer", customer);
+
+        let customer = customer.clone();
+
+        self.custom_fields.refresh_if_stale();
+        let fields = self.custom_fields.active_fields();
+        // Start over when another customer is shown or the stored values
+        // were reloaded, e.g. after a save or a change by another client.
+        let stored_values = self.custom_fields.values_of(customer.customer_id);
+        let up_to_date = self
+            .custom_values_edit
+            .as_ref()
+            .is_some_and(|(id, loaded, _)| *id == customer.customer_id && *loaded == stored_values);
+        if !up_to_date {
+            self.custom_values_edit =
+                Some((customer.customer_id, stored_values.clone(), stored_values));
+        }
+        if let Some((_, _, values)) = self.custom_values_edit.as_mut() {
+            custom_fields::render_field_inputs(ui, &fields, values, customer.customer_id);
+        }
+
+        self.tags.refresh_if_stale();
+        ui.horizontal(|ui| {
+            ui.label("Tags:");
+            tags::render_customer_tags(ui, &self.tags, customer.customer_id, auth::can_edit());
+        });
+
+        self.relations.refresh_if_stale();
+        ui.label("Relations:");
+        let all_customers = self.customers.lock().unwrap().clone();
+        let related = relations::render_customer_relations(
+            ui,
+            &self.relations,
+            &all_customers,
+            customer.customer_id,
+            auth::can_edit(),
+        );
+        if let Some(related) = related {
+            self.open_customer(related);
+        }
+
+        ui.horizontal(|ui| {
+            if auth::can_edit() && ui.button("Save Customer").clicked() {
+                self.save_customer_with_custom_values(customer.clone(), &fields);
+            }
+            ui.label(self.customer_status.lock().unwrap().as_str());
+        });
+        self.customer_audit
+            .show(ui, "customers", customer.customer_id, customer.version);
+
         // Contact History
         ui.add_space(20.0);
         ui.heading("Contact History");
 
         let history = self.contact_history_cache.lock().unwrap();
+        let mut follow_up = None;
         if let Some(history) = history.get(&customer.customer_id) {
             println!(
                 "Rendering history for customer {}: {} entries",
@@ -323,6 +542,9 @@ impl CrmApp {
                         ui.label(entry.contact_method.as_deref().unwrap_or("-"));
                         ui.label(&entry.contact_outcome);
                         ui.label(&entry.notes);
+                        if auth::can_edit() && ui.small_button("Create Task").clicked() {
+                            follow_up = Some(tasks::follow_up_task(entry));
+                        }
                         ui.end_row();
                     }
                 });
@@ -332,6 +554,127 @@ impl CrmApp {
             let customer_id = customer.customer_id;
             self.load_contact_history(customer_id);
         }
+        drop(history);
+        if let Some(task) = follow_up {
+            self.tasks_view.open(task);
+            self.current_view = View::Tasks;
+        }
+    }
+
+    /// Shows the customer in the Customer Contact window.
+    fn open_customer(&mut self, customer_id: i32) {
+        let index = self
+            .customers
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|c| c.customer_id == customer_id);
+        if let Some(index) = index {
+            self.active_customer_index = index;
+            self.customer_contact_window_open = true;
+            self.current_view = View::CustomerContact;
+        }
+    }
+
+    /// Points the edit buffer at `stored`. Edits in progress are kept when the
+    /// stored record changes underneath them; saving will then detect the
+    /// conflict. An untouched buffer just follows the stored record.
+    fn sync_customer_edit(&mut self, stored: Customer) {
+        let untouched = match (&self.customer_loaded, &self.customer_edit) {
+            (Some(loaded), Some(edit)) if loaded.customer_id == stored.customer_id => {
+                if loaded.version == stored.version {
+                    return;
+                }
+                edit.merge_fields() == loaded.merge_fields()
+                    || edit.merge_fields() == stored.merge_fields()
+            }
+            _ => true,
+        };
+        if untouched {
+            self.customer_loaded = Some(stored.clone());
+            self.customer_edit = Some(stored);
+        }
+    }
+
+    fn save_customer_with_custom_values(&mut self, customer: Customer, fields: &[db::CustomField]) {
+        let Some((_, _, values)) = self.custom_values_edit.clone() else {
+            return;
+        };
+        if !custom_fields::all_valid(fields, &values) {
+            *self.customer_status.lock().unwrap() =
+                "Please correct the highlighted fields".to_string();
+            return;
+        }
+        // Only send fields the user touched, so values loaded after the form
+        // was opened are not overwritten with blanks.
+        let stored = self.custom_fields.values_of(customer.customer_id);
+        let changed: HashMap<i32, String> = values
+            .into_iter()
+            .filter(|(field_id, value)| {
+                stored.get(field_id).map_or("", String::as_str) != value.trim()
+            })
+            .collect();
+        if !changed.is_empty() {
+            self.custom_fields.save_values(customer.customer_id, changed);
+        }
+        let base = self.customer_loaded.clone().filter(|c| c.customer_id == customer.customer_id);
+        self.save_customer(base, customer);
+    }
+
+    /// Saves `customer`, edited from `base`. On a conflict the merge dialog
+    /// compares it with the stored record.
+    fn save_customer(&self, base: Option<Customer>, customer: Customer) {
+        let Some(config) = db::get_config() else {
+            *self.customer_status.lock().unwrap() = "No database configuration found!".to_string();
+            return;
+        };
+        let customers = Arc::clone(&self.customers);
+        let merge = Arc::clone(&self.customer_merge);
+        let status = Arc::clone(&self.customer_status);
+        tokio::spawn(async move {
+            match db::update_customer(&config, &customer).await {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = "Customer saved".to_string();
+                    replace_customer(&customers, saved);
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() =
+                        "Customer was changed by someone else".to_string();
+                    replace_customer(&customers, current.clone());
+                    *merge.lock().unwrap() = Some(MergeDialog::new(&base.unwrap_or_else(|| current.clone()), customer, current));
+                }
+                Err(e) => {
+                    eprintln!("Error saving customer: {}", e);
+                    *status.lock().unwrap() = format!("Error saving customer: {}", e);
+                }
+            }
+        });
+    }
+
+    fn render_customer_merge_dialog(&mut self, ctx: &egui::Context) {
+        let mut merge = self.customer_merge.lock().unwrap();
+        let Some(dialog) = merge.as_mut() else {
+            return;
+        };
+        let Some(action) = dialog.show(ctx, "Customer changed by someone else") else {
+            return;
+        };
+
+        let dialog = merge.take().unwrap();
+        drop(merge);
+        match action {
+            MergeAction::SaveMerged => {
+                let merged = dialog.merged();
+                self.customer_loaded = Some(dialog.theirs.clone());
+                self.customer_edit = Some(merged.clone());
+                self.save_customer(Some(dialog.theirs), merged);
+            }
+            MergeAction::DiscardMine => {
+                self.customer_loaded = Some(dialog.theirs.clone());
+                self.customer_edit = Some(dialog.theirs);
+            }
+            MergeAction::Cancel => {}
+        }
     }
 
     fn load_contact_history(&self, customer_id: i32) {
@@ -371,23 +714,234 @@ impl CrmApp {
     }
 }
 
+/// Shared caches the change listener keeps up to date.
+struct ChangeTargets {
+    customers: Arc<Mutex<Vec<Customer>>>,
+    history_cache: Arc<Mutex<HashMap<i32, Vec<ContactHistory>>>>,
+    invoices_stale: Arc<AtomicBool>,
+    tags_stale: Arc<AtomicBool>,
+    custom_fields_stale: Arc<AtomicBool>,
+    relations_stale: Arc<AtomicBool>,
+    deals_stale: Arc<AtomicBool>,
+    quotes_stale: Arc<AtomicBool>,
+    dunning_stale: Arc<AtomicBool>,
+    recurring_stale: Arc<AtomicBool>,
+    tax_rates_stale: Arc<AtomicBool>,
+    price_lists_stale: Arc<AtomicBool>,
+    currencies_stale: Arc<AtomicBool>,
+    reports_stale: Arc<AtomicBool>,
+    banking_stale: Arc<AtomicBool>,
+    inventory_stale: Arc<AtomicBool>,
+    dashboard_stale: Arc<AtomicBool>,
+    customer_detail_stale: Arc<AtomicBool>,
+    tasks_stale: Arc<AtomicBool>,
+}
+
+/// Replaces the cached copy of `customer`, or adds it if it is not cached yet.
+fn replace_customer(customers: &Arc<Mutex<Vec<Customer>>>, customer: Customer) {
+    let mut customers = customers.lock().unwrap();
+    match customers
+        .iter_mut()
+        .find(|c| c.customer_id == customer.customer_id)
+    {
+        Some(existing) => *existing = customer,
+        None => {
+            customers.push(customer);
+            customers.sort_by(|a, b| a.company_name.cmp(&b.company_name));
+        }
+    }
+}
+
+/// Invalidates whatever cached data a row change from another client affects.
+fn apply_change(change: &db::ChangeNotification, targets: &ChangeTargets, config: &config::DbConfig) {
+    let customers = &targets.customers;
+    let history_cache = &targets.history_cache;
+    println!(
+        "Change received: {} {} id={}",
+        change.action, change.table, change.id
+    );
+    match change.table.as_str() {
+        "customers" => {
+            // Mandates are part of the customer.
+            targets.banking_stale.store(true, Ordering::SeqCst);
+            targets.customer_detail_stale.store(true, Ordering::SeqCst);
+            if change.action == "DELETE" {
+                customers
+                    .lock()
+                    .unwrap()
+                    .retain(|c| c.customer_id != change.id);
+                history_cache.lock().unwrap().remove(&change.id);
+                return;
+            }
+            let customers = Arc::clone(customers);
+            let history_cache = Arc::clone(history_cache);
+            let config = config.clone();
+            let customer_id = change.id;
+            tokio::spawn(async move {
+                match db::get_customer_with_history(&config, customer_id).await {
+                    Ok((customer, history)) => {
+                        replace_customer(&customers, customer);
+                        history_cache.lock().unwrap().insert(customer_id, history);
+                    }
+                    Err(e) => eprintln!("Error refreshing customer {}: {}", customer_id, e),
+                }
+            });
+        }
+        "contact_history" => {
+            targets.dashboard_stale.store(true, Ordering::SeqCst);
+            targets.customer_detail_stale.store(true, Ordering::SeqCst);
+            let Some(customer_id) = change.customer_id else {
+                return;
+            };
+            let mut cache = history_cache.lock().unwrap();
+            if change.action == "DELETE" {
+                if let Some(history) = cache.get_mut(&customer_id) {
+                    history.retain(|entry| entry.history_id != change.id);
+                }
+            } else {
+                // Dropping the entry makes the contact window reload it.
+                cache.remove(&customer_id);
+            }
+        }
+        "invoices" => {
+            targets.invoices_stale.store(true, Ordering::SeqCst);
+            targets.dunning_stale.store(true, Ordering::SeqCst);
+            targets.reports_stale.store(true, Ordering::SeqCst);
+            targets.banking_stale.store(true, Ordering::SeqCst);
+            targets.dashboard_stale.store(true, Ordering::SeqCst);
+            targets.customer_detail_stale.store(true, Ordering::SeqCst);
+        }
+        "tasks" => targets.tasks_stale.store(true, Ordering::SeqCst),
+        "contacts" | "customer_notes" | "payments" => targets.customer_detail_stale.store(true, Ordering::SeqCst),
+        // Price lists can apply to the customers with a tag.
+        "tags" | "customer_tags" => {
+            targets.tags_stale.store(true, Ordering::SeqCst);
+            targets.price_lists_stale.store(true, Ordering::SeqCst);
+        }
+        "custom_fields" | "customer_custom_values" => {
+            targets.custom_fields_stale.store(true, Ordering::SeqCst)
+        }
+        "customer_relations" => targets.relations_stale.store(true, Ordering::SeqCst),
+        "deals" | "deal_stages" => {
+            targets.deals_stale.store(true, Ordering::SeqCst);
+            targets.customer_detail_stale.store(true, Ordering::SeqCst);
+        }
+        "quotes" => targets.quotes_stale.store(true, Ordering::SeqCst),
+        "dunning_levels" | "dunning_notices" => targets.dunning_stale.store(true, Ordering::SeqCst),
+        "recurring_invoices" => targets.recurring_stale.store(true, Ordering::SeqCst),
+        "tax_rates" => targets.tax_rates_stale.store(true, Ordering::SeqCst),
+        "price_lists" => targets.price_lists_stale.store(true, Ordering::SeqCst),
+        // Stock changes with every invoice sent or cancelled.
+        "products" => {
+            targets.invoices_stale.store(true, Ordering::SeqCst);
+            targets.inventory_stale.store(true, Ordering::SeqCst);
+        }
+        "currencies" => {
+            targets.currencies_stale.store(true, Ordering::SeqCst);
+            targets.reports_stale.store(true, Ordering::SeqCst);
+            targets.dashboard_stale.store(true, Ordering::SeqCst);
+        }
+        _ => {}
+    }
+}
+
+/// Marks every view stale and reloads the customers, for when notifications
+/// may have been missed.
+fn reload_everything(targets: &ChangeTargets, config: &config::DbConfig) {
+    for stale in [
+        &targets.invoices_stale,
+        &targets.tags_stale,
+        &targets.custom_fields_stale,
+        &targets.relations_stale,
+        &targets.deals_stale,
+        &targets.quotes_stale,
+        &targets.dunning_stale,
+        &targets.recurring_stale,
+        &targets.tax_rates_stale,
+        &targets.price_lists_stale,
+        &targets.currencies_stale,
+        &targets.reports_stale,
+        &targets.banking_stale,
+        &targets.inventory_stale,
+        &targets.dashboard_stale,
+        &targets.customer_detail_stale,
+        &targets.tasks_stale,
+    ] {
+        stale.store(true, Ordering::SeqCst);
+    }
+    // Dropping the entries makes the contact window reload them.
+    targets.history_cache.lock().unwrap().clear();
+    let customers = Arc::clone(&targets.customers);
+    let config = config.clone();
+    tokio::spawn(async move {
+        match db::get_customers(&config).await {
+            Ok(loaded_customers) => *customers.lock().unwrap() = loaded_customers,
+            Err(e) => eprintln!("Error reloading customers: {}", e),
+        }
+    });
+}
 
 impl eframe::App for CrmApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ui::render_menu_bar(
+        let Some(user) = auth::current_user() else {
+            self.render_logged_out(ctx);
+            return;
+        };
+
+        if ui::render_menu_bar(
             ctx,
             &mut self.current_view,
             &mut self.customer_contact_window_open,
-        );
+            &user,
+        ) {
+            println!("User {} logged out", user.username);
+            auth::set_current_user(None);
+            self.current_view = View::Main;
+            self.login.recheck();
+            return;
+        }
+
+        if !user.role.can_access(&self.current_view) {
+            self.current_view = View::Main;
+        }
 
         match self.current_view {
-            View::Main => ui::render_main_view(ctx),
+            View::Main => self.dashboard_view.show(ctx, user.role, &self.currencies),
             View::Customers => {
                 let customers = self.customers.clone();
-                ui::render_customers_view(ctx, customers);
+                ui::render_customers_view(
+                    ctx,
+                    customers,
+                    &self.tags,
+                    &self.custom_fields,
+                    &self.currencies,
+                    &mut self.customer_list,
+                );
+                if let Some(customer_id) = self.customer_list.open_customer.take() {
+                    self.customer_detail.open(customer_id);
+                    self.current_view = View::CustomerDetail;
+                }
+            }
+            View::CustomerDetail => match self.customer_detail.show(ctx, user.role) {
+                Some(CustomerAction::Edit(customer_id)) => self.open_customer(customer_id),
+                Some(CustomerAction::NewInvoice(customer)) => {
+                    self.invoices_view.create_for(&customer);
+                    self.current_view = View::Invoices;
+                }
+                Some(CustomerAction::NewTask(task)) => {
+                    self.tasks_view.open(*task);
+                    self.current_view = View::Tasks;
+                }
+                None => {}
+            },
+            View::Invoices => {
+                self.invoices_view
+                    .show(ctx, &self.customers, &self.tax_rates, &self.currencies, &self.price_lists)
+            }
+            View::Settings => {
+                self.settings_view
+                    .show(ctx, &mut self.current_view, &self.custom_fields, &self.tax_rates, &self.currencies)
             }
-            View::Invoices => ui::render_invoices_view(ctx),
-            View::Settings => ui::render_settings_view(ctx),
             View::SetupWizard => ui::render_setup_wizard_view(ctx),
             View::CustomerContact => {
                 if self.customer_contact_window_open {
@@ -398,8 +952,31 @@ impl eframe::App for CrmApp {
                             self.render_customer_contact(ui);
                         });
                     self.customer_contact_window_open = open;
+                    self.render_customer_merge_dialog(ctx);
                 }
             },
+            View::RelationGraph => {
+                let clicked = self.relation_graph.show(ctx, &self.customers, &self.relations);
+                if let Some(customer_id) = clicked {
+                    self.open_customer(customer_id);
+                }
+            }
+            View::Duplicates => self.duplicates_view.show(ctx, &self.customers),
+            View::Deals => self.deals_view.show(ctx, &self.customers),
+            View::Quotes => self.quotes_view.show(ctx, &self.customers, &self.tax_rates, &self.price_lists),
+            View::Dunning => self.dunning_view.show(ctx, &self.customers),
+            View::Recurring => {
+                self.recurring_view
+                    .show(ctx, &self.customers, &self.tax_rates, &self.price_lists)
+            }
+            View::PriceLists => {
+                self.price_lists_view
+                    .show(ctx, &self.customers, &self.tags, &self.price_lists)
+            }
+            View::Reports => self.reports_view.show(ctx, &self.customers, &self.currencies),
+            View::Banking => self.banking_view.show(ctx),
+            View::Tasks => self.tasks_view.show(ctx, &self.customers),
+            View::Inventory => self.inventory_view.show(ctx),
             View::CustomerSearch => {
                 egui::Window::new("Customer Search")
                     .show(ctx, |ui| {
@@ -415,4 +992,21 @@ impl eframe::App for CrmApp {
     }
 }
 
-
+impl CrmApp {
+    /// Before login only the login screen is shown, plus the setup wizard
+    /// while the database is not ready for anyone to log in.
+    fn render_logged_out(&mut self, ctx: &egui::Context) {
+        if self.current_view == View::SetupWizard && self.login.setup_allowed() {
+            egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+                if ui.button("Back to Login").clicked() {
+                    self.current_view = View::Main;
+                    self.login.recheck();
+                }
+            });
+            ui::render_setup_wizard_view(ctx);
+        } else {
+            self.current_view = View::Main;
+            self.login.show(ctx, &mut self.current_view);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// audit.rs
+use crate::db::{self, AuditEntry};
+use eframe::egui;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// Bookkeeping fields that change on every write and would only add noise to
+/// the diffs.
+const IGNORED_FIELDS: [&str; 3] = ["version", "created_at", "updated_at"];
+
+/// Collapsible "History of changes" section listing the `audit_log` entries of
+/// one record, each expandable into a field-by-field diff.
+#[derive(Default)]
+pub struct AuditPanel {
+    /// Table, record and version the entries were loaded for.
+    loaded_for: Option<(String, i32, i32)>,
+    entries: Arc<Mutex<Vec<AuditEntry>>>,
+}
+
+impl AuditPanel {
+    /// `version` is the record's current version; a new version means new
+    /// entries, so the list is reloaded whenever it changes.
+    pub fn show(&mut self, ui: &mut egui::Ui, table_name: &str, record_id: i32, version: i32) {
+        egui::CollapsingHeader::new("History of changes")
+            .id_source(("audit", table_name, record_id))
+            .show(ui, |ui| {
+                let key = (table_name.to_string(), record_id, version);
+                if self.loaded_for.as_ref() != Some(&key) {
+                    self.loaded_for = Some(key);
+                    self.load(table_name, record_id);
+                }
+
+                let entries = self.entries.lock().unwrap();
+                if entries.is_empty() {
+                    ui.label("No changes recorded.");
+                    return;
+                }
+                egui::ScrollArea::vertical()
+                    .id_source(("audit_scroll", table_name, record_id))
+                    .max_height(250.0)
+                    .show(ui, |ui| {
+                        for entry in entries.iter() {
+                            render_entry(ui, entry);
+                        }
+                    });
+            });
+    }
+
+    fn load(&self, table_name: &str, record_id: i32) {
+        let entries = Arc::clone(&self.entries);
+        entries.lock().unwrap().clear();
+        let table_name = table_name.to_string();
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_audit_log(&config, &table_name, record_id).await {
+                    Ok(loaded) => *entries.lock().unwrap() = loaded,
+                    Err(e) => eprintln!(
+                        "Error loading audit log for {} {}: {}",
+                        table_name, record_id, e
+                    ),
+                }
+            }
+        });
+    }
+}
+
+fn render_entry(ui: &mut egui::Ui, entry: &AuditEntry) {
+    let title = format!(
+        "{}  {}  by {}",
+        entry.changed_at.format("%Y-%m-%d %H:%M:%S"),
+        entry.action,
+        entry.changed_by
+    );
+    egui::CollapsingHeader::new(title)
+        .id_source(("audit_entry", entry.audit_id))
+        .show(ui, |ui| {
+            let changes = changed_fields(entry);
+            if changes.is_empty() {
+                ui.label("No field values changed.");
+                return;
+            }
+            egui::Grid::new(("audit_diff", entry.audit_id))
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Field");
+                    ui.strong("Old value");
+                    ui.strong("New value");
+                    ui.end_row();
+                    for (field, old, new) in changes {
+                        ui.label(field);
+                        ui.colored_label(egui::Color32::LIGHT_RED, old);
+                        ui.colored_label(egui::Color32::LIGHT_GREEN, new);
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+/// Fields whose value differs between `old_values` and `new_values`, as
+/// (field, old, new) display strings.
+pub fn changed_fields(entry: &AuditEntry) -> Vec<(String, String, String)> {
+    let empty = serde_json::Map::new();
+    let old = entry
+        .old_values
+        .as_ref()
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let new = entry
+        .new_values
+        .as_ref()
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|field| !IGNORED_FIELDS.contains(&field.as_str()))
+        .filter(|field| old.get(*field) != new.get(*field))
+        .map(|field| {
+            (
+                field.clone(),
+                display_value(old.get(field)),
+                display_value(new.get(field)),
+            )
+        })
+        .collect()
+}
+
+fn display_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "-".to_string(),
+        Some(Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+    }
+}
@@ -0,0 +1,317 @@
+// auth.rs
+use crate::app::View;
+use crate::db::{self, User};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use eframe::egui;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    Sales,
+    Accounting,
+    ReadOnly,
+}
+
+pub const ROLES: [Role; 4] = [Role::Admin, Role::Sales, Role::Accounting, Role::ReadOnly];
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Sales => "sales",
+            Role::Accounting => "accounting",
+            Role::ReadOnly => "read_only",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Admin => "Admin",
+            Role::Sales => "Sales",
+            Role::Accounting => "Accounting",
+            Role::ReadOnly => "Read-only",
+        }
+    }
+
+    /// Whether users with this role may open `view` at all.
+    pub fn can_access(&self, view: &View) -> bool {
+        match view {
+            View::SetupWizard | View::Settings => *self == Role::Admin,
+            View::Invoices
+            | View::Dunning
+            | View::Recurring
+            | View::Reports
+            | View::Banking
+            | View::PriceLists => *self != Role::Sales,
+            _ => true,
+        }
+    }
+
+    /// Whether users with this role may change data, as opposed to only
+    /// looking at it.
+    pub fn can_edit(&self) -> bool {
+        *self != Role::ReadOnly
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ROLES
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("Unknown role: {}", s))
+    }
+}
+
+static CURRENT_USER: Lazy<Mutex<Option<User>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn set_current_user(user: Option<User>) {
+    *CURRENT_USER.lock().unwrap() = user;
+}
+
+pub fn current_user() -> Option<User> {
+    CURRENT_USER.lock().unwrap().clone()
+}
+
+/// Whether the logged-in user may change data.
+pub fn can_edit() -> bool {
+    current_user().is_some_and(|user| user.role.can_edit())
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// What the login screen found out about the database.
+#[derive(Clone, PartialEq)]
+enum LoginState {
+    Checking,
+    /// No configuration or no usable users table: the setup wizard is needed.
+    NeedsSetup(String),
+    /// No users yet: the first account created becomes the administrator.
+    NoUsers,
+    Ready,
+}
+
+/// Login screen shown instead of any data until a user has logged in.
+pub struct LoginView {
+    username: String,
+    display_name: String,
+    password: String,
+    state: Arc<Mutex<LoginState>>,
+    message: Arc<Mutex<String>>,
+    checked: bool,
+}
+
+impl Default for LoginView {
+    fn default() -> Self {
+        Self {
+            username: String::new(),
+            display_name: String::new(),
+            password: String::new(),
+            state: Arc::new(Mutex::new(LoginState::Checking)),
+            message: Arc::new(Mutex::new(String::new())),
+            checked: false,
+        }
+    }
+}
+
+impl LoginView {
+    /// The setup wizard may be used without logging in as long as there is
+    /// no user who could log in.
+    pub fn setup_allowed(&self) -> bool {
+        !matches!(*self.state.lock().unwrap(), LoginState::Ready)
+    }
+
+    /// Makes the next frame check the database again, e.g. after setup.
+    pub fn recheck(&mut self) {
+        self.checked = false;
+    }
+
+    /// Renders the login form; sets `current_view` to the setup wizard when
+    /// the user asks for it.
+    pub fn show(&mut self, ctx: &egui::Context, current_view: &mut View) {
+        if !self.checked {
+            self.checked = true;
+            self.check_database(ctx.clone());
+        }
+        let state = self.state.lock().unwrap().clone();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("CRM Application - Login");
+            ui.add_space(10.0);
+
+            match &state {
+                LoginState::Checking => {
+                    ui.label("Connecting to database...");
+                    return;
+                }
+                LoginState::NeedsSetup(reason) => {
+                    ui.label(reason);
+                    if ui.button("Open Setup Wizard").clicked() {
+                        *current_view = View::SetupWizard;
+                    }
+                    if ui.button("Retry").clicked() {
+                        self.recheck();
+                    }
+                    return;
+                }
+                LoginState::NoUsers => {
+                    ui.label("No users exist yet. Create the administrator account.");
+                }
+                LoginState::Ready => {}
+            }
+
+            egui::Grid::new("login_grid").show(ui, |ui| {
+                ui.label("Username:");
+                ui.text_edit_singleline(&mut self.username);
+                ui.end_row();
+                if state == LoginState::NoUsers {
+                    ui.label("Display Name:");
+                    ui.text_edit_singleline(&mut self.display_name);
+                    ui.end_row();
+                }
+                ui.label("Password:");
+                let password =
+                    ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+                ui.end_row();
+
+                let submit = password.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                let label = if state == LoginState::NoUsers {
+                    "Create Administrator"
+                } else {
+                    "Log in"
+                };
+                ui.label("");
+                if ui.button(label).clicked() || submit {
+                    if state == LoginState::NoUsers {
+                        self.create_admin(ctx.clone());
+                    } else {
+                        self.log_in(ctx.clone());
+                    }
+                }
+                ui.end_row();
+            });
+
+            let message = self.message.lock().unwrap().clone();
+            if !message.is_empty() {
+                ui.colored_label(egui::Color32::LIGHT_RED, message);
+            }
+        });
+    }
+
+    fn check_database(&self, ctx: egui::Context) {
+        let state = Arc::clone(&self.state);
+        *state.lock().unwrap() = LoginState::Checking;
+        tokio::spawn(async move {
+            let new_state = match db::get_config() {
+                None => LoginState::NeedsSetup(
+                    "No database configuration found. Please run the Setup Wizard first."
+                        .to_string(),
+                ),
+                Some(config) => match db::count_users(&config).await {
+                    Ok(0) => LoginState::NoUsers,
+                    Ok(_) => LoginState::Ready,
+                    Err(e) => LoginState::NeedsSetup(format!(
+                        "The database is not ready ({}). Please run the Setup Wizard.",
+                        e
+                    )),
+                },
+            };
+            *state.lock().unwrap() = new_state;
+            ctx.request_repaint();
+        });
+    }
+
+    fn log_in(&mut self, ctx: egui::Context) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let username = self.username.trim().to_string();
+        let password = std::mem::take(&mut self.password);
+        let message = Arc::clone(&self.message);
+        tokio::spawn(async move {
+            let result = match db::get_login_user(&config, &username).await {
+                Ok(Some((user, hash))) if verify_password(&password, &hash) => Ok(user),
+                Ok(_) => Err("Unknown user or wrong password".to_string()),
+                Err(e) => Err(format!("Login failed: {}", e)),
+            };
+            match result {
+                Ok(user) => {
+                    println!("User {} logged in", user.username);
+                    message.lock().unwrap().clear();
+                    set_current_user(Some(user));
+                }
+                Err(e) => *message.lock().unwrap() = e,
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    fn create_admin(&mut self, ctx: egui::Context) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        if self.username.trim().is_empty() || self.password.len() < 8 {
+            *self.message.lock().unwrap() =
+                "Please enter a username and a password of at least 8 characters".to_string();
+            return;
+        }
+        let user = User {
+            user_id: 0,
+            username: self.username.trim().to_string(),
+            display_name: self.display_name.trim().to_string(),
+            role: Role::Admin,
+            active: true,
+        };
+        let password = std::mem::take(&mut self.password);
+        let message = Arc::clone(&self.message);
+        let state = Arc::clone(&self.state);
+        tokio::spawn(async move {
+            let result = match hash_password(&password) {
+                Ok(hash) => db::add_first_admin(&config, &user, &hash)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(admin) => {
+                    message.lock().unwrap().clear();
+                    *state.lock().unwrap() = LoginState::Ready;
+                    set_current_user(Some(admin));
+                }
+                Err(e) => {
+                    *message.lock().unwrap() = format!("Could not create user: {}", e);
+                    // Someone else may have created the first user meanwhile.
+                    if db::count_users(&config).await.is_ok_and(|count| count > 0) {
+                        *state.lock().unwrap() = LoginState::Ready;
+                    }
+                }
+            }
+            ctx.request_repaint();
+        });
+    }
+}
@@ -0,0 +1,322 @@
+// banking.rs
+use crate::auth;
+use crate::currency::format_amount;
+use crate::db::{self, DirectDebit, OpenInvoice, StatementEntry, StatementImport};
+use crate::export;
+use crate::sepa;
+use crate::ui;
+use chrono::{Days, NaiveDate, Utc};
+use eframe::egui;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// SEPA direct debits of open invoices and the import of bank statements.
+pub struct BankingView {
+    candidates: Arc<Mutex<Vec<DirectDebit>>>,
+    open: Arc<Mutex<Vec<OpenInvoice>>>,
+    /// Set whenever invoices or mandates must be (re)loaded.
+    pub stale: Arc<AtomicBool>,
+    status: Arc<Mutex<String>>,
+    /// Invoices left out of the next direct debit file.
+    skipped: HashSet<i32>,
+    collection_date: NaiveDate,
+    debit_path: String,
+    statement_path: String,
+    /// Result of the last statement import.
+    import: Arc<Mutex<Option<StatementImport>>>,
+    /// Invoice chosen for unmatched entries, by bank reference.
+    assignments: HashMap<String, i32>,
+}
+
+impl Default for BankingView {
+    fn default() -> Self {
+        let today = Utc::now().date_naive();
+        Self {
+            candidates: Arc::new(Mutex::new(Vec::new())),
+            open: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            status: Arc::new(Mutex::new(String::new())),
+            skipped: HashSet::new(),
+            collection_date: today.checked_add_days(Days::new(2)).unwrap_or(today),
+            debit_path: export::default_export_path("direct_debits.xml"),
+            statement_path: export::default_export_path("statement.xml"),
+            import: Arc::new(Mutex::new(None)),
+            assignments: HashMap::new(),
+        }
+    }
+}
+
+impl BankingView {
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+        let candidates = self.candidates.lock().unwrap().clone();
+        let open = self.open.lock().unwrap().clone();
+        let import = self.import.lock().unwrap().clone();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Banking");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.strong(format!("Direct debits ({})", candidates.len()));
+                ui.label("Sent invoices in EUR of customers with a signed mandate that have not been collected yet.");
+                egui::Grid::new("direct_debit_grid").striped(true).show(ui, |ui| {
+                    ui.strong("");
+                    ui.strong("Invoice");
+                    ui.strong("Customer");
+                    ui.strong("Mandate");
+                    ui.strong("Sequence");
+                    ui.strong("Amount");
+                    ui.end_row();
+                    for debit in &candidates {
+                        let mut included = !self.skipped.contains(&debit.invoice_id);
+                        if ui.checkbox(&mut included, "").changed() {
+                            if included {
+                                self.skipped.remove(&debit.invoice_id);
+                            } else {
+                                self.skipped.insert(debit.invoice_id);
+                            }
+                        }
+                        ui.label(&debit.invoice_number);
+                        ui.label(&debit.debtor_name);
+                        ui.label(format!("{} of {}", debit.mandate_reference, debit.mandate_date));
+                        ui.label(&debit.sequence_type);
+                        ui.label(format_amount(debit.amount, "EUR"));
+                        ui.end_row();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Collection date:");
+                    ui::parsed_field(ui, "collection_date", &mut self.collection_date);
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.debit_path);
+                    if auth::can_edit() && ui.button("Create Direct Debit File").clicked() {
+                        let selected = candidates
+                            .iter()
+                            .filter(|d| !self.skipped.contains(&d.invoice_id))
+                            .cloned()
+                            .collect();
+                        self.create_debit_file(selected);
+                    }
+                });
+
+                ui.add_space(20.0);
+                ui.strong("Bank statement import");
+                ui.label("Credits of a camt.053 statement are booked as payments of the open invoice with the same amount whose number they mention, or of the collected invoice.");
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.statement_path);
+                    if auth::can_edit() && ui.button("Import").clicked() {
+                        self.import_statement();
+                    }
+                });
+
+                if let Some(import) = import {
+                    self.render_import(ui, &import, &open);
+                }
+            });
+        });
+    }
+
+    fn render_import(&mut self, ui: &mut egui::Ui, import: &StatementImport, open: &[OpenInvoice]) {
+        ui.label(format!(
+            "{} credits booked, {} imported before, {} without a matching invoice",
+            import.booked.len(),
+            import.already_imported,
+            import.unmatched.len()
+        ));
+        if !import.booked.is_empty() {
+            egui::Grid::new("statement_booked_grid").striped(true).show(ui, |ui| {
+                ui.strong("Date");
+                ui.strong("Amount");
+                ui.strong("From");
+                ui.strong("Invoice");
+                ui.end_row();
+                for (entry, invoice_number) in &import.booked {
+                    ui.label(entry.booking_date.to_string());
+                    ui.label(format_amount(entry.amount, &entry.currency));
+                    ui.label(&entry.debtor_name);
+                    ui.label(invoice_number);
+                    ui.end_row();
+                }
+            });
+        }
+        if import.unmatched.is_empty() {
+            return;
+        }
+
+        ui.add_space(10.0);
+        ui.strong("Unmatched credits");
+        let mut book = None;
+        egui::Grid::new("statement_unmatched_grid").striped(true).show(ui, |ui| {
+            ui.strong("Date");
+            ui.strong("Amount");
+            ui.strong("From");
+            ui.strong("Reference");
+            ui.strong("Invoice");
+            ui.end_row();
+            for entry in &import.unmatched {
+                ui.label(entry.booking_date.to_string());
+                ui.label(format_amount(entry.amount, &entry.currency));
+                ui.label(&entry.debtor_name);
+                ui.label(&entry.remittance);
+                let chosen = self.assignments.get(&entry.bank_reference).copied();
+                let selected = open
+                    .iter()
+                    .find(|i| Some(i.invoice_id) == chosen)
+                    .map_or_else(String::new, |i| i.invoice_number.clone());
+                egui::ComboBox::from_id_source(("statement_invoice", &entry.bank_reference))
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for invoice in open.iter().filter(|i| i.currency == entry.currency) {
+                            let label = format!(
+                                "{} {} ({} open)",
+                                invoice.invoice_number,
+                                invoice.company_name,
+                                format_amount(invoice.open_amount, &invoice.currency)
+                            );
+                            if ui.selectable_label(chosen == Some(invoice.invoice_id), label).clicked() {
+                                self.assignments.insert(entry.bank_reference.clone(), invoice.invoice_id);
+                            }
+                        }
+                    });
+                if let Some(invoice_id) = chosen {
+                    if auth::can_edit() && ui.button("Book").clicked() {
+                        book = Some((entry.clone(), invoice_id));
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        if let Some((entry, invoice_id)) = book {
+            self.book(entry, invoice_id);
+        }
+    }
+
+    fn load(&self) {
+        let candidates = Arc::clone(&self.candidates);
+        let open = Arc::clone(&self.open);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_direct_debit_candidates(&config).await {
+                Ok(loaded) => *candidates.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching direct debits: {}", e),
+            }
+            match db::get_open_invoices(&config).await {
+                Ok(loaded) => *open.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching open invoices: {}", e),
+            }
+        });
+    }
+
+    /// Writes the direct debit file for `debits` and records the
+    /// collections; the file is removed again if they cannot be recorded.
+    fn create_debit_file(&self, debits: Vec<DirectDebit>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let collection_date = self.collection_date;
+        let path = self.debit_path.clone();
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            let result: Result<String, Box<dyn std::error::Error>> = async {
+                let company = db::get_company_profile(&config).await?;
+                let message_id = format!("DD{}", Utc::now().format("%Y%m%d%H%M%S"));
+                let xml = sepa::pain_008(&company, &debits, &message_id, collection_date)
+                    .map_err(|errors| errors.join("; "))?;
+                fs::write(&path, xml)?;
+                if let Err(e) = db::record_direct_debits(&config, &debits, &message_id, collection_date).await {
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+                Ok(format!("{} direct debits written to {}", debits.len(), path))
+            }
+            .await;
+            *status.lock().unwrap() = match result {
+                Ok(message) => message,
+                Err(e) => format!("Error creating direct debit file: {}", e),
+            };
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    fn import_statement(&mut self) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let entries = match fs::read_to_string(&self.statement_path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| sepa::parse_camt053(&contents))
+        {
+            Ok(entries) => entries,
+            Err(e) => {
+                *self.status.lock().unwrap() = format!("Error reading statement: {}", e);
+                return;
+            }
+        };
+        self.assignments.clear();
+        let import = Arc::clone(&self.import);
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::import_statement(&config, &entries).await {
+                Ok(result) => {
+                    *status.lock().unwrap() = format!("{} credits read from the statement", entries.len());
+                    *import.lock().unwrap() = Some(result);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error importing statement: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    fn book(&self, entry: StatementEntry, invoice_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let invoice_number = self
+            .open
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|i| i.invoice_id == invoice_id)
+            .map(|i| i.invoice_number.clone())
+            .unwrap_or_default();
+        let import = Arc::clone(&self.import);
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::book_statement_entry(&config, &entry, invoice_id).await {
+                Ok(booked) => {
+                    if let Some(import) = import.lock().unwrap().as_mut() {
+                        import.unmatched.retain(|e| e.bank_reference != entry.bank_reference);
+                        if booked {
+                            import.booked.push((entry, invoice_number.clone()));
+                        } else {
+                            import.already_imported += 1;
+                        }
+                    }
+                    *status.lock().unwrap() = format!("Payment of {} booked", invoice_number);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error booking payment: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
@@ -0,0 +1,433 @@
+// currency.rs
+use crate::db::{self, Currency, ExchangeRate};
+use crate::export;
+use crate::ui;
+use chrono::NaiveDate;
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Currency the ECB reference rates are quoted against.
+const ECB_REFERENCE: &str = "EUR";
+
+/// Formats `amount` followed by its currency code, e.g. "1234.50 USD".
+pub fn format_amount(amount: Decimal, currency: &str) -> String {
+    format!("{:.2} {}", amount, currency)
+}
+
+/// Value of the attribute `name` in an XML tag such as
+/// `<Cube currency='USD' rate='1.0921'/>`.
+fn xml_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=", name))? + name.len() + 2;
+    let quote = tag[start..].chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let value = &tag[start + 1..];
+    value.find(quote).map(|end| &value[..end])
+}
+
+/// Rates per euro by date from the ECB XML files (`eurofxref-daily.xml`,
+/// `eurofxref-hist.xml` and the 90-day variant).
+fn parse_ecb_xml(contents: &str) -> Result<BTreeMap<NaiveDate, HashMap<String, Decimal>>, String> {
+    let mut rates: BTreeMap<NaiveDate, HashMap<String, Decimal>> = BTreeMap::new();
+    let mut date = None;
+    for part in contents.split("<Cube").skip(1) {
+        let tag = part.split('>').next().unwrap_or_default();
+        if let Some(time) = xml_attribute(tag, "time") {
+            let parsed = NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .map_err(|_| format!("Invalid date in rate file: {}", time))?;
+            date = Some(parsed);
+        }
+        if let (Some(currency), Some(rate)) = (xml_attribute(tag, "currency"), xml_attribute(tag, "rate")) {
+            let date = date.ok_or("Rate outside of a dated block")?;
+            let rate = Decimal::from_str(rate).map_err(|_| format!("Invalid rate for {}: {}", currency, rate))?;
+            rates.entry(date).or_default().insert(currency.to_string(), rate);
+        }
+    }
+    Ok(rates)
+}
+
+/// Rates per euro by date from the ECB CSV files (`eurofxref.csv` with
+/// dates like "05 January 2024" and `eurofxref-hist.csv` with ISO dates).
+/// Currencies without a rate on a day are given as "N/A".
+fn parse_ecb_csv(contents: &str) -> Result<BTreeMap<NaiveDate, HashMap<String, Decimal>>, String> {
+    let mut lines = contents.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = lines.next().ok_or("The rate file is empty")?.split(',').map(str::trim).collect();
+    if header.first() != Some(&"Date") {
+        return Err("Not an ECB reference rate file".to_string());
+    }
+
+    let mut rates: BTreeMap<NaiveDate, HashMap<String, Decimal>> = BTreeMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let date = NaiveDate::parse_from_str(fields[0], "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(fields[0], "%d %B %Y"))
+            .map_err(|_| format!("Invalid date in rate file: {}", fields[0]))?;
+        let day = rates.entry(date).or_default();
+        for (currency, value) in header.iter().zip(&fields).skip(1) {
+            if currency.is_empty() || value.is_empty() || *value == "N/A" {
+                continue;
+            }
+            let rate = Decimal::from_str(value).map_err(|_| format!("Invalid rate for {}: {}", currency, value))?;
+            day.insert(currency.to_string(), rate);
+        }
+    }
+    Ok(rates)
+}
+
+/// Reads an ECB reference rate file, XML or CSV, and turns its rates per
+/// euro into rates per unit of `base`. Days without a rate for the base
+/// currency are left out.
+pub fn parse_rate_file(contents: &str, base: &str) -> Result<Vec<ExchangeRate>, String> {
+    let per_euro = if contents.trim_start().starts_with('<') {
+        parse_ecb_xml(contents)?
+    } else {
+        parse_ecb_csv(contents)?
+    };
+
+    let mut rates = Vec::new();
+    for (date, mut day) in per_euro {
+        day.insert(ECB_REFERENCE.to_string(), Decimal::ONE);
+        let Some(base_rate) = day.get(base).copied().filter(|r| !r.is_zero()) else {
+            continue;
+        };
+        for (currency, rate) in day {
+            if currency != base && !rate.is_zero() {
+                rates.push(ExchangeRate {
+                    currency,
+                    rate_date: date,
+                    rate: (rate / base_rate).round_dp(6),
+                });
+            }
+        }
+    }
+    if rates.is_empty() {
+        return Err(format!("The file contains no rates that can be related to {}", base));
+    }
+    Ok(rates)
+}
+
+/// Currencies and the latest exchange rates, shared by all views.
+#[derive(Clone)]
+pub struct CurrencyStore {
+    currencies: Arc<Mutex<Vec<Currency>>>,
+    latest_rates: Arc<Mutex<Vec<ExchangeRate>>>,
+    /// Set whenever currencies or rates must be reloaded.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl Default for CurrencyStore {
+    fn default() -> Self {
+        Self {
+            currencies: Arc::new(Mutex::new(Vec::new())),
+            latest_rates: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl CurrencyStore {
+    pub fn refresh_if_stale(&self) {
+        if !self.stale.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let currencies = Arc::clone(&self.currencies);
+        let latest_rates = Arc::clone(&self.latest_rates);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_currencies(&config).await {
+                Ok(loaded) => *currencies.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching currencies: {}", e),
+            }
+            match db::get_latest_exchange_rates(&config).await {
+                Ok(loaded) => *latest_rates.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching exchange rates: {}", e),
+            }
+        });
+    }
+
+    pub fn all_currencies(&self) -> Vec<Currency> {
+        self.currencies.lock().unwrap().clone()
+    }
+
+    /// Code of the base currency; "EUR" until the currencies are loaded.
+    pub fn base(&self) -> String {
+        self.currencies
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.is_base)
+            .map_or_else(|| ECB_REFERENCE.to_string(), |c| c.code.clone())
+    }
+
+    pub fn latest_rates(&self) -> Vec<ExchangeRate> {
+        self.latest_rates.lock().unwrap().clone()
+    }
+
+    pub fn save_currency(&self, currency: Currency, status: Arc<Mutex<String>>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::save_currency(&config, &currency).await {
+                Ok(saved) => *status.lock().unwrap() = format!("Currency {} saved", saved.code),
+                Err(e) => *status.lock().unwrap() = format!("Error saving currency: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    pub fn save_rates(&self, rates: Vec<ExchangeRate>, status: Arc<Mutex<String>>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::save_exchange_rates(&config, &rates).await {
+                Ok(written) => *status.lock().unwrap() = format!("{} exchange rates saved", written),
+                Err(e) => *status.lock().unwrap() = format!("Error saving exchange rates: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    /// Imports an ECB reference rate file relative to the base currency.
+    pub fn import_rates(&self, path: &str, status: Arc<Mutex<String>>) {
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path, e))
+            .and_then(|contents| parse_rate_file(&contents, &self.base()));
+        match parsed {
+            Ok(rates) => self.save_rates(rates, status),
+            Err(e) => *status.lock().unwrap() = e,
+        }
+    }
+}
+
+/// Combo box choosing a currency code. Only active currencies are offered,
+/// but a stored inactive one is kept.
+pub fn currency_combo(ui: &mut egui::Ui, id_source: impl std::hash::Hash, code: &mut String, store: &CurrencyStore) {
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(code.clone())
+        .width(70.0)
+        .show_ui(ui, |ui| {
+            for currency in store.all_currencies().into_iter().filter(|c| c.active) {
+                let label = format!("{} - {}", currency.code, currency.name);
+                ui.selectable_value(code, currency.code, label);
+            }
+        });
+}
+
+fn empty_currency() -> Currency {
+    Currency {
+        currency_id: 0,
+        code: String::new(),
+        name: String::new(),
+        is_base: false,
+        active: true,
+    }
+}
+
+/// Admin section of the settings view for currencies and exchange rates.
+pub fn render_currency_settings(ui: &mut egui::Ui, store: &CurrencyStore, status: &Arc<Mutex<String>>) {
+    let id = egui::Id::new("currency_edits");
+    let mut edits: HashMap<i32, Currency> = ui.data_mut(|d| d.get_temp(id)).unwrap_or_default();
+
+    egui::Grid::new("currencies_grid").striped(true).show(ui, |ui| {
+        ui.strong("Code");
+        ui.strong("Name");
+        ui.strong("Base");
+        ui.strong("Active");
+        ui.end_row();
+
+        let rows = store.all_currencies().into_iter().chain(std::iter::once(empty_currency()));
+        for stored in rows {
+            let currency = edits.entry(stored.currency_id).or_insert(stored);
+            ui.add(egui::TextEdit::singleline(&mut currency.code).desired_width(40.0));
+            ui.text_edit_singleline(&mut currency.name);
+            ui.checkbox(&mut currency.is_base, "");
+            ui.checkbox(&mut currency.active, "");
+            let label = if currency.currency_id == 0 { "Add" } else { "Save" };
+            if ui.button(label).clicked() {
+                let mut currency = currency.clone();
+                currency.code = currency.code.trim().to_uppercase();
+                currency.name = currency.name.trim().to_string();
+                if currency.code.len() != 3 || !currency.code.chars().all(|c| c.is_ascii_uppercase()) {
+                    *status.lock().unwrap() = "Please enter a three-letter currency code".to_string();
+                } else {
+                    edits.remove(&currency.currency_id);
+                    store.save_currency(currency, Arc::clone(status));
+                }
+            }
+            ui.end_row();
+        }
+    });
+    ui.label("Changing the base currency requires importing the exchange rates again.");
+
+    ui.add_space(10.0);
+    ui.strong(format!("Exchange Rates (units per 1 {})", store.base()));
+    egui::Grid::new("latest_rates_grid").striped(true).show(ui, |ui| {
+        ui.strong("Currency");
+        ui.strong("Rate");
+        ui.strong("Since");
+        ui.end_row();
+        for rate in store.latest_rates() {
+            ui.label(&rate.currency);
+            ui.label(rate.rate.normalize().to_string());
+            ui.label(rate.rate_date.to_string());
+            ui.end_row();
+        }
+    });
+
+    let path_id = egui::Id::new("exchange_rate_import_path");
+    let mut path: String = ui
+        .data_mut(|d| d.get_temp(path_id))
+        .unwrap_or_else(|| export::default_export_path("eurofxref-hist.csv"));
+    ui.horizontal(|ui| {
+        ui.label("ECB rate file (CSV or XML):");
+        ui.text_edit_singleline(&mut path);
+        if ui.button("Import").clicked() {
+            store.import_rates(&path, Arc::clone(status));
+        }
+    });
+
+    let manual_id = egui::Id::new("manual_exchange_rate");
+    let mut manual: (String, NaiveDate, Decimal) = ui
+        .data_mut(|d| d.get_temp(manual_id))
+        .unwrap_or_else(|| (String::new(), chrono::Utc::now().date_naive(), Decimal::ZERO));
+    ui.horizontal(|ui| {
+        ui.label("Rate of");
+        currency_combo(ui, "manual_rate_currency", &mut manual.0, store);
+        ui.label("on");
+        ui::parsed_field(ui, "manual_rate_date", &mut manual.1);
+        ui.label("per 1");
+        ui.label(store.base());
+        ui::parsed_field(ui, "manual_rate_value", &mut manual.2);
+        if ui.button("Save Rate").clicked() {
+            if manual.0.is_empty() || manual.0 == store.base() || manual.2 <= Decimal::ZERO {
+                *status.lock().unwrap() =
+                    "Please pick a currency other than the base and enter a positive rate".to_string();
+            } else {
+                let rate = ExchangeRate {
+                    currency: manual.0.clone(),
+                    rate_date: manual.1,
+                    rate: manual.2,
+                };
+                store.save_rates(vec![rate], Arc::clone(status));
+            }
+        }
+    });
+
+    ui.data_mut(|d| {
+        d.insert_temp(id, edits);
+        d.insert_temp(path_id, path);
+        d.insert_temp(manual_id, manual);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Rates as (currency, date, rate), in a stable order.
+    fn sorted(rates: Vec<ExchangeRate>) -> Vec<(String, NaiveDate, String)> {
+        let mut rates: Vec<_> = rates
+            .into_iter()
+            .map(|r| (r.currency, r.rate_date, r.rate.normalize().to_string()))
+            .collect();
+        rates.sort();
+        rates
+    }
+
+    fn rate(currency: &str, rate_date: NaiveDate, rate: &str) -> (String, NaiveDate, String) {
+        (currency.to_string(), rate_date, rate.to_string())
+    }
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01" xmlns="http://www.ecb.int/vocabulary/2002-08-01/eurofxref">
+    <gesmes:subject>Reference rates</gesmes:subject>
+    <Cube>
+        <Cube time='2026-03-03'>
+            <Cube currency='USD' rate='1.1000'/>
+        </Cube>
+        <Cube time="2026-03-02">
+            <Cube currency="USD" rate="1.0800"/>
+            <Cube currency="GBP" rate="0.8500"/>
+        </Cube>
+    </Cube>
+</gesmes:Envelope>"#;
+
+    #[test]
+    fn parse_rate_file_reads_ecb_xml() {
+        assert_eq!(
+            sorted(parse_rate_file(XML, "EUR").unwrap()),
+            [
+                rate("GBP", date(2026, 3, 2), "0.85"),
+                rate("USD", date(2026, 3, 2), "1.08"),
+                rate("USD", date(2026, 3, 3), "1.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rate_file_relates_rates_to_the_base_currency() {
+        assert_eq!(
+            sorted(parse_rate_file(XML, "USD").unwrap()),
+            [
+                rate("EUR", date(2026, 3, 2), "0.925926"),
+                rate("EUR", date(2026, 3, 3), "0.909091"),
+                rate("GBP", date(2026, 3, 2), "0.787037"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rate_file_skips_days_without_a_base_rate() {
+        assert_eq!(
+            sorted(parse_rate_file(XML, "GBP").unwrap()),
+            [
+                rate("EUR", date(2026, 3, 2), "1.176471"),
+                rate("USD", date(2026, 3, 2), "1.270588"),
+            ]
+        );
+        assert!(parse_rate_file(XML, "CHF").is_err());
+    }
+
+    #[test]
+    fn parse_rate_file_reads_the_daily_csv() {
+        let csv = "Date, USD, JPY, GBP, \n05 January 2024, 1.0921, 157.89, N/A, \n";
+        assert_eq!(
+            sorted(parse_rate_file(csv, "EUR").unwrap()),
+            [rate("JPY", date(2024, 1, 5), "157.89"), rate("USD", date(2024, 1, 5), "1.0921")]
+        );
+    }
+
+    #[test]
+    fn parse_rate_file_reads_the_history_csv() {
+        let csv = "Date,USD,GBP,\n2024-01-05,1.0921,0.8608,\n2024-01-04,1.0953,N/A,\n";
+        assert_eq!(
+            sorted(parse_rate_file(csv, "EUR").unwrap()),
+            [
+                rate("GBP", date(2024, 1, 5), "0.8608"),
+                rate("USD", date(2024, 1, 4), "1.0953"),
+                rate("USD", date(2024, 1, 5), "1.0921"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rate_file_rejects_broken_files() {
+        assert!(parse_rate_file("", "EUR").is_err());
+        assert!(parse_rate_file("Currency,USD\n2024-01-05,1.09\n", "EUR").is_err());
+        assert!(parse_rate_file("Date,USD\n2024-13-05,1.09\n", "EUR").is_err());
+        assert!(parse_rate_file("Date,USD\n2024-01-05,abc\n", "EUR").is_err());
+        assert!(parse_rate_file("<Cube currency='USD' rate='1.09'/>", "EUR").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+// custom_fields.rs
+use crate::db::{self, CustomField};
+use chrono::NaiveDate;
+use eframe::egui;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    Text,
+    Number,
+    Date,
+    Choice,
+    Boolean,
+}
+
+pub const FIELD_TYPES: [FieldType; 5] = [
+    FieldType::Text,
+    FieldType::Number,
+    FieldType::Date,
+    FieldType::Choice,
+    FieldType::Boolean,
+];
+
+impl FieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Number => "number",
+            FieldType::Date => "date",
+            FieldType::Choice => "choice",
+            FieldType::Boolean => "boolean",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FieldType::Text => "Text",
+            FieldType::Number => "Number",
+            FieldType::Date => "Date",
+            FieldType::Choice => "Choice",
+            FieldType::Boolean => "Yes/No",
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FIELD_TYPES
+            .into_iter()
+            .find(|field_type| field_type.as_str() == s)
+            .ok_or_else(|| format!("Unknown field type: {}", s))
+    }
+}
+
+/// Whether `value` is acceptable for `field`. Empty values always are; they
+/// mean "not set".
+pub fn is_valid(field: &CustomField, value: &str) -> bool {
+    let value = value.trim();
+    if value.is_empty() {
+        return true;
+    }
+    match field.field_type {
+        FieldType::Text => true,
+        FieldType::Number => Decimal::from_str(value).is_ok(),
+        FieldType::Date => NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+        FieldType::Choice => field.choices.iter().any(|c| c == value),
+        FieldType::Boolean => value == "true" || value == "false",
+    }
+}
+
+/// Human-readable form of a stored value.
+pub fn display_value(field: &CustomField, value: &str) -> String {
+    match (field.field_type, value) {
+        (FieldType::Boolean, "true") => "Yes".to_string(),
+        (FieldType::Boolean, "false") => "No".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Field definitions and the values of all customers, shared by the forms,
+/// search, filters and exports.
+#[derive(Clone)]
+pub struct CustomFieldStore {
+    fields: Arc<Mutex<Vec<CustomField>>>,
+    values: Arc<Mutex<HashMap<i32, HashMap<i32, String>>>>,
+    /// Set whenever definitions or values must be reloaded.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl Default for CustomFieldStore {
+    fn default() -> Self {
+        Self {
+            fields: Arc::new(Mutex::new(Vec::new())),
+            values: Arc::new(Mutex::new(HashMap::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl CustomFieldStore {
+    pub fn refresh_if_stale(&self) {
+        if !self.stale.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let store = self.clone();
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_custom_fields(&config).await {
+                Ok(fields) => *store.fields.lock().unwrap() = fields,
+                Err(e) => eprintln!("Error fetching custom fields: {}", e),
+            }
+            match db::get_custom_values(&config).await {
+                Ok(rows) => {
+                    let mut values: HashMap<i32, HashMap<i32, String>> = HashMap::new();
+                    for (customer_id, field_id, value) in rows {
+                        values.entry(customer_id).or_default().insert(field_id, value);
+                    }
+                    *store.values.lock().unwrap() = values;
+                }
+                Err(e) => eprintln!("Error fetching custom values: {}", e),
+            }
+        });
+    }
+
+    /// All definitions, including inactive ones.
+    pub fn all_fields(&self) -> Vec<CustomField> {
+        self.fields.lock().unwrap().clone()
+    }
+
+    /// Definitions shown in forms, search, filters and exports.
+    pub fn active_fields(&self) -> Vec<CustomField> {
+        self.all_fields().into_iter().filter(|f| f.active).collect()
+    }
+
+    pub fn values_of(&self, customer_id: i32) -> HashMap<i32, String> {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&customer_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn value(&self, customer_id: i32, field_id: i32) -> String {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&customer_id)
+            .and_then(|values| values.get(&field_id).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Whether any active field of the customer contains `query`
+    /// (already lowercased).
+    pub fn matches(&self, customer_id: i32, query: &str) -> bool {
+        let values = self.values_of(customer_id);
+        self.active_fields().iter().any(|field| {
+            values.get(&field.field_id).is_some_and(|value| {
+                display_value(field, value).to_lowercase().contains(query)
+            })
+        })
+    }
+
+    /// Stores the values of one customer and reloads afterwards.
+    pub fn save_values(&self, customer_id: i32, values: HashMap<i32, String>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            if let Err(e) = db::set_custom_values(&config, customer_id, &values).await {
+                eprintln!("Error saving custom values of customer {}: {}", customer_id, e);
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    pub fn save_field(&self, field: CustomField, status: Arc<Mutex<String>>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::save_custom_field(&config, &field).await {
+                Ok(saved) => *status.lock().unwrap() = format!("Field {} saved", saved.name),
+                Err(e) => *status.lock().unwrap() = format!("Error saving field: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Input widget matching the field type. Invalid input is kept but marked.
+pub fn field_input(ui: &mut egui::Ui, field: &CustomField, value: &mut String, id_salt: i32) {
+    match field.field_type {
+        FieldType::Boolean => {
+            let mut checked = value == "true";
+            if ui.checkbox(&mut checked, "").changed() {
+                *value = checked.to_string();
+            }
+        }
+        FieldType::Choice => {
+            egui::ComboBox::from_id_source(("custom_choice", field.field_id, id_salt))
+                .selected_text(value.clone())
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(value, String::new(), "-");
+                    for choice in &field.choices {
+                        ui.selectable_value(value, choice.clone(), choice);
+                    }
+                });
+        }
+        FieldType::Text | FieldType::Number | FieldType::Date => {
+            let hint = match field.field_type {
+                FieldType::Date => "YYYY-MM-DD",
+                FieldType::Number => "0.00",
+                _ => "",
+            };
+            let valid = is_valid(field, value);
+            let mut edit = egui::TextEdit::singleline(value).hint_text(hint);
+            if !valid {
+                edit = edit.text_color(egui::Color32::LIGHT_RED);
+            }
+            ui.add(edit);
+        }
+    }
+}
+
+/// One labelled row per active field, for use inside a form.
+pub fn render_field_inputs(
+    ui: &mut egui::Ui,
+    fields: &[CustomField],
+    values: &mut HashMap<i32, String>,
+    id_salt: i32,
+) {
+    for field in fields {
+        ui.horizontal(|ui| {
+            ui.label(format!("{}:", field.name));
+            let value = values.entry(field.field_id).or_default();
+            field_input(ui, field, value, id_salt);
+        });
+    }
+}
+
+/// Whether all values are valid for their fields.
+pub fn all_valid(fields: &[CustomField], values: &HashMap<i32, String>) -> bool {
+    fields.iter().all(|field| {
+        values
+            .get(&field.field_id)
+            .is_none_or(|value| is_valid(field, value))
+    })
+}
+
+/// Admin section of the settings view for defining fields.
+pub fn render_field_definitions(
+    ui: &mut egui::Ui,
+    store: &CustomFieldStore,
+    new_field: &mut CustomField,
+    status: &Arc<Mutex<String>>,
+) {
+    let id = egui::Id::new("custom_field_edits");
+    let mut edits: HashMap<i32, (CustomField, String)> =
+        ui.data_mut(|d| d.get_temp(id)).unwrap_or_default();
+
+    egui::Grid::new("custom_fields_grid").striped(true).show(ui, |ui| {
+        ui.strong("Name");
+        ui.strong("Type");
+        ui.strong("Choices (comma-separated)");
+        ui.strong("Order");
+        ui.strong("Active");
+        ui.end_row();
+
+        for stored in store.all_fields() {
+            let (field, choices) = edits
+                .entry(stored.field_id)
+                .or_insert_with(|| (stored.clone(), stored.choices.join(", ")));
+            if definition_row(ui, field, choices) {
+                field.choices = split_choices(choices);
+                store.save_field(field.clone(), Arc::clone(status));
+                edits.remove(&stored.field_id);
+            }
+            ui.end_row();
+        }
+    });
+
+    ui.add_space(10.0);
+    ui.label("New field:");
+    let new_choices_id = egui::Id::new("new_custom_field_choices");
+    let mut new_choices: String = ui.data_mut(|d| d.get_temp(new_choices_id)).unwrap_or_default();
+    egui::Grid::new("new_custom_field_grid").show(ui, |ui| {
+        if definition_row(ui, new_field, &mut new_choices) && !new_field.name.trim().is_empty() {
+            new_field.choices = split_choices(&new_choices);
+            new_field.name = new_field.name.trim().to_string();
+            store.save_field(new_field.clone(), Arc::clone(status));
+            new_field.name.clear();
+            new_choices.clear();
+        }
+        ui.end_row();
+    });
+
+    ui.data_mut(|d| {
+        d.insert_temp(id, edits);
+        d.insert_temp(new_choices_id, new_choices);
+    });
+}
+
+/// Editable cells of one field definition; returns true when "Save" was
+/// clicked.
+fn definition_row(ui: &mut egui::Ui, field: &mut CustomField, choices: &mut String) -> bool {
+    ui.text_edit_singleline(&mut field.name);
+    egui::ComboBox::from_id_source(("custom_field_type", field.field_id))
+        .selected_text(field.field_type.label())
+        .show_ui(ui, |ui| {
+            for field_type in FIELD_TYPES {
+                ui.selectable_value(&mut field.field_type, field_type, field_type.label());
+            }
+        });
+    ui.add_enabled(
+        field.field_type == FieldType::Choice,
+        egui::TextEdit::singleline(choices),
+    );
+    ui.add(egui::DragValue::new(&mut field.sort_order));
+    ui.checkbox(&mut field.active, "");
+    ui.button("Save").clicked()
+}
+
+fn split_choices(choices: &str) -> Vec<String> {
+    choices
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+pub fn empty_field() -> CustomField {
+    CustomField {
+        field_id: 0,
+        name: String::new(),
+        field_type: FieldType::Text,
+        choices: Vec::new(),
+        sort_order: 0,
+        active: true,
+    }
+}
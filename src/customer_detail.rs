@@ -0,0 +1,553 @@
+// customer_detail.rs
+use crate::app::View;
+use crate::auth::{self, Role};
+use crate::currency::format_amount;
+use crate::db::{self, Contact, ContactHistory, Customer, CustomerOverview, Task};
+use crate::tasks;
+use crate::timeline::TimelinePanel;
+use crate::ui;
+use chrono::Utc;
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(PartialEq, Clone, Copy)]
+enum Tab {
+    Overview,
+    Contacts,
+    History,
+    Timeline,
+    Invoices,
+    Payments,
+    Deals,
+    Notes,
+}
+
+impl Tab {
+    const ALL: [Tab; 8] = [
+        Tab::Overview,
+        Tab::Contacts,
+        Tab::History,
+        Tab::Timeline,
+        Tab::Invoices,
+        Tab::Payments,
+        Tab::Deals,
+        Tab::Notes,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Tab::Overview => "Overview",
+            Tab::Contacts => "Contacts",
+            Tab::History => "History",
+            Tab::Timeline => "Timeline",
+            Tab::Invoices => "Invoices",
+            Tab::Payments => "Payments",
+            Tab::Deals => "Deals",
+            Tab::Notes => "Notes",
+        }
+    }
+
+    /// Whether the tab shows invoice data.
+    fn financial(self) -> bool {
+        matches!(self, Tab::Invoices | Tab::Payments)
+    }
+}
+
+/// What the detail screen asks the app to do.
+pub enum CustomerAction {
+    /// Edit the master data in the customer window.
+    Edit(i32),
+    /// Open a new invoice to the customer.
+    NewInvoice(Box<Customer>),
+    /// Open a task following up a contact in the task editor.
+    NewTask(Box<Task>),
+}
+
+/// One customer with everything recorded about them, in tabs.
+pub struct CustomerDetailView {
+    customer_id: Option<i32>,
+    overview: Arc<Mutex<Option<CustomerOverview>>>,
+    /// Set whenever the customer must be (re)loaded.
+    pub stale: Arc<AtomicBool>,
+    status: Arc<Mutex<String>>,
+    tab: Tab,
+    /// Call being logged, if any.
+    call: Option<ContactHistory>,
+    new_contact: Contact,
+    new_note: String,
+    timeline: TimelinePanel,
+}
+
+impl Default for CustomerDetailView {
+    fn default() -> Self {
+        Self {
+            customer_id: None,
+            overview: Arc::new(Mutex::new(None)),
+            stale: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(Mutex::new(String::new())),
+            tab: Tab::Overview,
+            call: None,
+            new_contact: Contact::default(),
+            new_note: String::new(),
+            timeline: TimelinePanel::default(),
+        }
+    }
+}
+
+impl CustomerDetailView {
+    pub fn open(&mut self, customer_id: i32) {
+        if self.customer_id != Some(customer_id) {
+            *self.overview.lock().unwrap() = None;
+            self.status.lock().unwrap().clear();
+            self.tab = Tab::Overview;
+            self.call = None;
+            self.new_contact = Contact::default();
+            self.new_note.clear();
+        }
+        self.customer_id = Some(customer_id);
+        self.stale.store(true, Ordering::SeqCst);
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, role: Role) -> Option<CustomerAction> {
+        let sees_invoices = role.can_access(&View::Invoices);
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load(sees_invoices);
+            self.timeline.reload();
+        }
+        let overview = self.overview.lock().unwrap().clone();
+        let mut action = None;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Some(overview) = overview else {
+                ui.heading("Customer");
+                let status = self.status.lock().unwrap().clone();
+                ui.label(if status.is_empty() { "Loading customer data...".to_string() } else { status });
+                return;
+            };
+            let customer = &overview.customer;
+            ui.heading(&customer.company_name);
+
+            ui.horizontal(|ui| {
+                if auth::can_edit() {
+                    if ui.button("Edit").clicked() {
+                        action = Some(CustomerAction::Edit(customer.customer_id));
+                    }
+                    if ui.button("Log Call").clicked() {
+                        self.start_call(customer.customer_id);
+                    }
+                    if sees_invoices && ui.button("New Invoice").clicked() {
+                        action = Some(CustomerAction::NewInvoice(Box::new(customer.clone())));
+                    }
+                }
+                let status = self.status.lock().unwrap().clone();
+                if !status.is_empty() {
+                    ui.label(status);
+                }
+            });
+            ui.add_space(5.0);
+
+            ui.horizontal(|ui| {
+                for tab in Tab::ALL.into_iter().filter(|t| sees_invoices || !t.financial()) {
+                    ui.selectable_value(&mut self.tab, tab, tab.label());
+                }
+            });
+            ui.separator();
+
+            egui::ScrollArea::vertical().show(ui, |ui| match self.tab {
+                Tab::Overview => render_overview(ui, &overview, sees_invoices),
+                Tab::Contacts => self.render_contacts(ui, &overview),
+                Tab::History => {
+                    if let Some(task) = self.render_history(ui, &overview) {
+                        action = Some(CustomerAction::NewTask(Box::new(task)));
+                    }
+                }
+                Tab::Timeline => self.timeline.show(ui, &overview.customer, sees_invoices),
+                Tab::Invoices => render_invoices(ui, &overview),
+                Tab::Payments => render_payments(ui, &overview),
+                Tab::Deals => render_deals(ui, &overview),
+                Tab::Notes => self.render_notes(ui, &overview),
+            });
+        });
+        action
+    }
+
+    fn start_call(&mut self, customer_id: i32) {
+        self.call = Some(ContactHistory {
+            customer_id,
+            contact_type: "Call".to_string(),
+            contact_method: Some("Phone".to_string()),
+            ..ContactHistory::default()
+        });
+        self.tab = Tab::History;
+    }
+
+    fn render_contacts(&mut self, ui: &mut egui::Ui, overview: &CustomerOverview) {
+        egui::Grid::new("customer_contacts_grid").striped(true).show(ui, |ui| {
+            ui.strong("Name");
+            ui.strong("Position");
+            ui.strong("Email");
+            ui.strong("Phone");
+            ui.strong("");
+            ui.end_row();
+            for contact in &overview.contacts {
+                ui.label(format!("{} {}", contact.first_name, contact.last_name));
+                ui.label(&contact.position);
+                ui.label(&contact.email);
+                ui.label(&contact.phone);
+                ui.label(if contact.is_primary { "primary" } else { "" });
+                ui.end_row();
+            }
+        });
+        if overview.contacts.is_empty() {
+            ui.label(format!("Main contact: {}", overview.customer.contact_name));
+        }
+        if !auth::can_edit() {
+            return;
+        }
+
+        ui.add_space(10.0);
+        ui.strong("Add Contact");
+        egui::Grid::new("new_contact_grid").show(ui, |ui| {
+            for (label, value) in [
+                ("First Name:", &mut self.new_contact.first_name),
+                ("Last Name:", &mut self.new_contact.last_name),
+                ("Position:", &mut self.new_contact.position),
+                ("Email:", &mut self.new_contact.email),
+                ("Phone:", &mut self.new_contact.phone),
+            ] {
+                ui.label(label);
+                ui.text_edit_singleline(value);
+                ui.end_row();
+            }
+            ui.label("Primary:");
+            ui.checkbox(&mut self.new_contact.is_primary, "");
+            ui.end_row();
+        });
+        if ui.button("Add").clicked() {
+            if self.new_contact.first_name.trim().is_empty() || self.new_contact.last_name.trim().is_empty() {
+                *self.status.lock().unwrap() = "Please enter the first and last name".to_string();
+            } else {
+                let contact = Contact {
+                    customer_id: overview.customer.customer_id,
+                    ..std::mem::take(&mut self.new_contact)
+                };
+                self.add_contact(contact);
+            }
+        }
+    }
+
+    /// Returns a task to follow up the entry whose Create Task was clicked.
+    fn render_history(&mut self, ui: &mut egui::Ui, overview: &CustomerOverview) -> Option<Task> {
+        if let Some(mut call) = self.call.take() {
+            let mut keep = true;
+            ui.group(|ui| {
+                ui.strong("Log Call");
+                egui::Grid::new("log_call_grid").show(ui, |ui| {
+                    ui.label("Contact Type:");
+                    ui.text_edit_singleline(&mut call.contact_type);
+                    ui.end_row();
+                    ui.label("Outcome:");
+                    ui.text_edit_singleline(&mut call.contact_outcome);
+                    ui.end_row();
+                    ui.label("Duration (minutes):");
+                    let mut minutes = call.contact_duration.unwrap_or(0);
+                    ui.add(egui::DragValue::new(&mut minutes).clamp_range(0..=1440));
+                    call.contact_duration = (minutes > 0).then_some(minutes);
+                    ui.end_row();
+                    ui.label("Notes:");
+                    ui.text_edit_multiline(&mut call.notes);
+                    ui.end_row();
+                    ui.label("Follow-up:");
+                    let mut follow_up = call.follow_up_date.is_some();
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut follow_up, "");
+                        if follow_up {
+                            let mut date = call
+                                .follow_up_date
+                                .unwrap_or_else(|| Utc::now().date_naive() + chrono::Duration::days(7));
+                            ui::parsed_field(ui, "log_call_follow_up", &mut date);
+                            call.follow_up_date = Some(date);
+                        } else {
+                            call.follow_up_date = None;
+                        }
+                    });
+                    ui.end_row();
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        if call.contact_type.trim().is_empty() || call.contact_outcome.trim().is_empty() {
+                            *self.status.lock().unwrap() = "Please enter the contact type and outcome".to_string();
+                        } else {
+                            self.save_call(call.clone());
+                            keep = false;
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        keep = false;
+                    }
+                });
+            });
+            if keep {
+                self.call = Some(call);
+            }
+            ui.add_space(10.0);
+        }
+
+        if overview.history.is_empty() {
+            ui.label("No contacts recorded yet.");
+        }
+        let mut follow_up = None;
+        for entry in &overview.history {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(entry.contact_date.format("%d.%m.%Y %H:%M").to_string());
+                    ui.label(&entry.contact_type);
+                    if let Some(method) = entry.contact_method.as_deref().filter(|m| !m.is_empty()) {
+                        ui.label(format!("via {}", method));
+                    }
+                    ui.label(format!("by {}", entry.created_by));
+                    if auth::can_edit() && ui.small_button("Create Task").clicked() {
+                        follow_up = Some(tasks::follow_up_task(entry));
+                    }
+                });
+                ui.label(format!("Outcome: {}", entry.contact_outcome));
+                if !entry.notes.is_empty() {
+                    ui.label(&entry.notes);
+                }
+                if let Some(date) = entry.follow_up_date {
+                    ui.label(format!("Follow-up on {}", date));
+                }
+            });
+        }
+        follow_up
+    }
+
+    fn render_notes(&mut self, ui: &mut egui::Ui, overview: &CustomerOverview) {
+        if auth::can_edit() {
+            ui.text_edit_multiline(&mut self.new_note);
+            if ui.button("Add Note").clicked() && !self.new_note.trim().is_empty() {
+                let note = std::mem::take(&mut self.new_note);
+                self.add_note(overview.customer.customer_id, note.trim().to_string());
+            }
+            ui.add_space(10.0);
+        }
+        for note in &overview.notes {
+            ui.group(|ui| {
+                ui.label(format!("{} by {}", note.created_at.format("%d.%m.%Y %H:%M"), note.created_by));
+                ui.label(&note.note);
+            });
+        }
+    }
+
+    fn load(&self, with_invoices: bool) {
+        let Some(customer_id) = self.customer_id else {
+            return;
+        };
+        let overview = Arc::clone(&self.overview);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_customer_overview(&config, customer_id, with_invoices).await {
+                Ok(loaded) => *overview.lock().unwrap() = Some(loaded),
+                Err(e) => *status.lock().unwrap() = format!("Error loading customer: {}", e),
+            }
+        });
+    }
+
+    fn save_call(&self, mut call: ContactHistory) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        if let Some(user) = auth::current_user() {
+            call.created_by = user.username;
+        }
+        call.contact_date = Utc::now();
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::add_contact_history(&config, &call).await {
+                Ok(()) => {
+                    *status.lock().unwrap() = format!("{} logged", call.contact_type);
+                    stale.store(true, Ordering::SeqCst);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error saving contact: {}", e),
+            }
+        });
+    }
+
+    fn add_contact(&self, contact: Contact) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::add_contact(&config, &contact).await {
+                Ok(added) => {
+                    *status.lock().unwrap() = format!("Contact {} {} added", added.first_name, added.last_name);
+                    stale.store(true, Ordering::SeqCst);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error adding contact: {}", e),
+            }
+        });
+    }
+
+    fn add_note(&self, customer_id: i32, note: String) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::add_customer_note(&config, customer_id, &note).await {
+                Ok(_) => {
+                    *status.lock().unwrap() = "Note added".to_string();
+                    stale.store(true, Ordering::SeqCst);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error adding note: {}", e),
+            }
+        });
+    }
+}
+
+/// Open amounts of the customer's invoices, by currency.
+fn balances(overview: &CustomerOverview) -> BTreeMap<String, Decimal> {
+    let mut balances = BTreeMap::new();
+    for (invoice, open) in &overview.invoices {
+        if !open.is_zero() {
+            *balances.entry(invoice.currency.clone()).or_insert(Decimal::ZERO) += *open;
+        }
+    }
+    balances
+}
+
+fn render_overview(ui: &mut egui::Ui, overview: &CustomerOverview, sees_invoices: bool) {
+    let customer = &overview.customer;
+    let city = format!("{} {}", customer.postal_code, customer.city);
+    egui::Grid::new("customer_overview_grid").show(ui, |ui| {
+        for (label, value) in [
+            ("Contact:", customer.contact_name.as_str()),
+            ("Position:", customer.contact_position.as_str()),
+            ("Address:", customer.address.as_str()),
+            ("City:", city.trim()),
+            ("Country:", customer.country.as_str()),
+            ("Phone:", customer.phone.as_str()),
+            ("Email:", customer.email.as_str()),
+            ("Website:", customer.website.as_str()),
+            ("Currency:", customer.currency.as_str()),
+            ("VAT ID:", customer.vat_id.as_str()),
+        ] {
+            if !value.is_empty() {
+                ui.label(label);
+                ui.label(value);
+                ui.end_row();
+            }
+        }
+    });
+
+    ui.add_space(10.0);
+    if sees_invoices {
+        let balances = balances(overview);
+        if balances.is_empty() {
+            ui.label("No open invoices.");
+        }
+        for (currency, open) in balances {
+            ui.strong(format!("Open balance: {}", format_amount(open, &currency)));
+        }
+    }
+    ui.label(format!(
+        "{} contacts recorded, {} deals, {} notes",
+        overview.history.len(),
+        overview.deals.len(),
+        overview.notes.len()
+    ));
+    if let Some(last) = overview.history.first() {
+        ui.label(format!(
+            "Last contact: {} ({}, {})",
+            last.contact_date.format("%d.%m.%Y"),
+            last.contact_type,
+            last.contact_outcome
+        ));
+    }
+    let today = Utc::now().date_naive();
+    if let Some(next) = overview.history.iter().filter_map(|h| h.follow_up_date).filter(|d| *d >= today).min() {
+        ui.label(format!("Next follow-up: {}", next));
+    }
+}
+
+fn render_invoices(ui: &mut egui::Ui, overview: &CustomerOverview) {
+    egui::Grid::new("customer_invoices_grid").striped(true).show(ui, |ui| {
+        ui.strong("Number");
+        ui.strong("Type");
+        ui.strong("Date");
+        ui.strong("Due");
+        ui.strong("Total");
+        ui.strong("Open");
+        ui.strong("Status");
+        ui.end_row();
+        for (invoice, open) in &overview.invoices {
+            ui.label(&invoice.invoice_number);
+            ui.label(invoice.document_label());
+            ui.label(invoice.invoice_date.to_string());
+            ui.label(invoice.due_date.to_string());
+            ui.label(format_amount(invoice.total_amount, &invoice.currency));
+            ui.label(format_amount(*open, &invoice.currency));
+            ui.label(&invoice.status);
+            ui.end_row();
+        }
+    });
+    for (currency, open) in balances(overview) {
+        ui.strong(format!("Open balance: {}", format_amount(open, &currency)));
+    }
+}
+
+fn render_payments(ui: &mut egui::Ui, overview: &CustomerOverview) {
+    if overview.payments.is_empty() {
+        ui.label("No payments received yet.");
+        return;
+    }
+    egui::Grid::new("customer_payments_grid").striped(true).show(ui, |ui| {
+        ui.strong("Date");
+        ui.strong("Invoice");
+        ui.strong("Amount");
+        ui.strong("Method");
+        ui.strong("Notes");
+        ui.end_row();
+        for payment in &overview.payments {
+            ui.label(payment.payment_date.to_string());
+            ui.label(&payment.invoice_number);
+            ui.label(format_amount(payment.amount, &payment.currency));
+            ui.label(&payment.payment_method);
+            ui.label(&payment.notes);
+            ui.end_row();
+        }
+    });
+}
+
+fn render_deals(ui: &mut egui::Ui, overview: &CustomerOverview) {
+    if overview.deals.is_empty() {
+        ui.label("No deals yet.");
+        return;
+    }
+    egui::Grid::new("customer_deals_grid").striped(true).show(ui, |ui| {
+        ui.strong("Title");
+        ui.strong("Stage");
+        ui.strong("Value");
+        ui.strong("Probability");
+        ui.strong("Expected Close");
+        ui.end_row();
+        for (deal, stage) in &overview.deals {
+            ui.label(&deal.title);
+            ui.label(stage);
+            ui.label(format_amount(deal.value, &overview.customer.currency));
+            ui.label(format!("{}%", deal.probability));
+            ui.label(deal.expected_close_date.to_string());
+            ui.end_row();
+        }
+    });
+}
@@ -0,0 +1,264 @@
+// dashboard.rs
+use crate::app::View;
+use crate::auth::Role;
+use crate::currency::{self, CurrencyStore};
+use crate::db::{self, ActivityFigures, RevenueFigures};
+use crate::ui;
+use chrono::{Datelike, Months, NaiveDate, Utc};
+use eframe::egui;
+use egui::plot::{Bar, BarChart, Plot};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Date ranges that can be picked with one click.
+const RANGES: [&str; 3] = ["This Month", "This Year", "Last 12 Months"];
+
+fn range(name: &str, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let month_start = today.with_day(1).unwrap_or(today);
+    let from = match name {
+        "This Month" => month_start,
+        "This Year" => NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap_or(today),
+        _ => month_start.checked_sub_months(Months::new(11)).unwrap_or(month_start),
+    };
+    (from, today)
+}
+
+/// First days of the months from `from` to `to`.
+fn months(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    let mut month = from.with_day(1).unwrap_or(from);
+    let mut months = Vec::new();
+    while month <= to {
+        months.push(month);
+        let Some(next) = month.checked_add_months(Months::new(1)) else {
+            break;
+        };
+        month = next;
+    }
+    months
+}
+
+/// Bar chart of labelled values, one bar per label. Horizontal charts list
+/// the labels top down.
+fn bar_chart(ui: &mut egui::Ui, id_source: &str, values: Vec<(String, f64)>, horizontal: bool) {
+    let labels: Vec<String> = values.iter().map(|(label, _)| label.clone()).collect();
+    let count = values.len();
+    let position = move |index: usize| if horizontal { (count - index) as f64 } else { index as f64 };
+    let bars: Vec<Bar> = values
+        .into_iter()
+        .enumerate()
+        .map(|(index, (label, value))| Bar::new(position(index), value).name(label).width(0.6))
+        .collect();
+    let label_of = move |value: f64, _: &std::ops::RangeInclusive<f64>| {
+        if value.fract() != 0.0 || value < 0.0 {
+            return String::new();
+        }
+        let index = if horizontal { count.checked_sub(value as usize) } else { Some(value as usize) };
+        index.and_then(|i| labels.get(i)).cloned().unwrap_or_default()
+    };
+    let mut chart = BarChart::new(bars).color(egui::Color32::from_rgb(70, 130, 180));
+    let mut plot = Plot::new(id_source)
+        .height(200.0)
+        .allow_zoom(false)
+        .allow_drag(false)
+        .allow_scroll(false)
+        .allow_boxed_zoom(false)
+        .show_x(false)
+        .show_y(false);
+    if horizontal {
+        chart = chart.horizontal();
+        plot = plot.y_axis_formatter(label_of).include_x(0.0);
+    } else {
+        plot = plot.x_axis_formatter(label_of).include_y(0.0);
+    }
+    plot.show(ui, |plot_ui| plot_ui.bar_chart(chart));
+}
+
+fn to_f64(amount: Decimal) -> f64 {
+    amount.to_f64().unwrap_or(0.0)
+}
+
+/// Start page with revenue, receivables, best customers and products for
+/// roles that see invoices, and everyone's contacts and follow-ups.
+pub struct DashboardView {
+    revenue: Arc<Mutex<Option<RevenueFigures>>>,
+    activity: Arc<Mutex<Option<ActivityFigures>>>,
+    /// Set whenever the figures must be (re)loaded.
+    pub stale: Arc<AtomicBool>,
+    from: NaiveDate,
+    to: NaiveDate,
+}
+
+impl Default for DashboardView {
+    fn default() -> Self {
+        let (from, to) = range("Last 12 Months", Utc::now().date_naive());
+        Self {
+            revenue: Arc::new(Mutex::new(None)),
+            activity: Arc::new(Mutex::new(None)),
+            stale: Arc::new(AtomicBool::new(true)),
+            from,
+            to,
+        }
+    }
+}
+
+impl DashboardView {
+    pub fn show(&mut self, ctx: &egui::Context, role: Role, currencies: &CurrencyStore) {
+        currencies.refresh_if_stale();
+        let sees_revenue = role.can_access(&View::Reports);
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load(sees_revenue);
+        }
+        let base = currencies.base();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Dashboard");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                let today = Utc::now().date_naive();
+                for name in RANGES {
+                    if ui.selectable_label(range(name, today) == (self.from, self.to), name).clicked() {
+                        (self.from, self.to) = range(name, today);
+                        self.load(sees_revenue);
+                    }
+                }
+                ui.separator();
+                ui.label("From");
+                ui::parsed_field(ui, "dashboard_from", &mut self.from);
+                ui.label("to");
+                ui::parsed_field(ui, "dashboard_to", &mut self.to);
+                if ui.button("Refresh").clicked() {
+                    self.load(sees_revenue);
+                }
+            });
+            ui.add_space(10.0);
+
+            let revenue = self.revenue.lock().unwrap().clone();
+            let activity = self.activity.lock().unwrap().clone();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                if let Some(revenue) = revenue.filter(|_| sees_revenue) {
+                    self.render_revenue(ui, &revenue, &base);
+                }
+                if let Some(activity) = activity {
+                    render_activity(ui, &activity);
+                }
+            });
+        });
+    }
+
+    fn render_revenue(&self, ui: &mut egui::Ui, revenue: &RevenueFigures, base: &str) {
+        ui.horizontal(|ui| {
+            ui.strong(format!(
+                "Open receivables: {} in {} invoices",
+                currency::format_amount(revenue.open_amount, base),
+                revenue.open_invoices
+            ));
+            ui.separator();
+            ui.colored_label(
+                egui::Color32::from_rgb(200, 120, 0),
+                format!("Overdue: {}", currency::format_amount(revenue.overdue_amount, base)),
+            );
+        });
+        ui.add_space(10.0);
+
+        let total: Decimal = revenue.monthly_revenue.iter().map(|(_, amount)| *amount).sum();
+        ui.strong(format!("Net revenue per month ({} in total)", currency::format_amount(total, base)));
+        if revenue.missing_rates > 0 {
+            ui.colored_label(
+                egui::Color32::RED,
+                format!("{} invoices left out for lack of an exchange rate", revenue.missing_rates),
+            );
+        }
+        let monthly = months(self.from, self.to)
+            .into_iter()
+            .map(|month| {
+                let amount = revenue
+                    .monthly_revenue
+                    .iter()
+                    .find(|(m, _)| *m == month)
+                    .map_or(Decimal::ZERO, |(_, amount)| *amount);
+                (month.format("%m/%Y").to_string(), to_f64(amount))
+            })
+            .collect();
+        bar_chart(ui, "dashboard_revenue", monthly, false);
+
+        ui.columns(2, |columns| {
+            columns[0].strong("Top customers by net revenue");
+            let customers = revenue
+                .top_customers
+                .iter()
+                .map(|(name, amount)| (name.clone(), to_f64(*amount)))
+                .collect();
+            bar_chart(&mut columns[0], "dashboard_customers", customers, true);
+
+            columns[1].strong("Products by quantity sold");
+            let products = revenue
+                .top_products
+                .iter()
+                .map(|(name, quantity)| (name.clone(), *quantity as f64))
+                .collect();
+            bar_chart(&mut columns[1], "dashboard_products", products, true);
+        });
+        ui.add_space(20.0);
+    }
+
+    fn load(&self, sees_revenue: bool) {
+        let (from, to) = (self.from, self.to);
+        let today = Utc::now().date_naive();
+        let revenue = Arc::clone(&self.revenue);
+        let activity = Arc::clone(&self.activity);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            if sees_revenue {
+                match db::get_revenue_figures(&config, from, to, today).await {
+                    Ok(loaded) => *revenue.lock().unwrap() = Some(loaded),
+                    Err(e) => eprintln!("Error fetching revenue figures: {}", e),
+                }
+            }
+            match db::get_activity_figures(&config, from, to, today).await {
+                Ok(loaded) => *activity.lock().unwrap() = Some(loaded),
+                Err(e) => eprintln!("Error fetching contact activity: {}", e),
+            }
+        });
+    }
+}
+
+fn render_activity(ui: &mut egui::Ui, activity: &ActivityFigures) {
+    let total: i64 = activity.contacts_by_type.iter().map(|(_, count)| count).sum();
+    ui.strong(format!("Contacts by type ({} in total)", total));
+    let contacts = activity
+        .contacts_by_type
+        .iter()
+        .map(|(contact_type, count)| (contact_type.clone(), *count as f64))
+        .collect();
+    bar_chart(ui, "dashboard_contacts", contacts, false);
+    ui.add_space(10.0);
+
+    ui.strong("Upcoming follow-ups");
+    if activity.follow_ups.is_empty() {
+        ui.label("No follow-ups are due.");
+        return;
+    }
+    egui::Grid::new("dashboard_follow_ups").striped(true).show(ui, |ui| {
+        ui.strong("Date");
+        ui.strong("Customer");
+        ui.strong("Contact");
+        ui.strong("Notes");
+        ui.end_row();
+        for follow_up in &activity.follow_ups {
+            ui.label(follow_up.follow_up_date.to_string());
+            ui.label(&follow_up.company_name);
+            ui.label(&follow_up.contact_type);
+            ui.label(follow_up.notes.lines().next().unwrap_or(""));
+            ui.end_row();
+        }
+    });
+}
@@ -0,0 +1,448 @@
+// datev.rs
+use crate::db::{self, BookedInvoice, BookedPayment, CompanyProfile, TaxRate};
+use crate::pdf;
+use crate::tax;
+use chrono::{Datelike, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Length of ledger accounts; debtor accounts have one digit more.
+const ACCOUNT_LENGTH: usize = 4;
+const FIRST_DEBTOR_ACCOUNT: i32 = 10000;
+const LAST_DEBTOR_ACCOUNT: i32 = 69999;
+
+/// Columns written of the booking batch format, which has many more; DATEV
+/// treats the missing ones as empty.
+const COLUMNS: [&str; 14] = [
+    "Umsatz (ohne Soll/Haben-Kz)",
+    "Soll/Haben-Kennzeichen",
+    "WKZ Umsatz",
+    "Kurs",
+    "Basis-Umsatz",
+    "WKZ Basis-Umsatz",
+    "Konto",
+    "Gegenkonto (ohne BU-Schlüssel)",
+    "BU-Schlüssel",
+    "Belegdatum",
+    "Belegfeld 1",
+    "Belegfeld 2",
+    "Skonto",
+    "Buchungstext",
+];
+
+/// Debtor account of a customer.
+fn debtor_account(customer_id: i32) -> String {
+    (FIRST_DEBTOR_ACCOUNT + customer_id).to_string()
+}
+
+/// First day of the fiscal year `date` falls in.
+fn fiscal_year_start(date: NaiveDate, start_month: u32) -> NaiveDate {
+    let year = if date.month() >= start_month { date.year() } else { date.year() - 1 };
+    NaiveDate::from_ymd_opt(year, start_month, 1).unwrap_or(date)
+}
+
+/// One line of the batch. Amounts are positive; `debit` says on which side
+/// of `account` they are booked.
+struct Booking {
+    amount: Decimal,
+    debit: bool,
+    currency: String,
+    /// Rate and amount in the base currency for foreign currency bookings.
+    foreign: Option<(Decimal, Decimal)>,
+    account: String,
+    contra_account: String,
+    date: NaiveDate,
+    document: String,
+    text: String,
+}
+
+impl Booking {
+    fn new(
+        amount: Decimal,
+        currency: &str,
+        base: &str,
+        exchange_rate: Option<Decimal>,
+        account: String,
+        contra_account: String,
+        date: NaiveDate,
+    ) -> Result<Self, String> {
+        let foreign = if currency == base {
+            None
+        } else {
+            let rate = exchange_rate
+                .filter(|r| !r.is_zero())
+                .ok_or_else(|| format!("There is no exchange rate for {} on {}", currency, date))?;
+            Some((rate, tax::round_money(amount.abs() / rate)))
+        };
+        Ok(Self {
+            amount: amount.abs(),
+            debit: amount >= Decimal::ZERO,
+            currency: currency.to_string(),
+            foreign,
+            account,
+            contra_account,
+            date,
+            document: String::new(),
+            text: String::new(),
+        })
+    }
+
+    fn write(&self, out: &mut String, base: &str) {
+        let (rate, base_amount, base_currency) = match self.foreign {
+            Some((rate, base_amount)) => (number(rate.round_dp(6)), amount(base_amount), text(base)),
+            None => (String::new(), String::new(), String::new()),
+        };
+        let fields = [
+            amount(self.amount),
+            text(if self.debit { "S" } else { "H" }),
+            text(&self.currency),
+            rate,
+            base_amount,
+            base_currency,
+            self.account.clone(),
+            self.contra_account.clone(),
+            String::new(),
+            self.date.format("%d%m").to_string(),
+            text(&self.document),
+            String::new(),
+            String::new(),
+            text(&self.text),
+        ];
+        out.push_str(&fields.join(";"));
+        out.push_str("\r\n");
+    }
+}
+
+fn number(value: Decimal) -> String {
+    value.normalize().to_string().replace('.', ",")
+}
+
+fn amount(value: Decimal) -> String {
+    format!("{:.2}", value).replace('.', ",")
+}
+
+fn text(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// Document number as DATEV accepts it: at most 36 of the characters it
+/// allows.
+fn document_field(number: &str) -> String {
+    number
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || "$&%*+-/".contains(*c))
+        .take(36)
+        .collect()
+}
+
+fn invoice_bookings(
+    booked: &BookedInvoice,
+    rates: &[TaxRate],
+    customer_names: &HashMap<i32, String>,
+    base: &str,
+) -> Result<Vec<Booking>, String> {
+    let invoice = &booked.invoice;
+    let customer_id = invoice
+        .customer_id
+        .ok_or_else(|| format!("Invoice {} has no customer", invoice.invoice_number))?;
+    if FIRST_DEBTOR_ACCOUNT + customer_id > LAST_DEBTOR_ACCOUNT {
+        return Err(format!(
+            "Customer {} of invoice {} has no debtor account in the DATEV range",
+            customer_id, invoice.invoice_number
+        ));
+    }
+    let revenue_account = |tax_rate_id: Option<i32>| {
+        let rate = rates
+            .iter()
+            .find(|r| Some(r.tax_rate_id) == tax_rate_id)
+            .ok_or_else(|| format!("Invoice {} has lines without a tax rate", invoice.invoice_number))?;
+        if rate.revenue_account.is_empty() {
+            return Err(format!("Tax rate {} has no revenue account", rate.name));
+        }
+        Ok(rate.revenue_account.clone())
+    };
+
+    // Revenue accounts with automatic tax take gross amounts; invoices
+    // without items hold the amount entered by hand and are stored without
+    // tax, so they go to the account of a tax-free rate.
+    let mut amounts: Vec<(Decimal, String)> = Vec::new();
+    if invoice.items.is_empty() {
+        let tax_free = rates
+            .iter()
+            .filter(|r| r.rate.is_zero() && !r.reverse_charge)
+            .max_by_key(|r| r.active)
+            .ok_or_else(|| format!("Invoice {} has no items and there is no 0% tax rate", invoice.invoice_number))?;
+        amounts.push((invoice.total_amount, revenue_account(Some(tax_free.tax_rate_id))?));
+    } else {
+        for group in tax::totals(&invoice.items).groups {
+            amounts.push((group.net + group.tax, revenue_account(group.tax_rate_id)?));
+        }
+    }
+
+    let name = customer_names.get(&customer_id).cloned().unwrap_or_default();
+    amounts
+        .into_iter()
+        .filter(|(gross, _)| !gross.is_zero())
+        .map(|(gross, account)| {
+            let mut booking = Booking::new(
+                gross,
+                &invoice.currency,
+                base,
+                booked.exchange_rate,
+                debtor_account(customer_id),
+                account,
+                invoice.invoice_date,
+            )?;
+            booking.document = document_field(&invoice.invoice_number);
+            booking.text = name.chars().take(60).collect();
+            Ok(booking)
+        })
+        .collect()
+}
+
+fn payment_booking(payment: &BookedPayment, company: &CompanyProfile, base: &str) -> Result<Booking, String> {
+    let customer_id = payment
+        .customer_id
+        .ok_or_else(|| format!("The invoice {} of a payment has no customer", payment.invoice_number))?;
+    let mut booking = Booking::new(
+        payment.amount,
+        &payment.currency,
+        base,
+        payment.exchange_rate,
+        company.datev_bank_account.clone(),
+        debtor_account(customer_id),
+        payment.payment_date,
+    )?;
+    booking.document = document_field(&payment.invoice_number);
+    booking.text = format!("Payment {}", payment.invoice_number).chars().take(60).collect();
+    Ok(booking)
+}
+
+/// Builds the booking batch of the invoices and payments of `period`, or
+/// the reasons it cannot be built.
+fn build_batch(
+    invoices: &[BookedInvoice],
+    payments: &[BookedPayment],
+    company: &CompanyProfile,
+    rates: &[TaxRate],
+    customer_names: &HashMap<i32, String>,
+    base: &str,
+    (from, to): (NaiveDate, NaiveDate),
+) -> Result<(String, usize), Vec<String>> {
+    let mut errors = Vec::new();
+    if !(1001..=9_999_999).contains(&company.datev_consultant_number) {
+        errors.push("The DATEV consultant number is not set".to_string());
+    }
+    if !(1..=99_999).contains(&company.datev_client_number) {
+        errors.push("The DATEV client number is not set".to_string());
+    }
+    if company.datev_bank_account.len() != ACCOUNT_LENGTH {
+        errors.push(format!("The bank account must have {} digits", ACCOUNT_LENGTH));
+    }
+    let start_month = company.datev_fiscal_year_start.clamp(1, 12) as u32;
+    let fiscal_year = fiscal_year_start(from, start_month);
+    if from > to || fiscal_year_start(to, start_month) != fiscal_year {
+        errors.push("The period must lie within one fiscal year".to_string());
+    }
+
+    let mut bookings = Vec::new();
+    for invoice in invoices {
+        match invoice_bookings(invoice, rates, customer_names, base) {
+            Ok(lines) => bookings.extend(lines),
+            Err(e) => errors.push(e),
+        }
+    }
+    for payment in payments.iter().filter(|p| !p.amount.is_zero()) {
+        match payment_booking(payment, company, base) {
+            Ok(booking) => bookings.push(booking),
+            Err(e) => errors.push(e),
+        }
+    }
+    errors.dedup();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let created = Utc::now().format("%Y%m%d%H%M%S%3f");
+    let header = [
+        text("EXTF"),
+        "700".to_string(),
+        "21".to_string(),
+        text("Buchungsstapel"),
+        "13".to_string(),
+        created.to_string(),
+        String::new(),
+        text("RE"),
+        text(""),
+        text(""),
+        company.datev_consultant_number.to_string(),
+        company.datev_client_number.to_string(),
+        fiscal_year.format("%Y%m%d").to_string(),
+        ACCOUNT_LENGTH.to_string(),
+        from.format("%Y%m%d").to_string(),
+        to.format("%Y%m%d").to_string(),
+        text(&format!("Invoices and payments {} to {}", from, to)),
+        text(""),
+        "1".to_string(),
+        "0".to_string(),
+        "0".to_string(),
+        text(base),
+        String::new(),
+        text(""),
+        String::new(),
+        String::new(),
+        text(""),
+        String::new(),
+        String::new(),
+        String::new(),
+        text(""),
+    ];
+    let mut out = header.join(";");
+    out.push_str("\r\n");
+    out.push_str(&COLUMNS.join(";"));
+    out.push_str("\r\n");
+    bookings.sort_by_key(|b| b.date);
+    for booking in &bookings {
+        booking.write(&mut out, base);
+    }
+    Ok((out, bookings.len()))
+}
+
+/// Writes the invoices and payments dated between `from` and `to` as a
+/// DATEV booking batch (EXTF) to `path`, in the Windows code page DATEV
+/// reads. Returns the number of bookings.
+pub async fn export(
+    from: NaiveDate,
+    to: NaiveDate,
+    customer_names: HashMap<i32, String>,
+    base: String,
+    path: &Path,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let config = db::get_config().ok_or("No database configuration found")?;
+    let company = db::get_company_profile(&config).await?;
+    let rates = db::get_tax_rates(&config).await?;
+    let (invoices, payments) = db::get_bookings(&config, from, to).await?;
+    let (batch, count) = build_batch(&invoices, &payments, &company, &rates, &customer_names, &base, (from, to))
+        .map_err(|errors| errors.join("; "))?;
+    let bytes: Vec<u8> = batch.chars().map(pdf::win_ansi).collect();
+    fs::write(path, bytes)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Invoice, LineItem};
+    use crate::tax::test_support::{item, money};
+
+    fn rate(tax_rate_id: i32, rate: i64, reverse_charge: bool, active: bool, revenue_account: &str) -> TaxRate {
+        TaxRate {
+            tax_rate_id,
+            name: format!("{}%", rate),
+            rate: Decimal::from(rate),
+            reverse_charge,
+            note: String::new(),
+            is_default: false,
+            active,
+            revenue_account: revenue_account.to_string(),
+        }
+    }
+
+    fn rates() -> Vec<TaxRate> {
+        vec![
+            rate(1, 19, false, true, "8400"),
+            rate(2, 7, false, true, "8300"),
+            rate(3, 0, true, true, "8336"),
+            rate(4, 0, false, false, "8100"),
+            rate(5, 0, false, true, "8120"),
+        ]
+    }
+
+    fn booked(items: Vec<LineItem>, total_amount: &str, currency: &str) -> BookedInvoice {
+        BookedInvoice {
+            invoice: Invoice {
+                invoice_id: 1,
+                customer_id: Some(42),
+                invoice_number: "RE_2026/0001".to_string(),
+                invoice_date: NaiveDate::from_ymd_opt(2026, 3, 9).unwrap(),
+                total_amount: total_amount.parse().unwrap(),
+                currency: currency.to_string(),
+                items,
+                ..Invoice::default()
+            },
+            exchange_rate: None,
+        }
+    }
+
+    fn names() -> HashMap<i32, String> {
+        HashMap::from([(42, "Muster GmbH".to_string())])
+    }
+
+    #[test]
+    fn invoice_bookings_book_gross_amounts_per_rate() {
+        let items = vec![item(2, "50.00", 1, 19), item(1, "10.00", 2, 7), item(1, "5.00", 1, 19)];
+        let invoice = booked(items, "135.65", "EUR");
+        let bookings = invoice_bookings(&invoice, &rates(), &names(), "EUR").unwrap();
+        let lines: Vec<_> = bookings
+            .iter()
+            .map(|b| (b.amount, b.debit, b.account.as_str(), b.contra_account.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (money("124.95"), true, "10042", "8400"),
+                (money("10.70"), true, "10042", "8300"),
+            ]
+        );
+        assert_eq!(bookings[0].document, "RE2026/0001");
+        assert_eq!(bookings[0].text, "Muster GmbH");
+        assert!(bookings[0].foreign.is_none());
+    }
+
+    #[test]
+    fn invoice_bookings_credit_credit_notes() {
+        let invoice = booked(vec![item(-1, "100.00", 1, 19)], "-119.00", "EUR");
+        let bookings = invoice_bookings(&invoice, &rates(), &names(), "EUR").unwrap();
+        assert_eq!(bookings.len(), 1);
+        assert_eq!(bookings[0].amount, money("119.00"));
+        assert!(!bookings[0].debit);
+    }
+
+    #[test]
+    fn invoice_bookings_book_invoices_without_items_tax_free() {
+        let invoice = booked(Vec::new(), "250.00", "EUR");
+        let bookings = invoice_bookings(&invoice, &rates(), &names(), "EUR").unwrap();
+        assert_eq!(bookings.len(), 1);
+        assert_eq!(bookings[0].amount, money("250.00"));
+        assert_eq!(bookings[0].contra_account, "8120");
+
+        let taxed_only: Vec<TaxRate> = rates().into_iter().filter(|r| !r.rate.is_zero() || r.reverse_charge).collect();
+        assert!(invoice_bookings(&invoice, &taxed_only, &names(), "EUR").is_err());
+    }
+
+    #[test]
+    fn invoice_bookings_convert_foreign_currencies() {
+        let mut invoice = booked(vec![item(1, "100.00", 1, 19)], "119.00", "USD");
+        assert!(invoice_bookings(&invoice, &rates(), &names(), "EUR").is_err());
+
+        invoice.exchange_rate = Some(money("1.1"));
+        let bookings = invoice_bookings(&invoice, &rates(), &names(), "EUR").unwrap();
+        assert_eq!(bookings[0].currency, "USD");
+        assert_eq!(bookings[0].foreign, Some((money("1.1"), money("108.18"))));
+    }
+
+    #[test]
+    fn invoice_bookings_reject_unbookable_invoices() {
+        let mut invoice = booked(vec![item(1, "100.00", 9, 19)], "119.00", "EUR");
+        assert!(invoice_bookings(&invoice, &rates(), &names(), "EUR").is_err());
+
+        invoice.invoice.items = vec![item(1, "100.00", 1, 19)];
+        invoice.invoice.customer_id = Some(LAST_DEBTOR_ACCOUNT - FIRST_DEBTOR_ACCOUNT + 1);
+        assert!(invoice_bookings(&invoice, &rates(), &names(), "EUR").is_err());
+
+        invoice.invoice.customer_id = None;
+        assert!(invoice_bookings(&invoice, &rates(), &names(), "EUR").is_err());
+    }
+}
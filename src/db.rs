@@ -1,8 +1,772 @@
+use crate::audit;
+use crate::auth::{self, Role};
 use crate::config::DbConfig;
-use chrono::{DateTime, NaiveDate, Utc};
+use crate::currency::format_amount;
+use crate::custom_fields::FieldType;
+use crate::numbering;
+use crate::recurring;
+use crate::relations::RelationType;
+use crate::sepa;
+use crate::tax;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use futures_util::StreamExt;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use tokio_postgres::NoTls;
+use std::collections::HashMap;
+use tokio_postgres::{AsyncMessage, Client, NoTls, Row, Transaction};
 
+/// Channel on which the triggers below publish row changes.
+pub const CHANGE_CHANNEL: &str = "crm_changes";
+
+// Every trigger passes the name of its primary key column, so the payload
+// always carries the changed row's id plus the owning customer (if any).
+const CREATE_NOTIFY_TRIGGERS_QUERY: &str = "
+    CREATE OR REPLACE FUNCTION notify_crm_change() RETURNS trigger AS $$
+    DECLARE
+        rec JSON;
+    BEGIN
+        IF TG_OP = 'DELETE' THEN
+            rec := row_to_json(OLD);
+        ELSE
+            rec := row_to_json(NEW);
+        END IF;
+        PERFORM pg_notify('crm_changes', json_build_object(
+            'table', TG_TABLE_NAME,
+            'action', TG_OP,
+            'id', (rec ->> TG_ARGV[0])::INTEGER,
+            'customer_id', (rec ->> 'customer_id')::INTEGER
+        )::TEXT);
+        RETURN NULL;
+    END;
+    $$ LANGUAGE plpgsql;
+
+    DROP TRIGGER IF EXISTS customers_notify ON customers;
+    CREATE TRIGGER customers_notify
+        AFTER INSERT OR UPDATE OR DELETE ON customers
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('customer_id');
+
+    DROP TRIGGER IF EXISTS contact_history_notify ON contact_history;
+    CREATE TRIGGER contact_history_notify
+        AFTER INSERT OR UPDATE OR DELETE ON contact_history
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('history_id');
+
+    DROP TRIGGER IF EXISTS invoices_notify ON invoices;
+    CREATE TRIGGER invoices_notify
+        AFTER INSERT OR UPDATE OR DELETE ON invoices
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('invoice_id');
+";
+
+// Records that several users edit concurrently carry a version number that is
+// bumped on every update, so stale edits can be detected instead of overwriting.
+const CREATE_VERSION_COLUMNS_QUERY: &str = "
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+";
+
+const CREATE_AUDIT_LOG_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS audit_log (
+        audit_id SERIAL PRIMARY KEY,
+        table_name VARCHAR(50) NOT NULL,
+        record_id INTEGER NOT NULL,
+        action VARCHAR(10) NOT NULL,
+        old_values JSONB,
+        new_values JSONB,
+        changed_by VARCHAR(100) NOT NULL,
+        changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_audit_log_record ON audit_log(table_name, record_id);
+";
+
+const CREATE_USERS_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        user_id SERIAL PRIMARY KEY,
+        username VARCHAR(50) UNIQUE NOT NULL,
+        display_name VARCHAR(100) NOT NULL,
+        password_hash TEXT NOT NULL,
+        role VARCHAR(20) NOT NULL CHECK (role IN ('admin', 'sales', 'accounting', 'read_only')),
+        active BOOLEAN NOT NULL DEFAULT true,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+";
+
+const CREATE_TAGS_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS tags (
+        tag_id SERIAL PRIMARY KEY,
+        name VARCHAR(50) UNIQUE NOT NULL,
+        color VARCHAR(7) NOT NULL DEFAULT '#808080',
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE TABLE IF NOT EXISTS customer_tags (
+        customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        tag_id INTEGER NOT NULL REFERENCES tags(tag_id) ON DELETE CASCADE,
+        PRIMARY KEY (customer_id, tag_id)
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_customer_tags_tag_id ON customer_tags(tag_id);
+
+    DROP TRIGGER IF EXISTS tags_notify ON tags;
+    CREATE TRIGGER tags_notify
+        AFTER INSERT OR UPDATE OR DELETE ON tags
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('tag_id');
+
+    DROP TRIGGER IF EXISTS customer_tags_notify ON customer_tags;
+    CREATE TRIGGER customer_tags_notify
+        AFTER INSERT OR UPDATE OR DELETE ON customer_tags
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('tag_id');
+";
+
+const CREATE_CUSTOM_FIELDS_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS custom_fields (
+        field_id SERIAL PRIMARY KEY,
+        name VARCHAR(50) UNIQUE NOT NULL,
+        field_type VARCHAR(10) NOT NULL CHECK (field_type IN ('text', 'number', 'date', 'choice', 'boolean')),
+        choices TEXT[] NOT NULL DEFAULT '{}',
+        sort_order INTEGER NOT NULL DEFAULT 0,
+        active BOOLEAN NOT NULL DEFAULT true
+    );
+
+    CREATE TABLE IF NOT EXISTS customer_custom_values (
+        customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        field_id INTEGER NOT NULL REFERENCES custom_fields(field_id) ON DELETE CASCADE,
+        value TEXT NOT NULL,
+        PRIMARY KEY (customer_id, field_id)
+    );
+
+    DROP TRIGGER IF EXISTS custom_fields_notify ON custom_fields;
+    CREATE TRIGGER custom_fields_notify
+        AFTER INSERT OR UPDATE OR DELETE ON custom_fields
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('field_id');
+
+    DROP TRIGGER IF EXISTS customer_custom_values_notify ON customer_custom_values;
+    CREATE TRIGGER customer_custom_values_notify
+        AFTER INSERT OR UPDATE OR DELETE ON customer_custom_values
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('field_id');
+";
+
+// Typed links between customers. Each link is stored once, in the direction
+// its type reads in (see `relations::RelationType`).
+const CREATE_RELATIONS_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS customer_relations (
+        relation_id SERIAL PRIMARY KEY,
+        from_customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        to_customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        relation_type VARCHAR(20) NOT NULL CHECK (relation_type IN ('parent_of', 'partner', 'reseller_of', 'competitor')),
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        CHECK (from_customer_id <> to_customer_id),
+        UNIQUE (from_customer_id, to_customer_id, relation_type)
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_customer_relations_to ON customer_relations(to_customer_id);
+
+    DROP TRIGGER IF EXISTS customer_relations_notify ON customer_relations;
+    CREATE TRIGGER customer_relations_notify
+        AFTER INSERT OR UPDATE OR DELETE ON customer_relations
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('relation_id');
+";
+
+// Sales pipeline. The default stages are only inserted into an empty table,
+// so stages edited by an administrator survive a re-run of the setup.
+const CREATE_DEALS_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS deal_stages (
+        stage_id SERIAL PRIMARY KEY,
+        name VARCHAR(50) UNIQUE NOT NULL,
+        sort_order INTEGER NOT NULL DEFAULT 0,
+        default_probability INTEGER NOT NULL DEFAULT 0 CHECK (default_probability BETWEEN 0 AND 100),
+        active BOOLEAN NOT NULL DEFAULT true
+    );
+
+    INSERT INTO deal_stages (name, sort_order, default_probability)
+    SELECT * FROM (VALUES
+        ('Lead', 10, 10),
+        ('Qualified', 20, 25),
+        ('Proposal', 30, 50),
+        ('Negotiation', 40, 75),
+        ('Won', 50, 100),
+        ('Lost', 60, 0)
+    ) AS defaults(name, sort_order, default_probability)
+    WHERE NOT EXISTS (SELECT 1 FROM deal_stages);
+
+    CREATE TABLE IF NOT EXISTS deals (
+        deal_id SERIAL PRIMARY KEY,
+        customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        title VARCHAR(200) NOT NULL,
+        value DECIMAL(12, 2) NOT NULL DEFAULT 0,
+        expected_close_date DATE NOT NULL,
+        probability INTEGER NOT NULL DEFAULT 0 CHECK (probability BETWEEN 0 AND 100),
+        stage_id INTEGER NOT NULL REFERENCES deal_stages(stage_id),
+        notes TEXT NOT NULL DEFAULT '',
+        version INTEGER NOT NULL DEFAULT 1,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_deals_customer_id ON deals(customer_id);
+    CREATE INDEX IF NOT EXISTS idx_deals_stage_id ON deals(stage_id);
+
+    DROP TRIGGER IF EXISTS deal_stages_notify ON deal_stages;
+    CREATE TRIGGER deal_stages_notify
+        AFTER INSERT OR UPDATE OR DELETE ON deal_stages
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('stage_id');
+
+    DROP TRIGGER IF EXISTS deals_notify ON deals;
+    CREATE TRIGGER deals_notify
+        AFTER INSERT OR UPDATE OR DELETE ON deals
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('deal_id');
+";
+
+// Quotes mirror invoices and their items. Line items also get a free-text
+// description, on invoices too, so converted quotes keep their wording.
+const CREATE_QUOTES_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS quotes (
+        quote_id SERIAL PRIMARY KEY,
+        customer_id INTEGER REFERENCES customers(customer_id),
+        quote_number VARCHAR(50) UNIQUE NOT NULL,
+        quote_date DATE NOT NULL,
+        valid_until DATE NOT NULL,
+        total_amount DECIMAL(10, 2) NOT NULL DEFAULT 0,
+        status VARCHAR(20) NOT NULL DEFAULT 'draft' CHECK (status IN ('draft', 'sent', 'accepted', 'rejected')),
+        notes TEXT,
+        invoice_id INTEGER REFERENCES invoices(invoice_id) ON DELETE SET NULL,
+        version INTEGER NOT NULL DEFAULT 1,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE TABLE IF NOT EXISTS quote_items (
+        item_id SERIAL PRIMARY KEY,
+        quote_id INTEGER NOT NULL REFERENCES quotes(quote_id) ON DELETE CASCADE,
+        product_id INTEGER REFERENCES products(product_id),
+        description TEXT NOT NULL DEFAULT '',
+        quantity INTEGER NOT NULL,
+        unit_price DECIMAL(10, 2) NOT NULL,
+        total_price DECIMAL(10, 2) NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    ALTER TABLE invoice_items ADD COLUMN IF NOT EXISTS description TEXT NOT NULL DEFAULT '';
+
+    CREATE INDEX IF NOT EXISTS idx_quotes_customer_id ON quotes(customer_id);
+    CREATE INDEX IF NOT EXISTS idx_quote_items_quote_id ON quote_items(quote_id);
+
+    DROP TRIGGER IF EXISTS quotes_notify ON quotes;
+    CREATE TRIGGER quotes_notify
+        AFTER INSERT OR UPDATE OR DELETE ON quotes
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('quote_id');
+";
+
+// Dunning levels are configured like deal stages; the defaults only go into
+// an empty table. Every notice sent is kept, one per invoice and level.
+const CREATE_DUNNING_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS dunning_levels (
+        level_id SERIAL PRIMARY KEY,
+        level INTEGER UNIQUE NOT NULL CHECK (level > 0),
+        name VARCHAR(50) NOT NULL,
+        days_overdue INTEGER NOT NULL DEFAULT 0 CHECK (days_overdue >= 0),
+        grace_days INTEGER NOT NULL DEFAULT 14 CHECK (grace_days >= 0),
+        fee DECIMAL(10, 2) NOT NULL DEFAULT 0,
+        interest_rate DECIMAL(5, 2) NOT NULL DEFAULT 0,
+        letter_text TEXT NOT NULL DEFAULT ''
+    );
+
+    INSERT INTO dunning_levels (level, name, days_overdue, grace_days, fee, interest_rate, letter_text)
+    SELECT * FROM (VALUES
+        (1, 'Reminder', 7, 14, 0.00, 0.00,
+         'Our invoice {invoice_number} of {invoice_date} was due on {due_date}. We may have overlooked your payment; if not, please transfer the open amount of {open_amount} by {pay_by}.'),
+        (2, '1st Mahnung', 21, 10, 5.00, 9.00,
+         'Despite our reminder we have not yet received payment for invoice {invoice_number}, due on {due_date}. Please pay {total} including a dunning fee of {fee} and interest of {interest} by {pay_by}.'),
+        (3, '2nd Mahnung', 35, 7, 10.00, 9.00,
+         'Invoice {invoice_number}, due on {due_date}, is still unpaid. Please pay {total} including dunning fees of {fee} and interest of {interest} by {pay_by}. Otherwise we will hand the claim over to a collection agency without further notice.')
+    ) AS defaults(level, name, days_overdue, grace_days, fee, interest_rate, letter_text)
+    WHERE NOT EXISTS (SELECT 1 FROM dunning_levels);
+
+    CREATE TABLE IF NOT EXISTS dunning_notices (
+        notice_id SERIAL PRIMARY KEY,
+        invoice_id INTEGER NOT NULL REFERENCES invoices(invoice_id) ON DELETE CASCADE,
+        level INTEGER NOT NULL,
+        level_name VARCHAR(50) NOT NULL,
+        notice_date DATE NOT NULL,
+        pay_by DATE NOT NULL,
+        open_amount DECIMAL(10, 2) NOT NULL,
+        fee DECIMAL(10, 2) NOT NULL,
+        interest DECIMAL(10, 2) NOT NULL,
+        history_id INTEGER REFERENCES contact_history(history_id) ON DELETE SET NULL,
+        created_by VARCHAR(100) NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE (invoice_id, level)
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_dunning_notices_invoice_id ON dunning_notices(invoice_id);
+
+    DROP TRIGGER IF EXISTS dunning_levels_notify ON dunning_levels;
+    CREATE TRIGGER dunning_levels_notify
+        AFTER INSERT OR UPDATE OR DELETE ON dunning_levels
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('level_id');
+
+    DROP TRIGGER IF EXISTS dunning_notices_notify ON dunning_notices;
+    CREATE TRIGGER dunning_notices_notify
+        AFTER INSERT OR UPDATE OR DELETE ON dunning_notices
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('notice_id');
+";
+
+// Recurring invoice templates. Generated invoices remember their template
+// and billing period; the unique index makes generation idempotent.
+const CREATE_RECURRING_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS recurring_invoices (
+        recurring_id SERIAL PRIMARY KEY,
+        customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        description VARCHAR(200) NOT NULL,
+        interval_months INTEGER NOT NULL DEFAULT 1 CHECK (interval_months > 0),
+        start_date DATE NOT NULL,
+        end_date DATE,
+        payment_days INTEGER NOT NULL DEFAULT 14 CHECK (payment_days >= 0),
+        active BOOLEAN NOT NULL DEFAULT true,
+        version INTEGER NOT NULL DEFAULT 1,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE TABLE IF NOT EXISTS recurring_invoice_items (
+        item_id SERIAL PRIMARY KEY,
+        recurring_id INTEGER NOT NULL REFERENCES recurring_invoices(recurring_id) ON DELETE CASCADE,
+        product_id INTEGER REFERENCES products(product_id),
+        description TEXT NOT NULL DEFAULT '',
+        quantity INTEGER NOT NULL,
+        unit_price DECIMAL(10, 2) NOT NULL,
+        total_price DECIMAL(10, 2) NOT NULL
+    );
+
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS recurring_id INTEGER
+        REFERENCES recurring_invoices(recurring_id) ON DELETE SET NULL;
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS period_start DATE;
+
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_invoices_recurring_period ON invoices(recurring_id, period_start);
+    CREATE INDEX IF NOT EXISTS idx_recurring_invoices_customer_id ON recurring_invoices(customer_id);
+    CREATE INDEX IF NOT EXISTS idx_recurring_invoice_items_recurring_id ON recurring_invoice_items(recurring_id);
+
+    DROP TRIGGER IF EXISTS recurring_invoices_notify ON recurring_invoices;
+    CREATE TRIGGER recurring_invoices_notify
+        AFTER INSERT OR UPDATE OR DELETE ON recurring_invoices
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('recurring_id');
+";
+
+// Tax rates. Lines keep a copy of the percentage next to the rate so that
+// changing a rate does not alter documents already written. Documents
+// from before have their total taken as net with no tax.
+const CREATE_TAX_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS tax_rates (
+        tax_rate_id SERIAL PRIMARY KEY,
+        name VARCHAR(50) UNIQUE NOT NULL,
+        rate DECIMAL(5, 2) NOT NULL CHECK (rate >= 0),
+        reverse_charge BOOLEAN NOT NULL DEFAULT false,
+        note TEXT NOT NULL DEFAULT '',
+        is_default BOOLEAN NOT NULL DEFAULT false,
+        active BOOLEAN NOT NULL DEFAULT true
+    );
+
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_tax_rates_default ON tax_rates(is_default) WHERE is_default;
+
+    INSERT INTO tax_rates (name, rate, reverse_charge, note, is_default)
+    SELECT * FROM (VALUES
+        ('19%', 19.00, false, '', true),
+        ('7%', 7.00, false, '', false),
+        ('0%', 0.00, false, '', false),
+        ('Reverse charge', 0.00, true,
+         'Reverse charge: the recipient of the service is liable for the VAT (section 13b UStG).', false)
+    ) AS defaults(name, rate, reverse_charge, note, is_default)
+    WHERE NOT EXISTS (SELECT 1 FROM tax_rates);
+
+    ALTER TABLE products ADD COLUMN IF NOT EXISTS tax_rate_id INTEGER REFERENCES tax_rates(tax_rate_id);
+
+    ALTER TABLE invoice_items ADD COLUMN IF NOT EXISTS tax_rate_id INTEGER REFERENCES tax_rates(tax_rate_id);
+    ALTER TABLE invoice_items ADD COLUMN IF NOT EXISTS tax_rate DECIMAL(5, 2) NOT NULL DEFAULT 0;
+    ALTER TABLE quote_items ADD COLUMN IF NOT EXISTS tax_rate_id INTEGER REFERENCES tax_rates(tax_rate_id);
+    ALTER TABLE quote_items ADD COLUMN IF NOT EXISTS tax_rate DECIMAL(5, 2) NOT NULL DEFAULT 0;
+    ALTER TABLE recurring_invoice_items ADD COLUMN IF NOT EXISTS tax_rate_id INTEGER REFERENCES tax_rates(tax_rate_id);
+    ALTER TABLE recurring_invoice_items ADD COLUMN IF NOT EXISTS tax_rate DECIMAL(5, 2) NOT NULL DEFAULT 0;
+
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS net_amount DECIMAL(10, 2);
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS tax_amount DECIMAL(10, 2);
+    UPDATE invoices SET net_amount = total_amount, tax_amount = 0 WHERE net_amount IS NULL;
+    ALTER TABLE invoices ALTER COLUMN net_amount SET NOT NULL;
+    ALTER TABLE invoices ALTER COLUMN tax_amount SET NOT NULL;
+
+    ALTER TABLE quotes ADD COLUMN IF NOT EXISTS net_amount DECIMAL(10, 2);
+    ALTER TABLE quotes ADD COLUMN IF NOT EXISTS tax_amount DECIMAL(10, 2);
+    UPDATE quotes SET net_amount = total_amount, tax_amount = 0 WHERE net_amount IS NULL;
+    ALTER TABLE quotes ALTER COLUMN net_amount SET NOT NULL;
+    ALTER TABLE quotes ALTER COLUMN tax_amount SET NOT NULL;
+
+    DROP TRIGGER IF EXISTS tax_rates_notify ON tax_rates;
+    CREATE TRIGGER tax_rates_notify
+        AFTER INSERT OR UPDATE OR DELETE ON tax_rates
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('tax_rate_id');
+";
+
+// Currencies and exchange rates. A rate is the amount of the currency
+// that one unit of the base currency buys on the given date. Existing
+// amounts are taken to be in the base currency.
+const CREATE_CURRENCY_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS currencies (
+        currency_id SERIAL PRIMARY KEY,
+        code VARCHAR(3) UNIQUE NOT NULL CHECK (code ~ '^[A-Z]{3}$'),
+        name VARCHAR(50) NOT NULL,
+        is_base BOOLEAN NOT NULL DEFAULT false,
+        active BOOLEAN NOT NULL DEFAULT true
+    );
+
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_currencies_base ON currencies(is_base) WHERE is_base;
+
+    INSERT INTO currencies (code, name, is_base)
+    SELECT * FROM (VALUES
+        ('EUR', 'Euro', true),
+        ('USD', 'US Dollar', false),
+        ('GBP', 'Pound Sterling', false),
+        ('CHF', 'Swiss Franc', false)
+    ) AS defaults(code, name, is_base)
+    WHERE NOT EXISTS (SELECT 1 FROM currencies);
+
+    CREATE TABLE IF NOT EXISTS exchange_rates (
+        exchange_rate_id SERIAL PRIMARY KEY,
+        currency VARCHAR(3) NOT NULL,
+        rate_date DATE NOT NULL,
+        rate DECIMAL(18, 6) NOT NULL CHECK (rate > 0),
+        UNIQUE (currency, rate_date)
+    );
+
+    CREATE OR REPLACE FUNCTION base_currency() RETURNS VARCHAR AS $$
+        SELECT COALESCE((SELECT code FROM currencies WHERE is_base), 'EUR')
+    $$ LANGUAGE SQL STABLE;
+
+    -- Rate of the currency on the date, or the latest one before; NULL if
+    -- there is none.
+    CREATE OR REPLACE FUNCTION exchange_rate(currency_code VARCHAR, on_date DATE) RETURNS NUMERIC AS $$
+        SELECT CASE WHEN currency_code = base_currency() THEN 1 ELSE (
+            SELECT rate FROM exchange_rates
+            WHERE currency = currency_code AND rate_date <= on_date
+            ORDER BY rate_date DESC LIMIT 1
+        ) END
+    $$ LANGUAGE SQL STABLE;
+
+    CREATE OR REPLACE FUNCTION convert_amount(amount NUMERIC, from_currency VARCHAR, to_currency VARCHAR, on_date DATE)
+    RETURNS NUMERIC AS $$
+        SELECT CASE WHEN from_currency = to_currency THEN amount ELSE
+            ROUND(amount / exchange_rate(from_currency, on_date) * exchange_rate(to_currency, on_date), 2)
+        END
+    $$ LANGUAGE SQL STABLE;
+
+    -- convert_amount for amounts that must not go missing, such as payments
+    -- settling an invoice: fails instead of returning NULL without a rate.
+    CREATE OR REPLACE FUNCTION convert_amount_strict(amount NUMERIC, from_currency VARCHAR, to_currency VARCHAR, on_date DATE)
+    RETURNS NUMERIC AS $$
+    DECLARE
+        converted NUMERIC := convert_amount(amount, from_currency, to_currency, on_date);
+    BEGIN
+        IF converted IS NULL AND amount IS NOT NULL THEN
+            RAISE EXCEPTION 'There is no exchange rate to convert % into % on %', from_currency, to_currency, on_date;
+        END IF;
+        RETURN converted;
+    END;
+    $$ LANGUAGE plpgsql STABLE;
+
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS currency VARCHAR(3) NOT NULL DEFAULT base_currency();
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS currency VARCHAR(3) NOT NULL DEFAULT base_currency();
+    ALTER TABLE payments ADD COLUMN IF NOT EXISTS currency VARCHAR(3) NOT NULL DEFAULT base_currency();
+
+    DROP TRIGGER IF EXISTS currencies_notify ON currencies;
+    CREATE TRIGGER currencies_notify
+        AFTER INSERT OR UPDATE OR DELETE ON currencies
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('currency_id');
+";
+
+// Seller details printed on documents and required for e-invoices, in a
+// table with a single row, and the buyer details e-invoices need.
+const CREATE_EINVOICE_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS company_profile (
+        company_id INTEGER PRIMARY KEY DEFAULT 1 CHECK (company_id = 1),
+        company_name VARCHAR(100) NOT NULL DEFAULT '',
+        street VARCHAR(100) NOT NULL DEFAULT '',
+        postal_code VARCHAR(20) NOT NULL DEFAULT '',
+        city VARCHAR(50) NOT NULL DEFAULT '',
+        country_code VARCHAR(2) NOT NULL DEFAULT 'DE',
+        vat_id VARCHAR(20) NOT NULL DEFAULT '',
+        tax_number VARCHAR(30) NOT NULL DEFAULT '',
+        contact_name VARCHAR(100) NOT NULL DEFAULT '',
+        phone VARCHAR(30) NOT NULL DEFAULT '',
+        email VARCHAR(100) NOT NULL DEFAULT '',
+        iban VARCHAR(34) NOT NULL DEFAULT '',
+        bic VARCHAR(11) NOT NULL DEFAULT '',
+        version INTEGER NOT NULL DEFAULT 1
+    );
+
+    INSERT INTO company_profile (company_id) VALUES (1) ON CONFLICT DO NOTHING;
+
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS vat_id VARCHAR(20) NOT NULL DEFAULT '';
+    -- Routing id of public-sector buyers (Leitweg-ID) or another reference
+    -- the buyer wants on its invoices.
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS buyer_reference VARCHAR(100) NOT NULL DEFAULT '';
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS buyer_reference VARCHAR(100) NOT NULL DEFAULT '';
+";
+
+// Settings of the DATEV booking export. Existing tax rates get the SKR03
+// revenue accounts matching them; debtor accounts are derived from the
+// customer id.
+const CREATE_DATEV_QUERY: &str = "
+    ALTER TABLE company_profile ADD COLUMN IF NOT EXISTS datev_consultant_number INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE company_profile ADD COLUMN IF NOT EXISTS datev_client_number INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE company_profile ADD COLUMN IF NOT EXISTS datev_fiscal_year_start INTEGER NOT NULL DEFAULT 1
+        CHECK (datev_fiscal_year_start BETWEEN 1 AND 12);
+    ALTER TABLE company_profile ADD COLUMN IF NOT EXISTS datev_bank_account VARCHAR(8) NOT NULL DEFAULT '1200'
+        CHECK (datev_bank_account ~ '^[0-9]*$');
+
+    ALTER TABLE tax_rates ADD COLUMN IF NOT EXISTS revenue_account VARCHAR(8) CHECK (revenue_account ~ '^[0-9]*$');
+    UPDATE tax_rates SET revenue_account = CASE
+        WHEN reverse_charge THEN '8337'
+        WHEN rate = 19 THEN '8400'
+        WHEN rate = 7 THEN '8300'
+        ELSE ''
+    END WHERE revenue_account IS NULL;
+    ALTER TABLE tax_rates ALTER COLUMN revenue_account SET DEFAULT '';
+    ALTER TABLE tax_rates ALTER COLUMN revenue_account SET NOT NULL;
+";
+
+// SEPA direct debits: the customer's account and mandate, the collections
+// made from it and the bank reference of payments imported from
+// statements, which makes importing a statement twice harmless.
+const CREATE_SEPA_QUERY: &str = "
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS iban VARCHAR(34) NOT NULL DEFAULT '';
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS bic VARCHAR(11) NOT NULL DEFAULT '';
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS mandate_reference VARCHAR(35) NOT NULL DEFAULT '';
+    ALTER TABLE customers ADD COLUMN IF NOT EXISTS mandate_date DATE;
+    ALTER TABLE company_profile ADD COLUMN IF NOT EXISTS creditor_id VARCHAR(35) NOT NULL DEFAULT '';
+
+    CREATE TABLE IF NOT EXISTS direct_debits (
+        direct_debit_id SERIAL PRIMARY KEY,
+        invoice_id INTEGER NOT NULL REFERENCES invoices(invoice_id),
+        customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE RESTRICT,
+        mandate_reference VARCHAR(35) NOT NULL,
+        amount DECIMAL(10, 2) NOT NULL CHECK (amount > 0),
+        sequence_type VARCHAR(4) NOT NULL CHECK (sequence_type IN ('FRST', 'RCUR')),
+        collection_date DATE NOT NULL,
+        message_id VARCHAR(35) NOT NULL,
+        end_to_end_id VARCHAR(35) NOT NULL UNIQUE,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_direct_debits_invoice_id ON direct_debits(invoice_id);
+    CREATE INDEX IF NOT EXISTS idx_direct_debits_mandate ON direct_debits(customer_id, mandate_reference);
+
+    ALTER TABLE payments ADD COLUMN IF NOT EXISTS bank_reference VARCHAR(100);
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_payments_bank_reference ON payments(bank_reference)
+        WHERE bank_reference IS NOT NULL;
+";
+
+// Stock movement ledger. `products.stock_quantity` holds the sum of a
+// product's movements; the stock products had before becomes their
+// opening balance. Products with movements cannot be deleted, so the ledger
+// stays complete.
+const CREATE_STOCK_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS stock_movements (
+        movement_id SERIAL PRIMARY KEY,
+        product_id INTEGER NOT NULL REFERENCES products(product_id) ON DELETE RESTRICT,
+        movement_type VARCHAR(20) NOT NULL
+            CHECK (movement_type IN ('receipt', 'sale', 'correction', 'return')),
+        quantity INTEGER NOT NULL CHECK (quantity <> 0),
+        invoice_id INTEGER REFERENCES invoices(invoice_id) ON DELETE SET NULL,
+        notes TEXT NOT NULL DEFAULT '',
+        created_by VARCHAR(100) NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_stock_movements_product_id ON stock_movements(product_id);
+    CREATE INDEX IF NOT EXISTS idx_stock_movements_invoice_id ON stock_movements(invoice_id);
+
+    INSERT INTO stock_movements (product_id, movement_type, quantity, notes, created_by)
+    SELECT p.product_id, 'correction', p.stock_quantity, 'Opening balance', 'system'
+    FROM products p
+    WHERE p.stock_quantity <> 0
+        AND NOT EXISTS (SELECT 1 FROM stock_movements m WHERE m.product_id = p.product_id);
+
+    ALTER TABLE products ADD COLUMN IF NOT EXISTS reorder_level INTEGER NOT NULL DEFAULT 0
+        CHECK (reorder_level >= 0);
+
+    DROP TRIGGER IF EXISTS products_notify ON products;
+    CREATE TRIGGER products_notify
+        AFTER INSERT OR UPDATE OR DELETE ON products
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('product_id');
+";
+
+// Price lists for one customer, the customers with a tag, or everyone.
+// An entry sets the product's price, or a discount on its catalog price,
+// from a minimum quantity on; several entries for one product make volume
+// tiers. Lines keep the price they were given and a discount of their own.
+const CREATE_PRICING_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS price_lists (
+        price_list_id SERIAL PRIMARY KEY,
+        name VARCHAR(100) UNIQUE NOT NULL,
+        customer_id INTEGER REFERENCES customers(customer_id) ON DELETE CASCADE,
+        tag_id INTEGER REFERENCES tags(tag_id) ON DELETE CASCADE,
+        active BOOLEAN NOT NULL DEFAULT true,
+        CHECK (customer_id IS NULL OR tag_id IS NULL)
+    );
+
+    CREATE TABLE IF NOT EXISTS price_list_entries (
+        entry_id SERIAL PRIMARY KEY,
+        price_list_id INTEGER NOT NULL REFERENCES price_lists(price_list_id) ON DELETE CASCADE,
+        product_id INTEGER NOT NULL REFERENCES products(product_id) ON DELETE CASCADE,
+        min_quantity INTEGER NOT NULL DEFAULT 1 CHECK (min_quantity >= 1),
+        unit_price DECIMAL(10, 2) CHECK (unit_price >= 0),
+        discount_percent DECIMAL(5, 2) NOT NULL DEFAULT 0 CHECK (discount_percent BETWEEN 0 AND 100),
+        UNIQUE (price_list_id, product_id, min_quantity)
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_price_lists_customer_id ON price_lists(customer_id);
+    CREATE INDEX IF NOT EXISTS idx_price_list_entries_product_id ON price_list_entries(product_id);
+
+    ALTER TABLE invoice_items ADD COLUMN IF NOT EXISTS discount_percent DECIMAL(5, 2) NOT NULL DEFAULT 0
+        CHECK (discount_percent BETWEEN 0 AND 100);
+    ALTER TABLE quote_items ADD COLUMN IF NOT EXISTS discount_percent DECIMAL(5, 2) NOT NULL DEFAULT 0
+        CHECK (discount_percent BETWEEN 0 AND 100);
+    ALTER TABLE recurring_invoice_items ADD COLUMN IF NOT EXISTS discount_percent DECIMAL(5, 2) NOT NULL DEFAULT 0
+        CHECK (discount_percent BETWEEN 0 AND 100);
+
+    DROP TRIGGER IF EXISTS price_lists_notify ON price_lists;
+    CREATE TRIGGER price_lists_notify
+        AFTER INSERT OR UPDATE OR DELETE ON price_lists
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('price_list_id');
+";
+
+// Credit notes are invoices with negative lines that reference the
+// invoice they correct. Once
+// issued they settle the original like a payment does. Invoices other than
+// drafts are never deleted; they are corrected with a credit note.
+const CREATE_CREDIT_NOTES_QUERY: &str = "
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS document_type VARCHAR(20) NOT NULL DEFAULT 'invoice'
+        CHECK (document_type IN ('invoice', 'credit_note'));
+    ALTER TABLE invoices ADD COLUMN IF NOT EXISTS original_invoice_id INTEGER REFERENCES invoices(invoice_id);
+    CREATE INDEX IF NOT EXISTS idx_invoices_original_invoice_id ON invoices(original_invoice_id);
+
+    -- Amount of an invoice not covered by its payments and issued credit
+    -- notes, in its currency. Credit notes themselves are never open.
+    CREATE OR REPLACE FUNCTION invoice_open_amount(open_invoice_id INTEGER) RETURNS NUMERIC AS $$
+        SELECT CASE WHEN i.document_type = 'credit_note' THEN 0 ELSE
+            i.total_amount
+            - COALESCE((SELECT SUM(convert_amount_strict(p.amount, p.currency, i.currency, p.payment_date))
+                        FROM payments p WHERE p.invoice_id = i.invoice_id), 0)
+            + COALESCE((SELECT SUM(convert_amount_strict(c.total_amount, c.currency, i.currency, c.invoice_date))
+                        FROM invoices c
+                        WHERE c.original_invoice_id = i.invoice_id AND c.status NOT IN ('draft', 'cancelled')), 0)
+        END
+        FROM invoices i WHERE i.invoice_id = open_invoice_id
+    $$ LANGUAGE SQL STABLE;
+
+    CREATE OR REPLACE FUNCTION prevent_invoice_delete() RETURNS trigger AS $$
+    BEGIN
+        IF OLD.status <> 'draft' THEN
+            RAISE EXCEPTION 'Invoice % is not a draft and cannot be deleted; issue a credit note instead',
+                OLD.invoice_number;
+        END IF;
+        RETURN OLD;
+    END;
+    $$ LANGUAGE plpgsql;
+
+    DROP TRIGGER IF EXISTS invoices_prevent_delete ON invoices;
+    CREATE TRIGGER invoices_prevent_delete
+        BEFORE DELETE ON invoices
+        FOR EACH ROW EXECUTE FUNCTION prevent_invoice_delete();
+";
+
+// Number sequences of invoices, quotes and credit notes. A number is taken
+// in the transaction saving the document, with the sequence row locked, so
+// numbers have no gaps. Invoices continue their former numbering by id.
+const CREATE_NUMBERING_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS number_sequences (
+        sequence_id SERIAL PRIMARY KEY,
+        document_type VARCHAR(20) UNIQUE NOT NULL
+            CHECK (document_type IN ('invoice', 'quote', 'credit_note')),
+        pattern VARCHAR(50) NOT NULL,
+        yearly_reset BOOLEAN NOT NULL DEFAULT true,
+        next_number INTEGER NOT NULL DEFAULT 1 CHECK (next_number >= 1),
+        current_year INTEGER,
+        version INTEGER NOT NULL DEFAULT 1
+    );
+
+    INSERT INTO number_sequences (document_type, pattern, yearly_reset, next_number)
+    VALUES
+        ('invoice', 'R{YYYY}-{00000}', false, COALESCE((SELECT MAX(invoice_id) FROM invoices), 0) + 1),
+        ('quote', 'A{YYYY}-{00000}', true, 1),
+        ('credit_note', 'G{YYYY}-{00000}', true, 1)
+    ON CONFLICT (document_type) DO NOTHING;
+
+    DROP SEQUENCE IF EXISTS credit_note_numbers;
+";
+
+// Free notes on customers, and notifications for the customer's contacts,
+// notes and payments shown together on the customer detail screen.
+const CREATE_CUSTOMER_NOTES_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS customer_notes (
+        note_id SERIAL PRIMARY KEY,
+        customer_id INTEGER NOT NULL REFERENCES customers(customer_id) ON DELETE CASCADE,
+        note TEXT NOT NULL CHECK (note <> ''),
+        created_by VARCHAR(100) NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_customer_notes_customer_id ON customer_notes(customer_id);
+
+    DROP TRIGGER IF EXISTS customer_notes_notify ON customer_notes;
+    CREATE TRIGGER customer_notes_notify
+        AFTER INSERT OR UPDATE OR DELETE ON customer_notes
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('note_id');
+
+    DROP TRIGGER IF EXISTS contacts_notify ON contacts;
+    CREATE TRIGGER contacts_notify
+        AFTER INSERT OR UPDATE OR DELETE ON contacts
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('contact_id');
+
+    DROP TRIGGER IF EXISTS payments_notify ON payments;
+    CREATE TRIGGER payments_notify
+        AFTER INSERT OR UPDATE OR DELETE ON payments
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('payment_id');
+";
+
+const CREATE_TASKS_QUERY: &str = "
+    CREATE TABLE IF NOT EXISTS tasks (
+        task_id SERIAL PRIMARY KEY,
+        title VARCHAR(200) NOT NULL CHECK (title <> ''),
+        description TEXT NOT NULL DEFAULT '',
+        customer_id INTEGER REFERENCES customers(customer_id) ON DELETE SET NULL,
+        history_id INTEGER REFERENCES contact_history(history_id) ON DELETE SET NULL,
+        assignee VARCHAR(50) REFERENCES users(username) ON UPDATE CASCADE ON DELETE SET NULL,
+        due_date DATE,
+        priority VARCHAR(10) NOT NULL DEFAULT 'normal' CHECK (priority IN ('low', 'normal', 'high')),
+        status VARCHAR(20) NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'in_progress', 'done')),
+        created_by VARCHAR(100) NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        version INTEGER NOT NULL DEFAULT 1
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_tasks_assignee ON tasks(assignee, status);
+    CREATE INDEX IF NOT EXISTS idx_tasks_customer_id ON tasks(customer_id);
+
+    DROP TRIGGER IF EXISTS tasks_notify ON tasks;
+    CREATE TRIGGER tasks_notify
+        AFTER INSERT OR UPDATE OR DELETE ON tasks
+        FOR EACH ROW EXECUTE FUNCTION notify_crm_change('task_id');
+";
+
+/// Payload sent by `notify_crm_change()` for every changed row.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChangeNotification {
+    pub table: String,
+    pub action: String,
+    pub id: i32,
+    pub customer_id: Option<i32>,
+}
 
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
@@ -17,486 +781,5026 @@ pub struct Customer {
     pub phone: String,
     pub email: String,
     pub website: String,
+    /// ISO 4217 code; invoices to the customer default to it.
+    pub currency: String,
+    pub vat_id: String,
+    /// Reference the customer needs on e-invoices, such as a Leitweg-ID.
+    pub buyer_reference: String,
+    pub iban: String,
+    pub bic: String,
+    /// SEPA direct debit mandate; invoices are only collected from
+    /// customers with a reference and the date it was signed.
+    pub mandate_reference: String,
+    pub mandate_date: Option<NaiveDate>,
     pub customer_id: i32,
+    pub version: i32,
 }
 
-#[derive(Debug, Clone)]
-pub struct ContactHistory {
-    pub history_id: i32,
-    pub customer_id: i32,
-    pub contact_type: String,
-    pub contact_date: DateTime<Utc>,
-    pub contact_duration: Option<i32>,
-    pub contact_method: Option<String>,
-    pub contact_outcome: String, // Ändern Sie dies von Option<String> zu String
-    pub notes: String,
-    pub follow_up_date: Option<NaiveDate>,
-    pub created_by: String,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
+#[derive(Serialize, Debug, Clone)]
+pub struct Invoice {
+    pub invoice_id: i32,
+    pub customer_id: Option<i32>,
+    pub invoice_number: String,
+    pub invoice_date: NaiveDate,
+    pub due_date: NaiveDate,
+    /// Sum of the item net amounts, or the total if there are no items.
+    pub net_amount: Decimal,
+    pub tax_amount: Decimal,
+    /// Gross amount. Entered by hand on invoices without items.
+    pub total_amount: Decimal,
+    pub currency: String,
+    /// Taken from the customer for new invoices; required for XRechnung.
+    pub buyer_reference: String,
+    pub status: String,
+    pub payment_method: Option<String>,
+    pub notes: Option<String>,
+    pub items: Vec<LineItem>,
+    /// "invoice" or "credit_note".
+    pub document_type: String,
+    /// Invoice a credit note corrects.
+    pub original_invoice_id: Option<i32>,
+    pub version: i32,
 }
 
-impl Default for ContactHistory {
-    fn default() -> Self {
-        ContactHistory {
-            history_id: 0, // Oder ein anderer geeigneter Standardwert
-            customer_id: 0,
-            contact_type: String::new(),
-            contact_date: Utc::now(),
-            contact_duration: None,
-            contact_method: None,
-            contact_outcome: String::new(),
-            notes: String::new(),
-            follow_up_date: None,
-            created_by: String::new(),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
+/// Statuses of invoices that have been issued. They are never deleted or
+/// cancelled, only corrected with a credit note.
+pub const ISSUED_STATUSES: [&str; 3] = ["sent", "paid", "credited"];
+
+impl Invoice {
+    pub fn is_credit_note(&self) -> bool {
+        self.document_type == "credit_note"
+    }
+
+    /// "Invoice" or "Credit note", for messages and documents.
+    pub fn document_label(&self) -> &'static str {
+        if self.is_credit_note() {
+            "Credit note"
+        } else {
+            "Invoice"
         }
     }
-}
 
-pub async fn create_database(config: &DbConfig) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Attempting to create database: {}", config.database);
-    let conn_string = format!(
-        "host={} port={} user=postgres dbname=postgres",
-        config.host, config.port
-    );
+    pub fn is_issued(&self) -> bool {
+        ISSUED_STATUSES.contains(&self.status.as_str())
+    }
 
-    println!("Connecting to PostgreSQL server as postgres user...");
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+    /// Whether the invoice may be saved with `status`. Issued invoices only
+    /// move on from sent to paid.
+    pub fn allows_status(&self, status: &str) -> bool {
+        !self.is_issued() || status == self.status || (self.status == "sent" && status == "paid")
+    }
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
+    /// Whether `other` says the same as the invoice, leaving aside status,
+    /// version and the ids of the items.
+    fn same_content(&self, other: &Invoice) -> bool {
+        let item = |i: &LineItem| {
+            (i.product_id, i.description.clone(), i.quantity, i.unit_price, i.tax_rate_id, i.tax_rate, i.discount_percent)
+        };
+        self.customer_id == other.customer_id
+            && self.invoice_number == other.invoice_number
+            && self.invoice_date == other.invoice_date
+            && self.due_date == other.due_date
+            && (other.currency.is_empty() || self.currency == other.currency)
+            && self.buyer_reference == other.buyer_reference
+            && self.payment_method == other.payment_method
+            && self.notes == other.notes
+            && (!self.items.is_empty() || self.total_amount == other.total_amount)
+            && self.items.iter().map(item).eq(other.items.iter().map(item))
+    }
+}
 
-    let query = "SELECT EXISTS(SELECT 1 FROM pg_database WHERE datname = $1)";
-    println!("Executing query: {}", query);
-    let db_exists: bool = client.query_one(query, &[&config.database]).await?.get(0);
+impl Default for Invoice {
+    fn default() -> Self {
+        let today = Utc::now().date_naive();
+        Invoice {
+            invoice_id: 0,
+            customer_id: None,
+            invoice_number: String::new(),
+            invoice_date: today,
+            due_date: today + chrono::Duration::days(14),
+            net_amount: Decimal::ZERO,
+            tax_amount: Decimal::ZERO,
+            total_amount: Decimal::ZERO,
+            // Saved in the base currency unless a customer's is picked.
+            currency: String::new(),
+            buyer_reference: String::new(),
+            status: String::from("draft"),
+            payment_method: None,
+            notes: None,
+            items: Vec::new(),
+            document_type: String::from("invoice"),
+            original_invoice_id: None,
+            version: 0,
+        }
+    }
+}
 
-    if !db_exists {
-        println!("Database does not exist. Creating...");
-        let create_db_query = format!("CREATE DATABASE {}", config.database);
-        println!("Executing query: {}", create_db_query);
-        client.execute(&create_db_query, &[]).await?;
-        println!("Database created successfully");
+/// An application user. The password hash never leaves `db.rs` except for
+/// verification at login.
+#[derive(Serialize, Debug, Clone)]
+pub struct User {
+    pub user_id: i32,
+    pub username: String,
+    pub display_name: String,
+    pub role: Role,
+    pub active: bool,
+}
 
-        let grant_query = format!(
-            "GRANT ALL PRIVILEGES ON DATABASE {} TO {}",
-            config.database, config.username
-        );
-        println!("Executing query: {}", grant_query);
-        client.execute(&grant_query, &[]).await?;
-        println!("Privileges granted to user {}", config.username);
-    } else {
-        println!("Database already exists");
-    }
+#[derive(Serialize, Debug, Clone)]
+pub struct Tag {
+    pub tag_id: i32,
+    pub name: String,
+    /// Display color as `#RRGGBB`.
+    pub color: String,
+}
 
-    Ok(())
+/// An admin-defined extra field on customers. Values are stored as text in
+/// the canonical form of the field type (see `custom_fields`).
+#[derive(Serialize, Debug, Clone)]
+pub struct CustomField {
+    pub field_id: i32,
+    pub name: String,
+    pub field_type: FieldType,
+    /// Allowed values of a `Choice` field.
+    pub choices: Vec<String>,
+    pub sort_order: i32,
+    pub active: bool,
 }
 
-use once_cell::sync::Lazy;
+#[derive(Serialize, Debug, Clone)]
+pub struct Relation {
+    pub relation_id: i32,
+    pub from_customer_id: i32,
+    pub to_customer_id: i32,
+    pub relation_type: RelationType,
+}
 
-use std::sync::Mutex;
+#[derive(Serialize, Debug, Clone)]
+pub struct DealStage {
+    pub stage_id: i32,
+    pub name: String,
+    pub sort_order: i32,
+    /// Probability given to deals moved into this stage.
+    pub default_probability: i32,
+    pub active: bool,
+}
 
-static DB_CONFIG: Lazy<Mutex<Option<DbConfig>>> = Lazy::new(|| Mutex::new(None));
+/// A contact person at a customer.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Contact {
+    pub contact_id: i32,
+    pub customer_id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub phone: String,
+    pub position: String,
+    pub is_primary: bool,
+}
 
-pub fn set_config(config: Option<DbConfig>) {
-    let mut db_config = DB_CONFIG.lock().unwrap();
-    *db_config = config.clone();
-    println!("Configuration set: {:?}", config);
+#[derive(Serialize, Debug, Clone)]
+pub struct CustomerNote {
+    pub note_id: i32,
+    pub customer_id: i32,
+    pub note: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
 }
-pub fn get_config() -> Option<DbConfig> {
-    let config = DB_CONFIG.lock().unwrap().clone();
-    println!("Retrieved configuration: {:?}", config);
-    config
+
+/// A payment with the number of the invoice it settles.
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub invoice_number: String,
+    pub payment_date: NaiveDate,
+    pub amount: Decimal,
+    pub currency: String,
+    pub payment_method: String,
+    pub notes: String,
 }
 
-pub async fn create_database_structure(
-    config: &DbConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Creating database structure for: {}", config.database);
-    let conn_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database
-    );
+/// Everything recorded about one customer, for the customer detail screen.
+/// Invoices come with their open amounts; they and the payments are only
+/// loaded for roles that see invoices.
+#[derive(Debug, Clone)]
+pub struct CustomerOverview {
+    pub customer: Customer,
+    pub contacts: Vec<Contact>,
+    pub history: Vec<ContactHistory>,
+    pub invoices: Vec<(Invoice, Decimal)>,
+    pub payments: Vec<Payment>,
+    /// Deals with the name of their stage.
+    pub deals: Vec<(Deal, String)>,
+    pub notes: Vec<CustomerNote>,
+}
 
-    println!("Connecting to database...");
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+/// A to-do, optionally about a customer and following up a contact.
+/// `assignee` is a username; `status` and `priority` are one of
+/// `tasks::TASK_STATUSES` and `tasks::TASK_PRIORITIES`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Task {
+    pub task_id: i32,
+    pub title: String,
+    pub description: String,
+    pub customer_id: Option<i32>,
+    /// Contact history entry the task follows up.
+    pub history_id: Option<i32>,
+    pub assignee: Option<String>,
+    pub due_date: Option<NaiveDate>,
+    pub priority: String,
+    pub status: String,
+    pub created_by: String,
+    pub version: i32,
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
+impl Default for Task {
+    fn default() -> Self {
+        Task {
+            task_id: 0,
+            title: String::new(),
+            description: String::new(),
+            customer_id: None,
+            history_id: None,
+            assignee: None,
+            due_date: None,
+            priority: "normal".to_string(),
+            status: "open".to_string(),
+            created_by: String::new(),
+            version: 1,
         }
-    });
+    }
+}
 
-    println!("Connected successfully. Creating tables...");
+/// One entry of a customer's activity timeline. `kind` is one of
+/// `timeline::EVENT_KINDS`.
+#[derive(Debug, Clone)]
+pub struct TimelineEvent {
+    pub occurred_at: DateTime<Utc>,
+    /// Payments are only known by their date.
+    pub all_day: bool,
+    pub kind: &'static str,
+    pub summary: String,
+    pub details: String,
+    pub user: String,
+}
 
-    let create_tables_query = "
-        -- Customers table
-        CREATE TABLE IF NOT EXISTS customers (
-            customer_id SERIAL PRIMARY KEY,
-            company_name VARCHAR(100) NOT NULL,
-            contact_name VARCHAR(100),
-            contact_position VARCHAR(50),
-            address TEXT,
-            city VARCHAR(50),
-            postal_code VARCHAR(20),
-            country VARCHAR(50),
-            phone VARCHAR(20),
-            email VARCHAR(100),
-            website VARCHAR(100),
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+#[derive(Serialize, Debug, Clone)]
+pub struct Deal {
+    pub deal_id: i32,
+    pub customer_id: i32,
+    pub title: String,
+    pub value: Decimal,
+    pub expected_close_date: NaiveDate,
+    /// Chance of winning in percent.
+    pub probability: i32,
+    pub stage_id: i32,
+    pub notes: String,
+    pub version: i32,
+}
+
+impl Default for Deal {
+    fn default() -> Self {
+        Deal {
+            deal_id: 0,
+            customer_id: 0,
+            title: String::new(),
+            value: Decimal::ZERO,
+            expected_close_date: Utc::now().date_naive() + chrono::Duration::days(30),
+            probability: 0,
+            stage_id: 0,
+            notes: String::new(),
+            version: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Product {
+    pub product_id: i32,
+    pub product_name: String,
+    pub description: Option<String>,
+    pub unit_price: Decimal,
+    pub tax_rate_id: Option<i32>,
+    /// Balance of the product's stock movements.
+    pub stock_quantity: i32,
+    /// Stock at or below which the product is reported as running low;
+    /// 0 to only report it once it is oversold.
+    pub reorder_level: i32,
+}
+
+impl Product {
+    pub fn is_low_on_stock(&self) -> bool {
+        self.stock_quantity < 0 || (self.reorder_level > 0 && self.stock_quantity <= self.reorder_level)
+    }
+}
+
+/// Kinds of stock movements: goods received, sold on an invoice,
+/// counted differently, or coming back.
+pub const MOVEMENT_TYPES: [&str; 4] = ["receipt", "sale", "correction", "return"];
+
+/// A change of a product's stock; `quantity` is negative for goods leaving.
+#[derive(Serialize, Debug, Clone)]
+pub struct StockMovement {
+    pub movement_id: i32,
+    pub product_id: i32,
+    pub movement_type: String,
+    pub quantity: i32,
+    pub invoice_id: Option<i32>,
+    pub invoice_number: Option<String>,
+    pub notes: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for StockMovement {
+    fn default() -> Self {
+        Self {
+            movement_id: 0,
+            product_id: 0,
+            movement_type: "receipt".to_string(),
+            quantity: 0,
+            invoice_id: None,
+            invoice_number: None,
+            notes: String::new(),
+            created_by: String::new(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineItem {
+    pub item_id: i32,
+    pub product_id: Option<i32>,
+    pub description: String,
+    pub quantity: i32,
+    /// Price the line was given, from a price list or by hand.
+    pub unit_price: Decimal,
+    /// Net amount: quantity times unit price less the discount, computed
+    /// when saving.
+    pub total_price: Decimal,
+    pub tax_rate_id: Option<i32>,
+    /// Percentage of the rate when the line was written.
+    pub tax_rate: Decimal,
+    #[serde(default)]
+    pub discount_percent: Decimal,
+}
+
+/// Prices of a price list. It applies to one customer, to the customers
+/// with a tag, or with neither to every customer.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PriceList {
+    pub price_list_id: i32,
+    pub name: String,
+    pub customer_id: Option<i32>,
+    pub tag_id: Option<i32>,
+    pub active: bool,
+    pub entries: Vec<PriceListEntry>,
+}
+
+/// Price of a product from `min_quantity` units on: `unit_price`, or the
+/// catalog price if there is none, less `discount_percent`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PriceListEntry {
+    pub entry_id: i32,
+    pub product_id: i32,
+    pub min_quantity: i32,
+    pub unit_price: Option<Decimal>,
+    pub discount_percent: Decimal,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Quote {
+    pub quote_id: i32,
+    pub customer_id: Option<i32>,
+    pub quote_number: String,
+    pub quote_date: NaiveDate,
+    pub valid_until: NaiveDate,
+    pub net_amount: Decimal,
+    pub tax_amount: Decimal,
+    /// Gross amount, computed from the items when saving.
+    pub total_amount: Decimal,
+    pub status: String,
+    pub notes: Option<String>,
+    /// Invoice the quote was converted into.
+    pub invoice_id: Option<i32>,
+    pub items: Vec<LineItem>,
+    pub version: i32,
+}
+
+impl Default for Quote {
+    fn default() -> Self {
+        let today = Utc::now().date_naive();
+        Quote {
+            quote_id: 0,
+            customer_id: None,
+            quote_number: String::new(),
+            quote_date: today,
+            valid_until: today + chrono::Duration::days(30),
+            net_amount: Decimal::ZERO,
+            tax_amount: Decimal::ZERO,
+            total_amount: Decimal::ZERO,
+            status: String::from("draft"),
+            notes: None,
+            invoice_id: None,
+            items: Vec::new(),
+            version: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DunningLevel {
+    pub level_id: i32,
+    /// Position in the escalation, starting at 1.
+    pub level: i32,
+    pub name: String,
+    /// Days past the due date before this level may be sent.
+    pub days_overdue: i32,
+    /// Days the customer is given to pay before the next level.
+    pub grace_days: i32,
+    pub fee: Decimal,
+    /// Yearly interest on the open amount in percent.
+    pub interest_rate: Decimal,
+    pub letter_text: String,
+}
+
+/// One dunning letter sent for an invoice.
+#[derive(Serialize, Debug, Clone)]
+pub struct DunningNotice {
+    pub notice_id: i32,
+    pub invoice_id: i32,
+    pub level: i32,
+    pub level_name: String,
+    pub notice_date: NaiveDate,
+    /// End of the grace period; no further notice is sent before.
+    pub pay_by: NaiveDate,
+    pub open_amount: Decimal,
+    pub fee: Decimal,
+    pub interest: Decimal,
+    pub history_id: Option<i32>,
+    pub created_by: String,
+}
+
+/// An invoice past its due date with an open balance.
+#[derive(Debug, Clone)]
+pub struct OverdueInvoice {
+    pub invoice: Invoice,
+    /// Total minus payments received.
+    pub open_amount: Decimal,
+}
+
+/// Template for an invoice sent every `interval_months` from `start_date`.
+#[derive(Serialize, Debug, Clone)]
+pub struct RecurringInvoice {
+    pub recurring_id: i32,
+    pub customer_id: i32,
+    pub description: String,
+    pub interval_months: i32,
+    pub start_date: NaiveDate,
+    /// Day the template ends; periods starting on or after it are not billed.
+    /// Open-ended if unset.
+    pub end_date: Option<NaiveDate>,
+    /// Days from the invoice date to the due date.
+    pub payment_days: i32,
+    pub active: bool,
+    pub items: Vec<LineItem>,
+    pub version: i32,
+}
+
+impl Default for RecurringInvoice {
+    fn default() -> Self {
+        RecurringInvoice {
+            recurring_id: 0,
+            customer_id: 0,
+            description: String::new(),
+            interval_months: 1,
+            start_date: Utc::now().date_naive(),
+            end_date: None,
+            payment_days: 14,
+            active: true,
+            items: Vec::new(),
+            version: 0,
+        }
+    }
+}
+
+/// Pattern and counter numbering one type of document; see
+/// `numbering::format_number`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct NumberSequence {
+    pub sequence_id: i32,
+    /// "invoice", "quote" or "credit_note".
+    pub document_type: String,
+    pub pattern: String,
+    /// Whether the counter starts at 1 again in a new year.
+    pub yearly_reset: bool,
+    pub next_number: i32,
+    /// Year of the last number taken.
+    pub current_year: Option<i32>,
+    pub version: i32,
+}
+
+/// The own company as seller on invoices.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CompanyProfile {
+    pub company_name: String,
+    pub street: String,
+    pub postal_code: String,
+    pub city: String,
+    /// ISO 3166-1 alpha-2 code, e.g. "DE".
+    pub country_code: String,
+    pub vat_id: String,
+    pub tax_number: String,
+    pub contact_name: String,
+    pub phone: String,
+    pub email: String,
+    pub iban: String,
+    pub bic: String,
+    /// DATEV consultant (Beraternummer) and client (Mandantennummer)
+    /// numbers; 0 until configured.
+    pub datev_consultant_number: i32,
+    pub datev_client_number: i32,
+    /// Month the fiscal year starts in.
+    pub datev_fiscal_year_start: i32,
+    /// Ledger account payments are booked to.
+    pub datev_bank_account: String,
+    /// SEPA creditor identifier (Gläubiger-ID) for direct debits.
+    pub creditor_id: String,
+    pub version: i32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Currency {
+    pub currency_id: i32,
+    /// ISO 4217 code, e.g. "EUR".
+    pub code: String,
+    pub name: String,
+    /// Reports are converted into the base currency.
+    pub is_base: bool,
+    pub active: bool,
+}
+
+/// Amount of `currency` one unit of the base currency buys on `rate_date`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub rate_date: NaiveDate,
+    pub rate: Decimal,
+}
+
+/// An invoice with its amounts converted into the base currency. The
+/// converted amounts are None if there is no rate for its currency.
+#[derive(Debug, Clone)]
+pub struct ConvertedInvoice {
+    pub invoice: Invoice,
+    pub net_base: Option<Decimal>,
+    pub gross_base: Option<Decimal>,
+    /// Open amount converted at the rate of the report's end date.
+    pub open_base: Option<Decimal>,
+}
+
+/// Revenue and receivables of the dashboard, in the base currency.
+#[derive(Debug, Clone, Default)]
+pub struct RevenueFigures {
+    /// Net revenue by the first day of the month.
+    pub monthly_revenue: Vec<(NaiveDate, Decimal)>,
+    /// Net revenue of the best customers, highest first.
+    pub top_customers: Vec<(String, Decimal)>,
+    /// Quantities sold of the best selling products, highest first.
+    pub top_products: Vec<(String, i64)>,
+    /// Invoices left out of the revenue as their currency has no rate.
+    pub missing_rates: i64,
+    pub open_invoices: i64,
+    pub open_amount: Decimal,
+    pub overdue_amount: Decimal,
+}
+
+/// Contacts and follow-ups of the dashboard.
+#[derive(Debug, Clone, Default)]
+pub struct ActivityFigures {
+    /// Number of contacts by contact type, most frequent first.
+    pub contacts_by_type: Vec<(String, i64)>,
+    pub follow_ups: Vec<FollowUp>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FollowUp {
+    pub follow_up_date: NaiveDate,
+    pub company_name: String,
+    pub contact_type: String,
+    pub notes: String,
+}
+
+/// An invoice to book, with the rate of its currency on the invoice date
+/// (None if there is none).
+#[derive(Debug, Clone)]
+pub struct BookedInvoice {
+    pub invoice: Invoice,
+    pub exchange_rate: Option<Decimal>,
+}
+
+/// A payment to book, with the invoice it was received for and the rate of
+/// its currency on the payment date.
+#[derive(Debug, Clone)]
+pub struct BookedPayment {
+    pub payment_date: NaiveDate,
+    pub amount: Decimal,
+    pub currency: String,
+    pub invoice_number: String,
+    pub customer_id: Option<i32>,
+    pub exchange_rate: Option<Decimal>,
+}
+
+/// An invoice that can be collected by direct debit, with the mandate of
+/// its customer.
+#[derive(Serialize, Debug, Clone)]
+pub struct DirectDebit {
+    pub invoice_id: i32,
+    pub invoice_number: String,
+    pub customer_id: i32,
+    pub debtor_name: String,
+    pub iban: String,
+    pub bic: String,
+    pub mandate_reference: String,
+    pub mandate_date: NaiveDate,
+    /// Open amount of the invoice.
+    pub amount: Decimal,
+    /// FRST for the first collection under a mandate, RCUR afterwards.
+    pub sequence_type: String,
+}
+
+/// A credit on a bank statement.
+#[derive(Debug, Clone)]
+pub struct StatementEntry {
+    /// Reference of the bank identifying the credit.
+    pub bank_reference: String,
+    pub booking_date: NaiveDate,
+    pub amount: Decimal,
+    pub currency: String,
+    /// Set by the payer, or by us on direct debits.
+    pub end_to_end_id: String,
+    pub remittance: String,
+    pub debtor_name: String,
+}
+
+/// An invoice still waiting for (part of) its payment.
+#[derive(Debug, Clone)]
+pub struct OpenInvoice {
+    pub invoice_id: i32,
+    pub invoice_number: String,
+    pub company_name: String,
+    pub currency: String,
+    pub open_amount: Decimal,
+}
+
+/// Outcome of importing a bank statement.
+#[derive(Debug, Clone, Default)]
+pub struct StatementImport {
+    /// Entries booked as payments, with the number of the invoice.
+    pub booked: Vec<(StatementEntry, String)>,
+    /// Entries already booked by an earlier import.
+    pub already_imported: usize,
+    pub unmatched: Vec<StatementEntry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TaxRate {
+    pub tax_rate_id: i32,
+    pub name: String,
+    /// Percentage; always 0 for reverse charge.
+    pub rate: Decimal,
+    pub reverse_charge: bool,
+    /// Printed on documents using the rate.
+    pub note: String,
+    /// Given to new lines without a product.
+    pub is_default: bool,
+    pub active: bool,
+    /// Ledger account of the DATEV export for revenue at this rate.
+    pub revenue_account: String,
+}
+
+/// One change recorded in `audit_log`. `old_values` is empty for inserts,
+/// `new_values` for deletes.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub audit_id: i32,
+    pub action: String,
+    pub old_values: Option<serde_json::Value>,
+    pub new_values: Option<serde_json::Value>,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Outcome of an update guarded by a record's `version`.
+pub enum SaveResult<T> {
+    /// The update went through; holds the record as now stored.
+    Saved(T),
+    /// Someone else changed the record first; holds their current values.
+    Conflict(T),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContactHistory {
+    pub history_id: i32,
+    pub customer_id: i32,
+    pub contact_type: String,
+    pub contact_date: DateTime<Utc>,
+    pub contact_duration: Option<i32>,
+    pub contact_method: Option<String>,
+    pub contact_outcome: String, // Ändern Sie dies von Option<String> zu String
+    pub notes: String,
+    pub follow_up_date: Option<NaiveDate>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for ContactHistory {
+    fn default() -> Self {
+        ContactHistory {
+            history_id: 0, // Oder ein anderer geeigneter Standardwert
+            customer_id: 0,
+            contact_type: String::new(),
+            contact_date: Utc::now(),
+            contact_duration: None,
+            contact_method: None,
+            contact_outcome: String::new(),
+            notes: String::new(),
+            follow_up_date: None,
+            created_by: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+pub async fn create_database(config: &DbConfig) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Attempting to create database: {}", config.database);
+    let conn_string = format!(
+        "host={} port={} user=postgres dbname=postgres",
+        config.host, config.port
+    );
+
+    println!("Connecting to PostgreSQL server as postgres user...");
+    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+
+    let query = "SELECT EXISTS(SELECT 1 FROM pg_database WHERE datname = $1)";
+    println!("Executing query: {}", query);
+    let db_exists: bool = client.query_one(query, &[&config.database]).await?.get(0);
+
+    if !db_exists {
+        println!("Database does not exist. Creating...");
+        let create_db_query = format!("CREATE DATABASE {}", config.database);
+        println!("Executing query: {}", create_db_query);
+        client.execute(&create_db_query, &[]).await?;
+        println!("Database created successfully");
+
+        let grant_query = format!(
+            "GRANT ALL PRIVILEGES ON DATABASE {} TO {}",
+            config.database, config.username
         );
+        println!("Executing query: {}", grant_query);
+        client.execute(&grant_query, &[]).await?;
+        println!("Privileges granted to user {}", config.username);
+    } else {
+        println!("Database already exists");
+    }
+
+    Ok(())
+}
+
+use once_cell::sync::Lazy;
+
+use std::sync::Mutex;
+
+static DB_CONFIG: Lazy<Mutex<Option<DbConfig>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn set_config(config: Option<DbConfig>) {
+    let mut db_config = DB_CONFIG.lock().unwrap();
+    *db_config = config.clone();
+    println!("Configuration set: {:?}", config);
+}
+pub fn get_config() -> Option<DbConfig> {
+    let config = DB_CONFIG.lock().unwrap().clone();
+    println!("Retrieved configuration: {:?}", config);
+    config
+}
+
+async fn connect(config: &DbConfig) -> Result<Client, Box<dyn std::error::Error>> {
+    let conn_string = format!(
+        "host={} port={} user={} password={} dbname={}",
+        config.host, config.port, config.username, config.password, config.database
+    );
+
+    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+
+    Ok(client)
+}
+
+fn customer_from_row(row: &Row) -> Customer {
+    Customer {
+        company_name: row.get("company_name"),
+        contact_name: row.get("contact_name"),
+        contact_position: row.get("contact_position"),
+        address: row.get("address"),
+        city: row.get("city"),
+        postal_code: row.get("postal_code"),
+        country: row.get("country"),
+        phone: row.get("phone"),
+        email: row.get("email"),
+        website: row.get("website"),
+        currency: row.get("currency"),
+        vat_id: row.get("vat_id"),
+        buyer_reference: row.get("buyer_reference"),
+        iban: row.get("iban"),
+        bic: row.get("bic"),
+        mandate_reference: row.get("mandate_reference"),
+        mandate_date: row.get("mandate_date"),
+        customer_id: row.get("customer_id"),
+        version: row.get("version"),
+    }
+}
+
+fn contact_history_from_row(row: &Row) -> ContactHistory {
+    ContactHistory {
+        history_id: row.get("history_id"),
+        customer_id: row.get("customer_id"),
+        contact_type: row.get("contact_type"),
+        contact_date: row.get("contact_date"),
+        contact_duration: row.get("contact_duration"),
+        contact_method: row.get("contact_method"),
+        contact_outcome: row.get("contact_outcome"),
+        notes: row.get("notes"),
+        follow_up_date: row.get("follow_up_date"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Name recorded as the author of changes made through this module: the
+/// logged-in user, or the database user before anyone has logged in.
+fn acting_user(config: &DbConfig) -> String {
+    auth::current_user()
+        .map(|user| user.username)
+        .unwrap_or_else(|| config.username.clone())
+}
+
+/// Writes an `audit_log` row as part of the transaction making the change.
+async fn record_audit<T: Serialize>(
+    transaction: &Transaction<'_>,
+    config: &DbConfig,
+    table_name: &str,
+    record_id: i32,
+    action: &str,
+    old_values: Option<&T>,
+    new_values: Option<&T>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let old_values = old_values.map(serde_json::to_value).transpose()?;
+    let new_values = new_values.map(serde_json::to_value).transpose()?;
+    transaction
+        .execute(
+            "INSERT INTO audit_log (table_name, record_id, action, old_values, new_values, changed_by)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &table_name,
+                &record_id,
+                &action,
+                &old_values,
+                &new_values,
+                &acting_user(config),
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+fn user_from_row(row: &Row) -> User {
+    let role: String = row.get("role");
+    User {
+        user_id: row.get("user_id"),
+        username: row.get("username"),
+        display_name: row.get("display_name"),
+        role: role.parse().unwrap_or(Role::ReadOnly),
+        active: row.get("active"),
+    }
+}
+
+fn invoice_from_row(row: &Row, items: Vec<LineItem>) -> Invoice {
+    Invoice {
+        invoice_id: row.get("invoice_id"),
+        customer_id: row.get("customer_id"),
+        invoice_number: row.get("invoice_number"),
+        invoice_date: row.get("invoice_date"),
+        due_date: row.get("due_date"),
+        net_amount: row.get("net_amount"),
+        tax_amount: row.get("tax_amount"),
+        total_amount: row.get("total_amount"),
+        currency: row.get("currency"),
+        buyer_reference: row.get("buyer_reference"),
+        status: row.get("status"),
+        payment_method: row.get("payment_method"),
+        notes: row.get("notes"),
+        items,
+        document_type: row.get("document_type"),
+        original_invoice_id: row.get("original_invoice_id"),
+        version: row.get("version"),
+    }
+}
+
+pub async fn create_database_structure(
+    config: &DbConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Creating database structure for: {}", config.database);
+    let conn_string = format!(
+        "host={} port={} user={} password={} dbname={}",
+        config.host, config.port, config.username, config.password, config.database
+    );
+
+    println!("Connecting to database...");
+    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+
+    println!("Connected successfully. Creating tables...");
+
+    let create_tables_query = "
+        -- Customers table
+        CREATE TABLE IF NOT EXISTS customers (
+            customer_id SERIAL PRIMARY KEY,
+            company_name VARCHAR(100) NOT NULL,
+            contact_name VARCHAR(100),
+            contact_position VARCHAR(50),
+            address TEXT,
+            city VARCHAR(50),
+            postal_code VARCHAR(20),
+            country VARCHAR(50),
+            phone VARCHAR(20),
+            email VARCHAR(100),
+            website VARCHAR(100),
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+
+        -- Products table
+        CREATE TABLE IF NOT EXISTS products (
+            product_id SERIAL PRIMARY KEY,
+            product_name VARCHAR(100) NOT NULL,
+            description TEXT,
+            unit_price DECIMAL(10, 2) NOT NULL,
+            stock_quantity INTEGER NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+
+        -- Invoices table
+        CREATE TABLE IF NOT EXISTS invoices (
+            invoice_id SERIAL PRIMARY KEY,
+            customer_id INTEGER REFERENCES customers(customer_id),
+            invoice_number VARCHAR(50) UNIQUE NOT NULL,
+            invoice_date DATE NOT NULL,
+            due_date DATE NOT NULL,
+            total_amount DECIMAL(10, 2) NOT NULL,
+            status VARCHAR(20) NOT NULL,
+            payment_method VARCHAR(50),
+            notes TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+
+               -- Invoice Items table
+        CREATE TABLE IF NOT EXISTS invoice_items (
+            item_id SERIAL PRIMARY KEY,
+            invoice_id INTEGER REFERENCES invoices(invoice_id),
+            product_id INTEGER REFERENCES products(product_id),
+            quantity INTEGER NOT NULL,
+            unit_price DECIMAL(10, 2) NOT NULL,
+            total_price DECIMAL(10, 2) NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+
+        -- Payments table
+CREATE TABLE IF NOT EXISTS payments (
+    payment_id SERIAL PRIMARY KEY,
+    invoice_id INTEGER REFERENCES invoices(invoice_id),
+    payment_date DATE NOT NULL,
+    amount DECIMAL(10, 2) NOT NULL,
+    payment_method VARCHAR(50) NOT NULL,
+    transaction_id VARCHAR(100),
+    notes TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Contact History table
+CREATE TABLE IF NOT EXISTS contact_history (
+    history_id SERIAL PRIMARY KEY,
+    customer_id INTEGER NOT NULL,
+    contact_type VARCHAR(50) NOT NULL,
+    contact_date TIMESTAMP WITH TIME ZONE NOT NULL,
+    contact_duration INTEGER,
+    contact_method VARCHAR(50),
+    contact_outcome VARCHAR(100) NOT NULL,
+    notes TEXT,
+    follow_up_date DATE,
+    created_by VARCHAR(100) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    FOREIGN KEY (customer_id) REFERENCES customers(customer_id)
+);
+
+-- Contacts table
+CREATE TABLE IF NOT EXISTS contacts (
+    contact_id SERIAL PRIMARY KEY,
+    customer_id INTEGER REFERENCES customers(customer_id),
+    first_name VARCHAR(50) NOT NULL,
+    last_name VARCHAR(50) NOT NULL,
+    email VARCHAR(100),
+    phone VARCHAR(20),
+    position VARCHAR(50),
+    is_primary BOOLEAN DEFAULT false,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+
+        -- Create indexes for better performance
+        CREATE INDEX IF NOT EXISTS idx_customers_company_name ON customers(company_name);
+        CREATE INDEX IF NOT EXISTS idx_products_product_name ON products(product_name);
+        CREATE INDEX IF NOT EXISTS idx_invoices_customer_id ON invoices(customer_id);
+        CREATE INDEX IF NOT EXISTS idx_invoice_items_invoice_id ON invoice_items(invoice_id);
+        CREATE INDEX IF NOT EXISTS idx_invoice_items_product_id ON invoice_items(product_id);
+        CREATE INDEX IF NOT EXISTS idx_payments_invoice_id ON payments(invoice_id);
+        CREATE INDEX IF NOT EXISTS idx_contacts_customer_id ON contacts(customer_id);
+    ";
+
+    println!("Executing query to create tables and set relationships...");
+    client.batch_execute(create_tables_query).await?;
+
+    println!("Adding version columns...");
+    client.batch_execute(CREATE_VERSION_COLUMNS_QUERY).await?;
+
+    println!("Creating audit log...");
+    client.batch_execute(CREATE_AUDIT_LOG_QUERY).await?;
+
+    println!("Creating users table...");
+    client.batch_execute(CREATE_USERS_QUERY).await?;
+
+    println!("Creating change notification triggers...");
+    client.batch_execute(CREATE_NOTIFY_TRIGGERS_QUERY).await?;
+
+    println!("Creating tags...");
+    client.batch_execute(CREATE_TAGS_QUERY).await?;
+
+    println!("Creating custom fields...");
+    client.batch_execute(CREATE_CUSTOM_FIELDS_QUERY).await?;
+
+    println!("Creating customer relations...");
+    client.batch_execute(CREATE_RELATIONS_QUERY).await?;
+
+    println!("Creating deals pipeline...");
+    client.batch_execute(CREATE_DEALS_QUERY).await?;
+
+    println!("Creating quotes...");
+    client.batch_execute(CREATE_QUOTES_QUERY).await?;
+
+    println!("Creating dunning levels...");
+    client.batch_execute(CREATE_DUNNING_QUERY).await?;
+
+    println!("Creating recurring invoices...");
+    client.batch_execute(CREATE_RECURRING_QUERY).await?;
+
+    println!("Creating tax rates...");
+    client.batch_execute(CREATE_TAX_QUERY).await?;
+    println!("Creating currencies...");
+    client.batch_execute(CREATE_CURRENCY_QUERY).await?;
+    println!("Creating company profile...");
+    client.batch_execute(CREATE_EINVOICE_QUERY).await?;
+    println!("Creating DATEV settings...");
+    client.batch_execute(CREATE_DATEV_QUERY).await?;
+    println!("Creating SEPA tables...");
+    client.batch_execute(CREATE_SEPA_QUERY).await?;
+    println!("Creating stock movements...");
+    client.batch_execute(CREATE_STOCK_QUERY).await?;
+    println!("Creating price lists...");
+    client.batch_execute(CREATE_PRICING_QUERY).await?;
+    println!("Creating credit notes...");
+    client.batch_execute(CREATE_CREDIT_NOTES_QUERY).await?;
+    println!("Creating number sequences...");
+    client.batch_execute(CREATE_NUMBERING_QUERY).await?;
+    println!("Creating customer notes...");
+    client.batch_execute(CREATE_CUSTOMER_NOTES_QUERY).await?;
+    println!("Creating tasks...");
+    client.batch_execute(CREATE_TASKS_QUERY).await?;
+    println!("Database structure created successfully");
+    Ok(())
+}
+
+pub async fn add_customer(
+    config: &DbConfig,
+    customer: &Customer,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let statement = "
+        INSERT INTO customers (company_name, contact_name, contact_position, address, city, postal_code, country, phone, email, website, currency, vat_id, buyer_reference, iban, bic, mandate_reference, mandate_date)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, COALESCE(NULLIF($11, ''), base_currency()), $12, $13, $14, $15, $16, $17)
+        RETURNING *
+    ";
+
+    let row = transaction
+        .query_one(
+            statement,
+            &[
+                &customer.company_name,
+                &customer.contact_name,
+                &customer.contact_position,
+                &customer.address,
+                &customer.city,
+                &customer.postal_code,
+                &customer.country,
+                &customer.phone,
+                &customer.email,
+                &customer.website,
+                &customer.currency,
+                &customer.vat_id,
+                &customer.buyer_reference,
+                &customer.iban,
+                &customer.bic,
+                &customer.mandate_reference,
+                &customer.mandate_date,
+            ],
+        )
+        .await?;
+    let added = customer_from_row(&row);
+    record_audit(&transaction, config, "customers", added.customer_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+
+    println!("Customer added successfully");
+    Ok(added.customer_id)
+}
+
+pub async fn get_customers(config: &DbConfig) -> Result<Vec<Customer>, Box<dyn std::error::Error>> {
+    let conn_string = format!(
+        "host={} port={} user={} password={} dbname={}",
+        config.host, config.port, config.username, config.password, config.database
+    );
+
+    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+
+    let rows = client
+        .query("SELECT * FROM customers ORDER BY company_name", &[])
+        .await?;
+
+    let customers: Vec<Customer> = rows.iter().map(customer_from_row).collect();
+
+    Ok(customers)
+}
+
+pub async fn get_contact_history(
+    config: &DbConfig,
+    customer_id: i32,
+) -> Result<Vec<ContactHistory>, Box<dyn std::error::Error>> {
+    let conn_string = format!(
+        "host={} port={} user={} password={} dbname={}",
+        config.host, config.port, config.username, config.password, config.database
+    );
+
+    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+
+    let rows = client
+        .query(
+            "SELECT * FROM contact_history WHERE customer_id = $1 ORDER BY contact_date DESC",
+            &[&customer_id],
+        )
+        .await?;
+
+    let history: Vec<ContactHistory> = rows.iter().map(contact_history_from_row).collect();
+
+    Ok(history)
+}
+
+pub async fn get_customer_with_history(
+    config: &DbConfig,
+    customer_id: i32,
+) -> Result<(Customer, Vec<ContactHistory>), Box<dyn std::error::Error>> {
+    let conn_string = format!(
+        "host={} port={} user={} password={} dbname={}",
+        config.host, config.port, config.username, config.password, config.database
+    );
+
+    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+
+    println!("Fetching customer data for customer_id: {}", customer_id);
+    let customer_row = client
+        .query_one(
+            "SELECT * FROM customers WHERE customer_id = $1",
+            &[&customer_id],
+        )
+        .await?;
+
+    let customer = customer_from_row(&customer_row);
+
+    println!("Fetched customer: {:?}", customer);
+
+    println!("Fetching contact history for customer_id: {}", customer_id);
+    let history_rows = client
+        .query(
+            "SELECT * FROM contact_history WHERE customer_id = $1 ORDER BY contact_date DESC",
+            &[&customer_id],
+        )
+        .await?;
+
+    let history: Vec<ContactHistory> = history_rows.iter().map(contact_history_from_row).collect();
+
+    println!("Fetched {} contact history entries", history.len());
+
+    Ok((customer, history))
+}
+
+
+pub async fn add_contact_history(config: &DbConfig, history: &ContactHistory) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let statement = "
+        INSERT INTO contact_history (customer_id, contact_type, contact_date, contact_duration, contact_method, contact_outcome, notes, follow_up_date, created_by, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING *
+    ";
+
+    let row = transaction.query_one(statement, &[
+        &history.customer_id,
+        &history.contact_type,
+        &history.contact_date,
+        &history.contact_duration,
+        &history.contact_method,
+        &history.contact_outcome,
+        &history.notes,
+        &history.follow_up_date,
+        &history.created_by,
+        &history.created_at,
+        &history.updated_at,
+    ]).await?;
+    let added = contact_history_from_row(&row);
+    record_audit(&transaction, config, "contact_history", added.history_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Creates the change notification triggers of the core tables unless they
+/// are all there. Recreating them takes an exclusive lock on each table, so
+/// this is only done when one is missing.
+async fn install_change_triggers(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    let row = client
+        .query_one(
+            "SELECT COUNT(*) FROM pg_trigger
+             WHERE tgname IN ('customers_notify', 'contact_history_notify', 'invoices_notify')",
+            &[],
+        )
+        .await?;
+    if row.get::<_, i64>(0) < 3 {
+        println!("Creating change notification triggers...");
+        client.batch_execute(CREATE_NOTIFY_TRIGGERS_QUERY).await?;
+    }
+    Ok(())
+}
+
+/// Subscribes to `CHANGE_CHANNEL` and calls `on_change` for every row change
+/// made by any client. `on_listening` is called once the subscription is in
+/// place. Returns once the connection to the server is lost.
+pub async fn listen_for_changes<L, F>(
+    config: &DbConfig,
+    on_listening: L,
+    mut on_change: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    L: FnOnce(),
+    F: FnMut(ChangeNotification),
+{
+    let conn_string = format!(
+        "host={} port={} user={} password={} dbname={}",
+        config.host, config.port, config.username, config.password, config.database
+    );
+
+    let (client, mut connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+
+    // Notifications arrive on the connection, not the client, so forward them
+    // from the connection task to this one.
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut messages = futures_util::stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    if sender.send(notification).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Connection error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    // Databases set up before change notifications existed lack the
+    // triggers; without them this client would never hear of any change.
+    if let Err(e) = install_change_triggers(&client).await {
+        eprintln!("Could not install change notification triggers: {}", e);
+    }
+    client
+        .batch_execute(&format!("LISTEN {}", CHANGE_CHANNEL))
+        .await?;
+    println!("Listening for changes on channel {}", CHANGE_CHANNEL);
+    on_listening();
+
+    while let Some(notification) = receiver.recv().await {
+        match serde_json::from_str::<ChangeNotification>(notification.payload()) {
+            Ok(change) => on_change(change),
+            Err(e) => eprintln!(
+                "Ignoring malformed change notification '{}': {}",
+                notification.payload(),
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Stores all editable fields of `customer` and bumps its version.
+async fn write_customer(
+    transaction: &Transaction<'_>,
+    customer: &Customer,
+) -> Result<Customer, Box<dyn std::error::Error>> {
+    let statement = "
+        UPDATE customers
+        SET company_name = $1, contact_name = $2, contact_position = $3, address = $4, city = $5,
+            postal_code = $6, country = $7, phone = $8, email = $9, website = $10,
+            currency = COALESCE(NULLIF($12, ''), currency), vat_id = $13, buyer_reference = $14,
+            iban = $15, bic = $16, mandate_reference = $17, mandate_date = $18,
+            version = version + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE customer_id = $11
+        RETURNING *
+    ";
+
+    let row = transaction
+        .query_one(
+            statement,
+            &[
+                &customer.company_name,
+                &customer.contact_name,
+                &customer.contact_position,
+                &customer.address,
+                &customer.city,
+                &customer.postal_code,
+                &customer.country,
+                &customer.phone,
+                &customer.email,
+                &customer.website,
+                &customer.customer_id,
+                &customer.currency,
+                &customer.vat_id,
+                &customer.buyer_reference,
+                &customer.iban,
+                &customer.bic,
+                &customer.mandate_reference,
+                &customer.mandate_date,
+            ],
+        )
+        .await?;
+    Ok(customer_from_row(&row))
+}
+
+/// Saves `customer` unless it was changed by someone else since it was loaded,
+/// i.e. unless its `version` no longer matches the stored one.
+pub async fn update_customer(
+    config: &DbConfig,
+    customer: &Customer,
+) -> Result<SaveResult<Customer>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let current = transaction
+        .query_opt(
+            "SELECT * FROM customers WHERE customer_id = $1 FOR UPDATE",
+            &[&customer.customer_id],
+        )
+        .await?
+        .as_ref()
+        .map(customer_from_row)
+        .ok_or_else(|| format!("Customer {} no longer exists", customer.customer_id))?;
+    if current.version != customer.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+
+    let saved = write_customer(&transaction, customer).await?;
+    record_audit(&transaction, config, "customers", saved.customer_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+
+    Ok(SaveResult::Saved(saved))
+}
+
+/// Merges the duplicate into `survivor`: stores the survivor's picked field
+/// values, moves contact history, contacts, notes, deals, quotes, recurring invoices and invoices
+/// (and with them their payments) over, combines tags, custom values and relations, and deletes
+/// the duplicate. All in one transaction; a conflict on the survivor aborts.
+pub async fn merge_customers(
+    config: &DbConfig,
+    survivor: &Customer,
+    duplicate_id: i32,
+) -> Result<SaveResult<Customer>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let survivor_id = survivor.customer_id;
+    if survivor_id == duplicate_id {
+        return Err("A customer cannot be merged with itself".into());
+    }
+
+    let current = transaction
+        .query_opt(
+            "SELECT * FROM customers WHERE customer_id = $1 FOR UPDATE",
+            &[&survivor_id],
+        )
+        .await?
+        .as_ref()
+        .map(customer_from_row)
+        .ok_or_else(|| format!("Customer {} no longer exists", survivor_id))?;
+    if current.version != survivor.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+    let duplicate = transaction
+        .query_opt(
+            "SELECT * FROM customers WHERE customer_id = $1 FOR UPDATE",
+            &[&duplicate_id],
+        )
+        .await?
+        .as_ref()
+        .map(customer_from_row)
+        .ok_or_else(|| format!("Customer {} no longer exists", duplicate_id))?;
+
+    let saved = write_customer(&transaction, survivor).await?;
+
+    for table in ["contact_history", "contacts", "customer_notes", "tasks"] {
+        transaction
+            .execute(
+                &format!("UPDATE {} SET customer_id = $1 WHERE customer_id = $2", table),
+                &[&survivor_id, &duplicate_id],
+            )
+            .await?;
+    }
+    let moved = transaction
+        .query(
+            "UPDATE invoices SET customer_id = $1, version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE customer_id = $2 RETURNING *",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    for row in &moved {
+        let invoice_id: i32 = row.get("invoice_id");
+        let invoice = invoice_from_row(row, line_items(&transaction, "invoice_items", "invoice_id", invoice_id).await?);
+        let before = Invoice {
+            customer_id: Some(duplicate_id),
+            version: invoice.version - 1,
+            ..invoice.clone()
+        };
+        record_audit(&transaction, config, "invoices", invoice.invoice_id, "UPDATE", Some(&before), Some(&invoice)).await?;
+    }
+
+    let moved = transaction
+        .query(
+            "UPDATE deals SET customer_id = $1, version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE customer_id = $2 RETURNING *",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    for row in &moved {
+        let deal = deal_from_row(row);
+        let before = Deal {
+            customer_id: duplicate_id,
+            version: deal.version - 1,
+            ..deal.clone()
+        };
+        record_audit(&transaction, config, "deals", deal.deal_id, "UPDATE", Some(&before), Some(&deal)).await?;
+    }
+
+    let moved = transaction
+        .query(
+            "UPDATE quotes SET customer_id = $1, version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE customer_id = $2 RETURNING *",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    for row in &moved {
+        let items = line_items(&transaction, "quote_items", "quote_id", row.get("quote_id")).await?;
+        let quote = quote_from_row(row, items);
+        let before = Quote {
+            customer_id: Some(duplicate_id),
+            version: quote.version - 1,
+            ..quote.clone()
+        };
+        record_audit(&transaction, config, "quotes", quote.quote_id, "UPDATE", Some(&before), Some(&quote)).await?;
+    }
+
+    let moved = transaction
+        .query(
+            "UPDATE recurring_invoices SET customer_id = $1, version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE customer_id = $2 RETURNING *",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    for row in &moved {
+        let recurring_id: i32 = row.get("recurring_id");
+        let items = line_items(&transaction, "recurring_invoice_items", "recurring_id", recurring_id).await?;
+        let recurring = recurring_from_row(row, items);
+        let before = RecurringInvoice {
+            customer_id: duplicate_id,
+            version: recurring.version - 1,
+            ..recurring.clone()
+        };
+        record_audit(&transaction, config, "recurring_invoices", recurring_id, "UPDATE", Some(&before), Some(&recurring)).await?;
+    }
+
+    // Collections stay with the invoices they settled, so they are not
+    // offered for collection again.
+    let moved = transaction
+        .query(
+            "UPDATE direct_debits SET customer_id = $1 WHERE customer_id = $2
+             RETURNING direct_debit_id, invoice_id, mandate_reference",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    for row in &moved {
+        let debit = |customer_id: i32| {
+            serde_json::json!({
+                "invoice_id": row.get::<_, i32>("invoice_id"),
+                "customer_id": customer_id,
+                "mandate_reference": row.get::<_, String>("mandate_reference"),
+            })
+        };
+        let (before, after) = (debit(duplicate_id), debit(survivor_id));
+        record_audit(&transaction, config, "direct_debits", row.get("direct_debit_id"), "UPDATE", Some(&before), Some(&after)).await?;
+    }
+
+    // Both customers' own lists together would quietly give the survivor
+    // the lower of two prices, so that has to be resolved by hand first.
+    let lists = transaction
+        .query(
+            "SELECT customer_id, name FROM price_lists WHERE customer_id IN ($1, $2) ORDER BY name FOR UPDATE",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    let names = |customer_id: i32| {
+        lists
+            .iter()
+            .filter(|row| row.get::<_, i32>("customer_id") == customer_id)
+            .map(|row| row.get::<_, String>("name"))
+            .collect::<Vec<_>>()
+    };
+    let (survivor_lists, duplicate_lists) = (names(survivor_id), names(duplicate_id));
+    if !survivor_lists.is_empty() && !duplicate_lists.is_empty() {
+        return Err(format!(
+            "Both customers have their own price lists ({} and {}); combine or remove them before merging",
+            survivor_lists.join(", "),
+            duplicate_lists.join(", ")
+        )
+        .into());
+    }
+    let moved = transaction
+        .query(
+            "UPDATE price_lists SET customer_id = $1 WHERE customer_id = $2 RETURNING *",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    for row in &moved {
+        let price_list_id: i32 = row.get("price_list_id");
+        let list = price_list_from_row(row, price_list_entries(&transaction, price_list_id).await?);
+        let before = PriceList {
+            customer_id: Some(duplicate_id),
+            ..list.clone()
+        };
+        record_audit(&transaction, config, "price_lists", price_list_id, "UPDATE", Some(&before), Some(&list)).await?;
+    }
+
+    // Rows the survivor already has win; the duplicate's rest goes with it.
+    let tags_before = customer_tag_names(&transaction, survivor_id).await?;
+    transaction
+        .execute(
+            "INSERT INTO customer_tags (customer_id, tag_id)
+             SELECT $1, tag_id FROM customer_tags WHERE customer_id = $2
+             ON CONFLICT DO NOTHING",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    let tags_after = customer_tag_names(&transaction, survivor_id).await?;
+    let values_before = custom_values_by_name(&transaction, survivor_id).await?;
+    transaction
+        .execute(
+            "INSERT INTO customer_custom_values (customer_id, field_id, value)
+             SELECT $1, field_id, value FROM customer_custom_values WHERE customer_id = $2
+             ON CONFLICT DO NOTHING",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    let values_after = custom_values_by_name(&transaction, survivor_id).await?;
+    transaction
+        .execute(
+            "UPDATE customer_relations r SET from_customer_id = $1
+             WHERE from_customer_id = $2 AND to_customer_id <> $1
+               AND NOT EXISTS (SELECT 1 FROM customer_relations x
+                               WHERE x.from_customer_id = $1 AND x.to_customer_id = r.to_customer_id
+                                 AND x.relation_type = r.relation_type)",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+    transaction
+        .execute(
+            "UPDATE customer_relations r SET to_customer_id = $1
+             WHERE to_customer_id = $2 AND from_customer_id <> $1
+               AND NOT EXISTS (SELECT 1 FROM customer_relations x
+                               WHERE x.to_customer_id = $1 AND x.from_customer_id = r.from_customer_id
+                                 AND x.relation_type = r.relation_type)",
+            &[&survivor_id, &duplicate_id],
+        )
+        .await?;
+
+    transaction
+        .execute("DELETE FROM customers WHERE customer_id = $1", &[&duplicate_id])
+        .await?;
+
+    record_audit(&transaction, config, "customers", survivor_id, "MERGE", Some(&current), Some(&saved)).await?;
+    if tags_before != tags_after {
+        record_audit(&transaction, config, "customers", survivor_id, "UPDATE", Some(&tags_before), Some(&tags_after)).await?;
+    }
+    if values_before != values_after {
+        record_audit(&transaction, config, "customers", survivor_id, "UPDATE", Some(&values_before), Some(&values_after)).await?;
+    }
+    record_audit(&transaction, config, "customers", duplicate_id, "DELETE", Some(&duplicate), None).await?;
+    transaction.commit().await?;
+
+    Ok(SaveResult::Saved(saved))
+}
+
+pub async fn get_invoices(config: &DbConfig) -> Result<Vec<Invoice>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let item_rows = client
+        .query("SELECT * FROM invoice_items ORDER BY item_id", &[])
+        .await?;
+    let mut items: HashMap<i32, Vec<LineItem>> = HashMap::new();
+    for row in &item_rows {
+        items.entry(row.get("invoice_id")).or_default().push(line_item_from_row(row));
+    }
+    let rows = client
+        .query(
+            "SELECT * FROM invoices ORDER BY invoice_date DESC, invoice_number DESC",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let invoice_id: i32 = row.get("invoice_id");
+            invoice_from_row(row, items.remove(&invoice_id).unwrap_or_default())
+        })
+        .collect())
+}
+
+/// Net, tax and gross of `invoice`: from its items, or the entered total
+/// taken as net if it has none.
+fn invoice_amounts(invoice: &Invoice, items: &[LineItem]) -> (Decimal, Decimal, Decimal) {
+    if items.is_empty() {
+        return (invoice.total_amount, Decimal::ZERO, invoice.total_amount);
+    }
+    let totals = tax::totals(items);
+    (totals.net, totals.tax, totals.gross)
+}
+
+pub async fn add_invoice(
+    config: &DbConfig,
+    invoice: &Invoice,
+) -> Result<Invoice, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let (net, tax, gross) = invoice_amounts(invoice, &invoice.items);
+    if invoice.is_credit_note() {
+        check_credit_note(&transaction, invoice, gross).await?;
+    }
+    let invoice_number = if invoice.invoice_number.trim().is_empty() {
+        next_document_number(&transaction, &invoice.document_type, invoice.invoice_date).await?
+    } else {
+        invoice.invoice_number.clone()
+    };
+    let statement = "
+        INSERT INTO invoices (customer_id, invoice_number, invoice_date, due_date, net_amount, tax_amount, total_amount, status, payment_method, notes, currency, buyer_reference, document_type, original_invoice_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, COALESCE(NULLIF($11, ''), base_currency()), $12, $13, $14)
+        RETURNING *
+    ";
+
+    let row = transaction
+        .query_one(
+            statement,
+            &[
+                &invoice.customer_id,
+                &invoice_number,
+                &invoice.invoice_date,
+                &invoice.due_date,
+                &net,
+                &tax,
+                &gross,
+                &invoice.status,
+                &invoice.payment_method,
+                &invoice.notes,
+                &invoice.currency,
+                &invoice.buyer_reference,
+                &invoice.document_type,
+                &invoice.original_invoice_id,
+            ],
+        )
+        .await?;
+    let invoice_id: i32 = row.get("invoice_id");
+    let items = replace_line_items(&transaction, "invoice_items", "invoice_id", invoice_id, &invoice.items).await?;
+    let added = invoice_from_row(&row, items);
+    sync_invoice_stock(&transaction, config, &added).await?;
+    settle_credited_invoice(&transaction, config, &added).await?;
+    record_audit(&transaction, config, "invoices", added.invoice_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+
+    println!("{} {} added successfully", added.document_label(), added.invoice_number);
+    Ok(added)
+}
+
+/// Saves `invoice` unless it was changed by someone else since it was loaded.
+pub async fn update_invoice(
+    config: &DbConfig,
+    invoice: &Invoice,
+) -> Result<SaveResult<Invoice>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "SELECT * FROM invoices WHERE invoice_id = $1 FOR UPDATE",
+            &[&invoice.invoice_id],
+        )
+        .await?
+        .ok_or_else(|| format!("Invoice {} no longer exists", invoice.invoice_id))?;
+    let items = line_items(&transaction, "invoice_items", "invoice_id", invoice.invoice_id).await?;
+    let current = invoice_from_row(&row, items);
+    if current.version != invoice.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+    // Numbers given by the sequence must stay as they are, or the series
+    // would get gaps.
+    if invoice.invoice_number != current.invoice_number {
+        return Err(format!(
+            "{} {} has been numbered and its number cannot be changed",
+            current.document_label(),
+            current.invoice_number
+        )
+        .into());
+    }
+    if current.is_issued() && (!current.allows_status(&invoice.status) || !current.same_content(invoice)) {
+        return Err(format!(
+            "{} {} has been issued and can only be corrected with a credit note",
+            current.document_label(),
+            current.invoice_number
+        )
+        .into());
+    }
+
+    let items = replace_line_items(&transaction, "invoice_items", "invoice_id", invoice.invoice_id, &invoice.items).await?;
+    let (net, tax, gross) = invoice_amounts(invoice, &items);
+    if current.is_credit_note() {
+        check_credit_note(&transaction, invoice, gross).await?;
+    }
+    let statement = "
+        UPDATE invoices
+        SET customer_id = $1, invoice_number = $2, invoice_date = $3, due_date = $4,
+            net_amount = $5, tax_amount = $6, total_amount = $7, status = $8,
+            payment_method = $9, notes = $10, currency = COALESCE(NULLIF($12, ''), currency),
+            buyer_reference = $13,
+            version = version + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE invoice_id = $11
+        RETURNING *
+    ";
+
+    let row = transaction
+        .query_one(
+            statement,
+            &[
+                &invoice.customer_id,
+                &invoice.invoice_number,
+                &invoice.invoice_date,
+                &invoice.due_date,
+                &net,
+                &tax,
+                &gross,
+                &invoice.status,
+                &invoice.payment_method,
+                &invoice.notes,
+                &invoice.invoice_id,
+                &invoice.currency,
+                &invoice.buyer_reference,
+            ],
+        )
+        .await?;
+    let saved = invoice_from_row(&row, items);
+    sync_invoice_stock(&transaction, config, &saved).await?;
+    settle_credited_invoice(&transaction, config, &saved).await?;
+    record_audit(&transaction, config, "invoices", saved.invoice_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+
+    Ok(SaveResult::Saved(saved))
+}
+
+pub async fn get_audit_log(
+    config: &DbConfig,
+    table_name: &str,
+    record_id: i32,
+) -> Result<Vec<AuditEntry>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT * FROM audit_log WHERE table_name = $1 AND record_id = $2 ORDER BY changed_at DESC, audit_id DESC",
+            &[&table_name, &record_id],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| AuditEntry {
+            audit_id: row.get("audit_id"),
+            action: row.get("action"),
+            old_values: row.get("old_values"),
+            new_values: row.get("new_values"),
+            changed_by: row.get("changed_by"),
+            changed_at: row.get("changed_at"),
+        })
+        .collect())
+}
+
+pub async fn count_users(config: &DbConfig) -> Result<i64, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let row = client.query_one("SELECT COUNT(*) FROM users", &[]).await?;
+    Ok(row.get(0))
+}
+
+/// Looks up an active user for login, returning it with its password hash.
+pub async fn get_login_user(
+    config: &DbConfig,
+    username: &str,
+) -> Result<Option<(User, String)>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let row = client
+        .query_opt(
+            "SELECT * FROM users WHERE username = $1 AND active",
+            &[&username],
+        )
+        .await?;
+    Ok(row.map(|row| (user_from_row(&row), row.get("password_hash"))))
+}
+
+pub async fn get_users(config: &DbConfig) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM users ORDER BY username", &[])
+        .await?;
+    Ok(rows.iter().map(user_from_row).collect())
+}
+
+pub async fn add_user(
+    config: &DbConfig,
+    user: &User,
+    password_hash: &str,
+) -> Result<User, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO users (username, display_name, password_hash, role, active)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING *",
+            &[
+                &user.username,
+                &user.display_name,
+                &password_hash,
+                &user.role.as_str(),
+                &user.active,
+            ],
+        )
+        .await?;
+    let added = user_from_row(&row);
+    record_audit(&transaction, config, "users", added.user_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+
+    Ok(added)
+}
+
+/// Creates the first user of a new installation as an administrator. Fails
+/// once any user exists, so the login screen cannot be used to add further
+/// administrators without logging in.
+pub async fn add_first_admin(
+    config: &DbConfig,
+    user: &User,
+    password_hash: &str,
+) -> Result<User, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    // Two clients creating the first user at once must not both see an
+    // empty table.
+    transaction
+        .batch_execute("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .await?;
+    let row = transaction
+        .query_opt(
+            "INSERT INTO users (username, display_name, password_hash, role, active)
+             SELECT $1, $2, $3, $4, true
+             WHERE NOT EXISTS (SELECT 1 FROM users)
+             RETURNING *",
+            &[&user.username, &user.display_name, &password_hash, &Role::Admin.as_str()],
+        )
+        .await?
+        .ok_or("Users have already been created; please log in")?;
+    let added = user_from_row(&row);
+    record_audit(&transaction, config, "users", added.user_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+
+    Ok(added)
+}
+
+/// Updates name, role and active flag; a new password hash is only stored
+/// when one is given.
+pub async fn update_user(
+    config: &DbConfig,
+    user: &User,
+    password_hash: Option<&str>,
+) -> Result<User, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let current = transaction
+        .query_opt(
+            "SELECT * FROM users WHERE user_id = $1 FOR UPDATE",
+            &[&user.user_id],
+        )
+        .await?
+        .as_ref()
+        .map(user_from_row)
+        .ok_or_else(|| format!("User {} no longer exists", user.username))?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE users
+             SET display_name = $1, role = $2, active = $3,
+                 password_hash = COALESCE($4, password_hash), updated_at = CURRENT_TIMESTAMP
+             WHERE user_id = $5
+             RETURNING *",
+            &[
+                &user.display_name,
+                &user.role.as_str(),
+                &user.active,
+                &password_hash,
+                &user.user_id,
+            ],
+        )
+        .await?;
+    let saved = user_from_row(&row);
+    record_audit(&transaction, config, "users", saved.user_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+
+    Ok(saved)
+}
+
+fn tag_from_row(row: &Row) -> Tag {
+    Tag {
+        tag_id: row.get("tag_id"),
+        name: row.get("name"),
+        color: row.get("color"),
+    }
+}
+
+pub async fn get_tags(config: &DbConfig) -> Result<Vec<Tag>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client.query("SELECT * FROM tags ORDER BY name", &[]).await?;
+    Ok(rows.iter().map(tag_from_row).collect())
+}
+
+/// All (customer_id, tag_id) pairs.
+pub async fn get_customer_tag_links(
+    config: &DbConfig,
+) -> Result<Vec<(i32, i32)>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT customer_id, tag_id FROM customer_tags", &[])
+        .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+pub async fn add_tag(config: &DbConfig, name: &str, color: &str) -> Result<Tag, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO tags (name, color) VALUES ($1, $2) RETURNING *",
+            &[&name, &color],
+        )
+        .await?;
+    let tag = tag_from_row(&row);
+    record_audit(&transaction, config, "tags", tag.tag_id, "INSERT", None, Some(&tag)).await?;
+    transaction.commit().await?;
+    Ok(tag)
+}
+
+pub async fn delete_tag(config: &DbConfig, tag_id: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_opt("DELETE FROM tags WHERE tag_id = $1 RETURNING *", &[&tag_id])
+        .await?;
+    if let Some(row) = row {
+        let tag = tag_from_row(&row);
+        record_audit(&transaction, config, "tags", tag_id, "DELETE", Some(&tag), None).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn customer_tag_names(
+    transaction: &Transaction<'_>,
+    customer_id: i32,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let rows = transaction
+        .query(
+            "SELECT t.name FROM customer_tags ct JOIN tags t ON t.tag_id = ct.tag_id
+             WHERE ct.customer_id = $1 ORDER BY t.name",
+            &[&customer_id],
+        )
+        .await?;
+    let names: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+    Ok(serde_json::json!({ "tags": names.join(", ") }))
+}
+
+/// Adds (`assign`) or removes the tag for all given customers in one
+/// transaction. Each affected customer gets an audit entry with its tag list
+/// before and after.
+pub async fn set_customer_tag(
+    config: &DbConfig,
+    customer_ids: &[i32],
+    tag_id: i32,
+    assign: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    for customer_id in customer_ids {
+        let before = customer_tag_names(&transaction, *customer_id).await?;
+        let changed = if assign {
+            transaction
+                .execute(
+                    "INSERT INTO customer_tags (customer_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                    &[customer_id, &tag_id],
+                )
+                .await?
+        } else {
+            transaction
+                .execute(
+                    "DELETE FROM customer_tags WHERE customer_id = $1 AND tag_id = $2",
+                    &[customer_id, &tag_id],
+                )
+                .await?
+        };
+        if changed > 0 {
+            let after = customer_tag_names(&transaction, *customer_id).await?;
+            record_audit(&transaction, config, "customers", *customer_id, "UPDATE", Some(&before), Some(&after)).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn custom_field_from_row(row: &Row) -> CustomField {
+    let field_type: String = row.get("field_type");
+    CustomField {
+        field_id: row.get("field_id"),
+        name: row.get("name"),
+        field_type: field_type.parse().unwrap_or(FieldType::Text),
+        choices: row.get("choices"),
+        sort_order: row.get("sort_order"),
+        active: row.get("active"),
+    }
+}
+
+pub async fn get_custom_fields(config: &DbConfig) -> Result<Vec<CustomField>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM custom_fields ORDER BY sort_order, name", &[])
+        .await?;
+    Ok(rows.iter().map(custom_field_from_row).collect())
+}
+
+/// Adds `field` if it has no id yet, otherwise updates its definition.
+pub async fn save_custom_field(
+    config: &DbConfig,
+    field: &CustomField,
+) -> Result<CustomField, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let params: [&(dyn tokio_postgres::types::ToSql + Sync); 5] = [
+        &field.name,
+        &field.field_type.as_str(),
+        &field.choices,
+        &field.sort_order,
+        &field.active,
+    ];
+    let saved = if field.field_id == 0 {
+        let row = transaction
+            .query_one(
+                "INSERT INTO custom_fields (name, field_type, choices, sort_order, active)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING *",
+                &params,
+            )
+            .await?;
+        let saved = custom_field_from_row(&row);
+        record_audit(&transaction, config, "custom_fields", saved.field_id, "INSERT", None, Some(&saved)).await?;
+        saved
+    } else {
+        let current = transaction
+            .query_opt("SELECT * FROM custom_fields WHERE field_id = $1 FOR UPDATE", &[&field.field_id])
+            .await?
+            .as_ref()
+            .map(custom_field_from_row)
+            .ok_or_else(|| format!("Custom field {} no longer exists", field.name))?;
+        let row = transaction
+            .query_one(
+                "UPDATE custom_fields
+                 SET name = $1, field_type = $2, choices = $3, sort_order = $4, active = $5
+                 WHERE field_id = $6
+                 RETURNING *",
+                &[params[0], params[1], params[2], params[3], params[4], &field.field_id],
+            )
+            .await?;
+        let saved = custom_field_from_row(&row);
+        record_audit(&transaction, config, "custom_fields", saved.field_id, "UPDATE", Some(&current), Some(&saved)).await?;
+        saved
+    };
+    transaction.commit().await?;
+    Ok(saved)
+}
+
+/// All stored custom values as (customer_id, field_id, value).
+pub async fn get_custom_values(
+    config: &DbConfig,
+) -> Result<Vec<(i32, i32, String)>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT customer_id, field_id, value FROM customer_custom_values", &[])
+        .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+}
+
+async fn custom_values_by_name(
+    transaction: &Transaction<'_>,
+    customer_id: i32,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let rows = transaction
+        .query(
+            "SELECT f.name, v.value FROM customer_custom_values v
+             JOIN custom_fields f ON f.field_id = v.field_id
+             WHERE v.customer_id = $1",
+            &[&customer_id],
+        )
+        .await?;
+    let values: serde_json::Map<String, serde_json::Value> = rows
+        .iter()
+        .map(|row| (row.get::<_, String>(0), serde_json::Value::String(row.get(1))))
+        .collect();
+    Ok(serde_json::Value::Object(values))
+}
+
+/// Replaces the custom values of a customer by field id. Empty values are
+/// removed rather than stored.
+pub async fn set_custom_values(
+    config: &DbConfig,
+    customer_id: i32,
+    values: &HashMap<i32, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let before = custom_values_by_name(&transaction, customer_id).await?;
+
+    for (field_id, value) in values {
+        if value.trim().is_empty() {
+            transaction
+                .execute(
+                    "DELETE FROM customer_custom_values WHERE customer_id = $1 AND field_id = $2",
+                    &[&customer_id, field_id],
+                )
+                .await?;
+        } else {
+            transaction
+                .execute(
+                    "INSERT INTO customer_custom_values (customer_id, field_id, value) VALUES ($1, $2, $3)
+                     ON CONFLICT (customer_id, field_id) DO UPDATE SET value = EXCLUDED.value",
+                    &[&customer_id, field_id, &value.trim()],
+                )
+                .await?;
+        }
+    }
+
+    let after = custom_values_by_name(&transaction, customer_id).await?;
+    if before != after {
+        record_audit(&transaction, config, "customers", customer_id, "UPDATE", Some(&before), Some(&after)).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn relation_from_row(row: &Row) -> Relation {
+    let relation_type: String = row.get("relation_type");
+    Relation {
+        relation_id: row.get("relation_id"),
+        from_customer_id: row.get("from_customer_id"),
+        to_customer_id: row.get("to_customer_id"),
+        relation_type: relation_type.parse().unwrap_or(RelationType::Partner),
+    }
+}
+
+pub async fn get_relations(config: &DbConfig) -> Result<Vec<Relation>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM customer_relations ORDER BY relation_id", &[])
+        .await?;
+    Ok(rows.iter().map(relation_from_row).collect())
+}
+
+pub async fn add_relation(
+    config: &DbConfig,
+    from_customer_id: i32,
+    to_customer_id: i32,
+    relation_type: RelationType,
+) -> Result<Relation, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO customer_relations (from_customer_id, to_customer_id, relation_type)
+             VALUES ($1, $2, $3) RETURNING *",
+            &[&from_customer_id, &to_customer_id, &relation_type.as_str()],
+        )
+        .await?;
+    let relation = relation_from_row(&row);
+    record_audit(&transaction, config, "customer_relations", relation.relation_id, "INSERT", None, Some(&relation)).await?;
+    transaction.commit().await?;
+    Ok(relation)
+}
+
+pub async fn delete_relation(config: &DbConfig, relation_id: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_opt(
+            "DELETE FROM customer_relations WHERE relation_id = $1 RETURNING *",
+            &[&relation_id],
+        )
+        .await?;
+    if let Some(row) = row {
+        let relation = relation_from_row(&row);
+        record_audit(&transaction, config, "customer_relations", relation_id, "DELETE", Some(&relation), None).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn deal_stage_from_row(row: &Row) -> DealStage {
+    DealStage {
+        stage_id: row.get("stage_id"),
+        name: row.get("name"),
+        sort_order: row.get("sort_order"),
+        default_probability: row.get("default_probability"),
+        active: row.get("active"),
+    }
+}
+
+fn deal_from_row(row: &Row) -> Deal {
+    Deal {
+        deal_id: row.get("deal_id"),
+        customer_id: row.get("customer_id"),
+        title: row.get("title"),
+        value: row.get("value"),
+        expected_close_date: row.get("expected_close_date"),
+        probability: row.get("probability"),
+        stage_id: row.get("stage_id"),
+        notes: row.get("notes"),
+        version: row.get("version"),
+    }
+}
+
+pub async fn get_deal_stages(config: &DbConfig) -> Result<Vec<DealStage>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM deal_stages ORDER BY sort_order, name", &[])
+        .await?;
+    Ok(rows.iter().map(deal_stage_from_row).collect())
+}
+
+/// Adds the stage if it has no id yet, otherwise updates it.
+pub async fn save_deal_stage(
+    config: &DbConfig,
+    stage: &DealStage,
+) -> Result<DealStage, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let saved = if stage.stage_id == 0 {
+        let row = transaction
+            .query_one(
+                "INSERT INTO deal_stages (name, sort_order, default_probability, active)
+                 VALUES ($1, $2, $3, $4) RETURNING *",
+                &[&stage.name, &stage.sort_order, &stage.default_probability, &stage.active],
+            )
+            .await?;
+        let saved = deal_stage_from_row(&row);
+        record_audit(&transaction, config, "deal_stages", saved.stage_id, "INSERT", None, Some(&saved)).await?;
+        saved
+    } else {
+        let current = transaction
+            .query_opt("SELECT * FROM deal_stages WHERE stage_id = $1 FOR UPDATE", &[&stage.stage_id])
+            .await?
+            .as_ref()
+            .map(deal_stage_from_row)
+            .ok_or_else(|| format!("Stage {} no longer exists", stage.stage_id))?;
+        let row = transaction
+            .query_one(
+                "UPDATE deal_stages SET name = $1, sort_order = $2, default_probability = $3, active = $4
+                 WHERE stage_id = $5 RETURNING *",
+                &[&stage.name, &stage.sort_order, &stage.default_probability, &stage.active, &stage.stage_id],
+            )
+            .await?;
+        let saved = deal_stage_from_row(&row);
+        record_audit(&transaction, config, "deal_stages", saved.stage_id, "UPDATE", Some(&current), Some(&saved)).await?;
+        saved
+    };
+    transaction.commit().await?;
+    Ok(saved)
+}
+
+pub async fn get_deals(config: &DbConfig) -> Result<Vec<Deal>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM deals ORDER BY expected_close_date, deal_id", &[])
+        .await?;
+    Ok(rows.iter().map(deal_from_row).collect())
+}
+
+pub async fn add_deal(config: &DbConfig, deal: &Deal) -> Result<Deal, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO deals (customer_id, title, value, expected_close_date, probability, stage_id, notes)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+            &[
+                &deal.customer_id,
+                &deal.title,
+                &deal.value,
+                &deal.expected_close_date,
+                &deal.probability,
+                &deal.stage_id,
+                &deal.notes,
+            ],
+        )
+        .await?;
+    let added = deal_from_row(&row);
+    record_audit(&transaction, config, "deals", added.deal_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
+
+/// Saves `deal` unless it was changed by someone else since it was loaded.
+pub async fn update_deal(
+    config: &DbConfig,
+    deal: &Deal,
+) -> Result<SaveResult<Deal>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let current = transaction
+        .query_opt("SELECT * FROM deals WHERE deal_id = $1 FOR UPDATE", &[&deal.deal_id])
+        .await?
+        .as_ref()
+        .map(deal_from_row)
+        .ok_or_else(|| format!("Deal {} no longer exists", deal.deal_id))?;
+    if current.version != deal.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+
+    let row = transaction
+        .query_one(
+            "UPDATE deals
+             SET customer_id = $1, title = $2, value = $3, expected_close_date = $4,
+                 probability = $5, stage_id = $6, notes = $7,
+                 version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE deal_id = $8
+             RETURNING *",
+            &[
+                &deal.customer_id,
+                &deal.title,
+                &deal.value,
+                &deal.expected_close_date,
+                &deal.probability,
+                &deal.stage_id,
+                &deal.notes,
+                &deal.deal_id,
+            ],
+        )
+        .await?;
+    let saved = deal_from_row(&row);
+    record_audit(&transaction, config, "deals", saved.deal_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(SaveResult::Saved(saved))
+}
+
+pub async fn delete_deal(config: &DbConfig, deal_id: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_opt("DELETE FROM deals WHERE deal_id = $1 RETURNING *", &[&deal_id])
+        .await?;
+    if let Some(row) = row {
+        let deal = deal_from_row(&row);
+        record_audit(&transaction, config, "deals", deal_id, "DELETE", Some(&deal), None).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn get_products(config: &DbConfig) -> Result<Vec<Product>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT product_id, product_name, description, unit_price, tax_rate_id, stock_quantity, reorder_level
+             FROM products ORDER BY product_name",
+            &[],
+        )
+        .await?;
+    Ok(rows.iter().map(product_from_row).collect())
+}
+
+fn product_from_row(row: &Row) -> Product {
+    Product {
+        product_id: row.get("product_id"),
+        product_name: row.get("product_name"),
+        description: row.get("description"),
+        unit_price: row.get("unit_price"),
+        tax_rate_id: row.get("tax_rate_id"),
+        stock_quantity: row.get("stock_quantity"),
+        reorder_level: row.get("reorder_level"),
+    }
+}
+
+fn stock_movement_from_row(row: &Row) -> StockMovement {
+    StockMovement {
+        movement_id: row.get("movement_id"),
+        product_id: row.get("product_id"),
+        movement_type: row.get("movement_type"),
+        quantity: row.get("quantity"),
+        invoice_id: row.get("invoice_id"),
+        invoice_number: row.get("invoice_number"),
+        notes: row.get("notes"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Movements of a product, latest first.
+pub async fn get_stock_movements(
+    config: &DbConfig,
+    product_id: i32,
+) -> Result<Vec<StockMovement>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT m.*, i.invoice_number FROM stock_movements m
+             LEFT JOIN invoices i ON i.invoice_id = m.invoice_id
+             WHERE m.product_id = $1
+             ORDER BY m.created_at DESC, m.movement_id DESC",
+            &[&product_id],
+        )
+        .await?;
+    Ok(rows.iter().map(stock_movement_from_row).collect())
+}
+
+/// Records `movement` and applies it to the product's stock.
+async fn insert_stock_movement(
+    transaction: &Transaction<'_>,
+    config: &DbConfig,
+    movement: &StockMovement,
+) -> Result<StockMovement, Box<dyn std::error::Error>> {
+    let row = transaction
+        .query_one(
+            "WITH inserted AS (
+                 INSERT INTO stock_movements (product_id, movement_type, quantity, invoice_id, notes, created_by)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 RETURNING *
+             )
+             SELECT inserted.*, i.invoice_number FROM inserted
+             LEFT JOIN invoices i ON i.invoice_id = inserted.invoice_id",
+            &[
+                &movement.product_id,
+                &movement.movement_type,
+                &movement.quantity,
+                &movement.invoice_id,
+                &movement.notes,
+                &acting_user(config),
+            ],
+        )
+        .await?;
+    let inserted = stock_movement_from_row(&row);
+    transaction
+        .execute(
+            "UPDATE products SET stock_quantity = stock_quantity + $1, updated_at = CURRENT_TIMESTAMP
+             WHERE product_id = $2",
+            &[&inserted.quantity, &inserted.product_id],
+        )
+        .await?;
+    record_audit(transaction, config, "stock_movements", inserted.movement_id, "INSERT", None, Some(&inserted)).await?;
+    Ok(inserted)
+}
+
+pub async fn add_stock_movement(
+    config: &DbConfig,
+    movement: &StockMovement,
+) -> Result<StockMovement, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let added = insert_stock_movement(&transaction, config, movement).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
+
+/// Books the goods of an invoice out of stock once it is sent, and back in
+/// when it returns to draft or is cancelled. Items changed on a booked
+/// invoice are booked by their difference; the negative lines of a credit
+/// note book its goods back in.
+async fn sync_invoice_stock(
+    transaction: &Transaction<'_>,
+    config: &DbConfig,
+    invoice: &Invoice,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rows = transaction
+        .query(
+            "WITH wanted AS (
+                 SELECT product_id, -SUM(quantity) AS quantity FROM invoice_items
+                 WHERE invoice_id = $1 AND product_id IS NOT NULL AND $2 IN ('sent', 'paid', 'credited')
+                 GROUP BY product_id
+             ), booked AS (
+                 SELECT product_id, SUM(quantity) AS quantity FROM stock_movements
+                 WHERE invoice_id = $1
+                 GROUP BY product_id
+             )
+             SELECT COALESCE(w.product_id, b.product_id) AS product_id,
+                 (COALESCE(w.quantity, 0) - COALESCE(b.quantity, 0))::INTEGER AS difference
+             FROM wanted w FULL JOIN booked b ON b.product_id = w.product_id
+             WHERE COALESCE(w.quantity, 0) <> COALESCE(b.quantity, 0)
+             ORDER BY 1",
+            &[&invoice.invoice_id, &invoice.status],
+        )
+        .await?;
+    for row in rows {
+        let quantity: i32 = row.get("difference");
+        let (movement_type, notes) = if quantity < 0 {
+            ("sale", format!("{} {}", invoice.document_label(), invoice.invoice_number))
+        } else if invoice.is_credit_note() {
+            ("return", format!("{} {}", invoice.document_label(), invoice.invoice_number))
+        } else {
+            ("return", format!("Invoice {} {}", invoice.invoice_number, invoice.status))
+        };
+        let movement = StockMovement {
+            product_id: row.get("product_id"),
+            movement_type: movement_type.to_string(),
+            quantity,
+            invoice_id: Some(invoice.invoice_id),
+            notes,
+            ..StockMovement::default()
+        };
+        insert_stock_movement(transaction, config, &movement).await?;
+    }
+    Ok(())
+}
+
+pub async fn set_reorder_level(
+    config: &DbConfig,
+    product_id: i32,
+    reorder_level: i32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let before = transaction
+        .query_opt("SELECT * FROM products WHERE product_id = $1 FOR UPDATE", &[&product_id])
+        .await?
+        .as_ref()
+        .map(product_from_row)
+        .ok_or_else(|| format!("Product {} no longer exists", product_id))?;
+    let row = transaction
+        .query_one(
+            "UPDATE products SET reorder_level = $1, updated_at = CURRENT_TIMESTAMP WHERE product_id = $2 RETURNING *",
+            &[&reorder_level, &product_id],
+        )
+        .await?;
+    let after = product_from_row(&row);
+    record_audit(&transaction, config, "products", product_id, "UPDATE", Some(&before), Some(&after)).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn price_list_entry_from_row(row: &Row) -> PriceListEntry {
+    PriceListEntry {
+        entry_id: row.get("entry_id"),
+        product_id: row.get("product_id"),
+        min_quantity: row.get("min_quantity"),
+        unit_price: row.get("unit_price"),
+        discount_percent: row.get("discount_percent"),
+    }
+}
+
+fn price_list_from_row(row: &Row, entries: Vec<PriceListEntry>) -> PriceList {
+    PriceList {
+        price_list_id: row.get("price_list_id"),
+        name: row.get("name"),
+        customer_id: row.get("customer_id"),
+        tag_id: row.get("tag_id"),
+        active: row.get("active"),
+        entries,
+    }
+}
+
+async fn price_list_entries(
+    transaction: &Transaction<'_>,
+    price_list_id: i32,
+) -> Result<Vec<PriceListEntry>, Box<dyn std::error::Error>> {
+    let rows = transaction
+        .query(
+            "SELECT * FROM price_list_entries WHERE price_list_id = $1 ORDER BY product_id, min_quantity",
+            &[&price_list_id],
+        )
+        .await?;
+    Ok(rows.iter().map(price_list_entry_from_row).collect())
+}
+
+pub async fn get_price_lists(config: &DbConfig) -> Result<Vec<PriceList>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let entry_rows = client
+        .query("SELECT * FROM price_list_entries ORDER BY product_id, min_quantity", &[])
+        .await?;
+    let mut entries: HashMap<i32, Vec<PriceListEntry>> = HashMap::new();
+    for row in &entry_rows {
+        entries
+            .entry(row.get("price_list_id"))
+            .or_default()
+            .push(price_list_entry_from_row(row));
+    }
+    let rows = client.query("SELECT * FROM price_lists ORDER BY name", &[]).await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let price_list_id: i32 = row.get("price_list_id");
+            price_list_from_row(row, entries.remove(&price_list_id).unwrap_or_default())
+        })
+        .collect())
+}
+
+/// Adds or updates `list` together with its entries.
+pub async fn save_price_list(config: &DbConfig, list: &PriceList) -> Result<PriceList, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let (row, current) = if list.price_list_id == 0 {
+        let row = transaction
+            .query_one(
+                "INSERT INTO price_lists (name, customer_id, tag_id, active) VALUES ($1, $2, $3, $4) RETURNING *",
+                &[&list.name, &list.customer_id, &list.tag_id, &list.active],
+            )
+            .await?;
+        (row, None)
+    } else {
+        let row = transaction
+            .query_opt("SELECT * FROM price_lists WHERE price_list_id = $1 FOR UPDATE", &[&list.price_list_id])
+            .await?
+            .ok_or_else(|| format!("Price list {} no longer exists", list.name))?;
+        let current = price_list_from_row(&row, price_list_entries(&transaction, list.price_list_id).await?);
+        let row = transaction
+            .query_one(
+                "UPDATE price_lists SET name = $1, customer_id = $2, tag_id = $3, active = $4
+                 WHERE price_list_id = $5 RETURNING *",
+                &[&list.name, &list.customer_id, &list.tag_id, &list.active, &list.price_list_id],
+            )
+            .await?;
+        (row, Some(current))
+    };
+    let price_list_id: i32 = row.get("price_list_id");
+    transaction
+        .execute("DELETE FROM price_list_entries WHERE price_list_id = $1", &[&price_list_id])
+        .await?;
+    for entry in &list.entries {
+        transaction
+            .execute(
+                "INSERT INTO price_list_entries (price_list_id, product_id, min_quantity, unit_price, discount_percent)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &price_list_id,
+                    &entry.product_id,
+                    &entry.min_quantity,
+                    &entry.unit_price,
+                    &entry.discount_percent,
+                ],
+            )
+            .await?;
+    }
+    let saved = price_list_from_row(&row, price_list_entries(&transaction, price_list_id).await?);
+    let action = if current.is_some() { "UPDATE" } else { "INSERT" };
+    record_audit(&transaction, config, "price_lists", price_list_id, action, current.as_ref(), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(saved)
+}
+
+pub async fn delete_price_list(config: &DbConfig, price_list_id: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let entries = price_list_entries(&transaction, price_list_id).await?;
+    let row = transaction
+        .query_opt("DELETE FROM price_lists WHERE price_list_id = $1 RETURNING *", &[&price_list_id])
+        .await?;
+    if let Some(row) = row {
+        let list = price_list_from_row(&row, entries);
+        record_audit(&transaction, config, "price_lists", price_list_id, "DELETE", Some(&list), None).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn line_item_from_row(row: &Row) -> LineItem {
+    LineItem {
+        item_id: row.get("item_id"),
+        product_id: row.get("product_id"),
+        description: row.get("description"),
+        quantity: row.get("quantity"),
+        unit_price: row.get("unit_price"),
+        total_price: row.get("total_price"),
+        tax_rate_id: row.get("tax_rate_id"),
+        tax_rate: row.get("tax_rate"),
+        discount_percent: row.get("discount_percent"),
+    }
+}
+
+fn quote_from_row(row: &Row, items: Vec<LineItem>) -> Quote {
+    Quote {
+        quote_id: row.get("quote_id"),
+        customer_id: row.get("customer_id"),
+        quote_number: row.get("quote_number"),
+        quote_date: row.get("quote_date"),
+        valid_until: row.get("valid_until"),
+        net_amount: row.get("net_amount"),
+        tax_amount: row.get("tax_amount"),
+        total_amount: row.get("total_amount"),
+        status: row.get("status"),
+        notes: row.get("notes"),
+        invoice_id: row.get("invoice_id"),
+        items,
+        version: row.get("version"),
+    }
+}
+
+/// Items stored in `table` for the document whose id is in column `key`.
+async fn line_items(
+    transaction: &Transaction<'_>,
+    table: &str,
+    key: &str,
+    id: i32,
+) -> Result<Vec<LineItem>, Box<dyn std::error::Error>> {
+    let rows = transaction
+        .query(&format!("SELECT * FROM {} WHERE {} = $1 ORDER BY item_id", table, key), &[&id])
+        .await?;
+    Ok(rows.iter().map(line_item_from_row).collect())
+}
+
+pub async fn get_quotes(config: &DbConfig) -> Result<Vec<Quote>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let item_rows = client
+        .query("SELECT * FROM quote_items ORDER BY item_id", &[])
+        .await?;
+    let mut items: HashMap<i32, Vec<LineItem>> = HashMap::new();
+    for row in &item_rows {
+        items.entry(row.get("quote_id")).or_default().push(line_item_from_row(row));
+    }
+    let rows = client
+        .query("SELECT * FROM quotes ORDER BY quote_date DESC, quote_id DESC", &[])
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let quote_id: i32 = row.get("quote_id");
+            quote_from_row(row, items.remove(&quote_id).unwrap_or_default())
+        })
+        .collect())
+}
+
+/// Replaces the items of a quote and returns them as stored, with totals.
+/// Replaces the items in `table` of the document whose id is in column
+/// `key`, computing the line totals.
+async fn replace_line_items(
+    transaction: &Transaction<'_>,
+    table: &str,
+    key: &str,
+    id: i32,
+    items: &[LineItem],
+) -> Result<Vec<LineItem>, Box<dyn std::error::Error>> {
+    transaction
+        .execute(&format!("DELETE FROM {} WHERE {} = $1", table, key), &[&id])
+        .await?;
+    let insert = format!(
+        "INSERT INTO {} ({}, product_id, description, quantity, unit_price, total_price, tax_rate_id, tax_rate, discount_percent)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *",
+        table, key
+    );
+    let mut stored = Vec::with_capacity(items.len());
+    for item in items {
+        let row = transaction
+            .query_one(
+                &insert,
+                &[
+                    &id,
+                    &item.product_id,
+                    &item.description,
+                    &item.quantity,
+                    &item.unit_price,
+                    &tax::line_net(item),
+                    &item.tax_rate_id,
+                    &item.tax_rate,
+                    &item.discount_percent,
+                ],
+            )
+            .await?;
+        stored.push(line_item_from_row(&row));
+    }
+    Ok(stored)
+}
+
+pub async fn add_quote(config: &DbConfig, quote: &Quote) -> Result<Quote, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let quote_number = if quote.quote_number.trim().is_empty() {
+        next_document_number(&transaction, "quote", quote.quote_date).await?
+    } else {
+        quote.quote_number.clone()
+    };
+    let row = transaction
+        .query_one(
+            "INSERT INTO quotes (customer_id, quote_number, quote_date, valid_until, status, notes)
+             VALUES ($1, $2, $3, $4, $5, $6) RETURNING quote_id",
+            &[
+                &quote.customer_id,
+                &quote_number,
+                &quote.quote_date,
+                &quote.valid_until,
+                &quote.status,
+                &quote.notes,
+            ],
+        )
+        .await?;
+    let quote_id: i32 = row.get(0);
+    let items = replace_line_items(&transaction, "quote_items", "quote_id", quote_id, &quote.items).await?;
+    let totals = tax::totals(&items);
+    let row = transaction
+        .query_one(
+            "UPDATE quotes SET net_amount = $1, tax_amount = $2, total_amount = $3 WHERE quote_id = $4 RETURNING *",
+            &[&totals.net, &totals.tax, &totals.gross, &quote_id],
+        )
+        .await?;
+    let added = quote_from_row(&row, items);
+    record_audit(&transaction, config, "quotes", quote_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
+
+/// Saves `quote` with its items unless it was changed by someone else since
+/// it was loaded.
+pub async fn update_quote(
+    config: &DbConfig,
+    quote: &Quote,
+) -> Result<SaveResult<Quote>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt("SELECT * FROM quotes WHERE quote_id = $1 FOR UPDATE", &[&quote.quote_id])
+        .await?
+        .ok_or_else(|| format!("Quote {} no longer exists", quote.quote_id))?;
+    let current = quote_from_row(&row, line_items(&transaction, "quote_items", "quote_id", quote.quote_id).await?);
+    if current.version != quote.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+
+    let items = replace_line_items(&transaction, "quote_items", "quote_id", quote.quote_id, &quote.items).await?;
+    let totals = tax::totals(&items);
+    let row = transaction
+        .query_one(
+            "UPDATE quotes
+             SET customer_id = $1, quote_number = $2, quote_date = $3, valid_until = $4,
+                 net_amount = $5, tax_amount = $6, total_amount = $7, status = $8, notes = $9,
+                 version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE quote_id = $10
+             RETURNING *",
+            &[
+                &quote.customer_id,
+                &quote.quote_number,
+                &quote.quote_date,
+                &quote.valid_until,
+                &totals.net,
+                &totals.tax,
+                &totals.gross,
+                &quote.status,
+                &quote.notes,
+                &quote.quote_id,
+            ],
+        )
+        .await?;
+    let saved = quote_from_row(&row, items);
+    record_audit(&transaction, config, "quotes", saved.quote_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(SaveResult::Saved(saved))
+}
+
+/// Takes the next number of the `document_type` sequence for a document
+/// dated `date`. The sequence stays locked until the transaction ends, so
+/// concurrent documents wait for each other and a document that is not
+/// saved gives its number back. Numbers already entered by hand are skipped.
+async fn next_document_number(
+    transaction: &Transaction<'_>,
+    document_type: &str,
+    date: NaiveDate,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let row = transaction
+        .query_opt(
+            "SELECT * FROM number_sequences WHERE document_type = $1 FOR UPDATE",
+            &[&document_type],
+        )
+        .await?
+        .ok_or_else(|| format!("There is no number sequence for {}", document_type))?;
+    let sequence = number_sequence_from_row(&row);
+    let year = date.year();
+    let mut number =
+        numbering::first_free_number(sequence.yearly_reset, sequence.current_year, sequence.next_number, year)?;
+    let taken = if document_type == "quote" {
+        "SELECT EXISTS (SELECT 1 FROM quotes WHERE quote_number = $1)"
+    } else {
+        "SELECT EXISTS (SELECT 1 FROM invoices WHERE invoice_number = $1)"
+    };
+    let formatted = loop {
+        let formatted = numbering::format_number(&sequence.pattern, date, number);
+        let exists: bool = transaction.query_one(taken, &[&formatted]).await?.get(0);
+        if !exists {
+            break formatted;
+        }
+        number += 1;
+    };
+    transaction
+        .execute(
+            "UPDATE number_sequences
+             SET next_number = $2, current_year = GREATEST(current_year, $3), version = version + 1
+             WHERE document_type = $1",
+            &[&document_type, &(number + 1), &year],
+        )
+        .await?;
+    Ok(formatted)
+}
+
+/// Creates a draft invoice with the items of an accepted quote and links the
+/// quote to it. Returns the invoice and the updated quote.
+pub async fn convert_quote_to_invoice(
+    config: &DbConfig,
+    quote_id: i32,
+) -> Result<(Invoice, Quote), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt("SELECT * FROM quotes WHERE quote_id = $1 FOR UPDATE", &[&quote_id])
+        .await?
+        .ok_or_else(|| format!("Quote {} no longer exists", quote_id))?;
+    let current = quote_from_row(&row, line_items(&transaction, "quote_items", "quote_id", quote_id).await?);
+    if current.status != "accepted" {
+        return Err(format!("Quote {} has not been accepted", current.quote_number).into());
+    }
+    if let Some(invoice_id) = current.invoice_id {
+        return Err(format!("Quote {} was already converted into invoice {}", current.quote_number, invoice_id).into());
+    }
+
+    let today = Utc::now().date_naive();
+    let invoice_number = next_document_number(&transaction, "invoice", today).await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO invoices (customer_id, invoice_number, invoice_date, due_date, net_amount, tax_amount, total_amount, status, notes, currency, buyer_reference)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, 'draft', $8,
+                     COALESCE((SELECT currency FROM customers WHERE customer_id = $1), base_currency()),
+                     COALESCE((SELECT buyer_reference FROM customers WHERE customer_id = $1), ''))
+             RETURNING *",
+            &[
+                &current.customer_id,
+                &invoice_number,
+                &today,
+                &(today + chrono::Duration::days(14)),
+                &current.net_amount,
+                &current.tax_amount,
+                &current.total_amount,
+                &format!("Based on quote {}", current.quote_number),
+            ],
+        )
+        .await?;
+    let invoice_id: i32 = row.get("invoice_id");
+    let items = replace_line_items(&transaction, "invoice_items", "invoice_id", invoice_id, &current.items).await?;
+    let invoice = invoice_from_row(&row, items);
+
+    let row = transaction
+        .query_one(
+            "UPDATE quotes SET invoice_id = $1, version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE quote_id = $2 RETURNING *",
+            &[&invoice_id, &quote_id],
+        )
+        .await?;
+    let converted = quote_from_row(&row, current.items.clone());
+    record_audit(&transaction, config, "invoices", invoice_id, "INSERT", None, Some(&invoice)).await?;
+    record_audit(&transaction, config, "quotes", quote_id, "UPDATE", Some(&current), Some(&converted)).await?;
+    transaction.commit().await?;
+
+    println!("Quote {} converted into invoice {}", converted.quote_number, invoice.invoice_number);
+    Ok((invoice, converted))
+}
+
+/// Creates a draft credit note cancelling the issued invoice `invoice_id`:
+/// the same customer, currency and lines with negated quantities, to be
+/// reduced to what is actually credited before it is sent.
+pub async fn create_credit_note(
+    config: &DbConfig,
+    invoice_id: i32,
+) -> Result<Invoice, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt("SELECT * FROM invoices WHERE invoice_id = $1 FOR UPDATE", &[&invoice_id])
+        .await?
+        .ok_or_else(|| format!("Invoice {} no longer exists", invoice_id))?;
+    let original = invoice_from_row(&row, line_items(&transaction, "invoice_items", "invoice_id", invoice_id).await?);
+    if original.is_credit_note() {
+        return Err(format!("{} is a credit note itself", original.invoice_number).into());
+    }
+    if !ISSUED_STATUSES.contains(&original.status.as_str()) {
+        return Err(format!("Invoice {} has not been issued; edit or cancel it instead", original.invoice_number).into());
+    }
+
+    let items: Vec<LineItem> = original
+        .items
+        .iter()
+        .map(|item| LineItem {
+            quantity: -item.quantity,
+            ..item.clone()
+        })
+        .collect();
+    let (net, tax, gross) = if items.is_empty() {
+        (-original.net_amount, -original.tax_amount, -original.total_amount)
+    } else {
+        let totals = tax::totals(&items);
+        (totals.net, totals.tax, totals.gross)
+    };
+    let today = Utc::now().date_naive();
+    let invoice_number = next_document_number(&transaction, "credit_note", today).await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO invoices (customer_id, invoice_number, invoice_date, due_date, net_amount, tax_amount, total_amount, status, notes, currency, buyer_reference, document_type, original_invoice_id)
+             VALUES ($1, $2, $3, $3, $4, $5, $6, 'draft', $7, $8, $9, 'credit_note', $10)
+             RETURNING *",
+            &[
+                &original.customer_id,
+                &invoice_number,
+                &today,
+                &net,
+                &tax,
+                &gross,
+                &format!("Credit note for invoice {} of {}", original.invoice_number, original.invoice_date),
+                &original.currency,
+                &original.buyer_reference,
+                &original.invoice_id,
+            ],
+        )
+        .await?;
+    let credit_note_id: i32 = row.get("invoice_id");
+    let items = replace_line_items(&transaction, "invoice_items", "invoice_id", credit_note_id, &items).await?;
+    let credit_note = invoice_from_row(&row, items);
+    record_audit(&transaction, config, "invoices", credit_note_id, "INSERT", None, Some(&credit_note)).await?;
+    transaction.commit().await?;
+
+    println!("Credit note {} created for invoice {}", credit_note.invoice_number, original.invoice_number);
+    Ok(credit_note)
+}
+
+/// Checks a credit note about to be saved with the gross amount `gross`:
+/// it must be in the currency of its invoice and reduce the amount owed,
+/// together with the other issued credit notes by no more than the total.
+async fn check_credit_note(
+    transaction: &Transaction<'_>,
+    credit_note: &Invoice,
+    gross: Decimal,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !matches!(credit_note.status.as_str(), "draft" | "sent" | "cancelled") {
+        return Err("A credit note can only be draft, sent or cancelled".into());
+    }
+    if gross >= Decimal::ZERO {
+        return Err("A credit note must have a negative total".into());
+    }
+    let original_id = credit_note
+        .original_invoice_id
+        .ok_or("A credit note must refer to an invoice")?;
+    let row = transaction
+        .query_one(
+            "SELECT o.invoice_number, o.currency, o.total_amount, COALESCE((
+                 SELECT SUM(c.total_amount) FROM invoices c
+                 WHERE c.original_invoice_id = o.invoice_id AND c.invoice_id <> $2
+                     AND c.status NOT IN ('draft', 'cancelled')
+             ), 0) AS credited
+             FROM invoices o WHERE o.invoice_id = $1",
+            &[&original_id, &credit_note.invoice_id],
+        )
+        .await?;
+    let invoice_number: String = row.get("invoice_number");
+    let currency: String = row.get("currency");
+    if !credit_note.currency.is_empty() && credit_note.currency != currency {
+        return Err(format!("A credit note must be in the currency of invoice {} ({})", invoice_number, currency).into());
+    }
+    let total: Decimal = row.get("total_amount");
+    let credited: Decimal = row.get("credited");
+    if credit_note.status != "cancelled" && total + credited + gross < Decimal::ZERO {
+        return Err(format!(
+            "Credit notes of invoice {} would exceed its total of {:.2} {}",
+            invoice_number, total, currency
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Marks the invoice corrected by an issued credit note as credited once
+/// its credit notes and payments leave nothing open.
+async fn settle_credited_invoice(
+    transaction: &Transaction<'_>,
+    config: &DbConfig,
+    credit_note: &Invoice,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(invoice_id) = credit_note.original_invoice_id.filter(|_| credit_note.status == "sent") else {
+        return Ok(());
+    };
+    let row = transaction
+        .query_opt(
+            "SELECT * FROM invoices WHERE invoice_id = $1 AND status IN ('sent', 'paid') FOR UPDATE",
+            &[&invoice_id],
+        )
+        .await?;
+    let Some(row) = row else {
+        return Ok(());
+    };
+    let before = invoice_from_row(&row, Vec::new());
+    let credited = transaction
+        .query_opt(
+            "UPDATE invoices SET status = 'credited', version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE invoice_id = $1 AND invoice_open_amount($1) <= 0
+             RETURNING *",
+            &[&invoice_id],
+        )
+        .await?;
+    if let Some(row) = credited {
+        let items = line_items(transaction, "invoice_items", "invoice_id", invoice_id).await?;
+        let before = Invoice {
+            items: items.clone(),
+            ..before
+        };
+        let after = invoice_from_row(&row, items);
+        record_audit(transaction, config, "invoices", invoice_id, "UPDATE", Some(&before), Some(&after)).await?;
+    }
+    Ok(())
+}
+
+fn dunning_level_from_row(row: &Row) -> DunningLevel {
+    DunningLevel {
+        level_id: row.get("level_id"),
+        level: row.get("level"),
+        name: row.get("name"),
+        days_overdue: row.get("days_overdue"),
+        grace_days: row.get("grace_days"),
+        fee: row.get("fee"),
+        interest_rate: row.get("interest_rate"),
+        letter_text: row.get("letter_text"),
+    }
+}
+
+fn dunning_notice_from_row(row: &Row) -> DunningNotice {
+    DunningNotice {
+        notice_id: row.get("notice_id"),
+        invoice_id: row.get("invoice_id"),
+        level: row.get("level"),
+        level_name: row.get("level_name"),
+        notice_date: row.get("notice_date"),
+        pay_by: row.get("pay_by"),
+        open_amount: row.get("open_amount"),
+        fee: row.get("fee"),
+        interest: row.get("interest"),
+        history_id: row.get("history_id"),
+        created_by: row.get("created_by"),
+    }
+}
+
+pub async fn get_dunning_levels(config: &DbConfig) -> Result<Vec<DunningLevel>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM dunning_levels ORDER BY level", &[])
+        .await?;
+    Ok(rows.iter().map(dunning_level_from_row).collect())
+}
+
+/// Adds the level if it has no id yet, otherwise updates it.
+pub async fn save_dunning_level(
+    config: &DbConfig,
+    level: &DunningLevel,
+) -> Result<DunningLevel, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let params: [&(dyn tokio_postgres::types::ToSql + Sync); 7] = [
+        &level.level,
+        &level.name,
+        &level.days_overdue,
+        &level.grace_days,
+        &level.fee,
+        &level.interest_rate,
+        &level.letter_text,
+    ];
+    let saved = if level.level_id == 0 {
+        let row = transaction
+            .query_one(
+                "INSERT INTO dunning_levels (level, name, days_overdue, grace_days, fee, interest_rate, letter_text)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+                &params,
+            )
+            .await?;
+        let saved = dunning_level_from_row(&row);
+        record_audit(&transaction, config, "dunning_levels", saved.level_id, "INSERT", None, Some(&saved)).await?;
+        saved
+    } else {
+        let current = transaction
+            .query_opt("SELECT * FROM dunning_levels WHERE level_id = $1 FOR UPDATE", &[&level.level_id])
+            .await?
+            .as_ref()
+            .map(dunning_level_from_row)
+            .ok_or_else(|| format!("Dunning level {} no longer exists", level.name))?;
+        let mut update_params = params.to_vec();
+        update_params.push(&level.level_id);
+        let row = transaction
+            .query_one(
+                "UPDATE dunning_levels
+                 SET level = $1, name = $2, days_overdue = $3, grace_days = $4, fee = $5,
+                     interest_rate = $6, letter_text = $7
+                 WHERE level_id = $8 RETURNING *",
+                &update_params,
+            )
+            .await?;
+        let saved = dunning_level_from_row(&row);
+        record_audit(&transaction, config, "dunning_levels", saved.level_id, "UPDATE", Some(&current), Some(&saved)).await?;
+        saved
+    };
+    transaction.commit().await?;
+    Ok(saved)
+}
+
+/// Sent invoices past their due date on `today` that are not fully paid.
+pub async fn get_overdue_invoices(
+    config: &DbConfig,
+    today: NaiveDate,
+) -> Result<Vec<OverdueInvoice>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT * FROM (
+                 SELECT i.*, invoice_open_amount(i.invoice_id) AS open_amount
+                 FROM invoices i
+                 WHERE i.due_date < $1 AND i.status NOT IN ('draft', 'paid', 'cancelled')
+             ) overdue
+             WHERE open_amount > 0
+             ORDER BY due_date, invoice_id",
+            &[&today],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| OverdueInvoice {
+            // Dunning works on the open amount; the items are not needed.
+            invoice: invoice_from_row(row, Vec::new()),
+            open_amount: row.get("open_amount"),
+        })
+        .collect())
+}
+
+pub async fn get_dunning_notices(config: &DbConfig) -> Result<Vec<DunningNotice>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM dunning_notices ORDER BY notice_date DESC, notice_id DESC", &[])
+        .await?;
+    Ok(rows.iter().map(dunning_notice_from_row).collect())
+}
+
+/// Stores a dunning notice together with a contact history entry holding
+/// the letter. Returns `None` without storing anything if the invoice was
+/// paid or withdrawn since the run was prepared. Fails if the invoice
+/// already got this level, or a notice whose grace period has not ended yet.
+pub async fn record_dunning_notice(
+    config: &DbConfig,
+    notice: &DunningNotice,
+    customer_id: Option<i32>,
+    letter: &str,
+) -> Result<Option<DunningNotice>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+
+    // Locking the invoice keeps two clients from dunning it at once.
+    let invoice = transaction
+        .query_opt("SELECT * FROM invoices WHERE invoice_id = $1 FOR UPDATE", &[&notice.invoice_id])
+        .await?
+        .as_ref()
+        .map(|row| invoice_from_row(row, Vec::new()))
+        .ok_or_else(|| format!("Invoice {} no longer exists", notice.invoice_id))?;
+    let open: Decimal = transaction
+        .query_one("SELECT invoice_open_amount($1)", &[&notice.invoice_id])
+        .await?
+        .get(0);
+    if invoice.status != "sent" || open <= Decimal::ZERO {
+        return Ok(None);
+    }
+    let last = transaction
+        .query_opt(
+            "SELECT * FROM dunning_notices WHERE invoice_id = $1 ORDER BY level DESC LIMIT 1",
+            &[&notice.invoice_id],
+        )
+        .await?
+        .as_ref()
+        .map(dunning_notice_from_row);
+    if let Some(last) = last {
+        if last.level >= notice.level {
+            return Err(format!("Invoice {} already got {}", invoice.invoice_number, last.level_name).into());
+        }
+        if last.pay_by >= notice.notice_date {
+            return Err(format!(
+                "Invoice {} is within its grace period until {}",
+                invoice.invoice_number, last.pay_by
+            )
+            .into());
+        }
+    }
+
+    let created_by = acting_user(config);
+    let history_id = match customer_id {
+        Some(customer_id) => {
+            let now = Utc::now();
+            let row = transaction
+                .query_one(
+                    "INSERT INTO contact_history (customer_id, contact_type, contact_date, contact_method, contact_outcome, notes, follow_up_date, created_by, created_at, updated_at)
+                     VALUES ($1, 'Dunning', $2, 'Letter', $3, $4, $5, $6, $2, $2)
+                     RETURNING *",
+                    &[
+                        &customer_id,
+                        &now,
+                        &format!("{} for invoice {}", notice.level_name, invoice.invoice_number),
+                        &letter,
+                        &notice.pay_by,
+                        &created_by,
+                    ],
+                )
+                .await?;
+            let history = contact_history_from_row(&row);
+            record_audit(&transaction, config, "contact_history", history.history_id, "INSERT", None, Some(&history)).await?;
+            Some(history.history_id)
+        }
+        None => None,
+    };
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO dunning_notices (invoice_id, level, level_name, notice_date, pay_by, open_amount, fee, interest, history_id, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *",
+            &[
+                &notice.invoice_id,
+                &notice.level,
+                &notice.level_name,
+                &notice.notice_date,
+                &notice.pay_by,
+                &notice.open_amount,
+                &notice.fee,
+                &notice.interest,
+                &history_id,
+                &created_by,
+            ],
+        )
+        .await?;
+    let added = dunning_notice_from_row(&row);
+    record_audit(&transaction, config, "dunning_notices", added.notice_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(Some(added))
+}
+
+fn recurring_from_row(row: &Row, items: Vec<LineItem>) -> RecurringInvoice {
+    RecurringInvoice {
+        recurring_id: row.get("recurring_id"),
+        customer_id: row.get("customer_id"),
+        description: row.get("description"),
+        interval_months: row.get("interval_months"),
+        start_date: row.get("start_date"),
+        end_date: row.get("end_date"),
+        payment_days: row.get("payment_days"),
+        active: row.get("active"),
+        items,
+        version: row.get("version"),
+    }
+}
 
-        -- Products table
-        CREATE TABLE IF NOT EXISTS products (
-            product_id SERIAL PRIMARY KEY,
-            product_name VARCHAR(100) NOT NULL,
-            description TEXT,
-            unit_price DECIMAL(10, 2) NOT NULL,
-            stock_quantity INTEGER NOT NULL,
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        );
+pub async fn get_recurring_invoices(config: &DbConfig) -> Result<Vec<RecurringInvoice>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let item_rows = client
+        .query("SELECT * FROM recurring_invoice_items ORDER BY item_id", &[])
+        .await?;
+    let mut items: HashMap<i32, Vec<LineItem>> = HashMap::new();
+    for row in &item_rows {
+        items.entry(row.get("recurring_id")).or_default().push(line_item_from_row(row));
+    }
+    let rows = client
+        .query("SELECT * FROM recurring_invoices ORDER BY start_date, recurring_id", &[])
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let recurring_id: i32 = row.get("recurring_id");
+            recurring_from_row(row, items.remove(&recurring_id).unwrap_or_default())
+        })
+        .collect())
+}
 
-        -- Invoices table
-        CREATE TABLE IF NOT EXISTS invoices (
-            invoice_id SERIAL PRIMARY KEY,
-            customer_id INTEGER REFERENCES customers(customer_id),
-            invoice_number VARCHAR(50) UNIQUE NOT NULL,
-            invoice_date DATE NOT NULL,
-            due_date DATE NOT NULL,
-            total_amount DECIMAL(10, 2) NOT NULL,
-            status VARCHAR(20) NOT NULL,
-            payment_method VARCHAR(50),
-            notes TEXT,
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        );
+pub async fn add_recurring_invoice(
+    config: &DbConfig,
+    recurring: &RecurringInvoice,
+) -> Result<RecurringInvoice, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO recurring_invoices (customer_id, description, interval_months, start_date, end_date, payment_days, active)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+            &[
+                &recurring.customer_id,
+                &recurring.description,
+                &recurring.interval_months,
+                &recurring.start_date,
+                &recurring.end_date,
+                &recurring.payment_days,
+                &recurring.active,
+            ],
+        )
+        .await?;
+    let recurring_id: i32 = row.get("recurring_id");
+    let items = replace_line_items(
+        &transaction,
+        "recurring_invoice_items",
+        "recurring_id",
+        recurring_id,
+        &recurring.items,
+    )
+    .await?;
+    let added = recurring_from_row(&row, items);
+    record_audit(&transaction, config, "recurring_invoices", recurring_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
 
-               -- Invoice Items table
-        CREATE TABLE IF NOT EXISTS invoice_items (
-            item_id SERIAL PRIMARY KEY,
-            invoice_id INTEGER REFERENCES invoices(invoice_id),
-            product_id INTEGER REFERENCES products(product_id),
-            quantity INTEGER NOT NULL,
-            unit_price DECIMAL(10, 2) NOT NULL,
-            total_price DECIMAL(10, 2) NOT NULL,
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        );
+/// Saves `recurring` unless it was changed by someone else since it was loaded.
+pub async fn update_recurring_invoice(
+    config: &DbConfig,
+    recurring: &RecurringInvoice,
+) -> Result<SaveResult<RecurringInvoice>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let recurring_id = recurring.recurring_id;
 
-        -- Payments table
-CREATE TABLE IF NOT EXISTS payments (
-    payment_id SERIAL PRIMARY KEY,
-    invoice_id INTEGER REFERENCES invoices(invoice_id),
-    payment_date DATE NOT NULL,
-    amount DECIMAL(10, 2) NOT NULL,
-    payment_method VARCHAR(50) NOT NULL,
-    transaction_id VARCHAR(100),
-    notes TEXT,
-    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
-);
+    let row = transaction
+        .query_opt("SELECT * FROM recurring_invoices WHERE recurring_id = $1 FOR UPDATE", &[&recurring_id])
+        .await?
+        .ok_or_else(|| format!("Recurring invoice {} no longer exists", recurring_id))?;
+    let items = line_items(&transaction, "recurring_invoice_items", "recurring_id", recurring_id).await?;
+    let current = recurring_from_row(&row, items);
+    if current.version != recurring.version {
+        return Ok(SaveResult::Conflict(current));
+    }
 
--- Contact History table
-CREATE TABLE IF NOT EXISTS contact_history (
-    history_id SERIAL PRIMARY KEY,
-    customer_id INTEGER NOT NULL,
-    contact_type VARCHAR(50) NOT NULL,
-    contact_date TIMESTAMP WITH TIME ZONE NOT NULL,
-    contact_duration INTEGER,
-    contact_method VARCHAR(50),
-    contact_outcome VARCHAR(100) NOT NULL,
-    notes TEXT,
-    follow_up_date DATE,
-    created_by VARCHAR(100) NOT NULL,
-    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
-    updated_at TIMESTAMP WITH TIME ZONE NOT NULL,
-    FOREIGN KEY (customer_id) REFERENCES customers(customer_id)
-);
+    let items = replace_line_items(
+        &transaction,
+        "recurring_invoice_items",
+        "recurring_id",
+        recurring_id,
+        &recurring.items,
+    )
+    .await?;
+    let row = transaction
+        .query_one(
+            "UPDATE recurring_invoices
+             SET customer_id = $1, description = $2, interval_months = $3, start_date = $4,
+                 end_date = $5, payment_days = $6, active = $7,
+                 version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE recurring_id = $8
+             RETURNING *",
+            &[
+                &recurring.customer_id,
+                &recurring.description,
+                &recurring.interval_months,
+                &recurring.start_date,
+                &recurring.end_date,
+                &recurring.payment_days,
+                &recurring.active,
+                &recurring_id,
+            ],
+        )
+        .await?;
+    let saved = recurring_from_row(&row, items);
+    record_audit(&transaction, config, "recurring_invoices", recurring_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(SaveResult::Saved(saved))
+}
 
--- Contacts table
-CREATE TABLE IF NOT EXISTS contacts (
-    contact_id SERIAL PRIMARY KEY,
-    customer_id INTEGER REFERENCES customers(customer_id),
-    first_name VARCHAR(50) NOT NULL,
-    last_name VARCHAR(50) NOT NULL,
-    email VARCHAR(100),
-    phone VARCHAR(20),
-    position VARCHAR(50),
-    is_primary BOOLEAN DEFAULT false,
-    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
-);
+/// Creates a draft invoice for every period of an active template that has
+/// started by `today` and has no invoice yet. Running it again for the same
+/// day creates nothing.
+pub async fn generate_recurring_invoices(
+    config: &DbConfig,
+    today: NaiveDate,
+) -> Result<Vec<Invoice>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
 
+    // Locking the templates serializes concurrent runs.
+    let rows = transaction
+        .query(
+            "SELECT * FROM recurring_invoices WHERE active AND start_date <= $1 ORDER BY recurring_id FOR UPDATE",
+            &[&today],
+        )
+        .await?;
+    let mut generated = Vec::new();
+    for row in &rows {
+        let recurring_id: i32 = row.get("recurring_id");
+        let items = line_items(&transaction, "recurring_invoice_items", "recurring_id", recurring_id).await?;
+        let recurring = recurring_from_row(row, items);
+        let billed: Vec<NaiveDate> = transaction
+            .query(
+                "SELECT period_start FROM invoices WHERE recurring_id = $1",
+                &[&recurring_id],
+            )
+            .await?
+            .iter()
+            .map(|r| r.get(0))
+            .collect();
 
-        -- Create indexes for better performance
-        CREATE INDEX IF NOT EXISTS idx_customers_company_name ON customers(company_name);
-        CREATE INDEX IF NOT EXISTS idx_products_product_name ON products(product_name);
-        CREATE INDEX IF NOT EXISTS idx_invoices_customer_id ON invoices(customer_id);
-        CREATE INDEX IF NOT EXISTS idx_invoice_items_invoice_id ON invoice_items(invoice_id);
-        CREATE INDEX IF NOT EXISTS idx_invoice_items_product_id ON invoice_items(product_id);
-        CREATE INDEX IF NOT EXISTS idx_payments_invoice_id ON payments(invoice_id);
-        CREATE INDEX IF NOT EXISTS idx_contacts_customer_id ON contacts(customer_id);
-    ";
+        for (period_start, period_end) in recurring::due_periods(&recurring, today) {
+            if billed.contains(&period_start) {
+                continue;
+            }
+            let invoice_number = next_document_number(&transaction, "invoice", today).await?;
+            let totals = tax::totals(&recurring.items);
+            let notes = format!(
+                "{} for {} to {}",
+                recurring.description,
+                period_start.format("%d.%m.%Y"),
+                period_end.format("%d.%m.%Y")
+            );
+            // The period was checked above under the template lock, so a
+            // conflict here is an error; it rolls the number back with the run.
+            let row = transaction
+                .query_one(
+                    "INSERT INTO invoices (customer_id, invoice_number, invoice_date, due_date, net_amount, tax_amount, total_amount, status, notes, recurring_id, period_start, currency, buyer_reference)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, 'draft', $8, $9, $10,
+                             COALESCE((SELECT currency FROM customers WHERE customer_id = $1), base_currency()),
+                             COALESCE((SELECT buyer_reference FROM customers WHERE customer_id = $1), ''))
+                     RETURNING *",
+                    &[
+                        &recurring.customer_id,
+                        &invoice_number,
+                        &today,
+                        &(today + chrono::Duration::days(recurring.payment_days.into())),
+                        &totals.net,
+                        &totals.tax,
+                        &totals.gross,
+                        &notes,
+                        &recurring_id,
+                        &period_start,
+                    ],
+                )
+                .await?;
+            let invoice_id: i32 = row.get("invoice_id");
+            let items = replace_line_items(&transaction, "invoice_items", "invoice_id", invoice_id, &recurring.items).await?;
+            let invoice = invoice_from_row(&row, items);
+            record_audit(&transaction, config, "invoices", invoice_id, "INSERT", None, Some(&invoice)).await?;
+            generated.push(invoice);
+        }
+    }
+    transaction.commit().await?;
 
-    println!("Executing query to create tables and set relationships...");
-    client.batch_execute(create_tables_query).await?;
+    println!("{} recurring invoices generated", generated.len());
+    Ok(generated)
+}
+
+fn tax_rate_from_row(row: &Row) -> TaxRate {
+    TaxRate {
+        tax_rate_id: row.get("tax_rate_id"),
+        name: row.get("name"),
+        rate: row.get("rate"),
+        reverse_charge: row.get("reverse_charge"),
+        note: row.get("note"),
+        is_default: row.get("is_default"),
+        active: row.get("active"),
+        revenue_account: row.get("revenue_account"),
+    }
+}
+
+pub async fn get_tax_rates(config: &DbConfig) -> Result<Vec<TaxRate>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM tax_rates ORDER BY rate DESC, name", &[])
+        .await?;
+    Ok(rows.iter().map(tax_rate_from_row).collect())
+}
+
+/// Adds the rate if it has no id yet, otherwise updates it. Making a rate
+/// the default takes that from the previous one.
+pub async fn save_tax_rate(config: &DbConfig, rate: &TaxRate) -> Result<TaxRate, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    if rate.is_default {
+        let previous = transaction
+            .query(
+                "UPDATE tax_rates SET is_default = false WHERE is_default AND tax_rate_id <> $1 RETURNING *",
+                &[&rate.tax_rate_id],
+            )
+            .await?;
+        for row in &previous {
+            let after = tax_rate_from_row(row);
+            let before = TaxRate {
+                is_default: true,
+                ..after.clone()
+            };
+            record_audit(&transaction, config, "tax_rates", after.tax_rate_id, "UPDATE", Some(&before), Some(&after)).await?;
+        }
+    }
+    let saved = if rate.tax_rate_id == 0 {
+        let row = transaction
+            .query_one(
+                "INSERT INTO tax_rates (name, rate, reverse_charge, note, is_default, active, revenue_account)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+                &[
+                    &rate.name,
+                    &rate.rate,
+                    &rate.reverse_charge,
+                    &rate.note,
+                    &rate.is_default,
+                    &rate.active,
+                    &rate.revenue_account,
+                ],
+            )
+            .await?;
+        let saved = tax_rate_from_row(&row);
+        record_audit(&transaction, config, "tax_rates", saved.tax_rate_id, "INSERT", None, Some(&saved)).await?;
+        saved
+    } else {
+        let current = transaction
+            .query_opt("SELECT * FROM tax_rates WHERE tax_rate_id = $1 FOR UPDATE", &[&rate.tax_rate_id])
+            .await?
+            .as_ref()
+            .map(tax_rate_from_row)
+            .ok_or_else(|| format!("Tax rate {} no longer exists", rate.name))?;
+        let row = transaction
+            .query_one(
+                "UPDATE tax_rates
+                 SET name = $1, rate = $2, reverse_charge = $3, note = $4, is_default = $5, active = $6,
+                     revenue_account = $7
+                 WHERE tax_rate_id = $8 RETURNING *",
+                &[
+                    &rate.name,
+                    &rate.rate,
+                    &rate.reverse_charge,
+                    &rate.note,
+                    &rate.is_default,
+                    &rate.active,
+                    &rate.revenue_account,
+                    &rate.tax_rate_id,
+                ],
+            )
+            .await?;
+        let saved = tax_rate_from_row(&row);
+        record_audit(&transaction, config, "tax_rates", saved.tax_rate_id, "UPDATE", Some(&current), Some(&saved)).await?;
+        saved
+    };
+    transaction.commit().await?;
+    Ok(saved)
+}
+
+pub async fn set_product_tax_rate(
+    config: &DbConfig,
+    product_id: i32,
+    tax_rate_id: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let before = transaction
+        .query_opt("SELECT * FROM products WHERE product_id = $1 FOR UPDATE", &[&product_id])
+        .await?
+        .as_ref()
+        .map(product_from_row)
+        .ok_or_else(|| format!("Product {} no longer exists", product_id))?;
+    let row = transaction
+        .query_one(
+            "UPDATE products SET tax_rate_id = $1, updated_at = CURRENT_TIMESTAMP WHERE product_id = $2 RETURNING *",
+            &[&tax_rate_id, &product_id],
+        )
+        .await?;
+    let after = product_from_row(&row);
+    record_audit(&transaction, config, "products", product_id, "UPDATE", Some(&before), Some(&after)).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn currency_from_row(row: &Row) -> Currency {
+    Currency {
+        currency_id: row.get("currency_id"),
+        code: row.get("code"),
+        name: row.get("name"),
+        is_base: row.get("is_base"),
+        active: row.get("active"),
+    }
+}
+
+pub async fn get_currencies(config: &DbConfig) -> Result<Vec<Currency>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query("SELECT * FROM currencies ORDER BY is_base DESC, code", &[])
+        .await?;
+    Ok(rows.iter().map(currency_from_row).collect())
+}
+
+/// Adds the currency if it has no id yet, otherwise updates it. Making a
+/// currency the base takes that from the previous one; stored rates are
+/// relative to the old base and need to be imported again.
+pub async fn save_currency(config: &DbConfig, currency: &Currency) -> Result<Currency, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    if currency.is_base {
+        let previous = transaction
+            .query(
+                "UPDATE currencies SET is_base = false WHERE is_base AND currency_id <> $1 RETURNING *",
+                &[&currency.currency_id],
+            )
+            .await?;
+        for row in &previous {
+            let after = currency_from_row(row);
+            let before = Currency {
+                is_base: true,
+                ..after.clone()
+            };
+            record_audit(&transaction, config, "currencies", after.currency_id, "UPDATE", Some(&before), Some(&after)).await?;
+        }
+    }
+    let saved = if currency.currency_id == 0 {
+        let row = transaction
+            .query_one(
+                "INSERT INTO currencies (code, name, is_base, active) VALUES ($1, $2, $3, $4) RETURNING *",
+                &[&currency.code, &currency.name, &currency.is_base, &currency.active],
+            )
+            .await?;
+        let saved = currency_from_row(&row);
+        record_audit(&transaction, config, "currencies", saved.currency_id, "INSERT", None, Some(&saved)).await?;
+        saved
+    } else {
+        let current = transaction
+            .query_opt("SELECT * FROM currencies WHERE currency_id = $1 FOR UPDATE", &[&currency.currency_id])
+            .await?
+            .as_ref()
+            .map(currency_from_row)
+            .ok_or_else(|| format!("Currency {} no longer exists", currency.code))?;
+        let row = transaction
+            .query_one(
+                "UPDATE currencies SET code = $1, name = $2, is_base = $3, active = $4
+                 WHERE currency_id = $5 RETURNING *",
+                &[&currency.code, &currency.name, &currency.is_base, &currency.active, &currency.currency_id],
+            )
+            .await?;
+        let saved = currency_from_row(&row);
+        record_audit(&transaction, config, "currencies", saved.currency_id, "UPDATE", Some(&current), Some(&saved)).await?;
+        saved
+    };
+    transaction.commit().await?;
+    Ok(saved)
+}
+
+/// The latest rate of every currency.
+pub async fn get_latest_exchange_rates(config: &DbConfig) -> Result<Vec<ExchangeRate>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT DISTINCT ON (currency) currency, rate_date, rate
+             FROM exchange_rates ORDER BY currency, rate_date DESC",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| ExchangeRate {
+            currency: row.get("currency"),
+            rate_date: row.get("rate_date"),
+            rate: row.get("rate"),
+        })
+        .collect())
+}
+
+/// Stores `rates`, replacing rates already stored for the same currency
+/// and date. Returns the number of rates written; unchanged rates are
+/// neither written nor audited.
+pub async fn save_exchange_rates(config: &DbConfig, rates: &[ExchangeRate]) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let currencies: Vec<&str> = rates.iter().map(|r| r.currency.as_str()).collect();
+    let dates: Vec<NaiveDate> = rates.iter().map(|r| r.rate_date).collect();
+    let values: Vec<Decimal> = rates.iter().map(|r| r.rate).collect();
+    // All parts of the statement see the rates as they were before the
+    // upsert, so each written row comes back with the rate it replaced.
+    let rows = transaction
+        .query(
+            "WITH stored AS (
+                 SELECT e.currency, e.rate_date, e.rate
+                 FROM exchange_rates e
+                 JOIN UNNEST($1::VARCHAR[], $2::DATE[]) AS n(currency, rate_date)
+                     ON n.currency = e.currency AND n.rate_date = e.rate_date
+             ), written AS (
+                 INSERT INTO exchange_rates (currency, rate_date, rate)
+                 SELECT * FROM UNNEST($1::VARCHAR[], $2::DATE[], $3::NUMERIC[])
+                 ON CONFLICT (currency, rate_date) DO UPDATE SET rate = EXCLUDED.rate
+                 WHERE exchange_rates.rate <> EXCLUDED.rate
+                 RETURNING exchange_rate_id, currency, rate_date, rate
+             )
+             SELECT w.*, s.rate AS old_rate
+             FROM written w
+             LEFT JOIN stored s ON s.currency = w.currency AND s.rate_date = w.rate_date
+             ORDER BY w.currency, w.rate_date",
+            &[&currencies, &dates, &values],
+        )
+        .await?;
+    for row in &rows {
+        let new = ExchangeRate {
+            currency: row.get("currency"),
+            rate_date: row.get("rate_date"),
+            rate: row.get("rate"),
+        };
+        let old = row.get::<_, Option<Decimal>>("old_rate").map(|rate| ExchangeRate { rate, ..new.clone() });
+        let action = if old.is_some() { "UPDATE" } else { "INSERT" };
+        record_audit(&transaction, config, "exchange_rates", row.get("exchange_rate_id"), action, old.as_ref(), Some(&new)).await?;
+    }
+    transaction.commit().await?;
+    Ok(rows.len() as u64)
+}
+
+/// Invoices dated between `from` and `to` except drafts and cancelled ones,
+/// converted into the base currency at the rate of their invoice date.
+/// Credit notes are included with their negative amounts.
+pub async fn get_converted_invoices(
+    config: &DbConfig,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<ConvertedInvoice>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT i.*,
+                 convert_amount(i.net_amount, i.currency, base_currency(), i.invoice_date) AS net_base,
+                 convert_amount(i.total_amount, i.currency, base_currency(), i.invoice_date) AS gross_base,
+                 convert_amount(invoice_open_amount(i.invoice_id), i.currency, base_currency(), $2) AS open_base
+             FROM invoices i
+             WHERE i.invoice_date BETWEEN $1 AND $2 AND i.status NOT IN ('draft', 'cancelled')
+             ORDER BY i.invoice_date, i.invoice_id",
+            &[&from, &to],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| ConvertedInvoice {
+            invoice: invoice_from_row(row, Vec::new()),
+            net_base: row.get("net_base"),
+            gross_base: row.get("gross_base"),
+            open_base: row.get("open_base"),
+        })
+        .collect())
+}
+
+/// Revenue of the invoices dated between `from` and `to` except drafts and
+/// cancelled ones, converted at the rate of their invoice date, and the
+/// receivables open on `today`.
+pub async fn get_revenue_figures(
+    config: &DbConfig,
+    from: NaiveDate,
+    to: NaiveDate,
+    today: NaiveDate,
+) -> Result<RevenueFigures, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT date_trunc('month', i.invoice_date)::DATE AS month,
+                 COALESCE(SUM(convert_amount(i.net_amount, i.currency, base_currency(), i.invoice_date)), 0) AS revenue,
+                 COUNT(*) FILTER (
+                     WHERE convert_amount(i.net_amount, i.currency, base_currency(), i.invoice_date) IS NULL
+                 ) AS missing
+             FROM invoices i
+             WHERE i.invoice_date BETWEEN $1 AND $2 AND i.status NOT IN ('draft', 'cancelled')
+             GROUP BY 1 ORDER BY 1",
+            &[&from, &to],
+        )
+        .await?;
+    let monthly_revenue = rows.iter().map(|row| (row.get("month"), row.get("revenue"))).collect();
+    let missing_rates = rows.iter().map(|row| row.get::<_, i64>("missing")).sum();
+
+    let top_customers = client
+        .query(
+            "SELECT c.company_name, SUM(convert_amount(i.net_amount, i.currency, base_currency(), i.invoice_date)) AS revenue
+             FROM invoices i JOIN customers c ON c.customer_id = i.customer_id
+             WHERE i.invoice_date BETWEEN $1 AND $2 AND i.status NOT IN ('draft', 'cancelled')
+             GROUP BY c.customer_id, c.company_name
+             HAVING SUM(convert_amount(i.net_amount, i.currency, base_currency(), i.invoice_date)) > 0
+             ORDER BY revenue DESC, c.company_name LIMIT 10",
+            &[&from, &to],
+        )
+        .await?
+        .iter()
+        .map(|row| (row.get("company_name"), row.get("revenue")))
+        .collect();
+
+    let top_products = client
+        .query(
+            "SELECT p.product_name, SUM(it.quantity)::BIGINT AS quantity
+             FROM invoice_items it
+             JOIN invoices i ON i.invoice_id = it.invoice_id
+             JOIN products p ON p.product_id = it.product_id
+             WHERE i.invoice_date BETWEEN $1 AND $2 AND i.status NOT IN ('draft', 'cancelled')
+             GROUP BY p.product_id, p.product_name
+             HAVING SUM(it.quantity) > 0
+             ORDER BY quantity DESC, p.product_name LIMIT 10",
+            &[&from, &to],
+        )
+        .await?
+        .iter()
+        .map(|row| (row.get("product_name"), row.get("quantity")))
+        .collect();
+
+    let row = client
+        .query_one(
+            "SELECT COUNT(*) AS open_invoices, COALESCE(SUM(open_base), 0) AS open_amount,
+                 COALESCE(SUM(open_base) FILTER (WHERE due_date < $1), 0) AS overdue_amount
+             FROM (
+                 SELECT i.due_date, invoice_open_amount(i.invoice_id) AS open_amount,
+                     convert_amount(invoice_open_amount(i.invoice_id), i.currency, base_currency(), $1) AS open_base
+                 FROM invoices i
+                 WHERE i.status NOT IN ('draft', 'cancelled')
+             ) open WHERE open_amount > 0",
+            &[&today],
+        )
+        .await?;
+
+    Ok(RevenueFigures {
+        monthly_revenue,
+        top_customers,
+        top_products,
+        missing_rates,
+        open_invoices: row.get("open_invoices"),
+        open_amount: row.get("open_amount"),
+        overdue_amount: row.get("overdue_amount"),
+    })
+}
+
+/// Contacts made between `from` and `to` and the follow-ups due from
+/// `today` on.
+pub async fn get_activity_figures(
+    config: &DbConfig,
+    from: NaiveDate,
+    to: NaiveDate,
+    today: NaiveDate,
+) -> Result<ActivityFigures, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let contacts_by_type = client
+        .query(
+            "SELECT contact_type, COUNT(*) AS contacts FROM contact_history
+             WHERE contact_date::DATE BETWEEN $1 AND $2
+             GROUP BY contact_type ORDER BY contacts DESC, contact_type",
+            &[&from, &to],
+        )
+        .await?
+        .iter()
+        .map(|row| (row.get("contact_type"), row.get("contacts")))
+        .collect();
+    let follow_ups = client
+        .query(
+            "SELECT h.follow_up_date, c.company_name, h.contact_type, COALESCE(h.notes, '') AS notes
+             FROM contact_history h JOIN customers c ON c.customer_id = h.customer_id
+             WHERE h.follow_up_date >= $1
+             ORDER BY h.follow_up_date, c.company_name LIMIT 20",
+            &[&today],
+        )
+        .await?
+        .iter()
+        .map(|row| FollowUp {
+            follow_up_date: row.get("follow_up_date"),
+            company_name: row.get("company_name"),
+            contact_type: row.get("contact_type"),
+            notes: row.get("notes"),
+        })
+        .collect();
+    Ok(ActivityFigures {
+        contacts_by_type,
+        follow_ups,
+    })
+}
+
+/// Invoices (except drafts and cancelled ones) and payments dated between
+/// `from` and `to`, for a booking export.
+pub async fn get_bookings(
+    config: &DbConfig,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<(Vec<BookedInvoice>, Vec<BookedPayment>), Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let item_rows = client
+        .query(
+            "SELECT it.* FROM invoice_items it JOIN invoices i ON i.invoice_id = it.invoice_id
+             WHERE i.invoice_date BETWEEN $1 AND $2 ORDER BY it.item_id",
+            &[&from, &to],
+        )
+        .await?;
+    let mut items: HashMap<i32, Vec<LineItem>> = HashMap::new();
+    for row in &item_rows {
+        items.entry(row.get("invoice_id")).or_default().push(line_item_from_row(row));
+    }
+    let rows = client
+        .query(
+            "SELECT i.*, exchange_rate(i.currency, i.invoice_date) AS exchange_rate
+             FROM invoices i
+             WHERE i.invoice_date BETWEEN $1 AND $2 AND i.status NOT IN ('draft', 'cancelled')
+             ORDER BY i.invoice_date, i.invoice_id",
+            &[&from, &to],
+        )
+        .await?;
+    let invoices = rows
+        .iter()
+        .map(|row| {
+            let invoice_id: i32 = row.get("invoice_id");
+            BookedInvoice {
+                invoice: invoice_from_row(row, items.remove(&invoice_id).unwrap_or_default()),
+                exchange_rate: row.get("exchange_rate"),
+            }
+        })
+        .collect();
+
+    let rows = client
+        .query(
+            "SELECT p.payment_date, p.amount, p.currency, i.invoice_number, i.customer_id,
+                 exchange_rate(p.currency, p.payment_date) AS exchange_rate
+             FROM payments p JOIN invoices i ON i.invoice_id = p.invoice_id
+             WHERE p.payment_date BETWEEN $1 AND $2
+             ORDER BY p.payment_date, p.payment_id",
+            &[&from, &to],
+        )
+        .await?;
+    let payments = rows
+        .iter()
+        .map(|row| BookedPayment {
+            payment_date: row.get("payment_date"),
+            amount: row.get("amount"),
+            currency: row.get("currency"),
+            invoice_number: row.get("invoice_number"),
+            customer_id: row.get("customer_id"),
+            exchange_rate: row.get("exchange_rate"),
+        })
+        .collect();
+    Ok((invoices, payments))
+}
+
+/// Sent invoices in euros with an open amount whose customer has a
+/// mandate and which have not been collected yet.
+pub async fn get_direct_debit_candidates(config: &DbConfig) -> Result<Vec<DirectDebit>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT * FROM (
+                 SELECT i.invoice_id, i.invoice_number, c.customer_id, c.company_name, c.iban, c.bic,
+                     c.mandate_reference, c.mandate_date,
+                     invoice_open_amount(i.invoice_id) AS open_amount,
+                     CASE WHEN EXISTS (
+                         SELECT 1 FROM direct_debits d
+                         WHERE d.customer_id = c.customer_id AND d.mandate_reference = c.mandate_reference
+                     ) THEN 'RCUR' ELSE 'FRST' END AS sequence_type,
+                     i.due_date
+                 FROM invoices i JOIN customers c ON c.customer_id = i.customer_id
+                 WHERE i.status = 'sent' AND i.currency = 'EUR'
+                     AND c.iban <> '' AND c.mandate_reference <> '' AND c.mandate_date IS NOT NULL
+                     AND NOT EXISTS (SELECT 1 FROM direct_debits d WHERE d.invoice_id = i.invoice_id)
+             ) candidates
+             WHERE open_amount > 0
+             ORDER BY due_date, invoice_number",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| DirectDebit {
+            invoice_id: row.get("invoice_id"),
+            invoice_number: row.get("invoice_number"),
+            customer_id: row.get("customer_id"),
+            debtor_name: row.get("company_name"),
+            iban: row.get("iban"),
+            bic: row.get("bic"),
+            mandate_reference: row.get("mandate_reference"),
+            mandate_date: row.get("mandate_date"),
+            amount: row.get("open_amount"),
+            sequence_type: row.get("sequence_type"),
+        })
+        .collect())
+}
+
+/// Records the collections of a direct debit file so that the invoices are
+/// not collected again.
+pub async fn record_direct_debits(
+    config: &DbConfig,
+    debits: &[DirectDebit],
+    message_id: &str,
+    collection_date: NaiveDate,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    for debit in debits {
+        let row = transaction
+            .query_one(
+                "INSERT INTO direct_debits (invoice_id, customer_id, mandate_reference, amount, sequence_type,
+                     collection_date, message_id, end_to_end_id)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING direct_debit_id",
+                &[
+                    &debit.invoice_id,
+                    &debit.customer_id,
+                    &debit.mandate_reference,
+                    &debit.amount,
+                    &debit.sequence_type,
+                    &collection_date,
+                    &message_id,
+                    &sepa::end_to_end_id(message_id, debit.invoice_id),
+                ],
+            )
+            .await?;
+        record_audit(&transaction, config, "direct_debits", row.get(0), "INSERT", None, Some(debit)).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn open_invoices(transaction: &Transaction<'_>) -> Result<Vec<OpenInvoice>, Box<dyn std::error::Error>> {
+    let rows = transaction
+        .query(
+            "SELECT * FROM (
+                 SELECT i.invoice_id, i.invoice_number, COALESCE(c.company_name, '') AS company_name, i.currency,
+                     invoice_open_amount(i.invoice_id) AS open_amount
+                 FROM invoices i LEFT JOIN customers c ON c.customer_id = i.customer_id
+                 WHERE i.status = 'sent'
+             ) open WHERE open_amount > 0
+             ORDER BY invoice_number",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| OpenInvoice {
+            invoice_id: row.get("invoice_id"),
+            invoice_number: row.get("invoice_number"),
+            company_name: row.get("company_name"),
+            currency: row.get("currency"),
+            open_amount: row.get("open_amount"),
+        })
+        .collect())
+}
 
-    println!("Database structure created successfully");
-    Ok(())
+pub async fn get_open_invoices(config: &DbConfig) -> Result<Vec<OpenInvoice>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    open_invoices(&transaction).await
 }
 
-pub async fn add_customer(
+/// Books `entry` as a payment of the invoice and marks the invoice paid once
+/// its payments cover it. Returns false if the entry was booked before.
+async fn book_entry(
+    transaction: &Transaction<'_>,
     config: &DbConfig,
-    customer: &Customer,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let conn_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database
-    );
+    entry: &StatementEntry,
+    invoice_id: i32,
+    payment_method: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let inserted = transaction
+        .query_opt(
+            "INSERT INTO payments (invoice_id, payment_date, amount, payment_method, transaction_id, notes, currency, bank_reference)
+             VALUES ($1, $2, $3, $4, NULLIF($5, ''), NULLIF($6, ''), $7, $8)
+             ON CONFLICT (bank_reference) WHERE bank_reference IS NOT NULL DO NOTHING
+             RETURNING payment_id",
+            &[
+                &invoice_id,
+                &entry.booking_date,
+                &entry.amount,
+                &payment_method,
+                &entry.end_to_end_id,
+                &entry.remittance,
+                &entry.currency,
+                &entry.bank_reference,
+            ],
+        )
+        .await?;
+    let Some(inserted) = inserted else {
+        return Ok(false);
+    };
+    let payment = serde_json::json!({
+        "invoice_id": invoice_id,
+        "payment_date": entry.booking_date,
+        "amount": entry.amount,
+        "currency": entry.currency,
+        "payment_method": payment_method,
+        "transaction_id": entry.end_to_end_id,
+        "notes": entry.remittance,
+        "bank_reference": entry.bank_reference,
+    });
+    record_audit(transaction, config, "payments", inserted.get("payment_id"), "INSERT", None, Some(&payment)).await?;
+    let paid = transaction
+        .query_opt(
+            "UPDATE invoices SET status = 'paid', version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE invoice_id = $1 AND status = 'sent' AND invoice_open_amount($1) <= 0
+             RETURNING *",
+            &[&invoice_id],
+        )
+        .await?;
+    if let Some(row) = paid {
+        let items = line_items(transaction, "invoice_items", "invoice_id", invoice_id).await?;
+        let after = invoice_from_row(&row, items);
+        let before = Invoice {
+            status: "sent".to_string(),
+            version: after.version - 1,
+            ..after.clone()
+        };
+        record_audit(transaction, config, "invoices", invoice_id, "UPDATE", Some(&before), Some(&after)).await?;
+    }
+    Ok(true)
+}
 
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+/// Books the credits of a bank statement that match an open invoice as its
+/// payments; see `sepa::match_invoice`.
+pub async fn import_statement(
+    config: &DbConfig,
+    entries: &[StatementEntry],
+) -> Result<StatementImport, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let mut open = open_invoices(&transaction).await?;
+    let debit_rows = transaction
+        .query(
+            "SELECT d.end_to_end_id, d.invoice_id, i.invoice_number
+             FROM direct_debits d JOIN invoices i ON i.invoice_id = d.invoice_id",
+            &[],
+        )
+        .await?;
+    let debits: HashMap<String, (i32, String)> = debit_rows
+        .iter()
+        .map(|row| (row.get(0), (row.get(1), row.get(2))))
+        .collect();
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
+    let mut result = StatementImport::default();
+    for entry in entries {
+        let known = transaction
+            .query_opt("SELECT 1 FROM payments WHERE bank_reference = $1", &[&entry.bank_reference])
+            .await?;
+        if known.is_some() {
+            result.already_imported += 1;
+            continue;
         }
-    });
+        let (invoice_id, invoice_number, method) = match debits.get(&entry.end_to_end_id) {
+            Some((invoice_id, number)) => (*invoice_id, number.clone(), "SEPA direct debit"),
+            None => match sepa::match_invoice(entry, &open) {
+                Some(invoice) => (invoice.invoice_id, invoice.invoice_number.clone(), "Bank transfer"),
+                None => {
+                    result.unmatched.push(entry.clone());
+                    continue;
+                }
+            },
+        };
+        book_entry(&transaction, config, entry, invoice_id, method).await?;
+        if let Some(invoice) = open.iter_mut().find(|i| i.invoice_id == invoice_id) {
+            invoice.open_amount -= entry.amount;
+        }
+        result.booked.push((entry.clone(), invoice_number));
+    }
+    transaction.commit().await?;
+    Ok(result)
+}
 
-    let statement = "
-        INSERT INTO customers (company_name, contact_name, contact_position, address, city, postal_code, country, phone, email, website)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-    ";
+/// Books a statement entry the import could not match as a payment of
+/// the chosen invoice.
+pub async fn book_statement_entry(
+    config: &DbConfig,
+    entry: &StatementEntry,
+    invoice_id: i32,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let booked = book_entry(&transaction, config, entry, invoice_id, "Bank transfer").await?;
+    transaction.commit().await?;
+    Ok(booked)
+}
 
-    client
-        .execute(
-            statement,
+fn company_profile_from_row(row: &Row) -> CompanyProfile {
+    CompanyProfile {
+        company_name: row.get("company_name"),
+        street: row.get("street"),
+        postal_code: row.get("postal_code"),
+        city: row.get("city"),
+        country_code: row.get("country_code"),
+        vat_id: row.get("vat_id"),
+        tax_number: row.get("tax_number"),
+        contact_name: row.get("contact_name"),
+        phone: row.get("phone"),
+        email: row.get("email"),
+        iban: row.get("iban"),
+        bic: row.get("bic"),
+        datev_consultant_number: row.get("datev_consultant_number"),
+        datev_client_number: row.get("datev_client_number"),
+        datev_fiscal_year_start: row.get("datev_fiscal_year_start"),
+        datev_bank_account: row.get("datev_bank_account"),
+        creditor_id: row.get("creditor_id"),
+        version: row.get("version"),
+    }
+}
+
+pub async fn get_company_profile(config: &DbConfig) -> Result<CompanyProfile, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let row = client
+        .query_one("SELECT * FROM company_profile WHERE company_id = 1", &[])
+        .await?;
+    Ok(company_profile_from_row(&row))
+}
+
+/// Saves the profile unless it was changed by someone else since it was loaded.
+pub async fn save_company_profile(
+    config: &DbConfig,
+    profile: &CompanyProfile,
+) -> Result<SaveResult<CompanyProfile>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one("SELECT * FROM company_profile WHERE company_id = 1 FOR UPDATE", &[])
+        .await?;
+    let current = company_profile_from_row(&row);
+    if current.version != profile.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+    let row = transaction
+        .query_one(
+            "UPDATE company_profile
+             SET company_name = $1, street = $2, postal_code = $3, city = $4, country_code = $5,
+                 vat_id = $6, tax_number = $7, contact_name = $8, phone = $9, email = $10,
+                 iban = $11, bic = $12, datev_consultant_number = $13, datev_client_number = $14,
+                 datev_fiscal_year_start = $15, datev_bank_account = $16, creditor_id = $17,
+                 version = version + 1
+             WHERE company_id = 1 RETURNING *",
             &[
-                &customer.company_name,
-                &customer.contact_name,
-                &customer.contact_position,
-                &customer.address,
-                &customer.city,
-                &customer.postal_code,
-                &customer.country,
-                &customer.phone,
-                &customer.email,
-                &customer.website,
+                &profile.company_name,
+                &profile.street,
+                &profile.postal_code,
+                &profile.city,
+                &profile.country_code,
+                &profile.vat_id,
+                &profile.tax_number,
+                &profile.contact_name,
+                &profile.phone,
+                &profile.email,
+                &profile.iban,
+                &profile.bic,
+                &profile.datev_consultant_number,
+                &profile.datev_client_number,
+                &profile.datev_fiscal_year_start,
+                &profile.datev_bank_account,
+                &profile.creditor_id,
             ],
         )
         .await?;
-
-    println!("Customer added successfully");
-    Ok(())
+    let saved = company_profile_from_row(&row);
+    record_audit(&transaction, config, "company_profile", 1, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(SaveResult::Saved(saved))
 }
 
-pub async fn get_customers(config: &DbConfig) -> Result<Vec<Customer>, Box<dyn std::error::Error>> {
-    let conn_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database
-    );
-
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
-
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
+fn number_sequence_from_row(row: &Row) -> NumberSequence {
+    NumberSequence {
+        sequence_id: row.get("sequence_id"),
+        document_type: row.get("document_type"),
+        pattern: row.get("pattern"),
+        yearly_reset: row.get("yearly_reset"),
+        next_number: row.get("next_number"),
+        current_year: row.get("current_year"),
+        version: row.get("version"),
+    }
+}
 
+pub async fn get_number_sequences(config: &DbConfig) -> Result<Vec<NumberSequence>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
     let rows = client
-        .query("SELECT * FROM customers ORDER BY company_name", &[])
+        .query("SELECT * FROM number_sequences ORDER BY sequence_id", &[])
         .await?;
-
-    let customers: Vec<Customer> = rows
-        .iter()
-        .map(|row| Customer {
-            company_name: row.get("company_name"),
-            contact_name: row.get("contact_name"),
-            contact_position: row.get("contact_position"),
-            address: row.get("address"),
-            city: row.get("city"),
-            postal_code: row.get("postal_code"),
-            country: row.get("country"),
-            phone: row.get("phone"),
-            email: row.get("email"),
-            website: row.get("website"),
-            customer_id: row.get("customer_id"),
-        })
-        .collect();
-
-    Ok(customers)
+    Ok(rows.iter().map(number_sequence_from_row).collect())
 }
 
-pub async fn get_contact_history(
+/// Saves the pattern, reset and next number of a sequence unless it was
+/// changed, or a number taken from it, since it was loaded.
+pub async fn save_number_sequence(
     config: &DbConfig,
-    customer_id: i32,
-) -> Result<Vec<ContactHistory>, Box<dyn std::error::Error>> {
-    let conn_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database
-    );
+    sequence: &NumberSequence,
+) -> Result<SaveResult<NumberSequence>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_opt(
+            "SELECT * FROM number_sequences WHERE sequence_id = $1 FOR UPDATE",
+            &[&sequence.sequence_id],
+        )
+        .await?
+        .ok_or_else(|| format!("Number sequence {} no longer exists", sequence.document_type))?;
+    let current = number_sequence_from_row(&row);
+    if current.version != sequence.version {
+        return Ok(SaveResult::Conflict(current));
+    }
+    let row = transaction
+        .query_one(
+            "UPDATE number_sequences SET pattern = $2, yearly_reset = $3, next_number = $4, version = version + 1
+             WHERE sequence_id = $1 RETURNING *",
+            &[&sequence.sequence_id, &sequence.pattern, &sequence.yearly_reset, &sequence.next_number],
+        )
+        .await?;
+    let saved = number_sequence_from_row(&row);
+    record_audit(&transaction, config, "number_sequences", saved.sequence_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(SaveResult::Saved(saved))
+}
 
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+fn contact_from_row(row: &Row) -> Contact {
+    Contact {
+        contact_id: row.get("contact_id"),
+        customer_id: row.get("customer_id"),
+        first_name: row.get("first_name"),
+        last_name: row.get("last_name"),
+        email: row.get::<_, Option<String>>("email").unwrap_or_default(),
+        phone: row.get::<_, Option<String>>("phone").unwrap_or_default(),
+        position: row.get::<_, Option<String>>("position").unwrap_or_default(),
+        is_primary: row.get::<_, Option<bool>>("is_primary").unwrap_or(false),
+    }
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
+fn customer_note_from_row(row: &Row) -> CustomerNote {
+    CustomerNote {
+        note_id: row.get("note_id"),
+        customer_id: row.get("customer_id"),
+        note: row.get("note"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+    }
+}
 
-    let rows = client
+pub async fn get_customer_overview(
+    config: &DbConfig,
+    customer_id: i32,
+    with_invoices: bool,
+) -> Result<CustomerOverview, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let row = client
+        .query_opt("SELECT * FROM customers WHERE customer_id = $1", &[&customer_id])
+        .await?
+        .ok_or_else(|| format!("Customer {} no longer exists", customer_id))?;
+    let customer = customer_from_row(&row);
+    let contacts = client
+        .query(
+            "SELECT * FROM contacts WHERE customer_id = $1 ORDER BY is_primary DESC, last_name, first_name",
+            &[&customer_id],
+        )
+        .await?
+        .iter()
+        .map(contact_from_row)
+        .collect();
+    let history = client
         .query(
             "SELECT * FROM contact_history WHERE customer_id = $1 ORDER BY contact_date DESC",
             &[&customer_id],
         )
-        .await?;
-
-    let history: Vec<ContactHistory> = rows
+        .await?
         .iter()
-        .map(|row| {
-            let contact_date: DateTime<Utc> = row.get("contact_date");
-            let follow_up_date: Option<NaiveDate> = row.get("follow_up_date");
-            let created_at: DateTime<Utc> = row.get("created_at");
-            let updated_at: DateTime<Utc> = row.get("updated_at");
-
-            ContactHistory {
-                history_id: row.get("history_id"),
-                customer_id: row.get("customer_id"),
-                contact_type: row.get("contact_type"),
-                contact_date,
-                contact_duration: row.get("contact_duration"),
-                contact_method: row.get("contact_method"),
-                contact_outcome: row.get("contact_outcome"),
-                notes: row.get("notes"),
-                follow_up_date,
-                created_by: row.get("created_by"),
-                created_at,
-                updated_at,
-            }
-        })
+        .map(contact_history_from_row)
+        .collect();
+    let deals = client
+        .query(
+            "SELECT d.*, s.name AS stage_name FROM deals d JOIN deal_stages s ON s.stage_id = d.stage_id
+             WHERE d.customer_id = $1 ORDER BY d.expected_close_date, d.deal_id",
+            &[&customer_id],
+        )
+        .await?
+        .iter()
+        .map(|row| (deal_from_row(row), row.get("stage_name")))
+        .collect();
+    let notes = client
+        .query(
+            "SELECT * FROM customer_notes WHERE customer_id = $1 ORDER BY created_at DESC, note_id DESC",
+            &[&customer_id],
+        )
+        .await?
+        .iter()
+        .map(customer_note_from_row)
         .collect();
 
-    Ok(history)
+    let (mut invoices, mut payments) = (Vec::new(), Vec::new());
+    if with_invoices {
+        invoices = client
+            .query(
+                "SELECT i.*, invoice_open_amount(i.invoice_id) AS open_amount FROM invoices i
+                 WHERE i.customer_id = $1
+                 ORDER BY i.invoice_date DESC, i.invoice_number DESC",
+                &[&customer_id],
+            )
+            .await?
+            .iter()
+            .map(|row| (invoice_from_row(row, Vec::new()), row.get("open_amount")))
+            .collect();
+        payments = client
+            .query(
+                "SELECT p.*, i.invoice_number FROM payments p JOIN invoices i ON i.invoice_id = p.invoice_id
+                 WHERE i.customer_id = $1
+                 ORDER BY p.payment_date DESC, p.payment_id DESC",
+                &[&customer_id],
+            )
+            .await?
+            .iter()
+            .map(|row| Payment {
+                invoice_number: row.get("invoice_number"),
+                payment_date: row.get("payment_date"),
+                amount: row.get("amount"),
+                currency: row.get("currency"),
+                payment_method: row.get("payment_method"),
+                notes: row.get::<_, Option<String>>("notes").unwrap_or_default(),
+            })
+            .collect();
+    }
+
+    Ok(CustomerOverview {
+        customer,
+        contacts,
+        history,
+        invoices,
+        payments,
+        deals,
+        notes,
+    })
 }
 
-pub async fn get_customer_with_history(
+pub async fn add_contact(config: &DbConfig, contact: &Contact) -> Result<Contact, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO contacts (customer_id, first_name, last_name, email, phone, position, is_primary)
+             VALUES ($1, $2, $3, NULLIF($4, ''), NULLIF($5, ''), NULLIF($6, ''), $7)
+             RETURNING *",
+            &[
+                &contact.customer_id,
+                &contact.first_name,
+                &contact.last_name,
+                &contact.email,
+                &contact.phone,
+                &contact.position,
+                &contact.is_primary,
+            ],
+        )
+        .await?;
+    let added = contact_from_row(&row);
+    if added.is_primary {
+        transaction
+            .execute(
+                "UPDATE contacts SET is_primary = false WHERE customer_id = $1 AND contact_id <> $2 AND is_primary",
+                &[&added.customer_id, &added.contact_id],
+            )
+            .await?;
+    }
+    record_audit(&transaction, config, "contacts", added.contact_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
+
+pub async fn add_customer_note(
     config: &DbConfig,
     customer_id: i32,
-) -> Result<(Customer, Vec<ContactHistory>), Box<dyn std::error::Error>> {
-    let conn_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database
-    );
-
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
-
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
-
-    println!("Fetching customer data for customer_id: {}", customer_id);
-    let customer_row = client
+    note: &str,
+) -> Result<CustomerNote, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
         .query_one(
-            "SELECT * FROM customers WHERE customer_id = $1",
-            &[&customer_id],
+            "INSERT INTO customer_notes (customer_id, note, created_by) VALUES ($1, $2, $3) RETURNING *",
+            &[&customer_id, &note, &acting_user(config)],
         )
         .await?;
+    let added = customer_note_from_row(&row);
+    record_audit(&transaction, config, "customer_notes", added.note_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
 
-    let customer = Customer {
-        customer_id: customer_row.get("customer_id"),
-        company_name: customer_row.get("company_name"),
-        contact_name: customer_row.get("contact_name"),
-        email: customer_row.get("email"),
-        phone: customer_row.get("phone"),
-        address: customer_row.get("address"),
-        city: customer_row.get("city"),
-        postal_code: customer_row.get("postal_code"),
-        country: customer_row.get("country"),
-        website: customer_row.get("website"),
-        contact_position: customer_row.get("contact_position"),
-    };
+/// Everything that happened with a customer between `from` and `to`, newest
+/// first: contacts, invoices and quotes created or changing status, payments
+/// and changes to the customer's records. Invoices and payments are left out
+/// unless `with_invoices`.
+pub async fn get_customer_timeline(
+    config: &DbConfig,
+    customer_id: i32,
+    from: NaiveDate,
+    to: NaiveDate,
+    with_invoices: bool,
+) -> Result<Vec<TimelineEvent>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let mut events = Vec::new();
 
-    println!("Fetched customer: {:?}", customer);
+    for row in client
+        .query(
+            "SELECT * FROM contact_history WHERE customer_id = $1 AND contact_date::date BETWEEN $2 AND $3",
+            &[&customer_id, &from, &to],
+        )
+        .await?
+    {
+        let entry = contact_history_from_row(&row);
+        let mut summary = format!("{}: {}", entry.contact_type, entry.contact_outcome);
+        if let Some(method) = entry.contact_method.as_deref().filter(|m| !m.is_empty()) {
+            summary.push_str(&format!(" (via {})", method));
+        }
+        let mut details = entry.notes;
+        if let Some(date) = entry.follow_up_date {
+            details = format!("{}\nFollow-up on {}", details, date).trim().to_string();
+        }
+        events.push(TimelineEvent {
+            occurred_at: entry.contact_date,
+            all_day: false,
+            kind: "Contact",
+            summary,
+            details,
+            user: entry.created_by,
+        });
+    }
 
-    println!("Fetching contact history for customer_id: {}", customer_id);
-    let history_rows = client
+    // Invoices and quotes created before changes were audited have no
+    // INSERT entry, so creation is taken from the records themselves.
+    if with_invoices {
+        for row in client
+            .query(
+                "SELECT i.*, COALESCE(i.created_at::timestamptz, i.invoice_date::timestamptz) AS created,
+                        COALESCE(a.changed_by, '') AS created_by
+                 FROM invoices i
+                 LEFT JOIN audit_log a ON a.table_name = 'invoices' AND a.record_id = i.invoice_id AND a.action = 'INSERT'
+                 WHERE i.customer_id = $1
+                   AND COALESCE(i.created_at::date, i.invoice_date) BETWEEN $2 AND $3",
+                &[&customer_id, &from, &to],
+            )
+            .await?
+        {
+            let invoice = invoice_from_row(&row, Vec::new());
+            events.push(TimelineEvent {
+                occurred_at: row.get("created"),
+                all_day: false,
+                kind: "Invoice",
+                summary: format!(
+                    "{} {} created over {}",
+                    invoice.document_label(),
+                    invoice.invoice_number,
+                    format_amount(invoice.total_amount, &invoice.currency)
+                ),
+                details: format!("Dated {}, due {}", invoice.invoice_date, invoice.due_date),
+                user: row.get("created_by"),
+            });
+        }
+
+        for row in client
+            .query(
+                "SELECT p.*, i.invoice_number, p.payment_date::timestamptz AS paid_at
+                 FROM payments p JOIN invoices i ON i.invoice_id = p.invoice_id
+                 WHERE i.customer_id = $1 AND p.payment_date BETWEEN $2 AND $3",
+                &[&customer_id, &from, &to],
+            )
+            .await?
+        {
+            let amount: Decimal = row.get("amount");
+            let currency: String = row.get("currency");
+            let invoice_number: String = row.get("invoice_number");
+            let method: String = row.get("payment_method");
+            let notes: Option<String> = row.get("notes");
+            events.push(TimelineEvent {
+                occurred_at: row.get("paid_at"),
+                all_day: true,
+                kind: "Payment",
+                summary: format!("Payment of {} for {}", format_amount(amount, &currency), invoice_number),
+                details: format!("{}\n{}", method, notes.unwrap_or_default()).trim().to_string(),
+                user: String::new(),
+            });
+        }
+    }
+
+    for row in client
         .query(
-            "SELECT * FROM contact_history WHERE customer_id = $1 ORDER BY contact_date DESC",
-            &[&customer_id],
+            "SELECT q.*, COALESCE(q.created_at, q.quote_date::timestamptz) AS created,
+                    COALESCE(a.changed_by, '') AS created_by
+             FROM quotes q
+             LEFT JOIN audit_log a ON a.table_name = 'quotes' AND a.record_id = q.quote_id AND a.action = 'INSERT'
+             WHERE q.customer_id = $1
+               AND COALESCE(q.created_at::date, q.quote_date) BETWEEN $2 AND $3",
+            &[&customer_id, &from, &to],
         )
-        .await?;
+        .await?
+    {
+        let quote = quote_from_row(&row, Vec::new());
+        events.push(TimelineEvent {
+            occurred_at: row.get("created"),
+            all_day: false,
+            kind: "Quote",
+            summary: format!("Quote {} created", quote.quote_number),
+            details: format!("Dated {}, valid until {}", quote.quote_date, quote.valid_until),
+            user: row.get("created_by"),
+        });
+    }
 
-    let history: Vec<ContactHistory> = history_rows
-        .into_iter()
-        .map(|row| {
-            let contact_date: DateTime<Utc> = row.get("contact_date");
-            let follow_up_date: Option<NaiveDate> = row.get("follow_up_date");
-            let created_at: DateTime<Utc> = row.get("created_at");
-            let updated_at: DateTime<Utc> = row.get("updated_at");
-
-            ContactHistory {
-                history_id: row.get("history_id"),
-                customer_id: row.get("customer_id"),
-                contact_type: row.get("contact_type"),
-                contact_date,
-                contact_duration: row.get("contact_duration"),
-                contact_method: row.get("contact_method"),
-                contact_outcome: row.get("contact_outcome"),
-                notes: row.get("notes"),
-                follow_up_date,
-                created_by: row.get("created_by"),
-                created_at,
-                updated_at,
-            }
-        })
-        .collect();
+    // Audited rows are matched by the customer_id stored with them, so
+    // changes to records deleted since are found as well.
+    for row in client
+        .query(
+            "SELECT * FROM audit_log
+             WHERE table_name IN ('customers', 'contacts', 'customer_notes', 'deals', 'tasks', 'invoices', 'quotes')
+               AND (COALESCE(new_values, old_values) ->> 'customer_id')::int = $1
+               AND changed_at::date BETWEEN $2 AND $3",
+            &[&customer_id, &from, &to],
+        )
+        .await?
+    {
+        let table_name: String = row.get("table_name");
+        if table_name == "invoices" && !with_invoices {
+            continue;
+        }
+        let entry = AuditEntry {
+            audit_id: row.get("audit_id"),
+            action: row.get("action"),
+            old_values: row.get("old_values"),
+            new_values: row.get("new_values"),
+            changed_by: row.get("changed_by"),
+            changed_at: row.get("changed_at"),
+        };
+        if let Some(event) = audit_event(&table_name, entry) {
+            events.push(event);
+        }
+    }
 
-    println!("Fetched {} contact history entries", history.len());
+    events.sort_by_key(|event| std::cmp::Reverse(event.occurred_at));
+    Ok(events)
+}
 
-    Ok((customer, history))
+/// Timeline entry for an audited change: status changes of invoices and
+/// quotes, and any change to the customer's other records. Creation of
+/// invoices and quotes and edits to them are left to the records.
+fn audit_event(table_name: &str, entry: AuditEntry) -> Option<TimelineEvent> {
+    let changes = audit::changed_fields(&entry);
+    let record = entry.new_values.as_ref().or(entry.old_values.as_ref());
+    let field = |name: &str| {
+        record
+            .and_then(|values| values.get(name))
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or("")
+            .to_string()
+    };
+    let verb = match entry.action.as_str() {
+        "INSERT" => "added",
+        "DELETE" => "deleted",
+        _ => "changed",
+    };
+    let (kind, summary) = match table_name {
+        "invoices" | "quotes" => {
+            let (kind, label, number) = if table_name == "quotes" {
+                ("Quote", "Quote", field("quote_number"))
+            } else if field("document_type") == "credit_note" {
+                ("Invoice", "Credit note", field("invoice_number"))
+            } else {
+                ("Invoice", "Invoice", field("invoice_number"))
+            };
+            match entry.action.as_str() {
+                "DELETE" => ("Change", format!("{} {} deleted", label, number)),
+                "UPDATE" => {
+                    let (_, old, new) = changes.iter().find(|(name, _, _)| name == "status")?;
+                    (kind, format!("{} {} {} (was {})", label, number, new, old))
+                }
+                _ => return None,
+            }
+        }
+        "customers" if entry.action == "INSERT" => ("Change", "Customer created".to_string()),
+        "customers" => ("Change", "Master data changed".to_string()),
+        "contacts" => (
+            "Change",
+            format!("Contact person {} {} {}", field("first_name"), field("last_name"), verb),
+        ),
+        "customer_notes" => ("Change", format!("Note {}", verb)),
+        "deals" => ("Change", format!("Deal {} {}", field("title"), verb)),
+        "tasks" => ("Change", format!("Task {} {}", field("title"), verb)),
+        _ => return None,
+    };
+    let details = match (table_name, entry.action.as_str()) {
+        ("customer_notes", _) => field("note"),
+        (_, "UPDATE") => changes
+            .iter()
+            .map(|(name, old, new)| format!("{}: {} -> {}", name, old, new))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    };
+    if entry.action == "UPDATE" && changes.is_empty() {
+        return None;
+    }
+    Some(TimelineEvent {
+        occurred_at: entry.changed_at,
+        all_day: false,
+        kind,
+        summary,
+        details,
+        user: entry.changed_by,
+    })
 }
 
+fn task_from_row(row: &Row) -> Task {
+    Task {
+        task_id: row.get("task_id"),
+        title: row.get("title"),
+        description: row.get("description"),
+        customer_id: row.get("customer_id"),
+        history_id: row.get("history_id"),
+        assignee: row.get("assignee"),
+        due_date: row.get("due_date"),
+        priority: row.get("priority"),
+        status: row.get("status"),
+        created_by: row.get("created_by"),
+        version: row.get("version"),
+    }
+}
 
-pub async fn add_contact_history(config: &DbConfig, history: &ContactHistory) -> Result<(), Box<dyn std::error::Error>> {
-    let conn_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database
-    );
+/// All tasks, unfinished ones first, by due date and priority.
+pub async fn get_tasks(config: &DbConfig) -> Result<Vec<Task>, Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let rows = client
+        .query(
+            "SELECT * FROM tasks
+             ORDER BY status = 'done', due_date NULLS LAST,
+                      CASE priority WHEN 'high' THEN 0 WHEN 'normal' THEN 1 ELSE 2 END, task_id",
+            &[],
+        )
+        .await?;
+    Ok(rows.iter().map(task_from_row).collect())
+}
 
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls).await?;
+pub async fn add_task(config: &DbConfig, task: &Task) -> Result<Task, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_one(
+            "INSERT INTO tasks (title, description, customer_id, history_id, assignee, due_date, priority, status, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *",
+            &[
+                &task.title,
+                &task.description,
+                &task.customer_id,
+                &task.history_id,
+                &task.assignee,
+                &task.due_date,
+                &task.priority,
+                &task.status,
+                &acting_user(config),
+            ],
+        )
+        .await?;
+    let added = task_from_row(&row);
+    record_audit(&transaction, config, "tasks", added.task_id, "INSERT", None, Some(&added)).await?;
+    transaction.commit().await?;
+    Ok(added)
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
+/// Saves `task` unless it was changed by someone else since it was loaded.
+pub async fn update_task(
+    config: &DbConfig,
+    task: &Task,
+) -> Result<SaveResult<Task>, Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
 
-    let statement = "
-        INSERT INTO contact_history (customer_id, contact_type, contact_date, contact_duration, contact_method, contact_outcome, notes, follow_up_date, created_by, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
-    ";
+    let current = transaction
+        .query_opt("SELECT * FROM tasks WHERE task_id = $1 FOR UPDATE", &[&task.task_id])
+        .await?
+        .as_ref()
+        .map(task_from_row)
+        .ok_or_else(|| format!("Task {} no longer exists", task.task_id))?;
+    if current.version != task.version {
+        return Ok(SaveResult::Conflict(current));
+    }
 
-    client.execute(statement, &[
-        &history.customer_id,
-        &history.contact_type,
-        &history.contact_date,
-        &history.contact_duration,
-        &history.contact_method,
-        &history.contact_outcome,
-        &history.notes,
-        &history.follow_up_date,
-        &history.created_by,
-        &history.created_at,
-        &history.updated_at,
-    ]).await?;
+    let row = transaction
+        .query_one(
+            "UPDATE tasks
+             SET title = $1, description = $2, customer_id = $3, assignee = $4, due_date = $5,
+                 priority = $6, status = $7,
+                 version = version + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE task_id = $8
+             RETURNING *",
+            &[
+                &task.title,
+                &task.description,
+                &task.customer_id,
+                &task.assignee,
+                &task.due_date,
+                &task.priority,
+                &task.status,
+                &task.task_id,
+            ],
+        )
+        .await?;
+    let saved = task_from_row(&row);
+    record_audit(&transaction, config, "tasks", saved.task_id, "UPDATE", Some(&current), Some(&saved)).await?;
+    transaction.commit().await?;
+    Ok(SaveResult::Saved(saved))
+}
 
+pub async fn delete_task(config: &DbConfig, task_id: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect(config).await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_opt("DELETE FROM tasks WHERE task_id = $1 RETURNING *", &[&task_id])
+        .await?;
+    if let Some(row) = row {
+        let task = task_from_row(&row);
+        record_audit(&transaction, config, "tasks", task_id, "DELETE", Some(&task), None).await?;
+    }
+    transaction.commit().await?;
     Ok(())
 }
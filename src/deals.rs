@@ -0,0 +1,579 @@
+// deals.rs
+use crate::audit::AuditPanel;
+use crate::auth::{self, Role};
+use crate::db::{self, Customer, Deal, DealStage, SaveResult};
+use crate::merge::{MergeAction, MergeDialog};
+use crate::ui;
+use chrono::Datelike;
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+const COLUMN_WIDTH: f32 = 220.0;
+
+/// Kanban board of all deals by stage, with the deal editor, the stage
+/// configuration and weighted totals per month.
+pub struct DealsView {
+    deals: Arc<Mutex<Vec<Deal>>>,
+    stages: Arc<Mutex<Vec<DealStage>>>,
+    /// Set whenever deals or stages must be (re)loaded from the database.
+    pub stale: Arc<AtomicBool>,
+    editor: Arc<Mutex<Option<Deal>>>,
+    /// The stored deal the editor started from, the base of a merge.
+    loaded: Option<Deal>,
+    merge: Arc<Mutex<Option<MergeDialog<Deal>>>>,
+    status: Arc<Mutex<String>>,
+    audit: AuditPanel,
+    /// Deal whose card is being dragged.
+    dragging: Option<i32>,
+    /// Stage definitions being edited, by stage id; 0 is the new stage.
+    stage_edits: HashMap<i32, DealStage>,
+}
+
+impl Default for DealsView {
+    fn default() -> Self {
+        Self {
+            deals: Arc::new(Mutex::new(Vec::new())),
+            stages: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            editor: Arc::new(Mutex::new(None)),
+            loaded: None,
+            merge: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(String::new())),
+            audit: AuditPanel::default(),
+            dragging: None,
+            stage_edits: HashMap::new(),
+        }
+    }
+}
+
+fn empty_stage() -> DealStage {
+    DealStage {
+        stage_id: 0,
+        name: String::new(),
+        sort_order: 0,
+        default_probability: 0,
+        active: true,
+    }
+}
+
+/// Value times probability.
+pub fn weighted_value(deal: &Deal) -> Decimal {
+    deal.value * Decimal::from(deal.probability) / Decimal::from(100)
+}
+
+impl DealsView {
+    pub fn show(&mut self, ctx: &egui::Context, customers: &Arc<Mutex<Vec<Customer>>>) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+
+        let customer_names: HashMap<i32, String> = customers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| (c.customer_id, c.company_name.clone()))
+            .collect();
+        let deals = self.deals.lock().unwrap().clone();
+        let stages = self.stages.lock().unwrap().clone();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Sales Pipeline");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            if auth::can_edit() && ui.button("Create New Deal").clicked() {
+                let first_stage = stages.iter().find(|s| s.active);
+                *self.editor.lock().unwrap() = Some(Deal {
+                    stage_id: first_stage.map_or(0, |s| s.stage_id),
+                    probability: first_stage.map_or(0, |s| s.default_probability),
+                    ..Deal::default()
+                });
+            }
+            ui.add_space(10.0);
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                self.render_board(ui, &deals, &stages, &customer_names);
+
+                ui.add_space(20.0);
+                ui.heading("Weighted Pipeline by Month");
+                render_monthly_totals(ui, &deals);
+
+                if auth::current_user().is_some_and(|u| u.role == Role::Admin) {
+                    ui.add_space(20.0);
+                    egui::CollapsingHeader::new("Pipeline Stages").show(ui, |ui| {
+                        self.render_stage_editor(ui, &stages);
+                    });
+                }
+            });
+        });
+
+        self.render_editor(ctx, &customer_names, &stages);
+        self.render_merge_dialog(ctx);
+    }
+
+    fn render_board(
+        &mut self,
+        ui: &mut egui::Ui,
+        deals: &[Deal],
+        stages: &[DealStage],
+        customer_names: &HashMap<i32, String>,
+    ) {
+        // Inactive stages stay visible as long as deals are still in them.
+        let columns: Vec<&DealStage> = stages
+            .iter()
+            .filter(|s| s.active || deals.iter().any(|d| d.stage_id == s.stage_id))
+            .collect();
+        let can_edit = auth::can_edit();
+        let pointer = ui.ctx().pointer_interact_pos();
+        let mut column_rects = Vec::new();
+        let mut open = None;
+
+        egui::ScrollArea::horizontal().id_source("deal_board").show(ui, |ui| {
+            ui.horizontal_top(|ui| {
+                for stage in &columns {
+                    let stage_deals: Vec<&Deal> =
+                        deals.iter().filter(|d| d.stage_id == stage.stage_id).collect();
+                    let frame = egui::Frame::group(ui.style()).show(ui, |ui| {
+                        ui.set_width(COLUMN_WIDTH);
+                        ui.set_min_height(300.0);
+                        ui.vertical(|ui| {
+                            ui.strong(&stage.name);
+                            let total: Decimal = stage_deals.iter().map(|d| d.value).sum();
+                            let weighted: Decimal = stage_deals.iter().map(|d| weighted_value(d)).sum();
+                            ui.label(format!(
+                                "{} deals · {:.2} (weighted {:.2})",
+                                stage_deals.len(),
+                                total,
+                                weighted
+                            ));
+                            ui.separator();
+                            for deal in stage_deals {
+                                let customer = customer_names
+                                    .get(&deal.customer_id)
+                                    .map_or("-", String::as_str);
+                                let dragged = self.dragging == Some(deal.deal_id);
+                                let card = egui::Frame::group(ui.style())
+                                    .fill(if dragged {
+                                        ui.visuals().faint_bg_color
+                                    } else {
+                                        ui.visuals().extreme_bg_color
+                                    })
+                                    .show(ui, |ui| {
+                                        ui.set_width(COLUMN_WIDTH - 20.0);
+                                        render_card(ui, deal, customer);
+                                    })
+                                    .response;
+                                let sense = if can_edit {
+                                    egui::Sense::click_and_drag()
+                                } else {
+                                    egui::Sense::click()
+                                };
+                                let response = ui
+                                    .interact(card.rect, egui::Id::new(("deal_card", deal.deal_id)), sense)
+                                    .on_hover_cursor(egui::CursorIcon::Grab);
+                                if response.drag_started() {
+                                    self.dragging = Some(deal.deal_id);
+                                }
+                                if response.clicked() {
+                                    open = Some(deal.clone());
+                                }
+                            }
+                        });
+                    });
+                    let rect = frame.response.rect;
+                    if self.dragging.is_some() && pointer.is_some_and(|p| rect.contains(p)) {
+                        ui.painter().rect_stroke(
+                            rect,
+                            4.0,
+                            egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                        );
+                    }
+                    column_rects.push((stage.stage_id, rect));
+                }
+            });
+        });
+
+        if let Some(deal) = open {
+            self.loaded = Some(deal.clone());
+            *self.editor.lock().unwrap() = Some(deal);
+        }
+        self.finish_drag(ui.ctx(), deals, stages, &column_rects, customer_names);
+    }
+
+    /// Draws the dragged card under the pointer and moves the deal to the
+    /// column it is dropped on.
+    fn finish_drag(
+        &mut self,
+        ctx: &egui::Context,
+        deals: &[Deal],
+        stages: &[DealStage],
+        column_rects: &[(i32, egui::Rect)],
+        customer_names: &HashMap<i32, String>,
+    ) {
+        let Some(deal_id) = self.dragging else {
+            return;
+        };
+        let Some(deal) = deals.iter().find(|d| d.deal_id == deal_id) else {
+            self.dragging = None;
+            return;
+        };
+        let pointer = ctx.pointer_interact_pos();
+
+        if let Some(pos) = pointer {
+            egui::Area::new("dragged_deal")
+                .order(egui::Order::Tooltip)
+                .fixed_pos(pos + egui::vec2(8.0, 8.0))
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_width(COLUMN_WIDTH - 20.0);
+                        let customer = customer_names.get(&deal.customer_id).map_or("-", String::as_str);
+                        render_card(ui, deal, customer);
+                    });
+                });
+        }
+
+        if !ctx.input(|i| i.pointer.any_released()) {
+            return;
+        }
+        self.dragging = None;
+        let target = pointer.and_then(|p| {
+            column_rects
+                .iter()
+                .find(|(_, rect)| rect.contains(p))
+                .map(|(stage_id, _)| *stage_id)
+        });
+        let Some(stage) = target.and_then(|id| stages.iter().find(|s| s.stage_id == id)) else {
+            return;
+        };
+        if stage.stage_id == deal.stage_id {
+            return;
+        }
+
+        let moved = Deal {
+            stage_id: stage.stage_id,
+            probability: stage.default_probability,
+            ..deal.clone()
+        };
+        // Show the move right away; the save replaces it with the stored row.
+        if let Some(local) = self.deals.lock().unwrap().iter_mut().find(|d| d.deal_id == deal_id) {
+            local.stage_id = moved.stage_id;
+            local.probability = moved.probability;
+        }
+        self.save_deal(Some(deal.clone()), moved);
+    }
+
+    fn render_editor(
+        &mut self,
+        ctx: &egui::Context,
+        customer_names: &HashMap<i32, String>,
+        stages: &[DealStage],
+    ) {
+        let Some(mut deal) = self.editor.lock().unwrap().clone() else {
+            return;
+        };
+
+        let mut open = true;
+        let mut save = false;
+        let mut delete = false;
+        egui::Window::new("Deal")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("deal_editor_grid").show(ui, |ui| {
+                    ui.label("Customer:");
+                    egui::ComboBox::from_id_source("deal_customer")
+                        .selected_text(customer_names.get(&deal.customer_id).cloned().unwrap_or_else(|| "-".to_string()))
+                        .show_ui(ui, |ui| {
+                            let mut names: Vec<_> = customer_names.iter().collect();
+                            names.sort_by(|a, b| a.1.cmp(b.1));
+                            for (id, name) in names {
+                                ui.selectable_value(&mut deal.customer_id, *id, name);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Title:");
+                    ui.text_edit_singleline(&mut deal.title);
+                    ui.end_row();
+
+                    ui.label("Value:");
+                    ui::parsed_field(ui, "deal_value", &mut deal.value);
+                    ui.end_row();
+
+                    ui.label("Expected Close:");
+                    ui::parsed_field(ui, "deal_close_date", &mut deal.expected_close_date);
+                    ui.end_row();
+
+                    ui.label("Stage:");
+                    let stage_name = stages
+                        .iter()
+                        .find(|s| s.stage_id == deal.stage_id)
+                        .map_or("-".to_string(), |s| s.name.clone());
+                    egui::ComboBox::from_id_source("deal_stage")
+                        .selected_text(stage_name)
+                        .show_ui(ui, |ui| {
+                            for stage in stages.iter().filter(|s| s.active) {
+                                if ui.selectable_value(&mut deal.stage_id, stage.stage_id, &stage.name).clicked() {
+                                    deal.probability = stage.default_probability;
+                                }
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Probability:");
+                    ui.add(egui::Slider::new(&mut deal.probability, 0..=100).suffix(" %"));
+                    ui.end_row();
+
+                    ui.label("Notes:");
+                    ui.text_edit_multiline(&mut deal.notes);
+                    ui.end_row();
+                });
+
+                if auth::can_edit() {
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            save = true;
+                        }
+                        if deal.deal_id != 0 && ui.button("Delete").clicked() {
+                            delete = true;
+                        }
+                    });
+                }
+
+                if deal.deal_id != 0 {
+                    self.audit.show(ui, "deals", deal.deal_id, deal.version);
+                }
+            });
+
+        if !open {
+            *self.editor.lock().unwrap() = None;
+            return;
+        }
+        *self.editor.lock().unwrap() = Some(deal.clone());
+        if delete {
+            *self.editor.lock().unwrap() = None;
+            self.delete_deal(deal.deal_id);
+        } else if save {
+            if deal.title.trim().is_empty() || deal.customer_id == 0 || deal.stage_id == 0 {
+                *self.status.lock().unwrap() = "Please enter a title, customer and stage".to_string();
+            } else {
+                let base = self.loaded.clone().filter(|d| d.deal_id == deal.deal_id);
+                self.save_deal(base, deal);
+            }
+        }
+    }
+
+    fn render_merge_dialog(&mut self, ctx: &egui::Context) {
+        let mut merge = self.merge.lock().unwrap();
+        let Some(dialog) = merge.as_mut() else {
+            return;
+        };
+        let Some(action) = dialog.show(ctx, "Deal changed by someone else") else {
+            return;
+        };
+
+        let dialog = merge.take().unwrap();
+        drop(merge);
+        match action {
+            MergeAction::SaveMerged => {
+                let merged = dialog.merged();
+                if self.loaded.as_ref().is_some_and(|d| d.deal_id == merged.deal_id) {
+                    self.loaded = Some(dialog.theirs.clone());
+                }
+                self.save_deal(Some(dialog.theirs), merged);
+            }
+            MergeAction::DiscardMine => {
+                let mut editor = self.editor.lock().unwrap();
+                if editor.as_ref().is_some_and(|d| d.deal_id == dialog.theirs.deal_id) {
+                    self.loaded = Some(dialog.theirs.clone());
+                    *editor = Some(dialog.theirs);
+                }
+            }
+            MergeAction::Cancel => {}
+        }
+    }
+
+    fn render_stage_editor(&mut self, ui: &mut egui::Ui, stages: &[DealStage]) {
+        let mut save = None;
+        egui::Grid::new("deal_stages_grid").striped(true).show(ui, |ui| {
+            ui.strong("Name");
+            ui.strong("Order");
+            ui.strong("Probability");
+            ui.strong("Active");
+            ui.end_row();
+
+            let rows = stages.iter().cloned().chain(std::iter::once(empty_stage()));
+            for stored in rows {
+                let stage = self.stage_edits.entry(stored.stage_id).or_insert(stored);
+                ui.text_edit_singleline(&mut stage.name);
+                ui.add(egui::DragValue::new(&mut stage.sort_order));
+                ui.add(egui::DragValue::new(&mut stage.default_probability).clamp_range(0..=100).suffix(" %"));
+                ui.checkbox(&mut stage.active, "");
+                let label = if stage.stage_id == 0 { "Add" } else { "Save" };
+                if ui.button(label).clicked() && !stage.name.trim().is_empty() {
+                    save = Some(stage.clone());
+                }
+                ui.end_row();
+            }
+        });
+
+        let Some(mut stage) = save else {
+            return;
+        };
+        self.stage_edits.remove(&stage.stage_id);
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        stage.name = stage.name.trim().to_string();
+        let stale = Arc::clone(&self.stale);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::save_deal_stage(&config, &stage).await {
+                Ok(saved) => *status.lock().unwrap() = format!("Stage {} saved", saved.name),
+                Err(e) => *status.lock().unwrap() = format!("Error saving stage: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    fn load(&self) {
+        let deals = Arc::clone(&self.deals);
+        let stages = Arc::clone(&self.stages);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_deal_stages(&config).await {
+                Ok(loaded) => *stages.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching deal stages: {}", e),
+            }
+            match db::get_deals(&config).await {
+                Ok(loaded) => *deals.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching deals: {}", e),
+            }
+        });
+    }
+
+    /// Saves `deal`, edited from `base`. On a conflict the merge dialog
+    /// compares it with the stored deal.
+    fn save_deal(&self, base: Option<Deal>, deal: Deal) {
+        let Some(config) = db::get_config() else {
+            *self.status.lock().unwrap() = "No database configuration found!".to_string();
+            return;
+        };
+        let deals = Arc::clone(&self.deals);
+        let editor = Arc::clone(&self.editor);
+        let merge = Arc::clone(&self.merge);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let result = if deal.deal_id == 0 {
+                db::add_deal(&config, &deal).await.map(SaveResult::Saved)
+            } else {
+                db::update_deal(&config, &deal).await
+            };
+            match result {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = format!("Deal {} saved", saved.title);
+                    // Only close the editor if it shows the deal just saved,
+                    // not when a card was dragged while another deal is open.
+                    let mut editor = editor.lock().unwrap();
+                    if editor.as_ref().is_some_and(|d| d.deal_id == deal.deal_id) {
+                        *editor = None;
+                    }
+                    let mut deals = deals.lock().unwrap();
+                    match deals.iter_mut().find(|d| d.deal_id == saved.deal_id) {
+                        Some(existing) => *existing = saved,
+                        None => deals.push(saved),
+                    }
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() = format!("Deal {} was changed by someone else", current.title);
+                    let mut deals = deals.lock().unwrap();
+                    if let Some(existing) = deals.iter_mut().find(|d| d.deal_id == current.deal_id) {
+                        *existing = current.clone();
+                    }
+                    let base = base.unwrap_or_else(|| current.clone());
+                    *merge.lock().unwrap() = Some(MergeDialog::new(&base, deal, current));
+                }
+                Err(e) => {
+                    eprintln!("Error saving deal: {}", e);
+                    *status.lock().unwrap() = format!("Error saving deal: {}", e);
+                }
+            }
+        });
+    }
+
+    fn delete_deal(&self, deal_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let deals = Arc::clone(&self.deals);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::delete_deal(&config, deal_id).await {
+                Ok(()) => deals.lock().unwrap().retain(|d| d.deal_id != deal_id),
+                Err(e) => *status.lock().unwrap() = format!("Error deleting deal: {}", e),
+            }
+        });
+    }
+}
+
+fn render_card(ui: &mut egui::Ui, deal: &Deal, customer: &str) {
+    ui.strong(&deal.title);
+    ui.label(customer);
+    ui.label(format!("{:.2} · {} %", deal.value, deal.probability));
+    ui.small(format!("Close: {}", deal.expected_close_date));
+}
+
+/// Deal count, value and weighted value per expected close month. Deals
+/// without any chance (e.g. lost ones) are left out.
+fn render_monthly_totals(ui: &mut egui::Ui, deals: &[Deal]) {
+    let mut months: BTreeMap<(i32, u32), (usize, Decimal, Decimal)> = BTreeMap::new();
+    for deal in deals.iter().filter(|d| d.probability > 0) {
+        let date = deal.expected_close_date;
+        let entry = months.entry((date.year(), date.month())).or_default();
+        entry.0 += 1;
+        entry.1 += deal.value;
+        entry.2 += weighted_value(deal);
+    }
+    if months.is_empty() {
+        ui.label("No open deals.");
+        return;
+    }
+
+    egui::Grid::new("deal_months_grid").striped(true).show(ui, |ui| {
+        ui.strong("Month");
+        ui.strong("Deals");
+        ui.strong("Total");
+        ui.strong("Weighted");
+        ui.end_row();
+
+        let (mut count, mut total, mut weighted) = (0, Decimal::ZERO, Decimal::ZERO);
+        for ((year, month), (month_count, month_total, month_weighted)) in &months {
+            ui.label(format!("{}-{:02}", year, month));
+            ui.label(month_count.to_string());
+            ui.label(format!("{:.2}", month_total));
+            ui.label(format!("{:.2}", month_weighted));
+            ui.end_row();
+            count += month_count;
+            total += month_total;
+            weighted += month_weighted;
+        }
+        ui.strong("Total");
+        ui.strong(count.to_string());
+        ui.strong(format!("{:.2}", total));
+        ui.strong(format!("{:.2}", weighted));
+        ui.end_row();
+    });
+}
@@ -0,0 +1,623 @@
+// dunning.rs
+use crate::auth::{self, Role};
+use crate::currency::format_amount;
+use crate::db::{self, Customer, DunningLevel, DunningNotice, Invoice, OverdueInvoice};
+use crate::export;
+use crate::pdf::{self, Font, PdfDocument};
+use crate::tax;
+use crate::ui;
+use chrono::{NaiveDate, Utc};
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// What the dunning run would do with an overdue invoice today.
+pub enum DunningStep {
+    /// Send this notice.
+    Send(DunningNotice),
+    /// The last notice's grace period runs until the given date.
+    Waiting(NaiveDate),
+    /// Not overdue long enough for the named next level.
+    NotYet(String),
+    /// The highest level was already sent.
+    Exhausted,
+}
+
+/// Decides the next step for `overdue` given the notices it already got.
+/// Levels escalate one at a time. The fee of a notice includes the fees of
+/// the earlier ones; interest runs from the due date.
+pub fn next_step(
+    levels: &[DunningLevel],
+    overdue: &OverdueInvoice,
+    notices: &[&DunningNotice],
+    today: NaiveDate,
+) -> DunningStep {
+    let last = notices.iter().max_by_key(|n| n.level);
+    if let Some(last) = last {
+        if last.pay_by >= today {
+            return DunningStep::Waiting(last.pay_by);
+        }
+    }
+    let last_level = last.map_or(0, |n| n.level);
+    let Some(level) = levels.iter().filter(|l| l.level > last_level).min_by_key(|l| l.level) else {
+        return DunningStep::Exhausted;
+    };
+    let days_overdue = (today - overdue.invoice.due_date).num_days();
+    if days_overdue < i64::from(level.days_overdue) {
+        return DunningStep::NotYet(level.name.clone());
+    }
+
+    let interest = overdue.open_amount * level.interest_rate / Decimal::from(100)
+        * Decimal::from(days_overdue)
+        / Decimal::from(365);
+    DunningStep::Send(DunningNotice {
+        notice_id: 0,
+        invoice_id: overdue.invoice.invoice_id,
+        level: level.level,
+        level_name: level.name.clone(),
+        notice_date: today,
+        pay_by: today + chrono::Duration::days(level.grace_days.into()),
+        open_amount: overdue.open_amount,
+        fee: last.map_or(Decimal::ZERO, |n| n.fee) + level.fee,
+        interest: tax::round_money(interest),
+        history_id: None,
+        created_by: String::new(),
+    })
+}
+
+/// Fills the placeholders of a level's letter text.
+pub fn letter_body(template: &str, notice: &DunningNotice, invoice: &Invoice) -> String {
+    let total = notice.open_amount + notice.fee + notice.interest;
+    [
+        ("{invoice_number}", invoice.invoice_number.clone()),
+        ("{invoice_date}", invoice.invoice_date.format("%d.%m.%Y").to_string()),
+        ("{due_date}", invoice.due_date.format("%d.%m.%Y").to_string()),
+        ("{open_amount}", format_amount(notice.open_amount, &invoice.currency)),
+        ("{fee}", format_amount(notice.fee, &invoice.currency)),
+        ("{interest}", format_amount(notice.interest, &invoice.currency)),
+        ("{total}", format_amount(total, &invoice.currency)),
+        ("{pay_by}", notice.pay_by.format("%d.%m.%Y").to_string()),
+    ]
+    .iter()
+    .fold(template.to_string(), |text, (key, value)| text.replace(key, value))
+}
+
+const MARGIN: f32 = 56.0;
+
+pub fn write_letter_pdf(
+    notice: &DunningNotice,
+    invoice: &Invoice,
+    customer: Option<&Customer>,
+    body: &str,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pdf = PdfDocument::default();
+    let right = pdf::PAGE_WIDTH - MARGIN;
+    let mut y = pdf::PAGE_HEIGHT - 150.0;
+
+    if let Some(customer) = customer {
+        let city = format!("{} {}", customer.postal_code, customer.city);
+        for line in [
+            customer.company_name.as_str(),
+            customer.contact_name.as_str(),
+            customer.address.as_str(),
+            city.trim(),
+            customer.country.as_str(),
+        ] {
+            for part in line.lines().filter(|l| !l.trim().is_empty()) {
+                pdf.text(MARGIN, y, 11.0, Font::Regular, part);
+                y -= 14.0;
+            }
+        }
+    }
+
+    y = y.min(pdf::PAGE_HEIGHT - 260.0);
+    pdf.text_right(right, y + 30.0, 10.0, Font::Regular, &notice.notice_date.format("%d.%m.%Y").to_string());
+    pdf.text(
+        MARGIN,
+        y,
+        16.0,
+        Font::Bold,
+        &format!("{}: Invoice {}", notice.level_name, invoice.invoice_number),
+    );
+    y -= 30.0;
+
+    for line in pdf::wrap(body, right - MARGIN, 11.0) {
+        pdf.text(MARGIN, y, 11.0, Font::Regular, &line);
+        y -= 15.0;
+    }
+    y -= 15.0;
+
+    let amounts = [
+        ("Open amount", notice.open_amount),
+        ("Dunning fees", notice.fee),
+        ("Interest", notice.interest),
+    ];
+    for (label, amount) in amounts {
+        pdf.text(MARGIN, y, 11.0, Font::Regular, label);
+        pdf.text_right(right, y, 11.0, Font::Regular, &format_amount(amount, &invoice.currency));
+        y -= 15.0;
+    }
+    pdf.line(MARGIN, y + 10.0, right, y + 10.0, 0.5);
+    y -= 4.0;
+    pdf.text(MARGIN, y, 11.0, Font::Bold, &format!("Payable by {}", notice.pay_by.format("%d.%m.%Y")));
+    pdf.text_right(
+        right,
+        y,
+        11.0,
+        Font::Bold,
+        &format_amount(notice.open_amount + notice.fee + notice.interest, &invoice.currency),
+    );
+
+    pdf.save(path)
+}
+
+fn letter_path(folder: &str, notice: &DunningNotice, invoice: &Invoice) -> PathBuf {
+    let number = invoice.invoice_number.replace(['/', '\\'], "-");
+    Path::new(folder).join(format!("dunning_{}_{}.pdf", number, notice.level))
+}
+
+fn empty_level(level: i32) -> DunningLevel {
+    DunningLevel {
+        level_id: 0,
+        level,
+        name: String::new(),
+        days_overdue: 0,
+        grace_days: 14,
+        fee: Decimal::ZERO,
+        interest_rate: Decimal::ZERO,
+        letter_text: String::new(),
+    }
+}
+
+/// The dunning run over all overdue invoices, the notices sent so far and
+/// the level configuration.
+pub struct DunningView {
+    overdue: Arc<Mutex<Vec<OverdueInvoice>>>,
+    notices: Arc<Mutex<Vec<DunningNotice>>>,
+    levels: Arc<Mutex<Vec<DunningLevel>>>,
+    /// Set whenever invoices, notices or levels must be (re)loaded.
+    pub stale: Arc<AtomicBool>,
+    status: Arc<Mutex<String>>,
+    /// Invoices left out of the next run although a step is due.
+    skipped: HashSet<i32>,
+    letter_folder: String,
+    /// Level definitions being edited, by level id; 0 is the new level.
+    level_edits: HashMap<i32, DunningLevel>,
+}
+
+impl Default for DunningView {
+    fn default() -> Self {
+        Self {
+            overdue: Arc::new(Mutex::new(Vec::new())),
+            notices: Arc::new(Mutex::new(Vec::new())),
+            levels: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            status: Arc::new(Mutex::new(String::new())),
+            skipped: HashSet::new(),
+            letter_folder: export::default_export_path("dunning"),
+            level_edits: HashMap::new(),
+        }
+    }
+}
+
+impl DunningView {
+    pub fn show(&mut self, ctx: &egui::Context, customers: &Arc<Mutex<Vec<Customer>>>) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+
+        let customers = customers.lock().unwrap().clone();
+        let overdue = self.overdue.lock().unwrap().clone();
+        let notices = self.notices.lock().unwrap().clone();
+        let levels = self.levels.lock().unwrap().clone();
+        let today = Utc::now().date_naive();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Dunning");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            let mut due = Vec::new();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.strong(format!("Overdue invoices ({})", overdue.len()));
+                egui::Grid::new("dunning_grid").striped(true).show(ui, |ui| {
+                    ui.strong("");
+                    ui.strong("Invoice");
+                    ui.strong("Customer");
+                    ui.strong("Due");
+                    ui.strong("Days");
+                    ui.strong("Open");
+                    ui.strong("Next step");
+                    ui.strong("Fees");
+                    ui.strong("Interest");
+                    ui.end_row();
+
+                    for entry in &overdue {
+                        let invoice = &entry.invoice;
+                        let sent: Vec<&DunningNotice> =
+                            notices.iter().filter(|n| n.invoice_id == invoice.invoice_id).collect();
+                        let step = next_step(&levels, entry, &sent, today);
+
+                        if let DunningStep::Send(notice) = &step {
+                            let mut selected = !self.skipped.contains(&invoice.invoice_id);
+                            if ui.checkbox(&mut selected, "").changed() {
+                                if selected {
+                                    self.skipped.remove(&invoice.invoice_id);
+                                } else {
+                                    self.skipped.insert(invoice.invoice_id);
+                                }
+                            }
+                            if selected {
+                                due.push((entry.clone(), notice.clone()));
+                            }
+                        } else {
+                            ui.label("");
+                        }
+                        ui.label(&invoice.invoice_number);
+                        ui.label(
+                            invoice
+                                .customer_id
+                                .and_then(|id| customers.iter().find(|c| c.customer_id == id))
+                                .map_or("-", |c| c.company_name.as_str()),
+                        );
+                        ui.label(invoice.due_date.to_string());
+                        ui.label((today - invoice.due_date).num_days().to_string());
+                        ui.label(format_amount(entry.open_amount, &invoice.currency));
+                        match &step {
+                            DunningStep::Send(notice) => {
+                                ui.label(&notice.level_name);
+                                ui.label(format!("{:.2}", notice.fee));
+                                ui.label(format!("{:.2}", notice.interest));
+                            }
+                            DunningStep::Waiting(until) => {
+                                ui.label(format!("grace period until {}", until));
+                            }
+                            DunningStep::NotYet(name) => {
+                                ui.label(format!("{} not yet due", name));
+                            }
+                            DunningStep::Exhausted => {
+                                ui.label("final level sent");
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    ui.label("Letters to:");
+                    ui.text_edit_singleline(&mut self.letter_folder);
+                });
+                if auth::can_edit()
+                    && ui
+                        .add_enabled(!due.is_empty(), egui::Button::new(format!("Send {} notices", due.len())))
+                        .clicked()
+                {
+                    self.run(std::mem::take(&mut due), &levels, &customers);
+                }
+
+                ui.add_space(20.0);
+                egui::CollapsingHeader::new("Sent Notices").show(ui, |ui| {
+                    self.render_notices(ui, &notices, &levels, &customers);
+                });
+
+                if auth::current_user().is_some_and(|u| u.role == Role::Admin) {
+                    egui::CollapsingHeader::new("Dunning Levels").show(ui, |ui| {
+                        self.render_level_editor(ui, &levels);
+                    });
+                }
+            });
+        });
+    }
+
+    fn render_notices(
+        &mut self,
+        ui: &mut egui::Ui,
+        notices: &[DunningNotice],
+        levels: &[DunningLevel],
+        customers: &[Customer],
+    ) {
+        let invoices = self.overdue.lock().unwrap().clone();
+        egui::Grid::new("dunning_notices_grid").striped(true).show(ui, |ui| {
+            ui.strong("Date");
+            ui.strong("Invoice");
+            ui.strong("Level");
+            ui.strong("Total");
+            ui.strong("Pay by");
+            ui.strong("Sent by");
+            ui.end_row();
+
+            for notice in notices {
+                let invoice = invoices.iter().find(|o| o.invoice.invoice_id == notice.invoice_id);
+                ui.label(notice.notice_date.to_string());
+                ui.label(invoice.map_or(format!("#{}", notice.invoice_id), |o| o.invoice.invoice_number.clone()));
+                ui.label(&notice.level_name);
+                ui.label(format!("{:.2}", notice.open_amount + notice.fee + notice.interest));
+                ui.label(notice.pay_by.to_string());
+                ui.label(&notice.created_by);
+                // Letters can be recreated while the invoice is still open.
+                if let Some(entry) = invoice {
+                    if ui.button("Letter").clicked() {
+                        let template = levels
+                            .iter()
+                            .find(|l| l.level == notice.level)
+                            .map_or("", |l| l.letter_text.as_str());
+                        let body = letter_body(template, notice, &entry.invoice);
+                        let customer = entry
+                            .invoice
+                            .customer_id
+                            .and_then(|id| customers.iter().find(|c| c.customer_id == id));
+                        let path = letter_path(&self.letter_folder, notice, &entry.invoice);
+                        *self.status.lock().unwrap() =
+                            match write_letter_pdf(notice, &entry.invoice, customer, &body, &path) {
+                                Ok(()) => format!("Letter written to {}", path.display()),
+                                Err(e) => format!("Error writing letter: {}", e),
+                            };
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    fn render_level_editor(&mut self, ui: &mut egui::Ui, levels: &[DunningLevel]) {
+        ui.label(
+            "Placeholders: {invoice_number}, {invoice_date}, {due_date}, {open_amount}, {fee}, {interest}, {total}, {pay_by}",
+        );
+        let mut save = None;
+        egui::Grid::new("dunning_levels_grid").striped(true).show(ui, |ui| {
+            ui.strong("Level");
+            ui.strong("Name");
+            ui.strong("Days overdue");
+            ui.strong("Grace days");
+            ui.strong("Fee");
+            ui.strong("Interest p.a.");
+            ui.strong("Letter text");
+            ui.end_row();
+
+            let next_level = levels.iter().map(|l| l.level).max().unwrap_or(0) + 1;
+            let rows = levels.iter().cloned().chain(std::iter::once(empty_level(next_level)));
+            for stored in rows {
+                let level = self.level_edits.entry(stored.level_id).or_insert(stored);
+                ui.add(egui::DragValue::new(&mut level.level).clamp_range(1..=99));
+                ui.text_edit_singleline(&mut level.name);
+                ui.add(egui::DragValue::new(&mut level.days_overdue).clamp_range(0..=3650));
+                ui.add(egui::DragValue::new(&mut level.grace_days).clamp_range(0..=365));
+                ui::parsed_field(ui, ("dunning_fee", level.level_id), &mut level.fee);
+                ui::parsed_field(ui, ("dunning_interest", level.level_id), &mut level.interest_rate);
+                ui.add(egui::TextEdit::multiline(&mut level.letter_text).desired_rows(2));
+                let label = if level.level_id == 0 { "Add" } else { "Save" };
+                if ui.button(label).clicked() && !level.name.trim().is_empty() {
+                    save = Some(level.clone());
+                }
+                ui.end_row();
+            }
+        });
+
+        let Some(mut level) = save else {
+            return;
+        };
+        self.level_edits.remove(&level.level_id);
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        level.name = level.name.trim().to_string();
+        let stale = Arc::clone(&self.stale);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::save_dunning_level(&config, &level).await {
+                Ok(saved) => *status.lock().unwrap() = format!("Dunning level {} saved", saved.name),
+                Err(e) => *status.lock().unwrap() = format!("Error saving dunning level: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    fn load(&self) {
+        let overdue = Arc::clone(&self.overdue);
+        let notices = Arc::clone(&self.notices);
+        let levels = Arc::clone(&self.levels);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_dunning_levels(&config).await {
+                Ok(loaded) => *levels.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching dunning levels: {}", e),
+            }
+            match db::get_dunning_notices(&config).await {
+                Ok(loaded) => *notices.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching dunning notices: {}", e),
+            }
+            match db::get_overdue_invoices(&config, Utc::now().date_naive()).await {
+                Ok(loaded) => *overdue.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching overdue invoices: {}", e),
+            }
+        });
+    }
+
+    /// Records every due notice and writes its letter.
+    fn run(&self, due: Vec<(OverdueInvoice, DunningNotice)>, levels: &[DunningLevel], customers: &[Customer]) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let levels = levels.to_vec();
+        let customers = customers.to_vec();
+        let folder = self.letter_folder.clone();
+        let stale = Arc::clone(&self.stale);
+        let status = Arc::clone(&self.status);
+        *status.lock().unwrap() = format!("Sending {} notices...", due.len());
+        tokio::spawn(async move {
+            let mut sent = 0;
+            let mut settled = 0;
+            let mut errors = Vec::new();
+            for (entry, notice) in due {
+                let invoice = &entry.invoice;
+                let template = levels
+                    .iter()
+                    .find(|l| l.level == notice.level)
+                    .map_or("", |l| l.letter_text.as_str());
+                let body = letter_body(template, &notice, invoice);
+                let saved = match db::record_dunning_notice(&config, &notice, invoice.customer_id, &body).await {
+                    Ok(Some(saved)) => saved,
+                    Ok(None) => {
+                        settled += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        errors.push(e.to_string());
+                        continue;
+                    }
+                };
+                let customer = invoice
+                    .customer_id
+                    .and_then(|id| customers.iter().find(|c| c.customer_id == id));
+                let path = letter_path(&folder, &saved, invoice);
+                match write_letter_pdf(&saved, invoice, customer, &body, &path) {
+                    Ok(()) => sent += 1,
+                    Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+            let mut message = format!("{} notices sent, letters in {}", sent, folder);
+            if settled > 0 {
+                message.push_str(&format!(". {} invoices were paid meanwhile and skipped", settled));
+            }
+            if !errors.is_empty() {
+                eprintln!("Dunning errors: {:?}", errors);
+                message.push_str(&format!(". Errors: {}", errors.join("; ")));
+            }
+            *status.lock().unwrap() = message;
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn level(level: i32, days_overdue: i32, fee: i64) -> DunningLevel {
+        DunningLevel {
+            level_id: level,
+            level,
+            name: format!("Level {}", level),
+            days_overdue,
+            grace_days: 7,
+            fee: Decimal::from(fee),
+            interest_rate: Decimal::from(5),
+            letter_text: String::new(),
+        }
+    }
+
+    fn overdue(open_amount: &str) -> OverdueInvoice {
+        OverdueInvoice {
+            invoice: Invoice {
+                invoice_id: 1,
+                due_date: date(2026, 1, 1),
+                ..Invoice::default()
+            },
+            open_amount: open_amount.parse().unwrap(),
+        }
+    }
+
+    fn notice(level: i32, pay_by: NaiveDate, fee: i64) -> DunningNotice {
+        DunningNotice {
+            notice_id: level,
+            invoice_id: 1,
+            level,
+            level_name: format!("Level {}", level),
+            notice_date: pay_by - chrono::Duration::days(7),
+            pay_by,
+            open_amount: Decimal::from(1000),
+            fee: Decimal::from(fee),
+            interest: Decimal::ZERO,
+            history_id: None,
+            created_by: String::new(),
+        }
+    }
+
+    fn sent(step: DunningStep) -> DunningNotice {
+        match step {
+            DunningStep::Send(notice) => notice,
+            _ => panic!("expected a notice to be sent"),
+        }
+    }
+
+    #[test]
+    fn next_step_waits_for_the_first_level() {
+        let levels = [level(1, 14, 0), level(2, 28, 5)];
+        match next_step(&levels, &overdue("1000"), &[], date(2026, 1, 10)) {
+            DunningStep::NotYet(name) => assert_eq!(name, "Level 1"),
+            _ => panic!("expected the first level not to be due yet"),
+        }
+    }
+
+    #[test]
+    fn next_step_sends_the_first_level_with_interest() {
+        let levels = [level(1, 14, 0), level(2, 28, 5)];
+        let notice = sent(next_step(&levels, &overdue("1000"), &[], date(2026, 3, 15)));
+        assert_eq!(notice.level, 1);
+        assert_eq!(notice.pay_by, date(2026, 3, 22));
+        assert_eq!(notice.fee, Decimal::ZERO);
+        // 73 days at 5% a year on 1000.
+        assert_eq!(notice.interest, Decimal::from(10));
+    }
+
+    #[test]
+    fn next_step_waits_for_the_grace_period() {
+        let levels = [level(1, 14, 0), level(2, 28, 5)];
+        let first = notice(1, date(2026, 2, 10), 0);
+        match next_step(&levels, &overdue("1000"), &[&first], date(2026, 2, 10)) {
+            DunningStep::Waiting(pay_by) => assert_eq!(pay_by, date(2026, 2, 10)),
+            _ => panic!("expected to wait for the grace period"),
+        }
+    }
+
+    #[test]
+    fn next_step_escalates_one_level_at_a_time() {
+        let levels = [level(3, 42, 10), level(1, 14, 0), level(2, 28, 5)];
+        let first = notice(1, date(2026, 1, 22), 0);
+        let notice = sent(next_step(&levels, &overdue("1000"), &[&first], date(2026, 6, 1)));
+        assert_eq!(notice.level, 2);
+        assert_eq!(notice.fee, Decimal::from(5));
+
+        let second = notice;
+        let notice = sent(next_step(&levels, &overdue("1000"), &[&first, &second], date(2026, 7, 1)));
+        assert_eq!(notice.level, 3);
+        assert_eq!(notice.fee, Decimal::from(15));
+    }
+
+    #[test]
+    fn next_step_stops_after_the_last_level() {
+        let levels = [level(1, 14, 0)];
+        let first = notice(1, date(2026, 1, 22), 0);
+        assert!(matches!(
+            next_step(&levels, &overdue("1000"), &[&first], date(2026, 6, 1)),
+            DunningStep::Exhausted
+        ));
+    }
+
+    #[test]
+    fn next_step_rounds_interest_halves_up() {
+        let levels = [level(1, 0, 0)];
+        // One day at 5% a year on 36.50 is 0.005.
+        let notice = sent(next_step(&levels, &overdue("36.50"), &[], date(2026, 1, 2)));
+        assert_eq!(notice.interest, "0.01".parse().unwrap());
+    }
+}
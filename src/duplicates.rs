@@ -0,0 +1,398 @@
+// duplicates.rs
+use crate::auth;
+use crate::db::{self, Customer, SaveResult};
+use crate::merge::{MergeAction, MergeDialog, MergeLabels};
+use eframe::egui;
+use std::sync::{Arc, Mutex};
+
+/// Pairs scoring below this are not listed.
+const MIN_SCORE: u32 = 40;
+
+/// Mail providers whose domain says nothing about the company.
+const FREEMAIL_DOMAINS: [&str; 10] = [
+    "gmail.com", "googlemail.com", "gmx.de", "gmx.net", "web.de", "outlook.com",
+    "hotmail.com", "yahoo.com", "t-online.de", "icloud.com",
+];
+
+/// Legal forms left out when comparing company names.
+const LEGAL_FORMS: [&str; 14] = [
+    "gmbh", "mbh", "ag", "kg", "ohg", "ug", "co", "ek", "gbr", "se", "ltd", "inc", "llc", "kgaa",
+];
+
+#[derive(Clone)]
+pub struct DuplicatePair {
+    pub first: Customer,
+    pub second: Customer,
+    pub score: u32,
+    /// What matched, for display.
+    pub reasons: Vec<&'static str>,
+}
+
+/// Lowercases, spells out umlauts and drops punctuation and legal forms, so
+/// "Müller GmbH" and "Mueller G.m.b.H." compare equal.
+pub fn normalize_company(name: &str) -> String {
+    let spelled: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'ä' => "ae".to_string(),
+            'ö' => "oe".to_string(),
+            'ü' => "ue".to_string(),
+            'ß' => "ss".to_string(),
+            '&' | '+' => " ".to_string(),
+            c if c.is_alphanumeric() || c.is_whitespace() => c.to_string(),
+            // "G.m.b.H." becomes "gmbh"; other punctuation separates words.
+            '.' => String::new(),
+            _ => " ".to_string(),
+        })
+        .collect();
+    spelled
+        .split_whitespace()
+        .filter(|word| !LEGAL_FORMS.contains(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn email_domain(email: &str) -> Option<String> {
+    let (_, domain) = email.trim().rsplit_once('@')?;
+    let domain = domain.to_lowercase();
+    (!domain.is_empty()).then_some(domain)
+}
+
+/// Digits of a phone number with a German country prefix replaced by the
+/// trunk prefix, so "+49 30 1234" and "030/1234" compare equal.
+fn normalize_phone(phone: &str) -> String {
+    let digits: String = phone.chars().filter(|c| c.is_ascii_digit()).collect();
+    if let Some(rest) = digits.strip_prefix("0049") {
+        format!("0{}", rest)
+    } else if phone.trim_start().starts_with('+') && digits.starts_with("49") {
+        format!("0{}", &digits[2..])
+    } else {
+        digits
+    }
+}
+
+fn normalize_street(address: &str) -> String {
+    address
+        .to_lowercase()
+        .replace("straße", "str")
+        .replace("strasse", "str")
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Similarity of two strings between 0.0 and 1.0.
+fn similarity(a: &str, b: &str) -> f64 {
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 {
+        return 0.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / longest as f64
+}
+
+/// Scores how likely two customers are the same company, with the reasons.
+pub fn score_pair(a: &Customer, b: &Customer) -> (u32, Vec<&'static str>) {
+    let mut score = 0;
+    let mut reasons = Vec::new();
+
+    let (name_a, name_b) = (normalize_company(&a.company_name), normalize_company(&b.company_name));
+    if !name_a.is_empty() && name_a == name_b {
+        score += 50;
+        reasons.push("same company name");
+    } else if !name_a.is_empty() && !name_b.is_empty() && similarity(&name_a, &name_b) >= 0.85 {
+        score += 35;
+        reasons.push("similar company name");
+    }
+
+    if !a.email.trim().is_empty() && a.email.trim().eq_ignore_ascii_case(b.email.trim()) {
+        score += 25;
+        reasons.push("same email");
+    } else if let (Some(domain_a), Some(domain_b)) = (email_domain(&a.email), email_domain(&b.email)) {
+        if domain_a == domain_b && !FREEMAIL_DOMAINS.contains(&domain_a.as_str()) {
+            score += 20;
+            reasons.push("same email domain");
+        }
+    }
+
+    let (phone_a, phone_b) = (normalize_phone(&a.phone), normalize_phone(&b.phone));
+    if phone_a.len() >= 6 && phone_a == phone_b {
+        score += 20;
+        reasons.push("same phone");
+    }
+
+    let same_postal_code = !a.postal_code.trim().is_empty() && a.postal_code.trim() == b.postal_code.trim();
+    let (street_a, street_b) = (normalize_street(&a.address), normalize_street(&b.address));
+    if same_postal_code && !street_a.is_empty() && street_a == street_b {
+        score += 15;
+        reasons.push("same address");
+    } else if same_postal_code {
+        score += 5;
+        reasons.push("same postal code");
+    }
+
+    (score, reasons)
+}
+
+/// All pairs scoring at least `MIN_SCORE`, best matches first.
+pub fn find_duplicates(customers: &[Customer]) -> Vec<DuplicatePair> {
+    let mut pairs = Vec::new();
+    for (i, first) in customers.iter().enumerate() {
+        for second in &customers[i + 1..] {
+            let (score, reasons) = score_pair(first, second);
+            if score >= MIN_SCORE {
+                pairs.push(DuplicatePair {
+                    first: first.clone(),
+                    second: second.clone(),
+                    score,
+                    reasons,
+                });
+            }
+        }
+    }
+    pairs.sort_by_key(|pair| std::cmp::Reverse(pair.score));
+    pairs
+}
+
+const DUPLICATE_LABELS: MergeLabels = MergeLabels {
+    intro: "The duplicate will be deleted. Its contact history, contacts, deals, quotes, invoices, recurring invoices, tags, custom values and relations move to the kept customer.",
+    mine: "Duplicate",
+    theirs: "Kept customer",
+    save: "Merge customers",
+    discard: None,
+};
+
+/// Merge of one duplicate into a kept customer in progress.
+struct PendingMerge {
+    duplicate: Customer,
+    dialog: MergeDialog<Customer>,
+}
+
+fn merge_dialog(kept: Customer, duplicate: Customer) -> PendingMerge {
+    let mut dialog = MergeDialog::new(&kept, duplicate.clone(), kept.clone()).with_labels(DUPLICATE_LABELS);
+    // Keep the surviving record's values unless it has none.
+    for field in &mut dialog.fields {
+        field.take_mine = field.theirs.trim().is_empty() && !field.mine.trim().is_empty();
+    }
+    PendingMerge { duplicate, dialog }
+}
+
+/// Lists likely duplicate customers and merges a chosen pair.
+pub struct DuplicatesView {
+    pairs: Vec<DuplicatePair>,
+    scanned: bool,
+    pending: Option<PendingMerge>,
+    status: Arc<Mutex<String>>,
+}
+
+impl Default for DuplicatesView {
+    fn default() -> Self {
+        Self {
+            pairs: Vec::new(),
+            scanned: false,
+            pending: None,
+            status: Arc::new(Mutex::new(String::new())),
+        }
+    }
+}
+
+impl DuplicatesView {
+    pub fn show(&mut self, ctx: &egui::Context, customers: &Arc<Mutex<Vec<Customer>>>) {
+        if !self.scanned {
+            self.scan(customers);
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Duplicate Customers");
+            ui.horizontal(|ui| {
+                if ui.button("Scan again").clicked() {
+                    self.scan(customers);
+                }
+                ui.label(self.status.lock().unwrap().as_str());
+            });
+            ui.add_space(10.0);
+
+            if self.pairs.is_empty() {
+                ui.label("No likely duplicates found.");
+                return;
+            }
+            let mut start = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("duplicates_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Score");
+                    ui.strong("Customer");
+                    ui.strong("Possible duplicate");
+                    ui.strong("Matches");
+                    ui.strong("");
+                    ui.end_row();
+
+                    for pair in &self.pairs {
+                        ui.label(pair.score.to_string());
+                        ui.label(describe(&pair.first));
+                        ui.label(describe(&pair.second));
+                        ui.label(pair.reasons.join(", "));
+                        ui.horizontal(|ui| {
+                            if auth::can_edit() {
+                                if ui.button("Keep left").clicked() {
+                                    start = Some((pair.first.clone(), pair.second.clone()));
+                                }
+                                if ui.button("Keep right").clicked() {
+                                    start = Some((pair.second.clone(), pair.first.clone()));
+                                }
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+            });
+            if let Some((kept, duplicate)) = start {
+                self.pending = Some(merge_dialog(kept, duplicate));
+            }
+        });
+
+        self.render_merge_dialog(ctx, customers);
+    }
+
+    fn scan(&mut self, customers: &Arc<Mutex<Vec<Customer>>>) {
+        let customers = customers.lock().unwrap();
+        // Wait for the customer list instead of reporting "no duplicates".
+        self.scanned = !customers.is_empty();
+        self.pairs = find_duplicates(&customers);
+    }
+
+    fn render_merge_dialog(&mut self, ctx: &egui::Context, customers: &Arc<Mutex<Vec<Customer>>>) {
+        let Some(pending) = self.pending.as_mut() else {
+            return;
+        };
+        let Some(action) = pending.dialog.show(ctx, "Merge duplicate customers") else {
+            return;
+        };
+        let pending = self.pending.take().unwrap();
+        if !matches!(action, MergeAction::SaveMerged) {
+            return;
+        }
+        let Some(config) = db::get_config() else {
+            return;
+        };
+
+        let merged = pending.dialog.merged();
+        let duplicate_id = pending.duplicate.customer_id;
+        // Drop the pairs now; a rescan after the merge would race the reload.
+        self.pairs.retain(|p| {
+            ![p.first.customer_id, p.second.customer_id].contains(&duplicate_id)
+        });
+        let customers = Arc::clone(customers);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let message = match db::merge_customers(&config, &merged, duplicate_id).await {
+                Ok(SaveResult::Saved(saved)) => {
+                    let mut customers = customers.lock().unwrap();
+                    customers.retain(|c| c.customer_id != duplicate_id);
+                    if let Some(existing) = customers.iter_mut().find(|c| c.customer_id == saved.customer_id) {
+                        *existing = saved.clone();
+                    }
+                    format!("Merged into {}", saved.company_name)
+                }
+                Ok(SaveResult::Conflict(_)) => {
+                    "The kept customer was changed meanwhile. Please scan again and retry.".to_string()
+                }
+                Err(e) => format!("Error merging customers: {}", e),
+            };
+            *status.lock().unwrap() = message;
+        });
+    }
+}
+
+fn describe(customer: &Customer) -> String {
+    let mut lines = vec![customer.company_name.clone()];
+    for detail in [&customer.email, &customer.phone, &customer.city] {
+        if !detail.trim().is_empty() {
+            lines.push(detail.clone());
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn customer(company_name: &str) -> Customer {
+        Customer {
+            company_name: company_name.to_string(),
+            ..Customer::default()
+        }
+    }
+
+    #[test]
+    fn normalize_company_spells_out_umlauts() {
+        assert_eq!(normalize_company("Müller GmbH"), "mueller");
+        assert_eq!(normalize_company("Müller GmbH"), normalize_company("Mueller GmbH"));
+        assert_eq!(normalize_company("Großhandel Öztürk"), "grosshandel oeztuerk");
+    }
+
+    #[test]
+    fn normalize_company_drops_legal_forms_and_punctuation() {
+        assert_eq!(normalize_company("Mueller G.m.b.H."), "mueller");
+        assert_eq!(normalize_company("Schmidt & Co. KG"), "schmidt");
+        assert_eq!(normalize_company("Acme, Inc."), "acme");
+        assert_eq!(normalize_company("AG Software Services AG"), "software services");
+    }
+
+    #[test]
+    fn score_pair_matches_transliterated_names() {
+        let (score, reasons) = score_pair(&customer("Müller GmbH"), &customer("Mueller GmbH"));
+        assert_eq!(score, 50);
+        assert_eq!(reasons, ["same company name"]);
+    }
+
+    #[test]
+    fn score_pair_ignores_freemail_domains() {
+        let mut a = customer("Alpha");
+        let mut b = customer("Beta");
+        a.email = "alpha@gmail.com".to_string();
+        b.email = "beta@gmail.com".to_string();
+        assert_eq!(score_pair(&a, &b).0, 0);
+        a.email = "info@example.de".to_string();
+        b.email = "sales@example.de".to_string();
+        assert_eq!(score_pair(&a, &b), (20, vec!["same email domain"]));
+    }
+
+    #[test]
+    fn find_duplicates_lists_pairs_from_the_minimum_score() {
+        // A similar name and the same postal code score exactly MIN_SCORE.
+        let mut similar = customer("Mueller Maschinenbau");
+        let mut typo = customer("Mueller Maschinenbsu");
+        similar.postal_code = "10115".to_string();
+        typo.postal_code = "10115".to_string();
+        assert_eq!(score_pair(&similar, &typo).0, MIN_SCORE);
+
+        // The same email and postal code alone stay below it.
+        let mut a = customer("Alpha");
+        let mut b = customer("Beta");
+        a.email = "office@example.de".to_string();
+        b.email = "Office@Example.de".to_string();
+        a.postal_code = "10115".to_string();
+        b.postal_code = "10115".to_string();
+        assert!(score_pair(&a, &b).0 < MIN_SCORE);
+
+        let duplicates = find_duplicates(&[similar, a, typo, b]);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].first.company_name, "Mueller Maschinenbau");
+        assert_eq!(duplicates[0].second.company_name, "Mueller Maschinenbsu");
+    }
+}
@@ -0,0 +1,896 @@
+// einvoice.rs
+use crate::db::{CompanyProfile, Customer, Invoice, LineItem, TaxRate};
+use crate::invoices::{draw_address, draw_items, draw_paragraph, PDF_MARGIN};
+use crate::pdf::{self, xml_escape, Attachment, Font, PdfDocument};
+use crate::tax::{self, TaxGroup, Totals};
+use rust_decimal::Decimal;
+use std::fs;
+use std::path::Path;
+
+const XRECHNUNG_GUIDELINE: &str = "urn:cen.eu:en16931:2017#compliant#urn:xeinkauf.de:kosit:xrechnung_3.0";
+const EN16931_GUIDELINE: &str = "urn:cen.eu:en16931:2017";
+const PEPPOL_PROCESS: &str = "urn:fdc:peppol.eu:2017:poacc:billing:01:1.0";
+const FACTUR_X_NAMESPACE: &str = "urn:factur-x:pdfa:CrossIndustryDocument:invoice:1p0#";
+const FACTUR_X_FILE_NAME: &str = "factur-x.xml";
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    XRechnungUbl,
+    XRechnungCii,
+    /// PDF/A-3 with the CII data of the EN 16931 profile attached.
+    Zugferd,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::XRechnungUbl, Format::XRechnungCii, Format::Zugferd];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Format::XRechnungUbl => "XRechnung (UBL)",
+            Format::XRechnungCii => "XRechnung (CII)",
+            Format::Zugferd => "ZUGFeRD (PDF)",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Zugferd => "pdf",
+            _ => "xml",
+        }
+    }
+}
+
+/// ISO 3166-1 alpha-2 code for a country as entered on a customer, which
+/// may already be a code.
+pub fn country_code(country: &str) -> Option<String> {
+    let country = country.trim();
+    if country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Some(country.to_ascii_uppercase());
+    }
+    let code = match country.to_lowercase().as_str() {
+        "deutschland" | "germany" => "DE",
+        "österreich" | "austria" => "AT",
+        "schweiz" | "switzerland" => "CH",
+        "frankreich" | "france" => "FR",
+        "niederlande" | "netherlands" => "NL",
+        "belgien" | "belgium" => "BE",
+        "luxemburg" | "luxembourg" => "LU",
+        "italien" | "italy" => "IT",
+        "spanien" | "spain" => "ES",
+        "polen" | "poland" => "PL",
+        "dänemark" | "denmark" => "DK",
+        "schweden" | "sweden" => "SE",
+        "tschechien" | "czech republic" | "czechia" => "CZ",
+        "großbritannien" | "vereinigtes königreich" | "united kingdom" | "uk" => "GB",
+        "usa" | "vereinigte staaten" | "united states" => "US",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+/// Country of the buyer; customers without a country are taken to be in
+/// the seller's country.
+fn buyer_country(customer: &Customer, seller: &CompanyProfile) -> Option<String> {
+    if customer.country.trim().is_empty() {
+        country_code(&seller.country_code)
+    } else {
+        country_code(&customer.country)
+    }
+}
+
+fn is_reverse_charge(group: &TaxGroup, rates: &[TaxRate]) -> bool {
+    rates
+        .iter()
+        .any(|r| Some(r.tax_rate_id) == group.tax_rate_id && r.reverse_charge)
+}
+
+/// VAT category code of a group: S (standard), Z (zero rated) or AE
+/// (reverse charge).
+fn category(group: &TaxGroup, rates: &[TaxRate]) -> &'static str {
+    if is_reverse_charge(group, rates) {
+        "AE"
+    } else if group.rate > Decimal::ZERO {
+        "S"
+    } else {
+        "Z"
+    }
+}
+
+fn exemption_reason(group: &TaxGroup, rates: &[TaxRate]) -> String {
+    rates
+        .iter()
+        .find(|r| Some(r.tax_rate_id) == group.tax_rate_id)
+        .map(|r| r.note.trim().to_string())
+        .filter(|note| !note.is_empty())
+        .unwrap_or_else(|| "Reverse charge".to_string())
+}
+
+/// Everything an EN 16931 invoice requires that may be missing here.
+pub fn validate(
+    invoice: &Invoice,
+    customer: Option<&Customer>,
+    seller: &CompanyProfile,
+    rates: &[TaxRate],
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    if invoice.is_credit_note() {
+        errors.push("E-invoices of credit notes are not supported".to_string());
+    }
+    let mut require = |value: &str, message: &str| {
+        if value.trim().is_empty() {
+            errors.push(message.to_string());
+        }
+    };
+    require(&invoice.invoice_number, "The invoice has no number");
+    require(&invoice.buyer_reference, "The invoice has no buyer reference (Leitweg-ID)");
+    require(&seller.company_name, "The company profile has no company name");
+    require(&seller.street, "The company profile has no street");
+    require(&seller.postal_code, "The company profile has no postal code");
+    require(&seller.city, "The company profile has no city");
+    require(&seller.contact_name, "The company profile has no contact name");
+    require(&seller.phone, "The company profile has no phone number");
+    require(&seller.email, "The company profile has no email address");
+    require(&seller.iban, "The company profile has no IBAN");
+    if seller.vat_id.trim().is_empty() && seller.tax_number.trim().is_empty() {
+        errors.push("The company profile has neither a VAT ID nor a tax number".to_string());
+    }
+    if country_code(&seller.country_code).is_none() {
+        errors.push("The company profile has no valid country code".to_string());
+    }
+    if invoice.items.is_empty() {
+        errors.push("The invoice has no items".to_string());
+    }
+
+    let Some(customer) = customer else {
+        errors.push("The invoice has no customer".to_string());
+        return errors;
+    };
+    let mut require = |value: &str, message: &str| {
+        if value.trim().is_empty() {
+            errors.push(message.to_string());
+        }
+    };
+    require(&customer.company_name, "The customer has no company name");
+    require(&customer.postal_code, "The customer has no postal code");
+    require(&customer.city, "The customer has no city");
+    require(&customer.email, "The customer has no email address");
+    if buyer_country(customer, seller).is_none() {
+        errors.push(format!("The country \"{}\" of the customer is not known", customer.country));
+    }
+
+    let totals = tax::totals(&invoice.items);
+    if totals.groups.iter().any(|g| is_reverse_charge(g, rates)) {
+        if seller.vat_id.trim().is_empty() {
+            errors.push("Reverse charge requires the VAT ID in the company profile".to_string());
+        }
+        if customer.vat_id.trim().is_empty() {
+            errors.push("Reverse charge requires the VAT ID of the customer".to_string());
+        }
+    }
+    errors
+}
+
+/// Street of an address entered over several lines, on one line.
+fn street(address: &str) -> String {
+    address
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn amount(value: Decimal) -> String {
+    format!("{:.2}", value)
+}
+
+/// Everything both syntaxes are built from.
+struct Document<'a> {
+    invoice: &'a Invoice,
+    customer: &'a Customer,
+    seller: &'a CompanyProfile,
+    rates: &'a [TaxRate],
+    totals: Totals,
+    currency: String,
+    seller_country: String,
+    buyer_country: String,
+}
+
+impl<'a> Document<'a> {
+    fn new(
+        invoice: &'a Invoice,
+        customer: &'a Customer,
+        seller: &'a CompanyProfile,
+        rates: &'a [TaxRate],
+    ) -> Self {
+        Self {
+            invoice,
+            customer,
+            seller,
+            rates,
+            totals: tax::totals(&invoice.items),
+            currency: xml_escape(&invoice.currency),
+            seller_country: country_code(&seller.country_code).unwrap_or_default(),
+            buyer_country: buyer_country(customer, seller).unwrap_or_default(),
+        }
+    }
+
+    fn money(&self, element: &str, value: Decimal) -> String {
+        format!(
+            "<{0} currencyID=\"{1}\">{2}</{0}>",
+            element,
+            self.currency,
+            amount(value)
+        )
+    }
+
+    /// Line allowance for the discount of `item`, if it has one.
+    fn ubl_line_discount(&self, item: &LineItem) -> String {
+        if item.discount_percent.is_zero() {
+            return String::new();
+        }
+        format!(
+            "<cac:AllowanceCharge><cbc:ChargeIndicator>false</cbc:ChargeIndicator>\
+             <cbc:AllowanceChargeReasonCode>95</cbc:AllowanceChargeReasonCode><cbc:AllowanceChargeReason>Discount</cbc:AllowanceChargeReason>\
+             <cbc:MultiplierFactorNumeric>{}</cbc:MultiplierFactorNumeric>{}{}</cac:AllowanceCharge>",
+            item.discount_percent.normalize(),
+            self.money("cbc:Amount", tax::line_discount(item)),
+            self.money("cbc:BaseAmount", tax::line_amount(item))
+        )
+    }
+
+    fn ubl(&self) -> String {
+        let (invoice, seller, customer) = (self.invoice, self.seller, self.customer);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(
+            "<ubl:Invoice xmlns:ubl=\"urn:oasis:names:specification:ubl:schema:xsd:Invoice-2\" \
+             xmlns:cac=\"urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2\" \
+             xmlns:cbc=\"urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2\">\n",
+        );
+        xml.push_str(&format!("<cbc:CustomizationID>{}</cbc:CustomizationID>\n", XRECHNUNG_GUIDELINE));
+        xml.push_str(&format!("<cbc:ProfileID>{}</cbc:ProfileID>\n", PEPPOL_PROCESS));
+        xml.push_str(&format!("<cbc:ID>{}</cbc:ID>\n", xml_escape(&invoice.invoice_number)));
+        xml.push_str(&format!("<cbc:IssueDate>{}</cbc:IssueDate>\n", invoice.invoice_date));
+        xml.push_str(&format!("<cbc:DueDate>{}</cbc:DueDate>\n", invoice.due_date));
+        xml.push_str("<cbc:InvoiceTypeCode>380</cbc:InvoiceTypeCode>\n");
+        if let Some(notes) = invoice.notes.as_deref().filter(|n| !n.trim().is_empty()) {
+            xml.push_str(&format!("<cbc:Note>{}</cbc:Note>\n", xml_escape(notes)));
+        }
+        xml.push_str(&format!("<cbc:DocumentCurrencyCode>{}</cbc:DocumentCurrencyCode>\n", self.currency));
+        xml.push_str(&format!(
+            "<cbc:BuyerReference>{}</cbc:BuyerReference>\n",
+            xml_escape(&invoice.buyer_reference)
+        ));
+
+        let seller_tax = if seller.vat_id.trim().is_empty() {
+            format!(
+                "<cac:PartyTaxScheme><cbc:CompanyID>{}</cbc:CompanyID><cac:TaxScheme><cbc:ID>FC</cbc:ID></cac:TaxScheme></cac:PartyTaxScheme>",
+                xml_escape(&seller.tax_number)
+            )
+        } else {
+            ubl_vat_scheme(&seller.vat_id)
+        };
+        xml.push_str(&format!(
+            "<cac:AccountingSupplierParty><cac:Party>\
+             <cbc:EndpointID schemeID=\"EM\">{}</cbc:EndpointID>\
+             {}{}\
+             <cac:PartyLegalEntity><cbc:RegistrationName>{}</cbc:RegistrationName></cac:PartyLegalEntity>\
+             <cac:Contact><cbc:Name>{}</cbc:Name><cbc:Telephone>{}</cbc:Telephone><cbc:ElectronicMail>{}</cbc:ElectronicMail></cac:Contact>\
+             </cac:Party></cac:AccountingSupplierParty>\n",
+            xml_escape(&seller.email),
+            ubl_address(&seller.street, &seller.city, &seller.postal_code, &self.seller_country),
+            seller_tax,
+            xml_escape(&seller.company_name),
+            xml_escape(&seller.contact_name),
+            xml_escape(&seller.phone),
+            xml_escape(&seller.email)
+        ));
+
+        let buyer_tax = if customer.vat_id.trim().is_empty() {
+            String::new()
+        } else {
+            ubl_vat_scheme(&customer.vat_id)
+        };
+        let buyer_contact = if customer.contact_name.trim().is_empty() {
+            String::new()
+        } else {
+            format!("<cac:Contact><cbc:Name>{}</cbc:Name></cac:Contact>", xml_escape(&customer.contact_name))
+        };
+        xml.push_str(&format!(
+            "<cac:AccountingCustomerParty><cac:Party>\
+             <cbc:EndpointID schemeID=\"EM\">{}</cbc:EndpointID>\
+             {}{}\
+             <cac:PartyLegalEntity><cbc:RegistrationName>{}</cbc:RegistrationName></cac:PartyLegalEntity>\
+             {}\
+             </cac:Party></cac:AccountingCustomerParty>\n",
+            xml_escape(&customer.email),
+            ubl_address(&street(&customer.address), &customer.city, &customer.postal_code, &self.buyer_country),
+            buyer_tax,
+            xml_escape(&customer.company_name),
+            buyer_contact
+        ));
+
+        let bic = if seller.bic.trim().is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<cac:FinancialInstitutionBranch><cbc:ID>{}</cbc:ID></cac:FinancialInstitutionBranch>",
+                xml_escape(seller.bic.trim())
+            )
+        };
+        xml.push_str(&format!(
+            "<cac:PaymentMeans><cbc:PaymentMeansCode>58</cbc:PaymentMeansCode>\
+             <cac:PayeeFinancialAccount><cbc:ID>{}</cbc:ID><cbc:Name>{}</cbc:Name>{}</cac:PayeeFinancialAccount>\
+             </cac:PaymentMeans>\n",
+            xml_escape(&seller.iban.replace(' ', "")),
+            xml_escape(&seller.company_name),
+            bic
+        ));
+
+        xml.push_str(&format!("<cac:TaxTotal>{}\n", self.money("cbc:TaxAmount", self.totals.tax)));
+        for group in &self.totals.groups {
+            let category = category(group, self.rates);
+            let exemption = if category == "AE" {
+                format!(
+                    "<cbc:TaxExemptionReasonCode>vatex-eu-ae</cbc:TaxExemptionReasonCode><cbc:TaxExemptionReason>{}</cbc:TaxExemptionReason>",
+                    xml_escape(&exemption_reason(group, self.rates))
+                )
+            } else {
+                String::new()
+            };
+            xml.push_str(&format!(
+                "<cac:TaxSubtotal>{}{}<cac:TaxCategory><cbc:ID>{}</cbc:ID><cbc:Percent>{}</cbc:Percent>{}\
+                 <cac:TaxScheme><cbc:ID>VAT</cbc:ID></cac:TaxScheme></cac:TaxCategory></cac:TaxSubtotal>\n",
+                self.money("cbc:TaxableAmount", group.net),
+                self.money("cbc:TaxAmount", group.tax),
+                category,
+                group.rate.normalize(),
+                exemption
+            ));
+        }
+        xml.push_str("</cac:TaxTotal>\n");
+
+        xml.push_str(&format!(
+            "<cac:LegalMonetaryTotal>{}{}{}{}</cac:LegalMonetaryTotal>\n",
+            self.money("cbc:LineExtensionAmount", self.totals.net),
+            self.money("cbc:TaxExclusiveAmount", self.totals.net),
+            self.money("cbc:TaxInclusiveAmount", self.totals.gross),
+            self.money("cbc:PayableAmount", self.totals.gross)
+        ));
+
+        for (index, item) in invoice.items.iter().enumerate() {
+            let group = self.group_of(item.tax_rate_id, item.tax_rate);
+            xml.push_str(&format!(
+                "<cac:InvoiceLine><cbc:ID>{}</cbc:ID>\
+                 <cbc:InvoicedQuantity unitCode=\"C62\">{}</cbc:InvoicedQuantity>{}{}\
+                 <cac:Item><cbc:Name>{}</cbc:Name>\
+                 <cac:ClassifiedTaxCategory><cbc:ID>{}</cbc:ID><cbc:Percent>{}</cbc:Percent>\
+                 <cac:TaxScheme><cbc:ID>VAT</cbc:ID></cac:TaxScheme></cac:ClassifiedTaxCategory></cac:Item>\
+                 <cac:Price>{}</cac:Price></cac:InvoiceLine>\n",
+                index + 1,
+                item.quantity,
+                self.money("cbc:LineExtensionAmount", tax::line_net(item)),
+                self.ubl_line_discount(item),
+                xml_escape(&item.description),
+                group.map_or("S", |g| category(g, self.rates)),
+                item.tax_rate.normalize(),
+                self.money("cbc:PriceAmount", item.unit_price)
+            ));
+        }
+        xml.push_str("</ubl:Invoice>\n");
+        xml
+    }
+
+    fn cii(&self, guideline: &str) -> String {
+        let (invoice, seller, customer) = (self.invoice, self.seller, self.customer);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(
+            "<rsm:CrossIndustryInvoice xmlns:rsm=\"urn:un:unece:uncefact:data:standard:CrossIndustryInvoice:100\" \
+             xmlns:ram=\"urn:un:unece:uncefact:data:standard:ReusableAggregateBusinessInformationEntity:100\" \
+             xmlns:udt=\"urn:un:unece:uncefact:data:standard:UnqualifiedDataType:100\">\n",
+        );
+        xml.push_str("<rsm:ExchangedDocumentContext>");
+        // The business process is only part of the XRechnung profile.
+        if guideline == XRECHNUNG_GUIDELINE {
+            xml.push_str(&format!(
+                "<ram:BusinessProcessSpecifiedDocumentContextParameter><ram:ID>{}</ram:ID></ram:BusinessProcessSpecifiedDocumentContextParameter>",
+                PEPPOL_PROCESS
+            ));
+        }
+        xml.push_str(&format!(
+            "<ram:GuidelineSpecifiedDocumentContextParameter><ram:ID>{}</ram:ID></ram:GuidelineSpecifiedDocumentContextParameter>\
+             </rsm:ExchangedDocumentContext>\n",
+            guideline
+        ));
+
+        xml.push_str(&format!(
+            "<rsm:ExchangedDocument><ram:ID>{}</ram:ID><ram:TypeCode>380</ram:TypeCode>{}",
+            xml_escape(&invoice.invoice_number),
+            cii_date("ram:IssueDateTime", invoice.invoice_date)
+        ));
+        if let Some(notes) = invoice.notes.as_deref().filter(|n| !n.trim().is_empty()) {
+            xml.push_str(&format!("<ram:IncludedNote><ram:Content>{}</ram:Content></ram:IncludedNote>", xml_escape(notes)));
+        }
+        xml.push_str("</rsm:ExchangedDocument>\n<rsm:SupplyChainTradeTransaction>\n");
+
+        for (index, item) in invoice.items.iter().enumerate() {
+            let group = self.group_of(item.tax_rate_id, item.tax_rate);
+            xml.push_str(&format!(
+                "<ram:IncludedSupplyChainTradeLineItem>\
+                 <ram:AssociatedDocumentLineDocument><ram:LineID>{}</ram:LineID></ram:AssociatedDocumentLineDocument>\
+                 <ram:SpecifiedTradeProduct><ram:Name>{}</ram:Name></ram:SpecifiedTradeProduct>\
+                 <ram:SpecifiedLineTradeAgreement><ram:NetPriceProductTradePrice><ram:ChargeAmount>{}</ram:ChargeAmount></ram:NetPriceProductTradePrice></ram:SpecifiedLineTradeAgreement>\
+                 <ram:SpecifiedLineTradeDelivery><ram:BilledQuantity unitCode=\"C62\">{}</ram:BilledQuantity></ram:SpecifiedLineTradeDelivery>\
+                 <ram:SpecifiedLineTradeSettlement>\
+                 <ram:ApplicableTradeTax><ram:TypeCode>VAT</ram:TypeCode><ram:CategoryCode>{}</ram:CategoryCode><ram:RateApplicablePercent>{}</ram:RateApplicablePercent></ram:ApplicableTradeTax>{}\
+                 <ram:SpecifiedTradeSettlementLineMonetarySummation><ram:LineTotalAmount>{}</ram:LineTotalAmount></ram:SpecifiedTradeSettlementLineMonetarySummation>\
+                 </ram:SpecifiedLineTradeSettlement></ram:IncludedSupplyChainTradeLineItem>\n",
+                index + 1,
+                xml_escape(&item.description),
+                amount(item.unit_price),
+                item.quantity,
+                group.map_or("S", |g| category(g, self.rates)),
+                item.tax_rate.normalize(),
+                cii_line_discount(item),
+                amount(tax::line_net(item))
+            ));
+        }
+
+        let seller_tax = if seller.vat_id.trim().is_empty() {
+            format!(
+                "<ram:SpecifiedTaxRegistration><ram:ID schemeID=\"FC\">{}</ram:ID></ram:SpecifiedTaxRegistration>",
+                xml_escape(&seller.tax_number)
+            )
+        } else {
+            cii_vat_registration(&seller.vat_id)
+        };
+        let buyer_tax = if customer.vat_id.trim().is_empty() {
+            String::new()
+        } else {
+            cii_vat_registration(&customer.vat_id)
+        };
+        let buyer_contact = if customer.contact_name.trim().is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<ram:DefinedTradeContact><ram:PersonName>{}</ram:PersonName></ram:DefinedTradeContact>",
+                xml_escape(&customer.contact_name)
+            )
+        };
+        xml.push_str(&format!(
+            "<ram:ApplicableHeaderTradeAgreement><ram:BuyerReference>{}</ram:BuyerReference>\
+             <ram:SellerTradeParty><ram:Name>{}</ram:Name>\
+             <ram:DefinedTradeContact><ram:PersonName>{}</ram:PersonName>\
+             <ram:TelephoneUniversalCommunication><ram:CompleteNumber>{}</ram:CompleteNumber></ram:TelephoneUniversalCommunication>\
+             <ram:EmailURIUniversalCommunication><ram:URIID>{}</ram:URIID></ram:EmailURIUniversalCommunication></ram:DefinedTradeContact>\
+             {}{}{}</ram:SellerTradeParty>\
+             <ram:BuyerTradeParty><ram:Name>{}</ram:Name>{}{}{}{}</ram:BuyerTradeParty>\
+             </ram:ApplicableHeaderTradeAgreement>\n",
+            xml_escape(&invoice.buyer_reference),
+            xml_escape(&seller.company_name),
+            xml_escape(&seller.contact_name),
+            xml_escape(&seller.phone),
+            xml_escape(&seller.email),
+            cii_address(&seller.street, &seller.city, &seller.postal_code, &self.seller_country),
+            cii_email(&seller.email),
+            seller_tax,
+            xml_escape(&customer.company_name),
+            buyer_contact,
+            cii_address(&street(&customer.address), &customer.city, &customer.postal_code, &self.buyer_country),
+            cii_email(&customer.email),
+            buyer_tax
+        ));
+        xml.push_str("<ram:ApplicableHeaderTradeDelivery/>\n");
+
+        let bic = if seller.bic.trim().is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<ram:PayeeSpecifiedCreditorFinancialInstitution><ram:BICID>{}</ram:BICID></ram:PayeeSpecifiedCreditorFinancialInstitution>",
+                xml_escape(seller.bic.trim())
+            )
+        };
+        xml.push_str(&format!(
+            "<ram:ApplicableHeaderTradeSettlement><ram:InvoiceCurrencyCode>{}</ram:InvoiceCurrencyCode>\
+             <ram:SpecifiedTradeSettlementPaymentMeans><ram:TypeCode>58</ram:TypeCode>\
+             <ram:PayeePartyCreditorFinancialAccount><ram:IBANID>{}</ram:IBANID></ram:PayeePartyCreditorFinancialAccount>{}\
+             </ram:SpecifiedTradeSettlementPaymentMeans>\n",
+            self.currency,
+            xml_escape(&seller.iban.replace(' ', "")),
+            bic
+        ));
+        for group in &self.totals.groups {
+            let category = category(group, self.rates);
+            let (reason, reason_code) = if category == "AE" {
+                (
+                    format!("<ram:ExemptionReason>{}</ram:ExemptionReason>", xml_escape(&exemption_reason(group, self.rates))),
+                    "<ram:ExemptionReasonCode>VATEX-EU-AE</ram:ExemptionReasonCode>".to_string(),
+                )
+            } else {
+                (String::new(), String::new())
+            };
+            xml.push_str(&format!(
+                "<ram:ApplicableTradeTax><ram:CalculatedAmount>{}</ram:CalculatedAmount><ram:TypeCode>VAT</ram:TypeCode>{}\
+                 <ram:BasisAmount>{}</ram:BasisAmount><ram:CategoryCode>{}</ram:CategoryCode>{}\
+                 <ram:RateApplicablePercent>{}</ram:RateApplicablePercent></ram:ApplicableTradeTax>\n",
+                amount(group.tax),
+                reason,
+                amount(group.net),
+                category,
+                reason_code,
+                group.rate.normalize()
+            ));
+        }
+        xml.push_str(&format!(
+            "<ram:SpecifiedTradePaymentTerms>{}</ram:SpecifiedTradePaymentTerms>\n",
+            cii_date("ram:DueDateDateTime", invoice.due_date)
+        ));
+        xml.push_str(&format!(
+            "<ram:SpecifiedTradeSettlementHeaderMonetarySummation>\
+             <ram:LineTotalAmount>{}</ram:LineTotalAmount><ram:TaxBasisTotalAmount>{}</ram:TaxBasisTotalAmount>\
+             <ram:TaxTotalAmount currencyID=\"{}\">{}</ram:TaxTotalAmount><ram:GrandTotalAmount>{}</ram:GrandTotalAmount>\
+             <ram:DuePayableAmount>{}</ram:DuePayableAmount></ram:SpecifiedTradeSettlementHeaderMonetarySummation>\n\
+             </ram:ApplicableHeaderTradeSettlement>\n",
+            amount(self.totals.net),
+            amount(self.totals.net),
+            self.currency,
+            amount(self.totals.tax),
+            amount(self.totals.gross),
+            amount(self.totals.gross)
+        ));
+        xml.push_str("</rsm:SupplyChainTradeTransaction>\n</rsm:CrossIndustryInvoice>\n");
+        xml
+    }
+
+    fn group_of(&self, tax_rate_id: Option<i32>, rate: Decimal) -> Option<&TaxGroup> {
+        self.totals
+            .groups
+            .iter()
+            .find(|g| g.tax_rate_id == tax_rate_id && g.rate == rate)
+    }
+
+    /// Human-readable invoice as PDF/A-3 with the CII data attached.
+    fn zugferd(&self) -> Result<Vec<u8>, String> {
+        let (invoice, seller) = (self.invoice, self.seller);
+        let mut pdf = PdfDocument::for_archive();
+        let right = pdf::PAGE_WIDTH - PDF_MARGIN;
+
+        let mut y = pdf::PAGE_HEIGHT - PDF_MARGIN;
+        pdf.text(PDF_MARGIN, y, 14.0, Font::Bold, &seller.company_name);
+        y -= 14.0;
+        let sender = format!(
+            "{}, {} {}",
+            seller.street, seller.postal_code, seller.city
+        );
+        pdf.text(PDF_MARGIN, y, 8.0, Font::Regular, &sender);
+
+        let mut y = draw_address(&mut pdf, Some(self.customer), pdf::PAGE_HEIGHT - 150.0);
+        y = y.min(pdf::PAGE_HEIGHT - 260.0);
+        pdf.text(PDF_MARGIN, y, 18.0, Font::Bold, &format!("Invoice {}", invoice.invoice_number));
+        pdf.text_right(right, y, 10.0, Font::Regular, &format!("Date: {}", invoice.invoice_date));
+        y -= 14.0;
+        pdf.text_right(right, y, 10.0, Font::Regular, &format!("Due: {}", invoice.due_date));
+        y -= 14.0;
+        pdf.text_right(right, y, 10.0, Font::Regular, &format!("Reference: {}", invoice.buyer_reference));
+        y -= 30.0;
+
+        y = draw_items(&mut pdf, &invoice.items, self.rates, &invoice.currency, y);
+        let mut payment = format!(
+            "Please transfer the amount by {} to IBAN {}",
+            invoice.due_date, seller.iban
+        );
+        if !seller.bic.trim().is_empty() {
+            payment.push_str(&format!(", BIC {}", seller.bic));
+        }
+        payment.push_str(&format!(", quoting {}.", invoice.invoice_number));
+        y = draw_paragraph(&mut pdf, &payment, y) - 6.0;
+        if let Some(notes) = &invoice.notes {
+            y = draw_paragraph(&mut pdf, notes, y) - 6.0;
+        }
+        let tax_id = if seller.vat_id.trim().is_empty() {
+            format!("Tax number: {}", seller.tax_number)
+        } else {
+            format!("VAT ID: {}", seller.vat_id)
+        };
+        draw_paragraph(&mut pdf, &format!("{} | {} | {}", seller.company_name, tax_id, seller.email), y);
+
+        let attachment = Attachment {
+            name: FACTUR_X_FILE_NAME.to_string(),
+            mime_type: "text/xml".to_string(),
+            description: "Factur-X/ZUGFeRD invoice".to_string(),
+            relationship: "Alternative".to_string(),
+            data: self.cii(EN16931_GUIDELINE).into_bytes(),
+        };
+        pdf.to_pdfa_bytes(
+            &format!("Invoice {}", invoice.invoice_number),
+            &[attachment],
+            &factur_x_metadata(),
+        )
+    }
+}
+
+fn ubl_address(street: &str, city: &str, postal_code: &str, country: &str) -> String {
+    format!(
+        "<cac:PostalAddress><cbc:StreetName>{}</cbc:StreetName><cbc:CityName>{}</cbc:CityName>\
+         <cbc:PostalZone>{}</cbc:PostalZone><cac:Country><cbc:IdentificationCode>{}</cbc:IdentificationCode></cac:Country></cac:PostalAddress>",
+        xml_escape(street),
+        xml_escape(city),
+        xml_escape(postal_code),
+        country
+    )
+}
+
+fn ubl_vat_scheme(vat_id: &str) -> String {
+    format!(
+        "<cac:PartyTaxScheme><cbc:CompanyID>{}</cbc:CompanyID><cac:TaxScheme><cbc:ID>VAT</cbc:ID></cac:TaxScheme></cac:PartyTaxScheme>",
+        xml_escape(&vat_id.replace(' ', ""))
+    )
+}
+
+fn cii_address(street: &str, city: &str, postal_code: &str, country: &str) -> String {
+    format!(
+        "<ram:PostalTradeAddress><ram:PostcodeCode>{}</ram:PostcodeCode><ram:LineOne>{}</ram:LineOne>\
+         <ram:CityName>{}</ram:CityName><ram:CountryID>{}</ram:CountryID></ram:PostalTradeAddress>",
+        xml_escape(postal_code),
+        xml_escape(street),
+        xml_escape(city),
+        country
+    )
+}
+
+fn cii_email(email: &str) -> String {
+    format!(
+        "<ram:URIUniversalCommunication><ram:URIID schemeID=\"EM\">{}</ram:URIID></ram:URIUniversalCommunication>",
+        xml_escape(email)
+    )
+}
+
+fn cii_vat_registration(vat_id: &str) -> String {
+    format!(
+        "<ram:SpecifiedTaxRegistration><ram:ID schemeID=\"VA\">{}</ram:ID></ram:SpecifiedTaxRegistration>",
+        xml_escape(&vat_id.replace(' ', ""))
+    )
+}
+
+/// Line allowance for the discount of `item`, if it has one.
+fn cii_line_discount(item: &LineItem) -> String {
+    if item.discount_percent.is_zero() {
+        return String::new();
+    }
+    format!(
+        "<ram:SpecifiedTradeAllowanceCharge><ram:ChargeIndicator><udt:Indicator>false</udt:Indicator></ram:ChargeIndicator>\
+         <ram:CalculationPercent>{}</ram:CalculationPercent><ram:BasisAmount>{}</ram:BasisAmount>\
+         <ram:ActualAmount>{}</ram:ActualAmount><ram:ReasonCode>95</ram:ReasonCode><ram:Reason>Discount</ram:Reason>\
+         </ram:SpecifiedTradeAllowanceCharge>",
+        item.discount_percent.normalize(),
+        amount(tax::line_amount(item)),
+        amount(tax::line_discount(item))
+    )
+}
+
+fn cii_date(element: &str, date: chrono::NaiveDate) -> String {
+    format!(
+        "<{0}><udt:DateTimeString format=\"102\">{1}</udt:DateTimeString></{0}>",
+        element,
+        date.format("%Y%m%d")
+    )
+}
+
+/// XMP for the Factur-X properties, with the extension schema PDF/A
+/// requires for properties outside its predefined schemas.
+fn factur_x_metadata() -> String {
+    let property = |name: &str, description: &str| {
+        format!(
+            "<rdf:li rdf:parseType=\"Resource\"><pdfaProperty:name>{}</pdfaProperty:name>\
+             <pdfaProperty:valueType>Text</pdfaProperty:valueType><pdfaProperty:category>external</pdfaProperty:category>\
+             <pdfaProperty:description>{}</pdfaProperty:description></rdf:li>",
+            name, description
+        )
+    };
+    format!(
+        "<rdf:Description rdf:about=\"\" xmlns:pdfaExtension=\"http://www.aiim.org/pdfa/ns/extension/\" \
+         xmlns:pdfaSchema=\"http://www.aiim.org/pdfa/ns/schema#\" xmlns:pdfaProperty=\"http://www.aiim.org/pdfa/ns/property#\">\
+         <pdfaExtension:schemas><rdf:Bag><rdf:li rdf:parseType=\"Resource\">\
+         <pdfaSchema:schema>Factur-X PDFA Extension Schema</pdfaSchema:schema>\
+         <pdfaSchema:namespaceURI>{0}</pdfaSchema:namespaceURI><pdfaSchema:prefix>fx</pdfaSchema:prefix>\
+         <pdfaSchema:property><rdf:Seq>{1}{2}{3}{4}</rdf:Seq></pdfaSchema:property>\
+         </rdf:li></rdf:Bag></pdfaExtension:schemas></rdf:Description>\n\
+         <rdf:Description rdf:about=\"\" xmlns:fx=\"{0}\">\
+         <fx:DocumentType>INVOICE</fx:DocumentType><fx:DocumentFileName>{5}</fx:DocumentFileName>\
+         <fx:Version>1.0</fx:Version><fx:ConformanceLevel>EN 16931</fx:ConformanceLevel></rdf:Description>",
+        FACTUR_X_NAMESPACE,
+        property("DocumentFileName", "Name of the embedded XML invoice file"),
+        property("DocumentType", "Type of the hybrid document"),
+        property("Version", "Version of the Factur-X XML schema"),
+        property("ConformanceLevel", "Conformance level of the embedded XML invoice"),
+        FACTUR_X_FILE_NAME
+    )
+}
+
+/// Writes `invoice` as an e-invoice in `format` after validating it.
+pub fn export(
+    format: Format,
+    invoice: &Invoice,
+    customer: Option<&Customer>,
+    seller: &CompanyProfile,
+    rates: &[TaxRate],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let errors = validate(invoice, customer, seller, rates);
+    let Some(customer) = customer.filter(|_| errors.is_empty()) else {
+        return Err(errors.join("; ").into());
+    };
+    let document = Document::new(invoice, customer, seller, rates);
+    let contents = match format {
+        Format::XRechnungUbl => document.ubl().into_bytes(),
+        Format::XRechnungCii => document.cii(XRECHNUNG_GUIDELINE).into_bytes(),
+        Format::Zugferd => document.zugferd()?,
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tax::test_support::item;
+
+    /// Change that leaves one field missing, and the error it must cause.
+    type Case<T> = (fn(&mut T), &'static str);
+
+    fn rates() -> Vec<TaxRate> {
+        let rate = |tax_rate_id: i32, rate: i64, reverse_charge: bool| TaxRate {
+            tax_rate_id,
+            name: format!("{}%", rate),
+            rate: Decimal::from(rate),
+            reverse_charge,
+            note: String::new(),
+            is_default: false,
+            active: true,
+            revenue_account: String::new(),
+        };
+        vec![rate(1, 19, false), rate(3, 0, true)]
+    }
+
+    fn invoice() -> Invoice {
+        Invoice {
+            invoice_number: "RE_2026/0001".to_string(),
+            buyer_reference: "04011000-12345-67".to_string(),
+            items: vec![item(2, "50.00", 1, 19)],
+            ..Invoice::default()
+        }
+    }
+
+    fn customer() -> Customer {
+        Customer {
+            company_name: "Muster GmbH".to_string(),
+            postal_code: "10115".to_string(),
+            city: "Berlin".to_string(),
+            country: "Deutschland".to_string(),
+            email: "rechnung@muster.de".to_string(),
+            vat_id: "DE123456789".to_string(),
+            ..Customer::default()
+        }
+    }
+
+    fn seller() -> CompanyProfile {
+        CompanyProfile {
+            company_name: "Beispiel AG".to_string(),
+            street: "Hauptstraße 1".to_string(),
+            postal_code: "80331".to_string(),
+            city: "München".to_string(),
+            country_code: "DE".to_string(),
+            vat_id: "DE987654321".to_string(),
+            contact_name: "Erika Beispiel".to_string(),
+            phone: "089 123456".to_string(),
+            email: "buchhaltung@beispiel.de".to_string(),
+            iban: "DE02120300000000202051".to_string(),
+            ..CompanyProfile::default()
+        }
+    }
+
+    /// Errors of the complete invoice after `change`.
+    fn errors_after(change: impl FnOnce(&mut Invoice, &mut Customer, &mut CompanyProfile)) -> Vec<String> {
+        let (mut invoice, mut customer, mut seller) = (invoice(), customer(), seller());
+        change(&mut invoice, &mut customer, &mut seller);
+        validate(&invoice, Some(&customer), &seller, &rates())
+    }
+
+    #[test]
+    fn validate_accepts_a_complete_invoice() {
+        assert_eq!(errors_after(|_, _, _| {}), Vec::<String>::new());
+    }
+
+    #[test]
+    fn validate_reports_each_missing_invoice_field() {
+        assert_eq!(
+            errors_after(|invoice, _, _| invoice.buyer_reference = " ".to_string()),
+            ["The invoice has no buyer reference (Leitweg-ID)"]
+        );
+        assert_eq!(
+            errors_after(|invoice, _, _| invoice.invoice_number.clear()),
+            ["The invoice has no number"]
+        );
+        assert_eq!(
+            errors_after(|invoice, _, _| invoice.items.clear()),
+            ["The invoice has no items"]
+        );
+        assert_eq!(
+            errors_after(|invoice, _, _| invoice.document_type = "credit_note".to_string()),
+            ["E-invoices of credit notes are not supported"]
+        );
+    }
+
+    #[test]
+    fn validate_reports_each_missing_seller_field() {
+        let cases: [Case<CompanyProfile>; 9] = [
+            (|s| s.company_name.clear(), "The company profile has no company name"),
+            (|s| s.street.clear(), "The company profile has no street"),
+            (|s| s.postal_code.clear(), "The company profile has no postal code"),
+            (|s| s.city.clear(), "The company profile has no city"),
+            (|s| s.contact_name.clear(), "The company profile has no contact name"),
+            (|s| s.phone.clear(), "The company profile has no phone number"),
+            (|s| s.email.clear(), "The company profile has no email address"),
+            (|s| s.iban.clear(), "The company profile has no IBAN"),
+            (|s| s.country_code = "Nowhere".to_string(), "The company profile has no valid country code"),
+        ];
+        for (change, message) in cases {
+            assert_eq!(errors_after(|_, _, seller| change(seller)), [message]);
+        }
+    }
+
+    #[test]
+    fn validate_requires_a_seller_vat_id_or_tax_number() {
+        assert_eq!(
+            errors_after(|_, _, seller| seller.vat_id.clear()),
+            ["The company profile has neither a VAT ID nor a tax number"]
+        );
+        assert_eq!(
+            errors_after(|_, _, seller| {
+                seller.vat_id.clear();
+                seller.tax_number = "143/123/45678".to_string();
+            }),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn validate_requires_both_vat_ids_for_reverse_charge() {
+        let reverse_charge = |invoice: &mut Invoice| invoice.items = vec![item(1, "100.00", 3, 0)];
+        assert_eq!(
+            errors_after(|invoice, _, seller| {
+                reverse_charge(invoice);
+                seller.vat_id.clear();
+                seller.tax_number = "143/123/45678".to_string();
+            }),
+            ["Reverse charge requires the VAT ID in the company profile"]
+        );
+        assert_eq!(
+            errors_after(|invoice, customer, _| {
+                reverse_charge(invoice);
+                customer.vat_id.clear();
+            }),
+            ["Reverse charge requires the VAT ID of the customer"]
+        );
+    }
+
+    #[test]
+    fn validate_reports_each_missing_customer_field() {
+        let cases: [Case<Customer>; 5] = [
+            (|c| c.company_name.clear(), "The customer has no company name"),
+            (|c| c.postal_code.clear(), "The customer has no postal code"),
+            (|c| c.city.clear(), "The customer has no city"),
+            (|c| c.email.clear(), "The customer has no email address"),
+            (|c| c.country = "Atlantis".to_string(), "The country \"Atlantis\" of the customer is not known"),
+        ];
+        for (change, message) in cases {
+            assert_eq!(errors_after(|_, customer, _| change(customer)), [message]);
+        }
+        assert_eq!(
+            validate(&invoice(), None, &seller(), &rates()),
+            ["The invoice has no customer"]
+        );
+    }
+}
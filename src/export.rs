@@ -0,0 +1,37 @@
+// export.rs
+use std::fs;
+use std::path::Path;
+
+/// Quotes `value` if it contains the separator, quotes or line breaks.
+pub fn csv_field(value: &str, separator: char) -> String {
+    if value.contains(separator) || value.contains('"') || value.contains('\n') || value.contains('\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn write_csv(
+    path: &Path,
+    separator: char,
+    header: &[&str],
+    rows: &[Vec<String>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let join = |fields: Vec<String>| fields.join(&separator.to_string());
+    let mut contents = join(header.iter().map(|h| csv_field(h, separator)).collect());
+    contents.push_str("\r\n");
+    for row in rows {
+        contents.push_str(&join(row.iter().map(|f| csv_field(f, separator)).collect()));
+        contents.push_str("\r\n");
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Default location for exported files: `file_name` in the home directory.
+pub fn default_export_path(file_name: &str) -> String {
+    format!("{}/{}", std::env::var("HOME").unwrap_or_default(), file_name)
+}
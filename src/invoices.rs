@@ -0,0 +1,777 @@
+// invoices.rs
+use crate::audit::AuditPanel;
+use crate::currency::{self, CurrencyStore};
+use crate::auth;
+use crate::db::{self, CompanyProfile, Customer, Invoice, LineItem, Product, SaveResult, TaxRate};
+use crate::einvoice;
+use crate::export;
+use crate::merge::{MergeAction, MergeDialog};
+use crate::pdf::{self, Font, PdfDocument};
+use crate::pricing::PriceListStore;
+use crate::stock;
+use crate::tax::{self, TaxRateStore};
+use crate::ui;
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// "credited" is set once issued credit notes cover an invoice.
+pub const INVOICE_STATUSES: [&str; 5] = ["draft", "sent", "paid", "credited", "cancelled"];
+
+pub struct InvoicesView {
+    invoices: Arc<Mutex<Vec<Invoice>>>,
+    products: Arc<Mutex<Vec<Product>>>,
+    /// Seller details of e-invoices.
+    company: Arc<Mutex<CompanyProfile>>,
+    /// Set whenever the invoice list must be (re)loaded from the database,
+    /// e.g. by the change listener after another client edited an invoice.
+    pub stale: Arc<AtomicBool>,
+    editor: Arc<Mutex<Option<Invoice>>>,
+    /// The stored invoice the editor started from, the base of a merge.
+    loaded: Arc<Mutex<Option<Invoice>>>,
+    merge: Arc<Mutex<Option<MergeDialog<Invoice>>>>,
+    status: Arc<Mutex<String>>,
+    audit: AuditPanel,
+    einvoice_format: einvoice::Format,
+    einvoice_path: String,
+    pdf_path: String,
+}
+
+impl Default for InvoicesView {
+    fn default() -> Self {
+        Self {
+            invoices: Arc::new(Mutex::new(Vec::new())),
+            products: Arc::new(Mutex::new(Vec::new())),
+            company: Arc::new(Mutex::new(CompanyProfile::default())),
+            stale: Arc::new(AtomicBool::new(true)),
+            editor: Arc::new(Mutex::new(None)),
+            loaded: Arc::new(Mutex::new(None)),
+            merge: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(String::new())),
+            audit: AuditPanel::default(),
+            einvoice_format: einvoice::Format::default(),
+            einvoice_path: String::new(),
+            pdf_path: String::new(),
+        }
+    }
+}
+
+impl InvoicesView {
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &Arc<Mutex<Vec<Customer>>>,
+        tax_rates: &TaxRateStore,
+        currencies: &CurrencyStore,
+        prices: &PriceListStore,
+    ) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load_invoices();
+        }
+        tax_rates.refresh_if_stale();
+        currencies.refresh_if_stale();
+        prices.refresh_if_stale();
+
+        let customers = customers.lock().unwrap().clone();
+        let customer_names: HashMap<i32, String> = customers
+            .iter()
+            .map(|c| (c.customer_id, c.company_name.clone()))
+            .collect();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Invoices");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            if auth::can_edit() && ui.button("Create New Invoice").clicked() {
+                self.open(Invoice::default());
+            }
+            ui.add_space(10.0);
+
+            let invoices = self.invoices.lock().unwrap().clone();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("invoice_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Number");
+                    ui.strong("Type");
+                    ui.strong("Customer");
+                    ui.strong("Date");
+                    ui.strong("Due");
+                    ui.strong("Total");
+                    ui.strong("Status");
+                    ui.end_row();
+
+                    for invoice in &invoices {
+                        ui.label(&invoice.invoice_number);
+                        ui.label(invoice.document_label());
+                        ui.label(customer_label(&customer_names, invoice.customer_id));
+                        ui.label(invoice.invoice_date.to_string());
+                        ui.label(invoice.due_date.to_string());
+                        ui.label(currency::format_amount(invoice.total_amount, &invoice.currency));
+                        ui.label(&invoice.status);
+                        if ui.button("Open").clicked() {
+                            self.open(invoice.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+
+        self.render_editor(ctx, &customers, &customer_names, tax_rates, currencies, prices);
+        self.render_merge_dialog(ctx);
+    }
+
+    fn open(&mut self, invoice: Invoice) {
+        self.einvoice_path = einvoice_path(&invoice, self.einvoice_format);
+        self.pdf_path = pdf_path(&invoice);
+        *self.loaded.lock().unwrap() = Some(invoice.clone());
+        *self.editor.lock().unwrap() = Some(invoice);
+    }
+
+    /// Opens a new invoice to `customer` in the editor.
+    pub fn create_for(&mut self, customer: &Customer) {
+        self.open(Invoice {
+            customer_id: Some(customer.customer_id),
+            currency: customer.currency.clone(),
+            buyer_reference: customer.buyer_reference.clone(),
+            ..Invoice::default()
+        });
+    }
+
+    fn render_editor(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &[Customer],
+        customer_names: &HashMap<i32, String>,
+        tax_rates: &TaxRateStore,
+        currencies: &CurrencyStore,
+        prices: &PriceListStore,
+    ) {
+        let Some(mut invoice) = self.editor.lock().unwrap().clone() else {
+            return;
+        };
+        if invoice.currency.is_empty() {
+            invoice.currency = currencies.base();
+        }
+        let products = self.products.lock().unwrap().clone();
+        let rates = tax_rates.all_rates();
+
+        let mut open = true;
+        let mut save = false;
+        let mut credit = false;
+        let mut write_pdf = false;
+        let mut export_einvoice = false;
+        let saved = self
+            .invoices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|i| i.invoice_id == invoice.invoice_id)
+            .cloned();
+        let original = self.original(&invoice);
+        // Issued invoices are corrected with a credit note; only their
+        // status can still move on.
+        let issued = saved.as_ref().is_some_and(Invoice::is_issued);
+        egui::Window::new(invoice.document_label())
+            .id(egui::Id::new("invoice_editor"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("invoice_editor_grid").show(ui, |ui| {
+                    if let Some(original) = &original {
+                        ui.label("Credit Note For:");
+                        ui.label(format!("Invoice {} of {}", original.invoice_number, original.invoice_date));
+                        ui.end_row();
+                    }
+
+                    ui.label("Customer:");
+                    ui.add_enabled_ui(!issued, |ui| {
+                        egui::ComboBox::from_id_source("invoice_customer")
+                            .selected_text(customer_label(customer_names, invoice.customer_id))
+                            .show_ui(ui, |ui| {
+                                let mut names: Vec<_> = customer_names.iter().collect();
+                                names.sort_by(|a, b| a.1.cmp(b.1));
+                                for (id, name) in names {
+                                    let picked = ui
+                                        .selectable_value(&mut invoice.customer_id, Some(*id), name)
+                                        .clicked();
+                                    // New invoices are in the customer's currency.
+                                    if picked && invoice.invoice_id == 0 {
+                                        if let Some(customer) = customers.iter().find(|c| c.customer_id == *id) {
+                                            invoice.currency = customer.currency.clone();
+                                            invoice.buyer_reference = customer.buyer_reference.clone();
+                                        }
+                                    }
+                                }
+                            });
+                    });
+                    ui.end_row();
+
+                    ui.label("Currency:");
+                    ui.add_enabled_ui(!issued, |ui| {
+                        currency::currency_combo(ui, "invoice_currency", &mut invoice.currency, currencies);
+                    });
+                    ui.end_row();
+
+                    // Only new invoices take a number by hand; once saved it is fixed.
+                    ui.label("Number:");
+                    ui.add_enabled(
+                        invoice.invoice_id == 0,
+                        egui::TextEdit::singleline(&mut invoice.invoice_number).hint_text("assigned on save"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Date:");
+                    ui.add_enabled_ui(!issued, |ui| ui::parsed_field(ui, "invoice_date", &mut invoice.invoice_date));
+                    ui.end_row();
+
+                    ui.label("Due Date:");
+                    ui.add_enabled_ui(!issued, |ui| ui::parsed_field(ui, "invoice_due_date", &mut invoice.due_date));
+                    ui.end_row();
+
+                    // Invoices from before item entry keep their manually entered total.
+                    if invoice.items.is_empty() {
+                        ui.label("Total Amount:");
+                        ui.add_enabled_ui(!issued, |ui| {
+                            ui::parsed_field(ui, "invoice_total", &mut invoice.total_amount)
+                        });
+                        ui.end_row();
+                    }
+
+                    ui.label("Status:");
+                    egui::ComboBox::from_id_source("invoice_status")
+                        .selected_text(invoice.status.clone())
+                        .show_ui(ui, |ui| {
+                            let allowed = INVOICE_STATUSES
+                                .into_iter()
+                                .filter(|status| saved.as_ref().is_none_or(|s| s.allows_status(status)));
+                            for status in allowed {
+                                ui.selectable_value(&mut invoice.status, status.to_string(), status);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Payment Method:");
+                    ui.add_enabled_ui(!issued, |ui| optional_text_edit(ui, &mut invoice.payment_method, false));
+                    ui.end_row();
+
+                    ui.label("Buyer Reference:");
+                    ui.add_enabled(!issued, egui::TextEdit::singleline(&mut invoice.buyer_reference));
+                    ui.end_row();
+
+                    ui.label("Notes:");
+                    ui.add_enabled_ui(!issued, |ui| optional_text_edit(ui, &mut invoice.notes, true));
+                    ui.end_row();
+                });
+
+                ui.add_space(10.0);
+                ui.strong("Items");
+                let customer_id = invoice.customer_id;
+                ui.add_enabled_ui(!issued, |ui| {
+                    render_items(ui, "invoice_items", &mut invoice.items, &products, tax_rates, prices, customer_id)
+                });
+                if !invoice.items.is_empty() {
+                    tax::render_totals(ui, &invoice.items, &rates);
+                }
+                for warning in stock::stock_warnings(&invoice, saved.as_ref(), &products) {
+                    ui.colored_label(egui::Color32::from_rgb(200, 120, 0), warning);
+                }
+                ui.add_space(10.0);
+
+                ui.horizontal(|ui| {
+                    if auth::can_edit() && ui.button("Save").clicked() {
+                        save = true;
+                    }
+                    let creditable = saved.as_ref().is_some_and(|s| {
+                        !s.is_credit_note() && db::ISSUED_STATUSES.contains(&s.status.as_str())
+                    });
+                    if auth::can_edit() && creditable && ui.button("Create Credit Note").clicked() {
+                        credit = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("PDF:");
+                    ui.text_edit_singleline(&mut self.pdf_path);
+                    if ui.button("Create PDF").clicked() {
+                        write_pdf = true;
+                    }
+                });
+
+                ui.add_space(10.0);
+                ui.strong("E-Invoice");
+                ui.horizontal(|ui| {
+                    let format = self.einvoice_format;
+                    egui::ComboBox::from_id_source("einvoice_format")
+                        .selected_text(format.label())
+                        .show_ui(ui, |ui| {
+                            for option in einvoice::Format::ALL {
+                                ui.selectable_value(&mut self.einvoice_format, option, option.label());
+                            }
+                        });
+                    if self.einvoice_format != format {
+                        self.einvoice_path = einvoice_path(&invoice, self.einvoice_format);
+                    }
+                    ui.text_edit_singleline(&mut self.einvoice_path);
+                    if ui.button("Export").clicked() {
+                        export_einvoice = true;
+                    }
+                });
+                let customer = invoice
+                    .customer_id
+                    .and_then(|id| customers.iter().find(|c| c.customer_id == id));
+                let errors = einvoice::validate(&invoice, customer, &self.company.lock().unwrap(), &rates);
+                for error in errors {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                if invoice.invoice_id != 0 {
+                    self.audit
+                        .show(ui, "invoices", invoice.invoice_id, invoice.version);
+                }
+            });
+
+        if !open {
+            *self.editor.lock().unwrap() = None;
+            return;
+        }
+        *self.editor.lock().unwrap() = Some(invoice.clone());
+        if write_pdf {
+            let customer = invoice
+                .customer_id
+                .and_then(|id| customers.iter().find(|c| c.customer_id == id));
+            let path = Path::new(&self.pdf_path);
+            *self.status.lock().unwrap() =
+                match write_invoice_pdf(&invoice, original.as_ref(), customer, &rates, path) {
+                    Ok(()) => format!("{} written to {}", invoice.document_label(), self.pdf_path),
+                    Err(e) => format!("Error writing PDF: {}", e),
+                };
+        }
+        if credit {
+            self.create_credit_note(invoice.invoice_id);
+            return;
+        }
+        if export_einvoice {
+            let customer = invoice
+                .customer_id
+                .and_then(|id| customers.iter().find(|c| c.customer_id == id));
+            let company = self.company.lock().unwrap().clone();
+            let path = Path::new(&self.einvoice_path);
+            *self.status.lock().unwrap() =
+                match einvoice::export(self.einvoice_format, &invoice, customer, &company, &rates, path) {
+                    Ok(()) => format!("E-invoice written to {}", self.einvoice_path),
+                    Err(e) => format!("Error writing e-invoice: {}", e),
+                };
+        }
+        if save {
+            self.save_invoice(invoice);
+        }
+    }
+
+    fn render_merge_dialog(&mut self, ctx: &egui::Context) {
+        let mut merge = self.merge.lock().unwrap();
+        let Some(dialog) = merge.as_mut() else {
+            return;
+        };
+        let Some(action) = dialog.show(ctx, "Invoice changed by someone else") else {
+            return;
+        };
+
+        let dialog = merge.take().unwrap();
+        drop(merge);
+        match action {
+            MergeAction::SaveMerged => {
+                let merged = dialog.merged();
+                *self.loaded.lock().unwrap() = Some(dialog.theirs.clone());
+                *self.editor.lock().unwrap() = Some(merged.clone());
+                self.save_invoice(merged);
+            }
+            MergeAction::DiscardMine => {
+                *self.loaded.lock().unwrap() = Some(dialog.theirs.clone());
+                *self.editor.lock().unwrap() = Some(dialog.theirs);
+            }
+            MergeAction::Cancel => {}
+        }
+    }
+
+    /// Invoice corrected by `invoice` if it is a credit note.
+    fn original(&self, invoice: &Invoice) -> Option<Invoice> {
+        let original_id = invoice.original_invoice_id?;
+        self.invoices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|i| i.invoice_id == original_id)
+            .cloned()
+    }
+
+    /// Creates a draft credit note for the invoice and opens it.
+    fn create_credit_note(&self, invoice_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let invoices = Arc::clone(&self.invoices);
+        let editor = Arc::clone(&self.editor);
+        let loaded = Arc::clone(&self.loaded);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::create_credit_note(&config, invoice_id).await {
+                Ok(credit_note) => {
+                    *status.lock().unwrap() = format!("Draft credit note {} created", credit_note.invoice_number);
+                    invoices.lock().unwrap().insert(0, credit_note.clone());
+                    *loaded.lock().unwrap() = Some(credit_note.clone());
+                    *editor.lock().unwrap() = Some(credit_note);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error creating credit note: {}", e),
+            }
+        });
+    }
+
+    fn load_invoices(&self) {
+        let invoices = Arc::clone(&self.invoices);
+        let products = Arc::clone(&self.products);
+        let company = Arc::clone(&self.company);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_invoices(&config).await {
+                    Ok(loaded) => *invoices.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching invoices: {}", e),
+                }
+                match db::get_products(&config).await {
+                    Ok(loaded) => *products.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching products: {}", e),
+                }
+                match db::get_company_profile(&config).await {
+                    Ok(loaded) => *company.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching company profile: {}", e),
+                }
+            }
+        });
+    }
+
+    fn save_invoice(&self, invoice: Invoice) {
+        let Some(config) = db::get_config() else {
+            *self.status.lock().unwrap() = "No database configuration found!".to_string();
+            return;
+        };
+        let invoices = Arc::clone(&self.invoices);
+        let editor = Arc::clone(&self.editor);
+        let loaded = Arc::clone(&self.loaded);
+        let merge = Arc::clone(&self.merge);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let result = if invoice.invoice_id == 0 {
+                db::add_invoice(&config, &invoice).await.map(SaveResult::Saved)
+            } else {
+                db::update_invoice(&config, &invoice).await
+            };
+            match result {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = format!("{} {} saved", saved.document_label(), saved.invoice_number);
+                    let mut invoices = invoices.lock().unwrap();
+                    match invoices.iter_mut().find(|i| i.invoice_id == saved.invoice_id) {
+                        Some(existing) => *existing = saved,
+                        None => invoices.insert(0, saved),
+                    }
+                    *editor.lock().unwrap() = None;
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() = format!(
+                        "{} {} was changed by someone else",
+                        current.document_label(),
+                        current.invoice_number
+                    );
+                    let base = loaded.lock().unwrap().clone().unwrap_or_else(|| current.clone());
+                    *merge.lock().unwrap() = Some(MergeDialog::new(&base, invoice, current));
+                }
+                Err(e) => {
+                    eprintln!("Error saving invoice: {}", e);
+                    *status.lock().unwrap() = format!("Error saving {}: {}", invoice.document_label().to_lowercase(), e);
+                }
+            }
+        });
+    }
+}
+
+pub fn customer_label(customer_names: &HashMap<i32, String>, customer_id: Option<i32>) -> String {
+    customer_id
+        .and_then(|id| customer_names.get(&id).cloned())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn optional_text_edit(ui: &mut egui::Ui, value: &mut Option<String>, multiline: bool) {
+    let mut text = value.clone().unwrap_or_default();
+    if multiline {
+        ui.text_edit_multiline(&mut text);
+    } else {
+        ui.text_edit_singleline(&mut text);
+    }
+    *value = (!text.is_empty()).then_some(text);
+}
+
+/// Editable item table. Picking a product fills in its name, tax rate and
+/// the customer's price for the quantity, which changing the quantity
+/// looks up again; lines without a product get the default rate. Credit
+/// notes have negative quantities.
+pub fn render_items(
+    ui: &mut egui::Ui,
+    id_source: &str,
+    items: &mut Vec<LineItem>,
+    products: &[Product],
+    tax_rates: &TaxRateStore,
+    prices: &PriceListStore,
+    customer_id: Option<i32>,
+) {
+    let rates = tax_rates.all_rates();
+    let mut remove = None;
+    egui::Grid::new((id_source, "items")).striped(true).show(ui, |ui| {
+        ui.strong("Product");
+        ui.strong("Description");
+        ui.strong("Quantity");
+        ui.strong("Unit Price");
+        ui.strong("Discount %");
+        ui.strong("Tax");
+        ui.strong("Net");
+        ui.end_row();
+
+        for (index, item) in items.iter_mut().enumerate() {
+            let product = item
+                .product_id
+                .and_then(|id| products.iter().find(|p| p.product_id == id));
+            let product_name = product.map_or("-".to_string(), |p| p.product_name.clone());
+            egui::ComboBox::from_id_source((id_source, "product", index))
+                .selected_text(product_name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut item.product_id, None, "-");
+                    for product in products {
+                        let picked = ui
+                            .selectable_value(&mut item.product_id, Some(product.product_id), &product.product_name)
+                            .clicked();
+                        if picked {
+                            item.description = product
+                                .description
+                                .clone()
+                                .unwrap_or_else(|| product.product_name.clone());
+                            item.unit_price = prices.unit_price(product, customer_id, item.quantity.abs());
+                            let rate = product
+                                .tax_rate_id
+                                .and_then(|id| rates.iter().find(|r| r.tax_rate_id == id).cloned())
+                                .or_else(|| tax_rates.default_rate());
+                            tax::apply_rate(item, rate.as_ref());
+                        }
+                    }
+                });
+            ui.text_edit_singleline(&mut item.description);
+            let quantity = ui.add(egui::DragValue::new(&mut item.quantity).clamp_range(-1_000_000..=1_000_000));
+            if let Some(product) = product.filter(|_| quantity.changed()) {
+                item.unit_price = prices.unit_price(product, customer_id, item.quantity.abs());
+            }
+            ui::parsed_field(ui, (id_source, "price", index), &mut item.unit_price);
+            ui::parsed_field(ui, (id_source, "discount", index), &mut item.discount_percent);
+            item.discount_percent = item.discount_percent.clamp(Decimal::ZERO, Decimal::from(100));
+            tax::rate_combo(ui, (id_source, "tax", index), item, &rates);
+            ui.label(format!("{:.2}", tax::line_net(item)));
+            if ui.small_button("x").on_hover_text("Remove item").clicked() {
+                remove = Some(index);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(index) = remove {
+        items.remove(index);
+    }
+    if ui.button("Add Item").clicked() {
+        let mut item = LineItem {
+            item_id: 0,
+            product_id: None,
+            description: String::new(),
+            quantity: 1,
+            unit_price: Decimal::ZERO,
+            total_price: Decimal::ZERO,
+            tax_rate_id: None,
+            tax_rate: Decimal::ZERO,
+            discount_percent: Decimal::ZERO,
+        };
+        tax::apply_rate(&mut item, tax_rates.default_rate().as_ref());
+        items.push(item);
+    }
+}
+
+/// Default e-invoice file of `invoice` in the export directory.
+fn einvoice_path(invoice: &Invoice, format: einvoice::Format) -> String {
+    let name = if invoice.invoice_number.is_empty() {
+        format!("invoice.{}", format.extension())
+    } else {
+        format!("invoice_{}.{}", invoice.invoice_number.replace(['/', '\\'], "-"), format.extension())
+    };
+    export::default_export_path(&name)
+}
+
+/// Default PDF file of `invoice` in the export directory.
+fn pdf_path(invoice: &Invoice) -> String {
+    let kind = if invoice.is_credit_note() { "credit_note" } else { "invoice" };
+    let name = if invoice.invoice_number.is_empty() {
+        format!("{}.pdf", kind)
+    } else {
+        format!("{}_{}.pdf", kind, invoice.invoice_number.replace(['/', '\\'], "-"))
+    };
+    export::default_export_path(&name)
+}
+
+/// Writes `invoice` as a PDF. Credit notes name the invoice they correct.
+pub fn write_invoice_pdf(
+    invoice: &Invoice,
+    original: Option<&Invoice>,
+    customer: Option<&Customer>,
+    rates: &[TaxRate],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pdf = PdfDocument::default();
+    let right = pdf::PAGE_WIDTH - PDF_MARGIN;
+    let mut y = draw_address(&mut pdf, customer, pdf::PAGE_HEIGHT - 150.0);
+
+    y = y.min(pdf::PAGE_HEIGHT - 260.0);
+    let title = if invoice.is_credit_note() { "Credit Note" } else { "Invoice" };
+    pdf.text(PDF_MARGIN, y, 18.0, Font::Bold, &format!("{} {}", title, invoice.invoice_number));
+    pdf.text_right(right, y, 10.0, Font::Regular, &format!("Date: {}", invoice.invoice_date));
+    y -= 14.0;
+    match original {
+        Some(original) => {
+            let reference = format!("For invoice {} of {}", original.invoice_number, original.invoice_date);
+            pdf.text_right(right, y, 10.0, Font::Regular, &reference);
+        }
+        None => pdf.text_right(right, y, 10.0, Font::Regular, &format!("Due: {}", invoice.due_date)),
+    }
+    y -= 30.0;
+
+    // Invoices from before item entry only have their entered total.
+    if invoice.items.is_empty() {
+        let total = format!("Total {}", currency::format_amount(invoice.total_amount, &invoice.currency));
+        pdf.text_right(right, y, 11.0, Font::Bold, &total);
+        y -= 30.0;
+    } else {
+        y = draw_items(&mut pdf, &invoice.items, rates, &invoice.currency, y);
+    }
+    if let Some(notes) = &invoice.notes {
+        draw_paragraph(&mut pdf, notes, y);
+    }
+
+    pdf.save(path)
+}
+
+pub const PDF_MARGIN: f32 = 56.0;
+
+/// Recipient address of a document, starting at `y`. Returns the height
+/// below it.
+pub fn draw_address(pdf: &mut PdfDocument, customer: Option<&Customer>, mut y: f32) -> f32 {
+    if let Some(customer) = customer {
+        let city = format!("{} {}", customer.postal_code, customer.city);
+        for line in [
+            customer.company_name.as_str(),
+            customer.contact_name.as_str(),
+            customer.address.as_str(),
+            city.trim(),
+            customer.country.as_str(),
+        ] {
+            for part in line.lines().filter(|l| !l.trim().is_empty()) {
+                pdf.text(PDF_MARGIN, y, 11.0, Font::Regular, part);
+                y -= 14.0;
+            }
+        }
+    }
+    y
+}
+
+/// Item table of a document followed by the totals per tax rate and the
+/// notes the rates require. Returns the height below it.
+pub fn draw_items(pdf: &mut PdfDocument, items: &[LineItem], rates: &[TaxRate], currency: &str, mut y: f32) -> f32 {
+    let right = pdf::PAGE_WIDTH - PDF_MARGIN;
+    // Columns: position, description, quantity, unit price, tax, net total.
+    let header = |pdf: &mut PdfDocument, y: f32| {
+        pdf.text(PDF_MARGIN, y, 10.0, Font::Bold, "Pos.");
+        pdf.text(PDF_MARGIN + 35.0, y, 10.0, Font::Bold, "Description");
+        pdf.text_right(right - 215.0, y, 10.0, Font::Bold, "Qty");
+        pdf.text_right(right - 130.0, y, 10.0, Font::Bold, "Unit Price");
+        pdf.text_right(right - 85.0, y, 10.0, Font::Bold, "Tax");
+        pdf.text_right(right, y, 10.0, Font::Bold, "Net");
+        pdf.line(PDF_MARGIN, y - 5.0, right, y - 5.0, 0.5);
+    };
+    header(pdf, y);
+    y -= 20.0;
+
+    for (index, item) in items.iter().enumerate() {
+        let mut lines = pdf.wrap(&item.description, right - 215.0 - 40.0 - PDF_MARGIN - 35.0, 10.0);
+        if !item.discount_percent.is_zero() {
+            lines.push(format!(
+                "less {}% discount ({:.2})",
+                item.discount_percent.normalize(),
+                tax::line_discount(item)
+            ));
+        }
+        if y - 12.0 * (lines.len() as f32) < PDF_MARGIN + 60.0 {
+            pdf.new_page();
+            y = pdf::PAGE_HEIGHT - PDF_MARGIN;
+            header(pdf, y);
+            y -= 20.0;
+        }
+        pdf.text(PDF_MARGIN, y, 10.0, Font::Regular, &(index + 1).to_string());
+        pdf.text_right(right - 215.0, y, 10.0, Font::Regular, &item.quantity.to_string());
+        pdf.text_right(right - 130.0, y, 10.0, Font::Regular, &format!("{:.2}", item.unit_price));
+        pdf.text_right(right - 85.0, y, 10.0, Font::Regular, &format!("{}%", item.tax_rate.normalize()));
+        pdf.text_right(right, y, 10.0, Font::Regular, &format!("{:.2}", tax::line_net(item)));
+        for line in lines {
+            pdf.text(PDF_MARGIN + 35.0, y, 10.0, Font::Regular, &line);
+            y -= 12.0;
+        }
+        y -= 4.0;
+    }
+
+    let totals = tax::totals(items);
+    if y < PDF_MARGIN + 40.0 + 14.0 * totals.groups.len() as f32 {
+        pdf.new_page();
+        y = pdf::PAGE_HEIGHT - PDF_MARGIN;
+    }
+    pdf.line(right - 250.0, y + 4.0, right, y + 4.0, 0.5);
+    y -= 12.0;
+    pdf.text_right(right - 85.0, y, 10.0, Font::Regular, "Net");
+    pdf.text_right(right, y, 10.0, Font::Regular, &format!("{:.2}", totals.net));
+    for group in &totals.groups {
+        y -= 14.0;
+        let label = format!("{} on {:.2}", tax::group_label(group, rates), group.net);
+        pdf.text_right(right - 85.0, y, 10.0, Font::Regular, &label);
+        pdf.text_right(right, y, 10.0, Font::Regular, &format!("{:.2}", group.tax));
+    }
+    y -= 16.0;
+    pdf.text_right(right - 85.0, y, 11.0, Font::Bold, format!("Total {}", currency).trim_end());
+    pdf.text_right(right, y, 11.0, Font::Bold, &format!("{:.2}", totals.gross));
+    y -= 30.0;
+
+    for note in tax::document_notes(&totals, rates) {
+        y = draw_paragraph(pdf, &note, y) - 6.0;
+    }
+    y
+}
+
+/// Text across the page width, continued on new pages as needed. Returns
+/// the height below it.
+pub fn draw_paragraph(pdf: &mut PdfDocument, text: &str, mut y: f32) -> f32 {
+    let right = pdf::PAGE_WIDTH - PDF_MARGIN;
+    for line in pdf.wrap(text, right - PDF_MARGIN, 10.0) {
+        if y < PDF_MARGIN {
+            pdf.new_page();
+            y = pdf::PAGE_HEIGHT - PDF_MARGIN;
+        }
+        pdf.text(PDF_MARGIN, y, 10.0, Font::Regular, &line);
+        y -= 12.0;
+    }
+    y
+}
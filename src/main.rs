@@ -1,8 +1,37 @@
 use eframe::egui;
 use std::path::PathBuf;
 mod app;
+mod audit;
+mod auth;
+mod banking;
 pub mod config;
+mod currency;
+mod dashboard;
+mod custom_fields;
+mod customer_detail;
+mod datev;
 mod db;
+mod deals;
+mod dunning;
+mod duplicates;
+mod einvoice;
+mod export;
+mod invoices;
+mod merge;
+mod numbering;
+mod pdf;
+mod pricing;
+mod quotes;
+mod recurring;
+mod relations;
+mod reports;
+mod sepa;
+mod settings;
+mod stock;
+mod tags;
+mod tasks;
+mod tax;
+mod timeline;
 mod ui;
 
 fn load_initial_config() {
@@ -29,6 +58,11 @@ fn load_initial_config() {
 async fn main() -> Result<(), eframe::Error> {
     load_initial_config();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "generate-recurring") {
+        std::process::exit(recurring::run_cli(&args[1..]).await);
+    }
+
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(800.0, 600.0)),
         ..Default::default()
@@ -0,0 +1,445 @@
+// merge.rs
+use crate::db::{Customer, Deal, Invoice, Quote, RecurringInvoice, Task};
+use chrono::NaiveDate;
+use eframe::egui;
+
+/// A record whose fields can be compared and picked one by one in a
+/// `MergeDialog`.
+pub trait Mergeable: Clone {
+    /// Field names and their values as displayed and edited in the dialog.
+    fn merge_fields(&self) -> Vec<(&'static str, String)>;
+    /// Sets the named field from its displayed value. Unparsable values are
+    /// ignored and leave the field unchanged.
+    fn set_merge_field(&mut self, name: &str, value: &str);
+}
+
+pub struct MergeField {
+    pub name: &'static str,
+    pub mine: String,
+    pub theirs: String,
+    pub take_mine: bool,
+}
+
+pub enum MergeAction {
+    /// Save the merged record on top of the current database version.
+    SaveMerged,
+    /// Throw away the local edits and use the database version.
+    DiscardMine,
+    Cancel,
+}
+
+/// Texts of a `MergeDialog`, so the same dialog serves for edit conflicts
+/// and for merging duplicate records.
+pub struct MergeLabels {
+    pub intro: &'static str,
+    pub mine: &'static str,
+    pub theirs: &'static str,
+    pub save: &'static str,
+    /// Label of the `DiscardMine` button; `None` hides it.
+    pub discard: Option<&'static str>,
+}
+
+pub const CONFLICT_LABELS: MergeLabels = MergeLabels {
+    intro: "This record was changed by someone else while you were editing it.",
+    mine: "Your changes",
+    theirs: "Current database",
+    save: "Save merged",
+    discard: Some("Discard my changes"),
+};
+
+/// Side-by-side comparison of a locally edited record and the version that
+/// is currently stored, letting the user pick a side per field.
+pub struct MergeDialog<T: Mergeable> {
+    pub theirs: T,
+    pub fields: Vec<MergeField>,
+    pub labels: MergeLabels,
+}
+
+impl<T: Mergeable> MergeDialog<T> {
+    /// Compares `mine` and `theirs`, both derived from `base`. Fields are
+    /// preselected from `mine` where the user changed them and from
+    /// `theirs` otherwise, so changes made by others are kept by default.
+    pub fn new(base: &T, mine: T, theirs: T) -> Self {
+        let fields = mine
+            .merge_fields()
+            .into_iter()
+            .zip(theirs.merge_fields())
+            .zip(base.merge_fields())
+            .map(|(((name, mine), (_, theirs)), (_, base))| MergeField {
+                name,
+                take_mine: mine != base,
+                mine,
+                theirs,
+            })
+            .collect();
+        MergeDialog {
+            theirs,
+            fields,
+            labels: CONFLICT_LABELS,
+        }
+    }
+
+    pub fn with_labels(mut self, labels: MergeLabels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// The stored record with every field the user kept from their side
+    /// applied on top, so it carries the current version number.
+    pub fn merged(&self) -> T {
+        let mut merged = self.theirs.clone();
+        for field in self.fields.iter().filter(|f| f.take_mine) {
+            merged.set_merge_field(field.name, &field.mine);
+        }
+        merged
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, title: &str) -> Option<MergeAction> {
+        let mut action = None;
+        egui::Window::new(title)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(self.labels.intro);
+                ui.label("Choose which value to keep for each field.");
+                ui.add_space(10.0);
+
+                egui::Grid::new(("merge_grid", title))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Field");
+                        ui.strong(self.labels.mine);
+                        ui.strong(self.labels.theirs);
+                        ui.end_row();
+
+                        for field in &mut self.fields {
+                            if field.mine == field.theirs {
+                                ui.label(field.name);
+                                ui.label(&field.mine);
+                                ui.label(&field.theirs);
+                            } else {
+                                ui.colored_label(egui::Color32::LIGHT_RED, field.name);
+                                ui.radio_value(&mut field.take_mine, true, &field.mine);
+                                ui.radio_value(&mut field.take_mine, false, &field.theirs);
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button(self.labels.save).clicked() {
+                        action = Some(MergeAction::SaveMerged);
+                    }
+                    if let Some(discard) = self.labels.discard {
+                        if ui.button(discard).clicked() {
+                            action = Some(MergeAction::DiscardMine);
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        action = Some(MergeAction::Cancel);
+                    }
+                });
+            });
+        action
+    }
+}
+
+impl Mergeable for Customer {
+    fn merge_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Company Name", self.company_name.clone()),
+            ("Contact Name", self.contact_name.clone()),
+            ("Contact Position", self.contact_position.clone()),
+            ("Address", self.address.clone()),
+            ("City", self.city.clone()),
+            ("Postal Code", self.postal_code.clone()),
+            ("Country", self.country.clone()),
+            ("Phone", self.phone.clone()),
+            ("Email", self.email.clone()),
+            ("Website", self.website.clone()),
+            ("Currency", self.currency.clone()),
+            ("VAT ID", self.vat_id.clone()),
+            ("Buyer Reference", self.buyer_reference.clone()),
+            ("IBAN", self.iban.clone()),
+            ("BIC", self.bic.clone()),
+            ("Mandate Reference", self.mandate_reference.clone()),
+            ("Mandate Date", self.mandate_date.map(|d| d.to_string()).unwrap_or_default()),
+        ]
+    }
+
+    fn set_merge_field(&mut self, name: &str, value: &str) {
+        let field = match name {
+            "Company Name" => &mut self.company_name,
+            "Contact Name" => &mut self.contact_name,
+            "Contact Position" => &mut self.contact_position,
+            "Address" => &mut self.address,
+            "City" => &mut self.city,
+            "Postal Code" => &mut self.postal_code,
+            "Country" => &mut self.country,
+            "Phone" => &mut self.phone,
+            "Email" => &mut self.email,
+            "Website" => &mut self.website,
+            "Currency" => &mut self.currency,
+            "VAT ID" => &mut self.vat_id,
+            "Buyer Reference" => &mut self.buyer_reference,
+            "IBAN" => &mut self.iban,
+            "BIC" => &mut self.bic,
+            "Mandate Reference" => &mut self.mandate_reference,
+            "Mandate Date" => {
+                if value.is_empty() {
+                    self.mandate_date = None;
+                } else if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.mandate_date = Some(date);
+                }
+                return;
+            }
+            _ => return,
+        };
+        *field = value.to_string();
+    }
+}
+
+impl Mergeable for Invoice {
+    fn merge_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "Customer",
+                self.customer_id.map(|id| id.to_string()).unwrap_or_default(),
+            ),
+            ("Invoice Number", self.invoice_number.clone()),
+            ("Invoice Date", self.invoice_date.to_string()),
+            ("Due Date", self.due_date.to_string()),
+            ("Total Amount", self.total_amount.to_string()),
+            ("Currency", self.currency.clone()),
+            ("Buyer Reference", self.buyer_reference.clone()),
+            ("Status", self.status.clone()),
+            (
+                "Payment Method",
+                self.payment_method.clone().unwrap_or_default(),
+            ),
+            ("Notes", self.notes.clone().unwrap_or_default()),
+            // Items are compared and taken as a whole.
+            ("Items", serde_json::to_string(&self.items).unwrap_or_default()),
+        ]
+    }
+
+    fn set_merge_field(&mut self, name: &str, value: &str) {
+        let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        match name {
+            "Customer" => self.customer_id = value.parse().ok(),
+            "Invoice Number" => self.invoice_number = value.to_string(),
+            "Invoice Date" => {
+                if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.invoice_date = date;
+                }
+            }
+            "Due Date" => {
+                if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.due_date = date;
+                }
+            }
+            "Total Amount" => {
+                if let Ok(amount) = value.parse() {
+                    self.total_amount = amount;
+                }
+            }
+            "Currency" => self.currency = value.to_string(),
+            "Buyer Reference" => self.buyer_reference = value.to_string(),
+            "Status" => self.status = value.to_string(),
+            "Payment Method" => self.payment_method = optional(value),
+            "Notes" => self.notes = optional(value),
+            "Items" => {
+                if let Ok(items) = serde_json::from_str(value) {
+                    self.items = items;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mergeable for Deal {
+    fn merge_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Customer", self.customer_id.to_string()),
+            ("Title", self.title.clone()),
+            ("Value", self.value.to_string()),
+            ("Expected Close", self.expected_close_date.to_string()),
+            ("Probability", self.probability.to_string()),
+            ("Stage", self.stage_id.to_string()),
+            ("Notes", self.notes.clone()),
+        ]
+    }
+
+    fn set_merge_field(&mut self, name: &str, value: &str) {
+        match name {
+            "Customer" => {
+                if let Ok(id) = value.parse() {
+                    self.customer_id = id;
+                }
+            }
+            "Title" => self.title = value.to_string(),
+            "Value" => {
+                if let Ok(amount) = value.parse() {
+                    self.value = amount;
+                }
+            }
+            "Expected Close" => {
+                if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.expected_close_date = date;
+                }
+            }
+            "Probability" => {
+                if let Ok(probability) = value.parse() {
+                    self.probability = probability;
+                }
+            }
+            "Stage" => {
+                if let Ok(id) = value.parse() {
+                    self.stage_id = id;
+                }
+            }
+            "Notes" => self.notes = value.to_string(),
+            _ => {}
+        }
+    }
+}
+
+impl Mergeable for Quote {
+    fn merge_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "Customer",
+                self.customer_id.map(|id| id.to_string()).unwrap_or_default(),
+            ),
+            ("Quote Number", self.quote_number.clone()),
+            ("Quote Date", self.quote_date.to_string()),
+            ("Valid Until", self.valid_until.to_string()),
+            ("Status", self.status.clone()),
+            ("Notes", self.notes.clone().unwrap_or_default()),
+            // Items are compared and taken as a whole.
+            ("Items", serde_json::to_string(&self.items).unwrap_or_default()),
+        ]
+    }
+
+    fn set_merge_field(&mut self, name: &str, value: &str) {
+        match name {
+            "Customer" => self.customer_id = value.parse().ok(),
+            "Quote Number" => self.quote_number = value.to_string(),
+            "Quote Date" => {
+                if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.quote_date = date;
+                }
+            }
+            "Valid Until" => {
+                if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.valid_until = date;
+                }
+            }
+            "Status" => self.status = value.to_string(),
+            "Notes" => self.notes = (!value.is_empty()).then(|| value.to_string()),
+            "Items" => {
+                if let Ok(items) = serde_json::from_str(value) {
+                    self.items = items;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mergeable for RecurringInvoice {
+    fn merge_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Customer", self.customer_id.to_string()),
+            ("Description", self.description.clone()),
+            ("Interval (months)", self.interval_months.to_string()),
+            ("Start Date", self.start_date.to_string()),
+            ("End Date", self.end_date.map(|d| d.to_string()).unwrap_or_default()),
+            ("Payment Days", self.payment_days.to_string()),
+            ("Active", self.active.to_string()),
+            // Items are compared and taken as a whole.
+            ("Items", serde_json::to_string(&self.items).unwrap_or_default()),
+        ]
+    }
+
+    fn set_merge_field(&mut self, name: &str, value: &str) {
+        match name {
+            "Customer" => {
+                if let Ok(id) = value.parse() {
+                    self.customer_id = id;
+                }
+            }
+            "Description" => self.description = value.to_string(),
+            "Interval (months)" => {
+                if let Ok(months) = value.parse() {
+                    self.interval_months = months;
+                }
+            }
+            "Start Date" => {
+                if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.start_date = date;
+                }
+            }
+            "End Date" => {
+                if value.is_empty() {
+                    self.end_date = None;
+                } else if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.end_date = Some(date);
+                }
+            }
+            "Payment Days" => {
+                if let Ok(days) = value.parse() {
+                    self.payment_days = days;
+                }
+            }
+            "Active" => {
+                if let Ok(active) = value.parse() {
+                    self.active = active;
+                }
+            }
+            "Items" => {
+                if let Ok(items) = serde_json::from_str(value) {
+                    self.items = items;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mergeable for Task {
+    fn merge_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Title", self.title.clone()),
+            ("Description", self.description.clone()),
+            (
+                "Customer",
+                self.customer_id.map(|id| id.to_string()).unwrap_or_default(),
+            ),
+            ("Assignee", self.assignee.clone().unwrap_or_default()),
+            ("Due Date", self.due_date.map(|d| d.to_string()).unwrap_or_default()),
+            ("Priority", self.priority.clone()),
+            ("Status", self.status.clone()),
+        ]
+    }
+
+    fn set_merge_field(&mut self, name: &str, value: &str) {
+        match name {
+            "Title" => self.title = value.to_string(),
+            "Description" => self.description = value.to_string(),
+            "Customer" => self.customer_id = value.parse().ok(),
+            "Assignee" => self.assignee = (!value.is_empty()).then(|| value.to_string()),
+            "Due Date" => {
+                if value.is_empty() {
+                    self.due_date = None;
+                } else if let Ok(date) = value.parse::<NaiveDate>() {
+                    self.due_date = Some(date);
+                }
+            }
+            "Priority" => self.priority = value.to_string(),
+            "Status" => self.status = value.to_string(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// numbering.rs
+use chrono::{Datelike, NaiveDate};
+
+/// Document types with a number sequence of their own.
+pub const SEQUENCE_TYPES: [(&str, &str); 3] = [
+    ("invoice", "Invoices"),
+    ("quote", "Quotes"),
+    ("credit_note", "Credit Notes"),
+];
+
+/// Expands a number pattern: `{YYYY}`, `{YY}` and `{MM}` become the year and
+/// month of `date`, and a run of zeros like `{0000}` the counter padded to
+/// that many digits.
+pub fn format_number(pattern: &str, date: NaiveDate, number: i32) -> String {
+    let mut formatted = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        formatted.push_str(&rest[..start]);
+        let token = &rest[start + 1..start + length];
+        match token {
+            "YYYY" => formatted.push_str(&format!("{:04}", date.year())),
+            "YY" => formatted.push_str(&format!("{:02}", date.year() % 100)),
+            "MM" => formatted.push_str(&format!("{:02}", date.month())),
+            zeros if !zeros.is_empty() && zeros.chars().all(|c| c == '0') => {
+                formatted.push_str(&format!("{:0width$}", number, width = zeros.len()))
+            }
+            _ => formatted.push_str(&rest[start..=start + length]),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    formatted.push_str(rest);
+    formatted
+}
+
+/// Counter value for a document dated in `year`. Sequences with a yearly
+/// reset start again at 1 in a new year and cannot number documents of a
+/// year they have left, since that number would continue the new year's
+/// series under the old year's prefix.
+pub fn first_free_number(
+    yearly_reset: bool,
+    current_year: Option<i32>,
+    next_number: i32,
+    year: i32,
+) -> Result<i32, String> {
+    if !yearly_reset {
+        return Ok(next_number);
+    }
+    match current_year {
+        Some(current) if year < current => Err(format!(
+            "Numbering has moved on to {}; documents dated {} need a number entered by hand",
+            current, year
+        )),
+        Some(current) if year == current => Ok(next_number),
+        _ => Ok(1),
+    }
+}
+
+/// Why `pattern` cannot number documents, if it cannot.
+pub fn pattern_error(pattern: &str) -> Option<String> {
+    let counters = pattern
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}'))
+        .filter(|(token, _)| !token.is_empty() && token.chars().all(|c| c == '0'))
+        .count();
+    if counters != 1 {
+        return Some("The pattern needs exactly one counter such as {0000}".to_string());
+    }
+    let example = format_number(pattern, NaiveDate::from_ymd_opt(2000, 1, 1)?, 1);
+    if example.len() > 50 {
+        return Some("Numbers of the pattern are longer than 50 characters".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn format_number_expands_tokens() {
+        assert_eq!(format_number("RE-{YYYY}-{0000}", date(2026, 3, 9), 42), "RE-2026-0042");
+        assert_eq!(format_number("{YY}{MM}/{000}", date(2009, 11, 30), 7), "0911/007");
+    }
+
+    #[test]
+    fn format_number_keeps_wider_counters_and_unknown_tokens() {
+        assert_eq!(format_number("{00}", date(2026, 1, 1), 12345), "12345");
+        assert_eq!(format_number("{X}-{0}-{", date(2026, 1, 1), 3), "{X}-3-{");
+    }
+
+    #[test]
+    fn first_free_number_without_reset_continues() {
+        assert_eq!(first_free_number(false, Some(2025), 17, 2026), Ok(17));
+        assert_eq!(first_free_number(false, Some(2026), 17, 2025), Ok(17));
+    }
+
+    #[test]
+    fn first_free_number_restarts_in_a_new_year() {
+        assert_eq!(first_free_number(true, Some(2025), 17, 2025), Ok(17));
+        assert_eq!(first_free_number(true, Some(2025), 17, 2026), Ok(1));
+        assert_eq!(first_free_number(true, None, 1, 2026), Ok(1));
+    }
+
+    #[test]
+    fn first_free_number_rejects_years_already_left() {
+        assert!(first_free_number(true, Some(2026), 3, 2025).is_err());
+    }
+
+    #[test]
+    fn pattern_error_needs_one_counter() {
+        assert_eq!(pattern_error("RE-{YYYY}-{0000}"), None);
+        assert!(pattern_error("RE-{YYYY}").is_some());
+        assert!(pattern_error("{000}-{000}").is_some());
+    }
+}
@@ -0,0 +1,560 @@
+// pdf.rs
+// Minimal PDF writer for business documents: A4 pages with text and
+// straight lines. Plain documents use the standard Helvetica fonts; PDF/A
+// documents embed a font and may carry attached files. No images.
+use ab_glyph::Font as _;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
+
+pub const PAGE_WIDTH: f32 = 595.0;
+pub const PAGE_HEIGHT: f32 = 842.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource(&self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+/// Helvetica glyph widths for ASCII 32..=126 in 1/1000 of the font size.
+/// Used for both fonts; bold text comes out slightly wider than measured.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // space - /
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, // 0 - 9
+    278, 278, 584, 584, 584, 556, 1015, // : - @
+    667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, // A - M
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, // N - Z
+    278, 278, 278, 469, 556, 333, // [ - `
+    556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, // a - m
+    556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, // n - z
+    334, 260, 334, 584, // { - ~
+];
+
+/// Width of `text` in points at `size`.
+pub fn text_width(text: &str, size: f32) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c as u32 {
+            code @ 32..=126 => HELVETICA_WIDTHS[(code - 32) as usize] as u32,
+            _ => 556,
+        })
+        .sum();
+    units as f32 * size / 1000.0
+}
+
+/// Splits `text` into lines no wider than `max_width`, breaking at spaces.
+pub fn wrap(text: &str, max_width: f32, size: f32) -> Vec<String> {
+    wrap_measured(text, max_width, |line| text_width(line, size))
+}
+
+fn wrap_measured(text: &str, max_width: f32, width: impl Fn(&str) -> f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if !line.is_empty() && width(&candidate) > max_width {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Characters of WinAnsiEncoding codes 0x80 to 0x9F; codes the encoding
+/// leaves undefined hold their own code point.
+const WIN_ANSI_80: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// WinAnsiEncoding (Windows-1252) code of `c`, or `?` for characters
+/// outside the encoding.
+pub fn win_ansi(c: char) -> u8 {
+    match c as u32 {
+        code @ (0..=0x7F | 0xA0..=0xFF) => code as u8,
+        _ => WIN_ANSI_80
+            .iter()
+            .position(|&w| w == c && (w as u32) > 0xFF)
+            .map_or(b'?', |index| 0x80 + index as u8),
+    }
+}
+
+/// Character of a WinAnsiEncoding code.
+fn win_ansi_char(code: u8) -> char {
+    match code {
+        0x80..=0x9F => WIN_ANSI_80[(code - 0x80) as usize],
+        _ => code as char,
+    }
+}
+
+/// Encodes text for a PDF string in WinAnsiEncoding, escaping delimiters.
+/// Characters outside the encoding become `?`.
+fn encode(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        let byte = win_ansi(c);
+        if matches!(byte, b'(' | b')' | b'\\') {
+            bytes.push(b'\\');
+        }
+        bytes.push(byte);
+    }
+    bytes
+}
+
+/// Writes `text` as a PDF string literal.
+fn string_literal(text: &str) -> Vec<u8> {
+    let mut literal = vec![b'('];
+    literal.extend(encode(text));
+    literal.push(b')');
+    literal
+}
+
+/// Font program embedded into PDF/A documents, which may not rely on the
+/// viewer's fonts: egui's default proportional font. It has no bold cut,
+/// so bold text is drawn with a stroked outline.
+struct EmbeddedFont {
+    data: Cow<'static, [u8]>,
+    /// Advance widths of WinAnsiEncoding codes 32..=255 in 1/1000 em.
+    widths: Vec<u16>,
+    ascent: i32,
+    descent: i32,
+}
+
+const EMBEDDED_FONT_NAME: &str = "Ubuntu-Light";
+
+static EMBEDDED_FONT: Lazy<Option<EmbeddedFont>> = Lazy::new(|| {
+    let data = eframe::egui::FontDefinitions::default()
+        .font_data
+        .remove(EMBEDDED_FONT_NAME)?
+        .font;
+    let font = ab_glyph::FontRef::try_from_slice(&data).ok()?;
+    let scale = 1000.0 / font.units_per_em()?;
+    let widths = (32..=255u8)
+        .map(|code| (font.h_advance_unscaled(font.glyph_id(win_ansi_char(code))) * scale).round() as u16)
+        .collect();
+    let ascent = (font.ascent_unscaled() * scale).round() as i32;
+    let descent = (font.descent_unscaled() * scale).round() as i32;
+    Some(EmbeddedFont {
+        data,
+        widths,
+        ascent,
+        descent,
+    })
+});
+
+/// A file attached to a PDF/A-3 document, such as the XML of an e-invoice.
+pub struct Attachment {
+    pub name: String,
+    /// MIME type, e.g. "text/xml".
+    pub mime_type: String,
+    pub description: String,
+    /// How the file relates to the document, e.g. "Alternative" for the
+    /// machine-readable form of the same invoice.
+    pub relationship: String,
+    pub data: Vec<u8>,
+}
+
+/// Appends a big-endian s15Fixed16Number.
+fn push_fixed(bytes: &mut Vec<u8>, value: f64) {
+    bytes.extend(((value * 65536.0).round() as i32).to_be_bytes());
+}
+
+/// ICC v2 display profile with the sRGB primaries (adapted to D50) and a
+/// 2.2 gamma, for the output intent PDF/A requires.
+fn srgb_profile() -> Vec<u8> {
+    let xyz = |values: [f64; 3]| {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for value in values {
+            push_fixed(&mut tag, value);
+        }
+        tag
+    };
+    let mut description = b"desc\0\0\0\0".to_vec();
+    let name = b"sRGB IEC61966-2.1";
+    description.extend((name.len() as u32 + 1).to_be_bytes());
+    description.extend(name);
+    description.push(0);
+    description.extend([0u8; 4 + 4 + 2 + 1 + 67]);
+    let mut copyright = b"text\0\0\0\0No copyright, use freely".to_vec();
+    copyright.push(0);
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend(1u32.to_be_bytes());
+    curve.extend(0x0233u16.to_be_bytes());
+
+    let tags: [(&[u8; 4], Vec<u8>); 9] = [
+        (b"desc", description),
+        (b"cprt", copyright),
+        (b"wtpt", xyz([0.9642, 1.0, 0.8249])),
+        (b"rXYZ", xyz([0.4361, 0.2225, 0.0139])),
+        (b"gXYZ", xyz([0.3851, 0.7169, 0.0971])),
+        (b"bXYZ", xyz([0.1431, 0.0606, 0.7141])),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend(*signature);
+        table.extend(((data_start + data.len()) as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+        data.extend(tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+
+    let mut header = vec![0u8; 128];
+    header[0..4].copy_from_slice(&((128 + table.len() + data.len()) as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&0x0210_0000u32.to_be_bytes());
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    for (index, part) in [2024u16, 1, 1, 0, 0, 0].iter().enumerate() {
+        header[24 + 2 * index..26 + 2 * index].copy_from_slice(&part.to_be_bytes());
+    }
+    header[36..40].copy_from_slice(b"acsp");
+    let mut illuminant = Vec::new();
+    for value in [0.9642, 1.0, 0.8249] {
+        push_fixed(&mut illuminant, value);
+    }
+    header[68..80].copy_from_slice(&illuminant);
+
+    header.extend(table);
+    header.extend(data);
+    header
+}
+
+/// Escapes text for XML content and attribute values.
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A document being drawn page by page. Coordinates are in points from the
+/// bottom left corner of the page.
+pub struct PdfDocument {
+    pages: Vec<Vec<u8>>,
+    /// Whether text uses the embedded font, as PDF/A requires.
+    embedded_font: bool,
+}
+
+impl Default for PdfDocument {
+    fn default() -> Self {
+        Self {
+            pages: vec![Vec::new()],
+            embedded_font: false,
+        }
+    }
+}
+
+impl PdfDocument {
+    /// A document to be written with `to_pdfa_bytes`. Falls back to the
+    /// standard fonts if the font cannot be loaded, which `to_pdfa_bytes`
+    /// then reports.
+    pub fn for_archive() -> Self {
+        Self {
+            pages: vec![Vec::new()],
+            embedded_font: EMBEDDED_FONT.is_some(),
+        }
+    }
+
+    /// Width of `text` in points at `size` in the document's font.
+    pub fn width(&self, text: &str, size: f32) -> f32 {
+        match EMBEDDED_FONT.as_ref().filter(|_| self.embedded_font) {
+            Some(font) => {
+                let units: u32 = text
+                    .chars()
+                    .map(|c| match win_ansi(c) {
+                        code @ 32.. => font.widths[(code - 32) as usize] as u32,
+                        _ => 0,
+                    })
+                    .sum();
+                units as f32 * size / 1000.0
+            }
+            None => text_width(text, size),
+        }
+    }
+
+    /// Like `wrap`, measured in the document's font.
+    pub fn wrap(&self, text: &str, max_width: f32, size: f32) -> Vec<String> {
+        wrap_measured(text, max_width, |line| self.width(line, size))
+    }
+
+    pub fn new_page(&mut self) {
+        self.pages.push(Vec::new());
+    }
+
+    fn content(&mut self) -> &mut Vec<u8> {
+        self.pages.last_mut().unwrap()
+    }
+
+    pub fn text(&mut self, x: f32, y: f32, size: f32, font: Font, text: &str) {
+        let embedded_font = self.embedded_font;
+        let content = self.content();
+        content.extend_from_slice(b"BT ");
+        if embedded_font {
+            // Fill and stroke the outline for bold, fill only otherwise.
+            let _ = write!(content, "{} Tr {:.2} w ", if font == Font::Bold { 2 } else { 0 }, size / 30.0);
+        }
+        let _ = write!(content, "/{} {} Tf {:.2} {:.2} Td (", font.resource(), size, x, y);
+        content.extend(encode(text));
+        content.extend_from_slice(b") Tj ET\n");
+    }
+
+    /// Text ending at `right`, for amounts in columns.
+    pub fn text_right(&mut self, right: f32, y: f32, size: f32, font: Font, text: &str) {
+        self.text(right - self.width(text, size), y, size, font, text);
+    }
+
+    pub fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32) {
+        let _ = writeln!(
+            self.content(),
+            "{} w {:.2} {:.2} m {:.2} {:.2} l S",
+            width, x1, y1, x2, y2
+        );
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.write(None)
+    }
+
+    /// Writes the document as PDF/A-3b: with the embedded font, an sRGB
+    /// output intent, XMP metadata including `xmp_extension` (further
+    /// `rdf:Description` elements) and `attachments` associated with it.
+    pub fn to_pdfa_bytes(
+        &self,
+        title: &str,
+        attachments: &[Attachment],
+        xmp_extension: &str,
+    ) -> Result<Vec<u8>, String> {
+        if !self.embedded_font {
+            return Err(format!("The font {} could not be loaded", EMBEDDED_FONT_NAME));
+        }
+        Ok(self.write(Some((title, attachments, xmp_extension))))
+    }
+
+    fn write(&self, archive: Option<(&str, &[Attachment], &str)>) -> Vec<u8> {
+        let version = if archive.is_some() { "1.7" } else { "1.4" };
+        let mut out: Vec<u8> = format!("%PDF-{}\n", version).into_bytes();
+        out.extend_from_slice(b"%\xE2\xE3\xCF\xD3\n");
+        let mut offsets = Vec::new();
+        let page_count = self.pages.len();
+        // Objects: 1 catalog, 2 page tree, 3 and 4 fonts (for PDF/A the font
+        // and its descriptor), then a page and its content stream for every
+        // page. PDF/A adds the font program, metadata, the color profile and
+        // two objects per attachment.
+        let page_ids: Vec<usize> = (0..page_count).map(|i| 5 + 2 * i).collect();
+        let font_file_id = 5 + 2 * page_count;
+        let metadata_id = font_file_id + 1;
+        let profile_id = font_file_id + 2;
+        let attachment_count = archive.map_or(0, |(_, attachments, _)| attachments.len());
+        let attachment_ids: Vec<usize> = (0..attachment_count).map(|i| font_file_id + 3 + 2 * i).collect();
+        let now = Utc::now();
+
+        let mut object = |out: &mut Vec<u8>, body: &[u8]| {
+            offsets.push(out.len());
+            let _ = writeln!(out, "{} 0 obj", offsets.len());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        };
+        let stream = |dictionary: &str, data: &[u8]| {
+            let mut stream = format!("<< {} /Length {} >>\nstream\n", dictionary, data.len()).into_bytes();
+            stream.extend_from_slice(data);
+            stream.extend_from_slice(b"\nendstream");
+            stream
+        };
+
+        match archive {
+            None => object(&mut out, b"<< /Type /Catalog /Pages 2 0 R >>"),
+            Some((_, attachments, _)) => {
+                let mut names = Vec::new();
+                for (attachment, id) in attachments.iter().zip(&attachment_ids) {
+                    names.extend(string_literal(&attachment.name));
+                    names.extend(format!(" {} 0 R ", id + 1).into_bytes());
+                }
+                let files: Vec<String> = attachment_ids.iter().map(|id| format!("{} 0 R", id + 1)).collect();
+                let mut catalog = format!(
+                    "<< /Type /Catalog /Pages 2 0 R /Metadata {} 0 R \
+                     /OutputIntents [<< /Type /OutputIntent /S /GTS_PDFA1 \
+                     /OutputConditionIdentifier (sRGB IEC61966-2.1) /Info (sRGB IEC61966-2.1) \
+                     /DestOutputProfile {} 0 R >>] /Names << /EmbeddedFiles << /Names [",
+                    metadata_id, profile_id
+                )
+                .into_bytes();
+                catalog.extend(names);
+                catalog.extend(format!("] >> >> /AF [{}] >>", files.join(" ")).into_bytes());
+                object(&mut out, &catalog);
+            }
+        }
+        let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+        object(
+            &mut out,
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), page_count).as_bytes(),
+        );
+        match EMBEDDED_FONT.as_ref().filter(|_| archive.is_some()) {
+            Some(font) => {
+                let widths: Vec<String> = font.widths.iter().map(|w| w.to_string()).collect();
+                object(
+                    &mut out,
+                    format!(
+                        "<< /Type /Font /Subtype /TrueType /BaseFont /{} /FirstChar 32 /LastChar 255 \
+                         /Widths [{}] /Encoding /WinAnsiEncoding /FontDescriptor 4 0 R >>",
+                        EMBEDDED_FONT_NAME,
+                        widths.join(" ")
+                    )
+                    .as_bytes(),
+                );
+                object(
+                    &mut out,
+                    format!(
+                        "<< /Type /FontDescriptor /FontName /{} /Flags 32 /FontBBox [-200 {} 1200 {}] \
+                         /ItalicAngle 0 /Ascent {} /Descent {} /CapHeight 700 /StemV 70 /FontFile2 {} 0 R >>",
+                        EMBEDDED_FONT_NAME, font.descent, font.ascent, font.ascent, font.descent, font_file_id
+                    )
+                    .as_bytes(),
+                );
+            }
+            None => {
+                for base_font in ["Helvetica", "Helvetica-Bold"] {
+                    object(
+                        &mut out,
+                        format!(
+                            "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                            base_font
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+        }
+        // With the embedded font both resources name the same font.
+        let bold_font = if archive.is_some() { 3 } else { 4 };
+        for (page, id) in self.pages.iter().zip(&page_ids) {
+            object(
+                &mut out,
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                     /Resources << /Font << /F1 3 0 R /F2 {} 0 R >> >> /Contents {} 0 R >>",
+                    PAGE_WIDTH,
+                    PAGE_HEIGHT,
+                    bold_font,
+                    id + 1
+                )
+                .as_bytes(),
+            );
+            object(&mut out, &stream("", page));
+        }
+
+        if let (Some((title, attachments, xmp_extension)), Some(font)) = (archive, EMBEDDED_FONT.as_ref()) {
+            object(
+                &mut out,
+                &stream(&format!("/Length1 {}", font.data.len()), &font.data),
+            );
+            let metadata = format!(
+                "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+                 <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+                 <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+                 <rdf:Description rdf:about=\"\" xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\
+                 <pdfaid:part>3</pdfaid:part><pdfaid:conformance>B</pdfaid:conformance></rdf:Description>\n\
+                 <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\
+                 <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title></rdf:Description>\n\
+                 <rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\
+                 <xmp:CreateDate>{}</xmp:CreateDate><xmp:ModifyDate>{}</xmp:ModifyDate></rdf:Description>\n\
+                 <rdf:Description rdf:about=\"\" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\
+                 <pdf:Producer>crm_app</pdf:Producer></rdf:Description>\n\
+                 {}\n\
+                 </rdf:RDF>\n\
+                 </x:xmpmeta>\n\
+                 <?xpacket end=\"w\"?>",
+                xml_escape(title),
+                now.format("%Y-%m-%dT%H:%M:%SZ"),
+                now.format("%Y-%m-%dT%H:%M:%SZ"),
+                xmp_extension
+            );
+            object(&mut out, &stream("/Type /Metadata /Subtype /XML", metadata.as_bytes()));
+            object(&mut out, &stream("/N 3", &srgb_profile()));
+            for (attachment, id) in attachments.iter().zip(&attachment_ids) {
+                object(
+                    &mut out,
+                    &stream(
+                        &format!(
+                            "/Type /EmbeddedFile /Subtype /{} /Params << /Size {} /ModDate (D:{}Z) >>",
+                            attachment.mime_type.replace('/', "#2F"),
+                            attachment.data.len(),
+                            now.format("%Y%m%d%H%M%S")
+                        ),
+                        &attachment.data,
+                    ),
+                );
+                let mut filespec = b"<< /Type /Filespec /F ".to_vec();
+                filespec.extend(string_literal(&attachment.name));
+                filespec.extend_from_slice(b" /UF ");
+                filespec.extend(string_literal(&attachment.name));
+                filespec.extend_from_slice(b" /Desc ");
+                filespec.extend(string_literal(&attachment.description));
+                filespec.extend(
+                    format!(
+                        " /AFRelationship /{} /EF << /F {} 0 R /UF {} 0 R >> >>",
+                        attachment.relationship, id, id
+                    )
+                    .into_bytes(),
+                );
+                object(&mut out, &filespec);
+            }
+        }
+
+        let xref_offset = out.len();
+        let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+        for offset in &offsets {
+            let _ = writeln!(out, "{:010} 00000 n ", offset);
+        }
+        let mut hasher = DefaultHasher::new();
+        out.hash(&mut hasher);
+        let id = format!("{:016X}{:016X}", hasher.finish(), out.len());
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {} /Root 1 0 R /ID [<{}> <{}>] >>\nstartxref\n{}\n%%EOF\n",
+            offsets.len() + 1,
+            id,
+            id,
+            xref_offset
+        );
+        out
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+// pricing.rs
+use crate::auth;
+use crate::db::{self, Customer, PriceList, PriceListEntry, Product};
+use crate::tags::TagStore;
+use crate::tax;
+use crate::ui;
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Price of a product under `entry`.
+fn entry_price(entry: &PriceListEntry, catalog_price: Decimal) -> Decimal {
+    let price = entry.unit_price.unwrap_or(catalog_price);
+    let hundred = Decimal::from(100);
+    tax::round_money(price * (hundred - entry.discount_percent) / hundred)
+}
+
+/// Price of `quantity` units of `product` for a customer with `tags`. The
+/// customer's own lists come before those of its tags, and those before
+/// lists for everyone; of the entries reached by the quantity on the most
+/// specific level, the lowest price wins. Without any, the catalog price.
+fn best_price(
+    lists: &[PriceList],
+    product: &Product,
+    customer_id: Option<i32>,
+    tags: Option<&BTreeSet<i32>>,
+    quantity: i32,
+) -> Decimal {
+    lists
+        .iter()
+        .filter(|list| list.active)
+        .filter_map(|list| {
+            let rank = match (list.customer_id, list.tag_id) {
+                (Some(id), _) => (Some(id) == customer_id).then_some(2),
+                (None, Some(tag_id)) => tags.is_some_and(|t| t.contains(&tag_id)).then_some(1),
+                (None, None) => Some(0),
+            }?;
+            Some(
+                list.entries
+                    .iter()
+                    .filter(|e| e.product_id == product.product_id && e.min_quantity <= quantity)
+                    .map(move |e| (rank, entry_price(e, product.unit_price))),
+            )
+        })
+        .flatten()
+        .min_by_key(|&(rank, price)| (Reverse(rank), price))
+        .map_or(product.unit_price, |(_, price)| price)
+}
+
+/// Price lists and the tags of customers they depend on, shared by the
+/// document editors.
+#[derive(Clone)]
+pub struct PriceListStore {
+    lists: Arc<Mutex<Vec<PriceList>>>,
+    links: Arc<Mutex<HashMap<i32, BTreeSet<i32>>>>,
+    /// Set whenever the price lists or customer tags must be reloaded.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl Default for PriceListStore {
+    fn default() -> Self {
+        Self {
+            lists: Arc::new(Mutex::new(Vec::new())),
+            links: Arc::new(Mutex::new(HashMap::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl PriceListStore {
+    pub fn refresh_if_stale(&self) {
+        if !self.stale.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let store = self.clone();
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_price_lists(&config).await {
+                Ok(lists) => *store.lists.lock().unwrap() = lists,
+                Err(e) => eprintln!("Error fetching price lists: {}", e),
+            }
+            match db::get_customer_tag_links(&config).await {
+                Ok(pairs) => {
+                    let mut links: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+                    for (customer_id, tag_id) in pairs {
+                        links.entry(customer_id).or_default().insert(tag_id);
+                    }
+                    *store.links.lock().unwrap() = links;
+                }
+                Err(e) => eprintln!("Error fetching customer tags: {}", e),
+            }
+        });
+    }
+
+    pub fn lists(&self) -> Vec<PriceList> {
+        self.lists.lock().unwrap().clone()
+    }
+
+    /// Price of `quantity` units of `product` for the customer.
+    pub fn unit_price(&self, product: &Product, customer_id: Option<i32>, quantity: i32) -> Decimal {
+        let links = self.links.lock().unwrap();
+        let tags = customer_id.and_then(|id| links.get(&id));
+        best_price(&self.lists.lock().unwrap(), product, customer_id, tags, quantity)
+    }
+
+    pub fn save(&self, list: PriceList, status: Arc<Mutex<String>>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::save_price_list(&config, &list).await {
+                Ok(saved) => *status.lock().unwrap() = format!("Price list {} saved", saved.name),
+                Err(e) => *status.lock().unwrap() = format!("Error saving price list: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+
+    pub fn delete(&self, price_list_id: i32, status: Arc<Mutex<String>>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::delete_price_list(&config, price_list_id).await {
+                Ok(()) => *status.lock().unwrap() = "Price list deleted".to_string(),
+                Err(e) => *status.lock().unwrap() = format!("Error deleting price list: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Whom a price list applies to.
+fn audience(list: &PriceList, customers: &[Customer], tags: &TagStore) -> String {
+    match (list.customer_id, list.tag_id) {
+        (Some(id), _) => customers
+            .iter()
+            .find(|c| c.customer_id == id)
+            .map_or_else(|| format!("Customer {}", id), |c| c.company_name.clone()),
+        (None, Some(id)) => tags
+            .tags()
+            .into_iter()
+            .find(|t| t.tag_id == id)
+            .map_or_else(|| format!("Tag {}", id), |t| format!("Tag {}", t.name)),
+        (None, None) => "All customers".to_string(),
+    }
+}
+
+/// Maintenance of the price lists.
+pub struct PriceListsView {
+    products: Arc<Mutex<Vec<Product>>>,
+    products_loaded: bool,
+    editor: Option<PriceList>,
+    status: Arc<Mutex<String>>,
+}
+
+impl Default for PriceListsView {
+    fn default() -> Self {
+        Self {
+            products: Arc::new(Mutex::new(Vec::new())),
+            products_loaded: false,
+            editor: None,
+            status: Arc::new(Mutex::new(String::new())),
+        }
+    }
+}
+
+impl PriceListsView {
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &Arc<Mutex<Vec<Customer>>>,
+        tags: &TagStore,
+        prices: &PriceListStore,
+    ) {
+        prices.refresh_if_stale();
+        tags.refresh_if_stale();
+        if !self.products_loaded {
+            self.products_loaded = true;
+            self.load_products();
+        }
+        let customers = customers.lock().unwrap().clone();
+        let lists = prices.lists();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Price Lists");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+            ui.label(
+                "Products added to invoices and quotes get the price of the customer's own list, \
+                 else of a list for one of its tags, else of a list for all customers.",
+            );
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("price_lists_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Name");
+                    ui.strong("Applies to");
+                    ui.strong("Entries");
+                    ui.strong("Active");
+                    ui.end_row();
+                    for list in &lists {
+                        let selected = self
+                            .editor
+                            .as_ref()
+                            .is_some_and(|e| e.price_list_id == list.price_list_id);
+                        if ui.selectable_label(selected, &list.name).clicked() {
+                            self.editor = Some(list.clone());
+                        }
+                        ui.label(audience(list, &customers, tags));
+                        ui.label(list.entries.len().to_string());
+                        ui.label(if list.active { "yes" } else { "no" });
+                        ui.end_row();
+                    }
+                });
+                if auth::can_edit() && ui.button("New Price List").clicked() {
+                    self.editor = Some(PriceList {
+                        active: true,
+                        ..PriceList::default()
+                    });
+                }
+
+                if self.editor.is_some() {
+                    ui.add_space(20.0);
+                    self.render_editor(ui, &customers, tags, prices);
+                }
+            });
+        });
+    }
+
+    fn render_editor(&mut self, ui: &mut egui::Ui, customers: &[Customer], tags: &TagStore, prices: &PriceListStore) {
+        let products = self.products.lock().unwrap().clone();
+        let Some(list) = self.editor.as_mut() else {
+            return;
+        };
+        let mut save = false;
+        let mut delete = false;
+
+        egui::Grid::new("price_list_editor_grid").show(ui, |ui| {
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut list.name);
+            ui.end_row();
+
+            ui.label("Applies to:");
+            egui::ComboBox::from_id_source("price_list_audience")
+                .selected_text(audience(list, customers, tags))
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(list.customer_id.is_none() && list.tag_id.is_none(), "All customers").clicked() {
+                        list.customer_id = None;
+                        list.tag_id = None;
+                    }
+                    for tag in tags.tags() {
+                        if ui
+                            .selectable_label(list.tag_id == Some(tag.tag_id), format!("Tag {}", tag.name))
+                            .clicked()
+                        {
+                            list.customer_id = None;
+                            list.tag_id = Some(tag.tag_id);
+                        }
+                    }
+                    for customer in customers {
+                        if ui
+                            .selectable_label(list.customer_id == Some(customer.customer_id), &customer.company_name)
+                            .clicked()
+                        {
+                            list.customer_id = Some(customer.customer_id);
+                            list.tag_id = None;
+                        }
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Active:");
+            ui.checkbox(&mut list.active, "");
+            ui.end_row();
+        });
+
+        ui.add_space(10.0);
+        ui.strong("Prices");
+        ui.label("Without a fixed price the discount applies to the catalog price.");
+        let mut remove = None;
+        egui::Grid::new("price_list_entries_grid").striped(true).show(ui, |ui| {
+            ui.strong("Product");
+            ui.strong("From Quantity");
+            ui.strong("Fixed Price");
+            ui.strong("Discount %");
+            ui.strong("Catalog");
+            ui.strong("Result");
+            ui.end_row();
+            for (index, entry) in list.entries.iter_mut().enumerate() {
+                let product = products.iter().find(|p| p.product_id == entry.product_id);
+                egui::ComboBox::from_id_source(("price_list_product", index))
+                    .selected_text(product.map_or("-".to_string(), |p| p.product_name.clone()))
+                    .show_ui(ui, |ui| {
+                        for product in &products {
+                            ui.selectable_value(&mut entry.product_id, product.product_id, &product.product_name);
+                        }
+                    });
+                ui.add(egui::DragValue::new(&mut entry.min_quantity).clamp_range(1..=1_000_000));
+                ui.horizontal(|ui| {
+                    let mut fixed = entry.unit_price.is_some();
+                    if ui.checkbox(&mut fixed, "").changed() {
+                        entry.unit_price = fixed.then(|| product.map_or(Decimal::ZERO, |p| p.unit_price));
+                    }
+                    if let Some(price) = entry.unit_price.as_mut() {
+                        ui::parsed_field(ui, ("price_list_price", index), price);
+                    }
+                });
+                ui::parsed_field(ui, ("price_list_discount", index), &mut entry.discount_percent);
+                entry.discount_percent = entry.discount_percent.clamp(Decimal::ZERO, Decimal::from(100));
+                match product {
+                    Some(product) => {
+                        ui.label(format!("{:.2}", product.unit_price));
+                        ui.label(format!("{:.2}", entry_price(entry, product.unit_price)));
+                    }
+                    None => {
+                        ui.label("");
+                        ui.label("");
+                    }
+                }
+                if ui.small_button("x").on_hover_text("Remove entry").clicked() {
+                    remove = Some(index);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(index) = remove {
+            list.entries.remove(index);
+        }
+
+        if auth::can_edit() {
+            ui.horizontal(|ui| {
+                if ui.button("Add Entry").clicked() {
+                    list.entries.push(PriceListEntry {
+                        entry_id: 0,
+                        product_id: products.first().map_or(0, |p| p.product_id),
+                        min_quantity: 1,
+                        unit_price: None,
+                        discount_percent: Decimal::ZERO,
+                    });
+                }
+                if ui.button("Save").clicked() {
+                    save = true;
+                }
+                if list.price_list_id != 0 && ui.button("Delete").clicked() {
+                    delete = true;
+                }
+            });
+        }
+
+        if save {
+            let mut list = list.clone();
+            list.name = list.name.trim().to_string();
+            let mut tiers = BTreeSet::new();
+            if list.name.is_empty() {
+                *self.status.lock().unwrap() = "Please enter a name".to_string();
+            } else if list.entries.iter().any(|e| e.product_id == 0) {
+                *self.status.lock().unwrap() = "Please choose a product for every entry".to_string();
+            } else if !list.entries.iter().all(|e| tiers.insert((e.product_id, e.min_quantity))) {
+                *self.status.lock().unwrap() =
+                    "A product can only have one price per quantity in a list".to_string();
+            } else {
+                prices.save(list, Arc::clone(&self.status));
+                self.editor = None;
+            }
+        } else if delete {
+            prices.delete(list.price_list_id, Arc::clone(&self.status));
+            self.editor = None;
+        }
+    }
+
+    fn load_products(&self) {
+        let products = Arc::clone(&self.products);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_products(&config).await {
+                    Ok(loaded) => *products.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching products: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tax::test_support::money;
+
+    const CUSTOMER: i32 = 7;
+    const TAG: i32 = 3;
+
+    fn product(unit_price: &str) -> Product {
+        Product {
+            product_id: 1,
+            product_name: "Widget".to_string(),
+            description: None,
+            unit_price: money(unit_price),
+            tax_rate_id: None,
+            stock_quantity: 0,
+            reorder_level: 0,
+        }
+    }
+
+    fn entry(min_quantity: i32, unit_price: Option<&str>, discount_percent: &str) -> PriceListEntry {
+        PriceListEntry {
+            entry_id: 0,
+            product_id: 1,
+            min_quantity,
+            unit_price: unit_price.map(money),
+            discount_percent: money(discount_percent),
+        }
+    }
+
+    fn list(customer_id: Option<i32>, tag_id: Option<i32>, entries: Vec<PriceListEntry>) -> PriceList {
+        PriceList {
+            customer_id,
+            tag_id,
+            active: true,
+            entries,
+            ..PriceList::default()
+        }
+    }
+
+    #[test]
+    fn entry_price_prefers_a_fixed_price_over_the_catalog_price() {
+        assert_eq!(entry_price(&entry(1, Some("8.00"), "0"), money("10.00")), money("8.00"));
+        assert_eq!(entry_price(&entry(1, None, "0"), money("10.00")), money("10.00"));
+    }
+
+    #[test]
+    fn entry_price_takes_the_discount_off_either_price() {
+        assert_eq!(entry_price(&entry(1, None, "15"), money("10.00")), money("8.50"));
+        assert_eq!(entry_price(&entry(1, Some("8.00"), "10"), money("10.00")), money("7.20"));
+        // 9.99 less 12.5% is 8.74125.
+        assert_eq!(entry_price(&entry(1, None, "12.5"), money("9.99")), money("8.74"));
+    }
+
+    #[test]
+    fn best_price_picks_the_tier_reached_by_the_quantity() {
+        let lists = [list(
+            None,
+            None,
+            vec![entry(1, Some("9.00"), "0"), entry(10, Some("8.00"), "0"), entry(50, Some("7.00"), "0")],
+        )];
+        let widget = product("10.00");
+        assert_eq!(best_price(&lists, &widget, None, None, 1), money("9.00"));
+        assert_eq!(best_price(&lists, &widget, None, None, 9), money("9.00"));
+        assert_eq!(best_price(&lists, &widget, None, None, 10), money("8.00"));
+        assert_eq!(best_price(&lists, &widget, None, None, 100), money("7.00"));
+    }
+
+    #[test]
+    fn best_price_falls_back_to_the_catalog_price() {
+        let mut inactive = list(None, None, vec![entry(1, Some("5.00"), "0")]);
+        inactive.active = false;
+        let lists = [inactive, list(None, None, vec![entry(10, Some("8.00"), "0")])];
+        assert_eq!(best_price(&lists, &product("10.00"), None, None, 5), money("10.00"));
+    }
+
+    #[test]
+    fn best_price_prefers_customer_over_tag_over_general_lists() {
+        // The more specific lists win even where a general one is cheaper.
+        let general = list(None, None, vec![entry(1, Some("6.00"), "0")]);
+        let tagged = list(None, Some(TAG), vec![entry(1, Some("8.00"), "0")]);
+        let own = list(Some(CUSTOMER), None, vec![entry(1, Some("9.00"), "0")]);
+        let widget = product("10.00");
+        let tags = BTreeSet::from([TAG]);
+
+        let lists = [general.clone(), tagged.clone(), own];
+        assert_eq!(best_price(&lists, &widget, Some(CUSTOMER), Some(&tags), 1), money("9.00"));
+        assert_eq!(best_price(&lists, &widget, Some(CUSTOMER + 1), Some(&tags), 1), money("8.00"));
+        assert_eq!(best_price(&lists, &widget, Some(CUSTOMER + 1), None, 1), money("6.00"));
+
+        let lists = [general, tagged];
+        assert_eq!(best_price(&lists, &widget, Some(CUSTOMER), Some(&tags), 1), money("8.00"));
+    }
+
+    #[test]
+    fn best_price_takes_the_lowest_price_on_the_same_level() {
+        let lists = [
+            list(None, None, vec![entry(1, None, "10")]),
+            list(None, None, vec![entry(1, Some("8.50"), "0")]),
+        ];
+        assert_eq!(best_price(&lists, &product("10.00"), None, None, 1), money("8.50"));
+    }
+}
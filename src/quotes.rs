@@ -0,0 +1,402 @@
+// quotes.rs
+use crate::audit::AuditPanel;
+use crate::auth;
+use crate::db::{self, Customer, Product, Quote, SaveResult, TaxRate};
+use crate::export;
+use crate::invoices::{customer_label, draw_address, draw_items, draw_paragraph, optional_text_edit, render_items, PDF_MARGIN};
+use crate::merge::{MergeAction, MergeDialog};
+use crate::pdf::{self, Font, PdfDocument};
+use crate::pricing::PriceListStore;
+use crate::tax::{self, TaxRateStore};
+use crate::ui;
+use eframe::egui;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const QUOTE_STATUSES: [&str; 4] = ["draft", "sent", "accepted", "rejected"];
+
+pub struct QuotesView {
+    quotes: Arc<Mutex<Vec<Quote>>>,
+    products: Arc<Mutex<Vec<Product>>>,
+    /// Set whenever the quote list must be (re)loaded from the database.
+    pub stale: Arc<AtomicBool>,
+    editor: Arc<Mutex<Option<Quote>>>,
+    /// The stored quote the editor started from, the base of a merge.
+    loaded: Arc<Mutex<Option<Quote>>>,
+    merge: Arc<Mutex<Option<MergeDialog<Quote>>>>,
+    status: Arc<Mutex<String>>,
+    audit: AuditPanel,
+    pdf_path: String,
+}
+
+impl Default for QuotesView {
+    fn default() -> Self {
+        Self {
+            quotes: Arc::new(Mutex::new(Vec::new())),
+            products: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            editor: Arc::new(Mutex::new(None)),
+            loaded: Arc::new(Mutex::new(None)),
+            merge: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(String::new())),
+            audit: AuditPanel::default(),
+            pdf_path: String::new(),
+        }
+    }
+}
+
+impl QuotesView {
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &Arc<Mutex<Vec<Customer>>>,
+        tax_rates: &TaxRateStore,
+        prices: &PriceListStore,
+    ) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+        tax_rates.refresh_if_stale();
+        prices.refresh_if_stale();
+
+        let customers = customers.lock().unwrap().clone();
+        let customer_names: HashMap<i32, String> = customers
+            .iter()
+            .map(|c| (c.customer_id, c.company_name.clone()))
+            .collect();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Quotes");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            if auth::can_edit() && ui.button("Create New Quote").clicked() {
+                self.open(Quote::default());
+            }
+            ui.add_space(10.0);
+
+            let quotes = self.quotes.lock().unwrap().clone();
+            let mut open = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("quote_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Number");
+                    ui.strong("Customer");
+                    ui.strong("Date");
+                    ui.strong("Valid Until");
+                    ui.strong("Total");
+                    ui.strong("Status");
+                    ui.end_row();
+
+                    for quote in &quotes {
+                        ui.label(&quote.quote_number);
+                        ui.label(customer_label(&customer_names, quote.customer_id));
+                        ui.label(quote.quote_date.to_string());
+                        ui.label(quote.valid_until.to_string());
+                        ui.label(quote.total_amount.to_string());
+                        if quote.invoice_id.is_some() {
+                            ui.label(format!("{} (invoiced)", quote.status));
+                        } else {
+                            ui.label(&quote.status);
+                        }
+                        if ui.button("Open").clicked() {
+                            open = Some(quote.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+            if let Some(quote) = open {
+                self.open(quote);
+            }
+        });
+
+        self.render_editor(ctx, &customers, &customer_names, tax_rates, prices);
+        self.render_merge_dialog(ctx);
+    }
+
+    fn open(&mut self, quote: Quote) {
+        let name = if quote.quote_number.is_empty() {
+            "quote.pdf".to_string()
+        } else {
+            format!("quote_{}.pdf", quote.quote_number.replace(['/', '\\'], "-"))
+        };
+        self.pdf_path = export::default_export_path(&name);
+        *self.loaded.lock().unwrap() = Some(quote.clone());
+        *self.editor.lock().unwrap() = Some(quote);
+    }
+
+    fn render_editor(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &[Customer],
+        customer_names: &HashMap<i32, String>,
+        tax_rates: &TaxRateStore,
+        prices: &PriceListStore,
+    ) {
+        let Some(mut quote) = self.editor.lock().unwrap().clone() else {
+            return;
+        };
+        let products = self.products.lock().unwrap().clone();
+
+        let mut open = true;
+        let mut save = false;
+        let mut convert = false;
+        let mut write_pdf = false;
+        egui::Window::new("Quote")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("quote_editor_grid").show(ui, |ui| {
+                    ui.label("Customer:");
+                    egui::ComboBox::from_id_source("quote_customer")
+                        .selected_text(customer_label(customer_names, quote.customer_id))
+                        .show_ui(ui, |ui| {
+                            let mut names: Vec<_> = customer_names.iter().collect();
+                            names.sort_by(|a, b| a.1.cmp(b.1));
+                            for (id, name) in names {
+                                ui.selectable_value(&mut quote.customer_id, Some(*id), name);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Quote Number:");
+                    ui.add(egui::TextEdit::singleline(&mut quote.quote_number).hint_text("assigned on save"));
+                    ui.end_row();
+
+                    ui.label("Quote Date:");
+                    ui::parsed_field(ui, "quote_date", &mut quote.quote_date);
+                    ui.end_row();
+
+                    ui.label("Valid Until:");
+                    ui::parsed_field(ui, "quote_valid_until", &mut quote.valid_until);
+                    ui.end_row();
+
+                    ui.label("Status:");
+                    egui::ComboBox::from_id_source("quote_status")
+                        .selected_text(quote.status.clone())
+                        .show_ui(ui, |ui| {
+                            for status in QUOTE_STATUSES {
+                                ui.selectable_value(&mut quote.status, status.to_string(), status);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Notes:");
+                    optional_text_edit(ui, &mut quote.notes, true);
+                    ui.end_row();
+                });
+
+                ui.add_space(10.0);
+                ui.strong("Items");
+                let customer_id = quote.customer_id;
+                render_items(ui, "quote_items", &mut quote.items, &products, tax_rates, prices, customer_id);
+                tax::render_totals(ui, &quote.items, &tax_rates.all_rates());
+
+                ui.add_space(10.0);
+                if let Some(invoice_id) = quote.invoice_id {
+                    ui.label(format!("Converted into invoice #{}", invoice_id));
+                }
+                ui.horizontal(|ui| {
+                    if auth::can_edit() && ui.button("Save").clicked() {
+                        save = true;
+                    }
+                    let convertible = quote.quote_id != 0
+                        && quote.status == "accepted"
+                        && quote.invoice_id.is_none();
+                    if auth::can_edit()
+                        && convertible
+                        && ui.button("Convert to Invoice").clicked()
+                    {
+                        convert = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("PDF:");
+                    ui.text_edit_singleline(&mut self.pdf_path);
+                    if ui.button("Create PDF").clicked() {
+                        write_pdf = true;
+                    }
+                });
+
+                if quote.quote_id != 0 {
+                    self.audit.show(ui, "quotes", quote.quote_id, quote.version);
+                }
+            });
+
+        if !open {
+            *self.editor.lock().unwrap() = None;
+            return;
+        }
+        *self.editor.lock().unwrap() = Some(quote.clone());
+
+        if write_pdf {
+            let customer = quote
+                .customer_id
+                .and_then(|id| customers.iter().find(|c| c.customer_id == id));
+            *self.status.lock().unwrap() =
+                match write_quote_pdf(&quote, customer, &tax_rates.all_rates(), Path::new(&self.pdf_path)) {
+                    Ok(()) => format!("Quote written to {}", self.pdf_path),
+                    Err(e) => format!("Error writing PDF: {}", e),
+                };
+        }
+        if convert {
+            self.convert(quote.quote_id);
+        } else if save {
+            if quote.quote_id != 0 && quote.quote_number.is_empty() {
+                *self.status.lock().unwrap() = "Please enter a quote number".to_string();
+            } else {
+                self.save_quote(quote);
+            }
+        }
+    }
+
+    fn render_merge_dialog(&mut self, ctx: &egui::Context) {
+        let mut merge = self.merge.lock().unwrap();
+        let Some(dialog) = merge.as_mut() else {
+            return;
+        };
+        let Some(action) = dialog.show(ctx, "Quote changed by someone else") else {
+            return;
+        };
+
+        let dialog = merge.take().unwrap();
+        drop(merge);
+        match action {
+            MergeAction::SaveMerged => {
+                let merged = dialog.merged();
+                *self.loaded.lock().unwrap() = Some(dialog.theirs);
+                *self.editor.lock().unwrap() = Some(merged.clone());
+                self.save_quote(merged);
+            }
+            MergeAction::DiscardMine => {
+                *self.loaded.lock().unwrap() = Some(dialog.theirs.clone());
+                *self.editor.lock().unwrap() = Some(dialog.theirs);
+            }
+            MergeAction::Cancel => {}
+        }
+    }
+
+    fn load(&self) {
+        let quotes = Arc::clone(&self.quotes);
+        let products = Arc::clone(&self.products);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_quotes(&config).await {
+                Ok(loaded) => *quotes.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching quotes: {}", e),
+            }
+            match db::get_products(&config).await {
+                Ok(loaded) => *products.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching products: {}", e),
+            }
+        });
+    }
+
+    fn save_quote(&self, quote: Quote) {
+        let Some(config) = db::get_config() else {
+            *self.status.lock().unwrap() = "No database configuration found!".to_string();
+            return;
+        };
+        let quotes = Arc::clone(&self.quotes);
+        let editor = Arc::clone(&self.editor);
+        let loaded = Arc::clone(&self.loaded);
+        let merge = Arc::clone(&self.merge);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let result = if quote.quote_id == 0 {
+                db::add_quote(&config, &quote).await.map(SaveResult::Saved)
+            } else {
+                db::update_quote(&config, &quote).await
+            };
+            match result {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = format!("Quote {} saved", saved.quote_number);
+                    // Keep the editor open so the PDF can be created next.
+                    *loaded.lock().unwrap() = Some(saved.clone());
+                    *editor.lock().unwrap() = Some(saved.clone());
+                    replace_quote(&quotes, saved);
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() = format!(
+                        "Quote {} was changed by someone else",
+                        current.quote_number
+                    );
+                    let base = loaded.lock().unwrap().clone().unwrap_or_else(|| current.clone());
+                    *merge.lock().unwrap() = Some(MergeDialog::new(&base, quote, current));
+                }
+                Err(e) => {
+                    eprintln!("Error saving quote: {}", e);
+                    *status.lock().unwrap() = format!("Error saving quote: {}", e);
+                }
+            }
+        });
+    }
+
+    fn convert(&self, quote_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let quotes = Arc::clone(&self.quotes);
+        let editor = Arc::clone(&self.editor);
+        let loaded = Arc::clone(&self.loaded);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::convert_quote_to_invoice(&config, quote_id).await {
+                Ok((invoice, quote)) => {
+                    *status.lock().unwrap() = format!(
+                        "Draft invoice {} created from quote {}",
+                        invoice.invoice_number, quote.quote_number
+                    );
+                    *loaded.lock().unwrap() = Some(quote.clone());
+                    *editor.lock().unwrap() = Some(quote.clone());
+                    replace_quote(&quotes, quote);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error converting quote: {}", e),
+            }
+        });
+    }
+}
+
+fn replace_quote(quotes: &Arc<Mutex<Vec<Quote>>>, quote: Quote) {
+    let mut quotes = quotes.lock().unwrap();
+    match quotes.iter_mut().find(|q| q.quote_id == quote.quote_id) {
+        Some(existing) => *existing = quote,
+        None => quotes.insert(0, quote),
+    }
+}
+
+pub fn write_quote_pdf(
+    quote: &Quote,
+    customer: Option<&Customer>,
+    rates: &[TaxRate],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pdf = PdfDocument::default();
+    let right = pdf::PAGE_WIDTH - PDF_MARGIN;
+    let mut y = draw_address(&mut pdf, customer, pdf::PAGE_HEIGHT - 150.0);
+
+    y = y.min(pdf::PAGE_HEIGHT - 260.0);
+    pdf.text(PDF_MARGIN, y, 18.0, Font::Bold, &format!("Quote {}", quote.quote_number));
+    pdf.text_right(right, y, 10.0, Font::Regular, &format!("Date: {}", quote.quote_date));
+    y -= 14.0;
+    pdf.text_right(right, y, 10.0, Font::Regular, &format!("Valid until: {}", quote.valid_until));
+    y -= 30.0;
+
+    y = draw_items(&mut pdf, &quote.items, rates, "", y);
+    if let Some(notes) = &quote.notes {
+        draw_paragraph(&mut pdf, notes, y);
+    }
+
+    pdf.save(path)
+}
@@ -0,0 +1,491 @@
+// recurring.rs
+use crate::audit::AuditPanel;
+use crate::auth;
+use crate::db::{self, Customer, Product, RecurringInvoice, SaveResult};
+use crate::invoices::{customer_label, render_items};
+use crate::merge::{MergeAction, MergeDialog};
+use crate::pricing::PriceListStore;
+use crate::tax::{self, TaxRateStore};
+use crate::ui;
+use chrono::{Months, NaiveDate, Utc};
+use eframe::egui;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const INTERVALS: [(i32, &str); 4] = [
+    (1, "monthly"),
+    (3, "quarterly"),
+    (6, "half-yearly"),
+    (12, "yearly"),
+];
+
+fn interval_label(months: i32) -> String {
+    INTERVALS
+        .iter()
+        .find(|(m, _)| *m == months)
+        .map_or_else(|| format!("every {} months", months), |(_, label)| label.to_string())
+}
+
+/// First and last day of every billing period of `recurring` that has
+/// started by `today` and before the end date. Periods are counted from the
+/// start date, so a template starting on the 31st bills on the last day of
+/// shorter months.
+pub fn due_periods(recurring: &RecurringInvoice, today: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+    let months = recurring.interval_months.max(1) as u32;
+    let period_start = |n: u32| recurring.start_date.checked_add_months(Months::new(n * months));
+    let mut periods = Vec::new();
+    let mut n = 0;
+    while let Some(start) = period_start(n) {
+        if start > today || recurring.end_date.is_some_and(|end| start >= end) {
+            break;
+        }
+        let Some(next) = period_start(n + 1) else {
+            break;
+        };
+        periods.push((start, next.pred_opt().unwrap_or(next)));
+        n += 1;
+    }
+    periods
+}
+
+/// Entry point of the `generate-recurring [--date YYYY-MM-DD]` command, for
+/// running the scheduler from cron. Returns the process exit code.
+pub async fn run_cli(args: &[String]) -> i32 {
+    let today = match args {
+        [] => Utc::now().date_naive(),
+        [flag, date] if flag == "--date" => match date.parse() {
+            Ok(date) => date,
+            Err(e) => {
+                eprintln!("Invalid date '{}': {}", date, e);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("Usage: generate-recurring [--date YYYY-MM-DD]");
+            return 2;
+        }
+    };
+    let Some(config) = db::get_config() else {
+        eprintln!("No database configuration found. Please run the Setup Wizard first.");
+        return 1;
+    };
+    match db::generate_recurring_invoices(&config, today).await {
+        Ok(invoices) => {
+            for invoice in &invoices {
+                println!("{} {} {}", invoice.invoice_number, invoice.invoice_date, invoice.total_amount);
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Error generating recurring invoices: {}", e);
+            1
+        }
+    }
+}
+
+/// Recurring invoice templates with their editor and the button running the
+/// scheduler.
+pub struct RecurringView {
+    templates: Arc<Mutex<Vec<RecurringInvoice>>>,
+    products: Arc<Mutex<Vec<Product>>>,
+    /// Set whenever the templates must be (re)loaded from the database.
+    pub stale: Arc<AtomicBool>,
+    editor: Arc<Mutex<Option<RecurringInvoice>>>,
+    /// The stored template the editor started from, the base of a merge.
+    loaded: Arc<Mutex<Option<RecurringInvoice>>>,
+    merge: Arc<Mutex<Option<MergeDialog<RecurringInvoice>>>>,
+    status: Arc<Mutex<String>>,
+    audit: AuditPanel,
+}
+
+impl Default for RecurringView {
+    fn default() -> Self {
+        Self {
+            templates: Arc::new(Mutex::new(Vec::new())),
+            products: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            editor: Arc::new(Mutex::new(None)),
+            loaded: Arc::new(Mutex::new(None)),
+            merge: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(String::new())),
+            audit: AuditPanel::default(),
+        }
+    }
+}
+
+impl RecurringView {
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &Arc<Mutex<Vec<Customer>>>,
+        tax_rates: &TaxRateStore,
+        prices: &PriceListStore,
+    ) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+        tax_rates.refresh_if_stale();
+        prices.refresh_if_stale();
+
+        let customer_names: HashMap<i32, String> = customers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| (c.customer_id, c.company_name.clone()))
+            .collect();
+        let today = Utc::now().date_naive();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Recurring Invoices");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            if auth::can_edit() {
+                ui.horizontal(|ui| {
+                    if ui.button("Create New Template").clicked() {
+                        *self.editor.lock().unwrap() = Some(RecurringInvoice::default());
+                    }
+                    if ui.button("Generate Due Invoices").clicked() {
+                        self.generate(today);
+                    }
+                });
+            }
+            ui.add_space(10.0);
+
+            let templates = self.templates.lock().unwrap().clone();
+            let mut open = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("recurring_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Customer");
+                    ui.strong("Description");
+                    ui.strong("Interval");
+                    ui.strong("Start");
+                    ui.strong("End");
+                    ui.strong("Amount");
+                    ui.strong("Active");
+                    ui.end_row();
+
+                    for template in &templates {
+                        ui.label(customer_label(&customer_names, Some(template.customer_id)));
+                        ui.label(&template.description);
+                        ui.label(interval_label(template.interval_months));
+                        ui.label(template.start_date.to_string());
+                        ui.label(template.end_date.map_or("-".to_string(), |d| d.to_string()));
+                        ui.label(format!("{:.2}", tax::totals(&template.items).gross));
+                        ui.label(if template.active { "yes" } else { "no" });
+                        if ui.button("Open").clicked() {
+                            open = Some(template.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+            if let Some(template) = open {
+                *self.loaded.lock().unwrap() = Some(template.clone());
+                *self.editor.lock().unwrap() = Some(template);
+            }
+        });
+
+        self.render_editor(ctx, &customer_names, tax_rates, prices);
+        self.render_merge_dialog(ctx);
+    }
+
+    fn render_editor(
+        &mut self,
+        ctx: &egui::Context,
+        customer_names: &HashMap<i32, String>,
+        tax_rates: &TaxRateStore,
+        prices: &PriceListStore,
+    ) {
+        let Some(mut template) = self.editor.lock().unwrap().clone() else {
+            return;
+        };
+        let products = self.products.lock().unwrap().clone();
+
+        let mut open = true;
+        let mut save = false;
+        egui::Window::new("Recurring Invoice")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("recurring_editor_grid").show(ui, |ui| {
+                    ui.label("Customer:");
+                    egui::ComboBox::from_id_source("recurring_customer")
+                        .selected_text(customer_label(customer_names, Some(template.customer_id)))
+                        .show_ui(ui, |ui| {
+                            let mut names: Vec<_> = customer_names.iter().collect();
+                            names.sort_by(|a, b| a.1.cmp(b.1));
+                            for (id, name) in names {
+                                ui.selectable_value(&mut template.customer_id, *id, name);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Description:");
+                    ui.text_edit_singleline(&mut template.description);
+                    ui.end_row();
+
+                    ui.label("Interval:");
+                    egui::ComboBox::from_id_source("recurring_interval")
+                        .selected_text(interval_label(template.interval_months))
+                        .show_ui(ui, |ui| {
+                            for (months, label) in INTERVALS {
+                                ui.selectable_value(&mut template.interval_months, months, label);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Start Date:");
+                    ui::parsed_field(ui, "recurring_start", &mut template.start_date);
+                    ui.end_row();
+
+                    ui.label("End Date:");
+                    ui.horizontal(|ui| {
+                        let mut ends = template.end_date.is_some();
+                        if ui.checkbox(&mut ends, "").changed() {
+                            let first_period_end = template
+                                .start_date
+                                .checked_add_months(Months::new(template.interval_months.max(1) as u32));
+                            template.end_date = first_period_end.filter(|_| ends);
+                        }
+                        if let Some(end_date) = template.end_date.as_mut() {
+                            ui::parsed_field(ui, "recurring_end", end_date);
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Payment Days:");
+                    ui.add(egui::DragValue::new(&mut template.payment_days).clamp_range(0..=365));
+                    ui.end_row();
+
+                    ui.label("Active:");
+                    ui.checkbox(&mut template.active, "");
+                    ui.end_row();
+                });
+
+                ui.add_space(10.0);
+                ui.strong("Items");
+                let customer_id = Some(template.customer_id);
+                render_items(ui, "recurring_items", &mut template.items, &products, tax_rates, prices, customer_id);
+                ui.label("Amount per period:");
+                tax::render_totals(ui, &template.items, &tax_rates.all_rates());
+
+                ui.add_space(10.0);
+                if auth::can_edit() && ui.button("Save").clicked() {
+                    save = true;
+                }
+
+                if template.recurring_id != 0 {
+                    self.audit.show(ui, "recurring_invoices", template.recurring_id, template.version);
+                }
+            });
+
+        if !open {
+            *self.editor.lock().unwrap() = None;
+            return;
+        }
+        *self.editor.lock().unwrap() = Some(template.clone());
+
+        if save {
+            if template.customer_id == 0 || template.description.trim().is_empty() {
+                *self.status.lock().unwrap() = "Please enter a customer and description".to_string();
+            } else if template.end_date.is_some_and(|end| end <= template.start_date) {
+                *self.status.lock().unwrap() = "The end date must lie after the start date".to_string();
+            } else {
+                self.save_template(template);
+            }
+        }
+    }
+
+    fn render_merge_dialog(&mut self, ctx: &egui::Context) {
+        let mut merge = self.merge.lock().unwrap();
+        let Some(dialog) = merge.as_mut() else {
+            return;
+        };
+        let Some(action) = dialog.show(ctx, "Recurring invoice changed by someone else") else {
+            return;
+        };
+
+        let dialog = merge.take().unwrap();
+        drop(merge);
+        match action {
+            MergeAction::SaveMerged => {
+                let merged = dialog.merged();
+                *self.loaded.lock().unwrap() = Some(dialog.theirs);
+                *self.editor.lock().unwrap() = Some(merged.clone());
+                self.save_template(merged);
+            }
+            MergeAction::DiscardMine => {
+                *self.loaded.lock().unwrap() = Some(dialog.theirs.clone());
+                *self.editor.lock().unwrap() = Some(dialog.theirs);
+            }
+            MergeAction::Cancel => {}
+        }
+    }
+
+    fn load(&self) {
+        let templates = Arc::clone(&self.templates);
+        let products = Arc::clone(&self.products);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_recurring_invoices(&config).await {
+                Ok(loaded) => *templates.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching recurring invoices: {}", e),
+            }
+            match db::get_products(&config).await {
+                Ok(loaded) => *products.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching products: {}", e),
+            }
+        });
+    }
+
+    fn save_template(&self, template: RecurringInvoice) {
+        let Some(config) = db::get_config() else {
+            *self.status.lock().unwrap() = "No database configuration found!".to_string();
+            return;
+        };
+        let editor = Arc::clone(&self.editor);
+        let loaded = Arc::clone(&self.loaded);
+        let merge = Arc::clone(&self.merge);
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            let result = if template.recurring_id == 0 {
+                db::add_recurring_invoice(&config, &template).await.map(SaveResult::Saved)
+            } else {
+                db::update_recurring_invoice(&config, &template).await
+            };
+            match result {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = format!("Recurring invoice {} saved", saved.description);
+                    *editor.lock().unwrap() = None;
+                    stale.store(true, Ordering::SeqCst);
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() = format!(
+                        "Recurring invoice {} was changed by someone else",
+                        current.description
+                    );
+                    let base = loaded.lock().unwrap().clone().unwrap_or_else(|| current.clone());
+                    *merge.lock().unwrap() = Some(MergeDialog::new(&base, template, current));
+                }
+                Err(e) => {
+                    eprintln!("Error saving recurring invoice: {}", e);
+                    *status.lock().unwrap() = format!("Error saving recurring invoice: {}", e);
+                }
+            }
+        });
+    }
+
+    fn generate(&self, today: NaiveDate) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            *status.lock().unwrap() = match db::generate_recurring_invoices(&config, today).await {
+                Ok(invoices) if invoices.is_empty() => "No recurring invoices are due".to_string(),
+                Ok(invoices) => format!(
+                    "{} draft invoices generated: {}",
+                    invoices.len(),
+                    invoices
+                        .iter()
+                        .map(|i| i.invoice_number.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Err(e) => format!("Error generating recurring invoices: {}", e),
+            };
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn template(start_date: NaiveDate, interval_months: i32, end_date: Option<NaiveDate>) -> RecurringInvoice {
+        RecurringInvoice {
+            start_date,
+            interval_months,
+            end_date,
+            ..RecurringInvoice::default()
+        }
+    }
+
+    #[test]
+    fn due_periods_lists_started_periods() {
+        let monthly = template(date(2026, 1, 1), 1, None);
+        assert_eq!(
+            due_periods(&monthly, date(2026, 3, 1)),
+            [
+                (date(2026, 1, 1), date(2026, 1, 31)),
+                (date(2026, 2, 1), date(2026, 2, 28)),
+                (date(2026, 3, 1), date(2026, 3, 31)),
+            ]
+        );
+        assert!(due_periods(&monthly, date(2025, 12, 31)).is_empty());
+    }
+
+    #[test]
+    fn due_periods_keep_month_ends() {
+        let monthly = template(date(2026, 1, 31), 1, None);
+        assert_eq!(
+            due_periods(&monthly, date(2026, 3, 31)),
+            [
+                (date(2026, 1, 31), date(2026, 2, 27)),
+                (date(2026, 2, 28), date(2026, 3, 30)),
+                (date(2026, 3, 31), date(2026, 4, 29)),
+            ]
+        );
+    }
+
+    #[test]
+    fn due_periods_handle_leap_years() {
+        let quarterly = template(date(2023, 11, 30), 3, None);
+        assert_eq!(
+            due_periods(&quarterly, date(2024, 3, 1)),
+            [
+                (date(2023, 11, 30), date(2024, 2, 28)),
+                (date(2024, 2, 29), date(2024, 5, 29)),
+            ]
+        );
+    }
+
+    #[test]
+    fn due_periods_stop_at_the_end_date() {
+        let monthly = template(date(2026, 1, 15), 1, Some(date(2026, 2, 15)));
+        assert_eq!(
+            due_periods(&monthly, date(2026, 12, 31)),
+            [(date(2026, 1, 15), date(2026, 2, 14))]
+        );
+        let ending_mid_period = template(date(2026, 1, 15), 1, Some(date(2026, 3, 1)));
+        assert_eq!(
+            due_periods(&ending_mid_period, date(2026, 12, 31)),
+            [
+                (date(2026, 1, 15), date(2026, 2, 14)),
+                (date(2026, 2, 15), date(2026, 3, 14)),
+            ]
+        );
+    }
+
+    #[test]
+    fn due_periods_treat_invalid_intervals_as_monthly() {
+        let broken = template(date(2026, 1, 1), 0, None);
+        assert_eq!(due_periods(&broken, date(2026, 2, 1)).len(), 2);
+    }
+}
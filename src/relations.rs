@@ -0,0 +1,423 @@
+// relations.rs
+use crate::db::{self, Customer, Relation};
+use eframe::egui;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Kind of link between two customers. Directed types read from the
+/// `from` customer to the `to` customer, e.g. "A is parent company of B".
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationType {
+    ParentOf,
+    Partner,
+    ResellerOf,
+    Competitor,
+}
+
+pub const RELATION_TYPES: [RelationType; 4] = [
+    RelationType::ParentOf,
+    RelationType::Partner,
+    RelationType::ResellerOf,
+    RelationType::Competitor,
+];
+
+impl RelationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RelationType::ParentOf => "parent_of",
+            RelationType::Partner => "partner",
+            RelationType::ResellerOf => "reseller_of",
+            RelationType::Competitor => "competitor",
+        }
+    }
+
+    /// Label seen from the `from` customer.
+    pub fn label(&self) -> &'static str {
+        match self {
+            RelationType::ParentOf => "Parent company of",
+            RelationType::Partner => "Partner of",
+            RelationType::ResellerOf => "Reseller of",
+            RelationType::Competitor => "Competitor of",
+        }
+    }
+
+    /// Label seen from the `to` customer.
+    pub fn inverse_label(&self) -> &'static str {
+        match self {
+            RelationType::ParentOf => "Subsidiary of",
+            RelationType::ResellerOf => "Sold through reseller",
+            RelationType::Partner | RelationType::Competitor => self.label(),
+        }
+    }
+
+    /// Partner and competitor links read the same in both directions.
+    pub fn is_symmetric(&self) -> bool {
+        matches!(self, RelationType::Partner | RelationType::Competitor)
+    }
+
+    pub fn color(&self) -> egui::Color32 {
+        match self {
+            RelationType::ParentOf => egui::Color32::from_rgb(70, 130, 180),
+            RelationType::Partner => egui::Color32::from_rgb(60, 170, 90),
+            RelationType::ResellerOf => egui::Color32::from_rgb(220, 160, 40),
+            RelationType::Competitor => egui::Color32::from_rgb(200, 70, 70),
+        }
+    }
+}
+
+impl FromStr for RelationType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RELATION_TYPES
+            .into_iter()
+            .find(|relation_type| relation_type.as_str() == s)
+            .ok_or_else(|| format!("Unknown relation type: {}", s))
+    }
+}
+
+/// All customer relations, shared by the contact window and the graph view.
+#[derive(Clone)]
+pub struct RelationStore {
+    relations: Arc<Mutex<Vec<Relation>>>,
+    /// Set whenever relations must be reloaded, e.g. by the change listener.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl Default for RelationStore {
+    fn default() -> Self {
+        Self {
+            relations: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl RelationStore {
+    pub fn refresh_if_stale(&self) {
+        if !self.stale.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let relations = Arc::clone(&self.relations);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_relations(&config).await {
+                Ok(loaded) => *relations.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching customer relations: {}", e),
+            }
+        });
+    }
+
+    pub fn relations(&self) -> Vec<Relation> {
+        self.relations.lock().unwrap().clone()
+    }
+
+    /// Relations in which the customer takes part, in either direction.
+    pub fn relations_of(&self, customer_id: i32) -> Vec<Relation> {
+        self.relations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.from_customer_id == customer_id || r.to_customer_id == customer_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn add(&self, from_customer_id: i32, to_customer_id: i32, relation_type: RelationType) {
+        self.run(move |config| async move {
+            db::add_relation(&config, from_customer_id, to_customer_id, relation_type)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    pub fn delete(&self, relation_id: i32) {
+        self.run(move |config| async move {
+            db::delete_relation(&config, relation_id)
+                .await
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    /// Runs a database change and reloads the relations afterwards.
+    fn run<F, Fut>(&self, change: F)
+    where
+        F: FnOnce(crate::config::DbConfig) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Result<(), String>> + Send,
+    {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            if let Err(e) = change(config).await {
+                eprintln!("Error changing customer relations: {}", e);
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+fn customer_name(customers: &[Customer], customer_id: i32) -> String {
+    customers
+        .iter()
+        .find(|c| c.customer_id == customer_id)
+        .map(|c| c.company_name.clone())
+        .unwrap_or_else(|| format!("Customer {}", customer_id))
+}
+
+/// Lists the relations of a customer; with `editable`, relations can be
+/// removed and added. Returns the customer whose name was clicked.
+pub fn render_customer_relations(
+    ui: &mut egui::Ui,
+    store: &RelationStore,
+    customers: &[Customer],
+    customer_id: i32,
+    editable: bool,
+) -> Option<i32> {
+    let mut open = None;
+    for relation in store.relations_of(customer_id) {
+        let outgoing = relation.from_customer_id == customer_id;
+        let (label, other) = if outgoing {
+            (relation.relation_type.label(), relation.to_customer_id)
+        } else {
+            (relation.relation_type.inverse_label(), relation.from_customer_id)
+        };
+        ui.horizontal(|ui| {
+            ui.colored_label(relation.relation_type.color(), label);
+            if ui.link(customer_name(customers, other)).clicked() {
+                open = Some(other);
+            }
+            if editable && ui.small_button("x").on_hover_text("Remove relation").clicked() {
+                store.delete(relation.relation_id);
+            }
+        });
+    }
+    if !editable {
+        return open;
+    }
+
+    let id = egui::Id::new(("new_relation", customer_id));
+    let (mut relation_type, mut other): (usize, Option<i32>) =
+        ui.data_mut(|d| d.get_temp(id)).unwrap_or((0, None));
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(("new_relation_type", customer_id))
+            .selected_text(RELATION_TYPES[relation_type].label())
+            .show_ui(ui, |ui| {
+                for (i, option) in RELATION_TYPES.iter().enumerate() {
+                    ui.selectable_value(&mut relation_type, i, option.label());
+                }
+            });
+        let selected = other
+            .map(|id| customer_name(customers, id))
+            .unwrap_or_else(|| "Select customer...".to_string());
+        egui::ComboBox::from_id_source(("new_relation_customer", customer_id))
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                for c in customers.iter().filter(|c| c.customer_id != customer_id) {
+                    ui.selectable_value(&mut other, Some(c.customer_id), &c.company_name);
+                }
+            });
+        if ui.button("Add Relation").clicked() {
+            if let Some(other) = other.take() {
+                store.add(customer_id, other, RELATION_TYPES[relation_type]);
+            }
+        }
+    });
+    ui.data_mut(|d| d.insert_temp(id, (relation_type, other)));
+    open
+}
+
+const NODE_RADIUS: f32 = 14.0;
+
+/// Interactive node graph of all related customers. Nodes can be dragged,
+/// the background pans the view and clicking a node opens that customer.
+pub struct RelationGraphView {
+    /// Node positions in graph coordinates, by customer id.
+    positions: HashMap<i32, egui::Vec2>,
+    offset: egui::Vec2,
+    show_unrelated: bool,
+}
+
+impl Default for RelationGraphView {
+    fn default() -> Self {
+        Self {
+            positions: HashMap::new(),
+            offset: egui::Vec2::ZERO,
+            show_unrelated: false,
+        }
+    }
+}
+
+impl RelationGraphView {
+    /// Returns the customer to open when a node was clicked.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        customers: &Arc<Mutex<Vec<Customer>>>,
+        store: &RelationStore,
+    ) -> Option<i32> {
+        store.refresh_if_stale();
+        let customers = customers.lock().unwrap().clone();
+        let relations = store.relations();
+
+        let node_ids: Vec<i32> = customers
+            .iter()
+            .map(|c| c.customer_id)
+            .filter(|id| {
+                self.show_unrelated
+                    || relations
+                        .iter()
+                        .any(|r| r.from_customer_id == *id || r.to_customer_id == *id)
+            })
+            .collect();
+        self.positions.retain(|id, _| node_ids.contains(id));
+        if node_ids.iter().any(|id| !self.positions.contains_key(id)) {
+            self.layout(&node_ids, &relations);
+        }
+
+        let mut open = None;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Customer Relationships");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.show_unrelated, "Show customers without relations");
+                if ui.button("Re-arrange").clicked() {
+                    self.positions.clear();
+                    self.offset = egui::Vec2::ZERO;
+                }
+                ui.separator();
+                for relation_type in RELATION_TYPES {
+                    ui.colored_label(relation_type.color(), relation_type.label());
+                }
+            });
+            if node_ids.is_empty() {
+                ui.label("No relations between customers yet. Add them in the Customer Contact window.");
+                return;
+            }
+            open = self.render_graph(ui, &customers, &relations);
+        });
+        open
+    }
+
+    fn render_graph(
+        &mut self,
+        ui: &mut egui::Ui,
+        customers: &[Customer],
+        relations: &[Relation],
+    ) -> Option<i32> {
+        let (background, painter) =
+            ui.allocate_painter(ui.available_size(), egui::Sense::click_and_drag());
+        if background.dragged() {
+            self.offset += background.drag_delta();
+        }
+        let center = background.rect.center() + self.offset;
+        let stroke_color = ui.visuals().text_color();
+
+        for relation in relations {
+            let (Some(from), Some(to)) = (
+                self.positions.get(&relation.from_customer_id),
+                self.positions.get(&relation.to_customer_id),
+            ) else {
+                continue;
+            };
+            let (from, to) = (center + *from, center + *to);
+            let stroke = egui::Stroke::new(2.0, relation.relation_type.color());
+            if relation.relation_type.is_symmetric() {
+                painter.line_segment([from, to], stroke);
+            } else {
+                // Stop at the node border so the arrow head stays visible.
+                let direction = (to - from).normalized();
+                let tip = to - direction * NODE_RADIUS;
+                painter.arrow(from, tip - from, stroke);
+            }
+        }
+
+        let mut open = None;
+        for customer in customers {
+            let Some(position) = self.positions.get_mut(&customer.customer_id) else {
+                continue;
+            };
+            let node_center = center + *position;
+            let rect = egui::Rect::from_center_size(node_center, egui::Vec2::splat(NODE_RADIUS * 2.0));
+            let response = ui
+                .interact(rect, ui.id().with(("relation_node", customer.customer_id)), egui::Sense::click_and_drag())
+                .on_hover_text(format!("{}\n{}", customer.company_name, customer.contact_name));
+            if response.dragged() {
+                *position += response.drag_delta();
+            }
+            if response.clicked() {
+                open = Some(customer.customer_id);
+            }
+            let fill = if response.hovered() {
+                ui.visuals().selection.bg_fill
+            } else {
+                ui.visuals().widgets.inactive.bg_fill
+            };
+            painter.circle(node_center, NODE_RADIUS, fill, egui::Stroke::new(1.5, stroke_color));
+            painter.text(
+                node_center + egui::vec2(0.0, NODE_RADIUS + 2.0),
+                egui::Align2::CENTER_TOP,
+                &customer.company_name,
+                egui::FontId::proportional(13.0),
+                stroke_color,
+            );
+        }
+        open
+    }
+
+    /// Places new nodes on a circle and then lets linked nodes attract and
+    /// all nodes repel each other for a fixed number of rounds.
+    fn layout(&mut self, node_ids: &[i32], relations: &[Relation]) {
+        let count = node_ids.len().max(1) as f32;
+        let radius = 60.0 * count.sqrt() + 80.0;
+        for (i, id) in node_ids.iter().enumerate() {
+            self.positions.entry(*id).or_insert_with(|| {
+                let angle = i as f32 / count * std::f32::consts::TAU;
+                egui::vec2(angle.cos(), angle.sin()) * radius
+            });
+        }
+
+        let ideal = 140.0;
+        for _ in 0..200 {
+            let mut forces: HashMap<i32, egui::Vec2> = HashMap::new();
+            for (i, a) in node_ids.iter().enumerate() {
+                for b in &node_ids[i + 1..] {
+                    let delta = self.positions[a] - self.positions[b];
+                    let distance = delta.length().max(1.0);
+                    let push = delta / distance * (ideal * ideal / distance);
+                    *forces.entry(*a).or_default() += push;
+                    *forces.entry(*b).or_default() -= push;
+                }
+            }
+            for relation in relations {
+                let (a, b) = (relation.from_customer_id, relation.to_customer_id);
+                let (Some(pa), Some(pb)) = (self.positions.get(&a), self.positions.get(&b)) else {
+                    continue;
+                };
+                let delta = *pa - *pb;
+                let distance = delta.length().max(1.0);
+                let pull = delta / distance * (distance * distance / ideal);
+                *forces.entry(a).or_default() -= pull;
+                *forces.entry(b).or_default() += pull;
+            }
+            for (id, force) in forces {
+                let Some(position) = self.positions.get_mut(&id) else {
+                    continue;
+                };
+                let length = force.length();
+                if length > 0.0 {
+                    *position += force / length * length.min(10.0);
+                }
+                // Weak pull to the middle keeps unlinked nodes in view.
+                *position -= *position * 0.01;
+            }
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// reports.rs
+use crate::currency::{self, CurrencyStore};
+use crate::datev;
+use crate::db::{self, ConvertedInvoice, Customer};
+use crate::export;
+use crate::invoices::customer_label;
+use crate::ui;
+use chrono::{Datelike, NaiveDate, Utc};
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Sums of converted invoices; amounts without a rate are counted in
+/// `missing` instead.
+#[derive(Default)]
+struct Summary {
+    invoices: usize,
+    net: Decimal,
+    gross: Decimal,
+    open: Decimal,
+    missing: usize,
+}
+
+impl Summary {
+    fn add(&mut self, row: &ConvertedInvoice) {
+        self.invoices += 1;
+        match (row.net_base, row.gross_base, row.open_base) {
+            (Some(net), Some(gross), Some(open)) => {
+                self.net += net;
+                self.gross += gross;
+                self.open += open.max(Decimal::ZERO);
+            }
+            _ => self.missing += 1,
+        }
+    }
+
+    fn show_row(&self, ui: &mut egui::Ui, label: &str, base: &str) {
+        ui.label(label);
+        ui.label(self.invoices.to_string());
+        ui.label(currency::format_amount(self.net, base));
+        ui.label(currency::format_amount(self.gross, base));
+        ui.label(currency::format_amount(self.open, base));
+        if self.missing > 0 {
+            ui.colored_label(egui::Color32::RED, format!("{} without rate", self.missing));
+        }
+        ui.end_row();
+    }
+}
+
+fn header(ui: &mut egui::Ui, first: &str) {
+    ui.strong(first);
+    ui.strong("Invoices");
+    ui.strong("Net");
+    ui.strong("Gross");
+    ui.strong("Open");
+    ui.end_row();
+}
+
+/// Revenue and receivables in the base currency.
+pub struct ReportsView {
+    rows: Arc<Mutex<Vec<ConvertedInvoice>>>,
+    /// Set whenever the report must be reloaded.
+    pub stale: Arc<AtomicBool>,
+    from: NaiveDate,
+    to: NaiveDate,
+    datev_path: String,
+    status: Arc<Mutex<String>>,
+}
+
+impl Default for ReportsView {
+    fn default() -> Self {
+        let today = Utc::now().date_naive();
+        Self {
+            rows: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            from: NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap_or(today),
+            to: today,
+            datev_path: export::default_export_path("EXTF_Buchungsstapel.csv"),
+            status: Arc::new(Mutex::new(String::new())),
+        }
+    }
+}
+
+impl ReportsView {
+    pub fn show(&mut self, ctx: &egui::Context, customers: &Arc<Mutex<Vec<Customer>>>, currencies: &CurrencyStore) {
+        currencies.refresh_if_stale();
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+
+        let customer_names: HashMap<i32, String> = customers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| (c.customer_id, c.company_name.clone()))
+            .collect();
+        let base = currencies.base();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Reports");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Invoices from");
+                ui::parsed_field(ui, "report_from", &mut self.from);
+                ui.label("to");
+                ui::parsed_field(ui, "report_to", &mut self.to);
+                if ui.button("Refresh").clicked() {
+                    self.load();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("DATEV booking batch:");
+                ui.text_edit_singleline(&mut self.datev_path);
+                if ui.button("Export").clicked() {
+                    self.export_datev(customer_names.clone(), base.clone());
+                }
+            });
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+            ui.label(format!(
+                "Amounts in {}, converted at the rate of the invoice date; open amounts at the rate of {}.",
+                base, self.to
+            ));
+            ui.add_space(10.0);
+
+            let rows = self.rows.lock().unwrap().clone();
+            let mut total = Summary::default();
+            let mut by_month: BTreeMap<(i32, u32), Summary> = BTreeMap::new();
+            let mut by_customer: HashMap<Option<i32>, Summary> = HashMap::new();
+            let mut by_currency: BTreeMap<String, (Summary, Decimal)> = BTreeMap::new();
+            for row in &rows {
+                let date = row.invoice.invoice_date;
+                total.add(row);
+                by_month.entry((date.year(), date.month())).or_default().add(row);
+                by_customer.entry(row.invoice.customer_id).or_default().add(row);
+                let (summary, original) = by_currency.entry(row.invoice.currency.clone()).or_default();
+                summary.add(row);
+                *original += row.invoice.total_amount;
+            }
+            let mut customers: Vec<(String, Summary)> = by_customer
+                .into_iter()
+                .map(|(id, summary)| (customer_label(&customer_names, id), summary))
+                .collect();
+            customers.sort_by_key(|c| std::cmp::Reverse(c.1.gross));
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.strong("By Month");
+                egui::Grid::new("report_months").striped(true).show(ui, |ui| {
+                    header(ui, "Month");
+                    for ((year, month), summary) in &by_month {
+                        summary.show_row(ui, &format!("{}-{:02}", year, month), &base);
+                    }
+                    total.show_row(ui, "Total", &base);
+                });
+
+                ui.add_space(10.0);
+                ui.strong("By Customer");
+                egui::Grid::new("report_customers").striped(true).show(ui, |ui| {
+                    header(ui, "Customer");
+                    for (name, summary) in &customers {
+                        summary.show_row(ui, name, &base);
+                    }
+                });
+
+                ui.add_space(10.0);
+                ui.strong("By Currency");
+                egui::Grid::new("report_currencies").striped(true).show(ui, |ui| {
+                    ui.strong("Currency");
+                    ui.strong("Invoices");
+                    ui.strong("Gross");
+                    ui.strong(format!("Gross in {}", base));
+                    ui.end_row();
+                    for (code, (summary, original)) in &by_currency {
+                        ui.label(code);
+                        ui.label(summary.invoices.to_string());
+                        ui.label(currency::format_amount(*original, code));
+                        ui.label(currency::format_amount(summary.gross, &base));
+                        if summary.missing > 0 {
+                            ui.colored_label(egui::Color32::RED, format!("{} without rate", summary.missing));
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    }
+
+    fn export_datev(&self, customer_names: HashMap<i32, String>, base: String) {
+        let (from, to) = (self.from, self.to);
+        let path = self.datev_path.clone();
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            *status.lock().unwrap() = match datev::export(from, to, customer_names, base, Path::new(&path)).await {
+                Ok(count) => format!("{} bookings written to {}", count, path),
+                Err(e) => format!("Error writing DATEV export: {}", e),
+            };
+        });
+    }
+
+    fn load(&self) {
+        let rows = Arc::clone(&self.rows);
+        let (from, to) = (self.from, self.to);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_converted_invoices(&config, from, to).await {
+                    Ok(loaded) => *rows.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching report: {}", e),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,432 @@
+// sepa.rs
+use crate::db::{CompanyProfile, Customer, DirectDebit, OpenInvoice, StatementEntry};
+use crate::pdf::xml_escape;
+use crate::ui;
+use chrono::{NaiveDate, Utc};
+use eframe::egui;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// IBAN without spaces, in capitals.
+pub fn normalize_iban(iban: &str) -> String {
+    iban.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase()
+}
+
+/// Whether `iban` has a valid structure and check digits (ISO 13616).
+pub fn valid_iban(iban: &str) -> bool {
+    let iban = normalize_iban(iban);
+    if !(15..=34).contains(&iban.len())
+        || !iban.chars().all(|c| c.is_ascii_alphanumeric())
+        || !iban[..2].chars().all(|c| c.is_ascii_alphabetic())
+        || !iban[2..4].chars().all(|c| c.is_ascii_digit())
+    {
+        return false;
+    }
+    let rearranged = iban[4..].chars().chain(iban[..4].chars());
+    let mut remainder: u32 = 0;
+    for c in rearranged {
+        let value = c.to_digit(36).unwrap_or(0);
+        remainder = if value < 10 {
+            (remainder * 10 + value) % 97
+        } else {
+            (remainder * 100 + value) % 97
+        };
+    }
+    remainder == 1
+}
+
+/// End-to-end id of the collection of an invoice, returned by the bank
+/// with the credit.
+pub fn end_to_end_id(message_id: &str, invoice_id: i32) -> String {
+    format!("{}-{}", message_id, invoice_id)
+}
+
+/// Text in the character set SEPA allows, at most `max` characters long.
+fn sepa_text(text: &str, max: usize) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            'ä' => out.push_str("ae"),
+            'ö' => out.push_str("oe"),
+            'ü' => out.push_str("ue"),
+            'Ä' => out.push_str("Ae"),
+            'Ö' => out.push_str("Oe"),
+            'Ü' => out.push_str("Ue"),
+            'ß' => out.push_str("ss"),
+            '&' => out.push('+'),
+            c if c.is_ascii_alphanumeric() || " /-?:().,'+".contains(c) => out.push(c),
+            _ => out.push(' '),
+        }
+    }
+    xml_escape(out.trim().chars().take(max).collect::<String>().trim())
+}
+
+fn agent(bic: &str) -> String {
+    if bic.trim().is_empty() {
+        "<FinInstnId><Othr><Id>NOTPROVIDED</Id></Othr></FinInstnId>".to_string()
+    } else {
+        format!("<FinInstnId><BICFI>{}</BICFI></FinInstnId>", xml_escape(&bic.trim().to_uppercase()))
+    }
+}
+
+/// Direct debit initiation (pain.008.001.08, SEPA core scheme) collecting
+/// `debits` on `collection_date`, or the reasons it cannot be created.
+pub fn pain_008(
+    company: &CompanyProfile,
+    debits: &[DirectDebit],
+    message_id: &str,
+    collection_date: NaiveDate,
+) -> Result<String, Vec<String>> {
+    let mut errors = Vec::new();
+    if company.company_name.trim().is_empty() {
+        errors.push("The company profile has no company name".to_string());
+    }
+    if company.creditor_id.trim().is_empty() {
+        errors.push("The company profile has no creditor id".to_string());
+    }
+    if !valid_iban(&company.iban) {
+        errors.push("The IBAN in the company profile is not valid".to_string());
+    }
+    if debits.is_empty() {
+        errors.push("No invoices selected".to_string());
+    }
+    if collection_date <= Utc::now().date_naive() {
+        errors.push("The collection date must be in the future".to_string());
+    }
+    for debit in debits.iter().filter(|d| !valid_iban(&d.iban)) {
+        errors.push(format!("The IBAN of {} is not valid", debit.debtor_name));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let control_sum = |debits: &[&DirectDebit]| debits.iter().map(|d| d.amount).sum::<Decimal>();
+    let all: Vec<&DirectDebit> = debits.iter().collect();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<Document xmlns=\"urn:iso:std:iso:20022:tech:xsd:pain.008.001.08\">\n<CstmrDrctDbtInitn>\n");
+    xml.push_str(&format!(
+        "<GrpHdr><MsgId>{}</MsgId><CreDtTm>{}</CreDtTm><NbOfTxs>{}</NbOfTxs><CtrlSum>{:.2}</CtrlSum>\
+         <InitgPty><Nm>{}</Nm></InitgPty></GrpHdr>\n",
+        xml_escape(message_id),
+        Utc::now().format("%Y-%m-%dT%H:%M:%S"),
+        all.len(),
+        control_sum(&all),
+        sepa_text(&company.company_name, 70)
+    ));
+
+    // The banks want first and recurring collections in separate blocks.
+    for sequence_type in ["FRST", "RCUR"] {
+        let block: Vec<&DirectDebit> = debits.iter().filter(|d| d.sequence_type == sequence_type).collect();
+        if block.is_empty() {
+            continue;
+        }
+        xml.push_str(&format!(
+            "<PmtInf><PmtInfId>{}-{}</PmtInfId><PmtMtd>DD</PmtMtd><NbOfTxs>{}</NbOfTxs><CtrlSum>{:.2}</CtrlSum>\
+             <PmtTpInf><SvcLvl><Cd>SEPA</Cd></SvcLvl><LclInstrm><Cd>CORE</Cd></LclInstrm><SeqTp>{}</SeqTp></PmtTpInf>\
+             <ReqdColltnDt>{}</ReqdColltnDt>\
+             <Cdtr><Nm>{}</Nm></Cdtr><CdtrAcct><Id><IBAN>{}</IBAN></Id></CdtrAcct><CdtrAgt>{}</CdtrAgt>\
+             <ChrgBr>SLEV</ChrgBr>\
+             <CdtrSchmeId><Id><PrvtId><Othr><Id>{}</Id><SchmeNm><Prtry>SEPA</Prtry></SchmeNm></Othr></PrvtId></Id></CdtrSchmeId>\n",
+            xml_escape(message_id),
+            sequence_type,
+            block.len(),
+            control_sum(&block),
+            sequence_type,
+            collection_date,
+            sepa_text(&company.company_name, 70),
+            normalize_iban(&company.iban),
+            agent(&company.bic),
+            xml_escape(company.creditor_id.trim())
+        ));
+        for debit in block {
+            xml.push_str(&format!(
+                "<DrctDbtTxInf><PmtId><EndToEndId>{}</EndToEndId></PmtId>\
+                 <InstdAmt Ccy=\"EUR\">{:.2}</InstdAmt>\
+                 <DrctDbtTx><MndtRltdInf><MndtId>{}</MndtId><DtOfSgntr>{}</DtOfSgntr></MndtRltdInf></DrctDbtTx>\
+                 <DbtrAgt>{}</DbtrAgt><Dbtr><Nm>{}</Nm></Dbtr><DbtrAcct><Id><IBAN>{}</IBAN></Id></DbtrAcct>\
+                 <RmtInf><Ustrd>{}</Ustrd></RmtInf></DrctDbtTxInf>\n",
+                xml_escape(&end_to_end_id(message_id, debit.invoice_id)),
+                debit.amount,
+                sepa_text(&debit.mandate_reference, 35),
+                debit.mandate_date,
+                agent(&debit.bic),
+                sepa_text(&debit.debtor_name, 70),
+                normalize_iban(&debit.iban),
+                sepa_text(&format!("Invoice {}", debit.invoice_number), 140)
+            ));
+        }
+        xml.push_str("</PmtInf>\n");
+    }
+    xml.push_str("</CstmrDrctDbtInitn>\n</Document>\n");
+    Ok(xml)
+}
+
+fn child<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+/// Text of the element at `path` below `node`; empty if there is none.
+fn text_at(node: roxmltree::Node, path: &[&str]) -> String {
+    let mut current = node;
+    for name in path {
+        match child(current, name) {
+            Some(next) => current = next,
+            None => return String::new(),
+        }
+    }
+    current.text().unwrap_or_default().trim().to_string()
+}
+
+fn amount_of(node: roxmltree::Node) -> Result<(Decimal, String), String> {
+    let text = node.text().unwrap_or_default().trim();
+    let amount = Decimal::from_str(text).map_err(|_| format!("Invalid amount in statement: {}", text))?;
+    Ok((amount, node.attribute("Ccy").unwrap_or_default().to_string()))
+}
+
+/// Booked credits of a bank statement (camt.053, any version). Entries
+/// holding several transactions, like the credit of a direct debit file,
+/// yield one entry per transaction.
+pub fn parse_camt053(contents: &str) -> Result<Vec<StatementEntry>, String> {
+    let document = roxmltree::Document::parse(contents).map_err(|e| format!("Invalid XML: {}", e))?;
+    if !document.descendants().any(|n| n.tag_name().name() == "BkToCstmrStmt") {
+        return Err("The file is not a camt.053 bank statement".to_string());
+    }
+
+    let mut entries = Vec::new();
+    for entry in document.descendants().filter(|n| n.tag_name().name() == "Ntry") {
+        let status = text_at(entry, &["Sts"]);
+        let status = if status.is_empty() { text_at(entry, &["Sts", "Cd"]) } else { status };
+        if text_at(entry, &["CdtDbtInd"]) != "CRDT"
+            || text_at(entry, &["RvslInd"]) == "true"
+            || (!status.is_empty() && status != "BOOK")
+        {
+            continue;
+        }
+        let booking_date = [text_at(entry, &["BookgDt", "Dt"]), text_at(entry, &["BookgDt", "DtTm"])]
+            .into_iter()
+            .find(|d| d.len() >= 10)
+            .and_then(|d| NaiveDate::parse_from_str(&d[..10], "%Y-%m-%d").ok())
+            .ok_or("Statement entry without a booking date")?;
+        let (entry_amount, entry_currency) =
+            amount_of(child(entry, "Amt").ok_or("Statement entry without an amount")?)?;
+        let entry_reference = [text_at(entry, &["AcctSvcrRef"]), text_at(entry, &["NtryRef"])]
+            .into_iter()
+            .find(|r| !r.is_empty())
+            .unwrap_or_default();
+
+        let transactions: Vec<roxmltree::Node> = entry
+            .children()
+            .filter(|n| n.tag_name().name() == "NtryDtls")
+            .flat_map(|d| d.children().filter(|n| n.tag_name().name() == "TxDtls"))
+            .collect();
+        let single = transactions.len() <= 1;
+        let details = if transactions.is_empty() { vec![entry] } else { transactions };
+        for (index, transaction) in details.into_iter().enumerate() {
+            let (amount, currency) = match (single, child(transaction, "Amt")) {
+                (false, Some(amount)) => amount_of(amount)?,
+                (false, None) => match transaction
+                    .descendants()
+                    .find(|n| n.tag_name().name() == "TxAmt")
+                    .and_then(|n| child(n, "Amt"))
+                {
+                    Some(amount) => amount_of(amount)?,
+                    None => return Err("Statement transaction without an amount".to_string()),
+                },
+                (true, _) => (entry_amount, entry_currency.clone()),
+            };
+            let end_to_end_id = Some(text_at(transaction, &["Refs", "EndToEndId"]))
+                .filter(|id| id != "NOTPROVIDED")
+                .unwrap_or_default();
+            let remittance = transaction
+                .descendants()
+                .filter(|n| n.tag_name().name() == "Ustrd")
+                .filter_map(|n| n.text())
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let debtor_name = transaction
+                .descendants()
+                .find(|n| n.tag_name().name() == "Dbtr")
+                .and_then(|d| d.descendants().find(|n| n.tag_name().name() == "Nm"))
+                .and_then(|n| n.text())
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+
+            let transaction_reference = text_at(transaction, &["Refs", "AcctSvcrRef"]);
+            let bank_reference = if !transaction_reference.is_empty() {
+                transaction_reference
+            } else if !entry_reference.is_empty() {
+                if single { entry_reference.clone() } else { format!("{}/{}", entry_reference, index + 1) }
+            } else {
+                format!("{}/{}/{}/{}", booking_date, amount, end_to_end_id, remittance)
+            };
+            entries.push(StatementEntry {
+                bank_reference: bank_reference.chars().take(100).collect(),
+                booking_date,
+                amount,
+                currency,
+                end_to_end_id,
+                remittance,
+                debtor_name,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether `text` mentions `number` as a word of its own, ignoring case.
+fn mentions(text: &str, number: &str) -> bool {
+    let text = text.to_uppercase();
+    let number = number.trim().to_uppercase();
+    if number.is_empty() {
+        return false;
+    }
+    text.match_indices(&number).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + number.len()..].chars().next();
+        !before.is_some_and(|c| c.is_alphanumeric()) && !after.is_some_and(|c| c.is_alphanumeric())
+    })
+}
+
+/// The one open invoice whose open amount equals the credit and whose
+/// number its remittance information mentions.
+pub fn match_invoice<'a>(entry: &StatementEntry, open: &'a [OpenInvoice]) -> Option<&'a OpenInvoice> {
+    let mut candidates = open.iter().filter(|invoice| {
+        invoice.currency == entry.currency
+            && invoice.open_amount == entry.amount
+            && mentions(&entry.remittance, &invoice.invoice_number)
+    });
+    let found = candidates.next()?;
+    candidates.next().is_none().then_some(found)
+}
+
+/// Bank account and direct debit mandate fields of the customer forms.
+pub fn render_bank_fields(ui: &mut egui::Ui, id_source: &str, customer: &mut Customer) {
+    ui.horizontal(|ui| {
+        ui.label("IBAN:");
+        ui.text_edit_singleline(&mut customer.iban);
+        if !customer.iban.trim().is_empty() && !valid_iban(&customer.iban) {
+            ui.colored_label(egui::Color32::RED, "invalid");
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("BIC:");
+        ui.text_edit_singleline(&mut customer.bic);
+    });
+    ui.horizontal(|ui| {
+        ui.label("Mandate Reference:");
+        ui.text_edit_singleline(&mut customer.mandate_reference);
+    });
+    ui.horizontal(|ui| {
+        ui.label("Mandate Signed:");
+        let mut signed = customer.mandate_date.is_some();
+        if ui.checkbox(&mut signed, "").changed() {
+            customer.mandate_date = signed.then(|| Utc::now().date_naive());
+        }
+        if let Some(date) = customer.mandate_date.as_mut() {
+            ui::parsed_field(ui, (id_source, "mandate_date"), date);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_iban_accepts_correct_check_digits() {
+        assert!(valid_iban("DE89370400440532013000"));
+        assert!(valid_iban("GB82WEST12345698765432"));
+        assert!(valid_iban("de89 3704 0044 0532 0130 00"));
+    }
+
+    #[test]
+    fn valid_iban_rejects_wrong_check_digits() {
+        assert!(!valid_iban("DE88370400440532013000"));
+        assert!(!valid_iban("DE89370400440532013001"));
+        assert!(!valid_iban("GB82WEST12345698765423"));
+    }
+
+    #[test]
+    fn valid_iban_rejects_malformed_input() {
+        assert!(!valid_iban(""));
+        assert!(!valid_iban("DE89"));
+        assert!(!valid_iban("1289370400440532013000"));
+        assert!(!valid_iban("DEXX370400440532013000"));
+        assert!(!valid_iban("DE89-3704-0044-0532-0130-00"));
+    }
+
+    const STATEMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+<BkToCstmrStmt><Stmt>
+  <Ntry>
+    <Amt Ccy="EUR">119.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+    <BookgDt><Dt>2026-03-02</Dt></BookgDt><AcctSvcrRef>REF-1</AcctSvcrRef>
+    <NtryDtls><TxDtls>
+      <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+      <RltdPties><Dbtr><Nm> Muster GmbH </Nm></Dbtr></RltdPties>
+      <RmtInf><Ustrd>Invoice RE-2026-0001</Ustrd></RmtInf>
+    </TxDtls></NtryDtls>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">300.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>
+    <BookgDt><DtTm>2026-03-03T10:00:00</DtTm></BookgDt><AcctSvcrRef>REF-2</AcctSvcrRef>
+    <NtryDtls>
+      <TxDtls><Amt Ccy="EUR">100.00</Amt><Refs><EndToEndId>MSG-1-7</EndToEndId></Refs></TxDtls>
+      <TxDtls><AmtDtls><TxAmt><Amt Ccy="EUR">200.00</Amt></TxAmt></AmtDtls><Refs><EndToEndId>MSG-1-8</EndToEndId></Refs></TxDtls>
+    </NtryDtls>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">50.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>BOOK</Sts>
+    <BookgDt><Dt>2026-03-03</Dt></BookgDt>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">60.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>PDNG</Sts>
+    <BookgDt><Dt>2026-03-04</Dt></BookgDt>
+  </Ntry>
+  <Ntry>
+    <Amt Ccy="EUR">70.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><RvslInd>true</RvslInd><Sts>BOOK</Sts>
+    <BookgDt><Dt>2026-03-04</Dt></BookgDt>
+  </Ntry>
+</Stmt></BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn parse_camt053_reads_booked_credits() {
+        let entries = parse_camt053(STATEMENT).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let single = &entries[0];
+        assert_eq!(single.bank_reference, "REF-1");
+        assert_eq!(single.booking_date, NaiveDate::from_ymd_opt(2026, 3, 2).unwrap());
+        assert_eq!(single.amount, Decimal::new(11900, 2));
+        assert_eq!(single.currency, "EUR");
+        assert_eq!(single.end_to_end_id, "");
+        assert_eq!(single.remittance, "Invoice RE-2026-0001");
+        assert_eq!(single.debtor_name, "Muster GmbH");
+    }
+
+    #[test]
+    fn parse_camt053_splits_batch_entries() {
+        let entries = parse_camt053(STATEMENT).unwrap();
+        let batch: Vec<_> = entries[1..]
+            .iter()
+            .map(|e| (e.bank_reference.as_str(), e.amount, e.end_to_end_id.as_str(), e.booking_date))
+            .collect();
+        let date = NaiveDate::from_ymd_opt(2026, 3, 3).unwrap();
+        assert_eq!(
+            batch,
+            [
+                ("REF-2/1", Decimal::new(100, 0), "MSG-1-7", date),
+                ("REF-2/2", Decimal::new(200, 0), "MSG-1-8", date),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_camt053_rejects_other_documents() {
+        assert!(parse_camt053("<Document><CstmrDrctDbtInitn/></Document>").is_err());
+        assert!(parse_camt053("not xml").is_err());
+    }
+}
@@ -0,0 +1,492 @@
+// settings.rs
+use crate::app::View;
+use crate::auth::{self, Role, ROLES};
+use crate::currency::{self, CurrencyStore};
+use crate::custom_fields::{self, CustomFieldStore};
+use crate::db::{self, CompanyProfile, CustomField, NumberSequence, Product, SaveResult, User};
+use crate::numbering;
+use crate::tax::{self, TaxRateStore};
+use crate::ui;
+use chrono::Utc;
+use eframe::egui;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub struct SettingsView {
+    users: Arc<Mutex<Vec<User>>>,
+    users_loaded: bool,
+    /// New passwords typed in the user list, by user id.
+    new_passwords: HashMap<i32, String>,
+    new_user: User,
+    new_user_password: String,
+    new_field: CustomField,
+    products: Arc<Mutex<Vec<Product>>>,
+    /// Seller details printed on e-invoices.
+    company: Arc<Mutex<CompanyProfile>>,
+    sequences: Arc<Mutex<Vec<NumberSequence>>>,
+    status: Arc<Mutex<String>>,
+}
+
+impl Default for SettingsView {
+    fn default() -> Self {
+        Self {
+            users: Arc::new(Mutex::new(Vec::new())),
+            users_loaded: false,
+            new_passwords: HashMap::new(),
+            new_user: empty_user(),
+            new_user_password: String::new(),
+            new_field: custom_fields::empty_field(),
+            products: Arc::new(Mutex::new(Vec::new())),
+            company: Arc::new(Mutex::new(CompanyProfile::default())),
+            sequences: Arc::new(Mutex::new(Vec::new())),
+            status: Arc::new(Mutex::new(String::new())),
+        }
+    }
+}
+
+fn empty_user() -> User {
+    User {
+        user_id: 0,
+        username: String::new(),
+        display_name: String::new(),
+        role: Role::Sales,
+        active: true,
+    }
+}
+
+impl SettingsView {
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        current_view: &mut View,
+        custom_fields: &CustomFieldStore,
+        tax_rates: &TaxRateStore,
+        currencies: &CurrencyStore,
+    ) {
+        custom_fields.refresh_if_stale();
+        tax_rates.refresh_if_stale();
+        currencies.refresh_if_stale();
+        if !self.users_loaded {
+            self.users_loaded = true;
+            self.load_users();
+            self.load_products();
+            self.load_company();
+            self.load_sequences();
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Settings");
+            ui.label("Configure your CRM application here.");
+            if ui.button("Database Settings").clicked() {
+                *current_view = View::SetupWizard;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.add_space(20.0);
+                ui.heading("Users");
+                self.render_users(ui);
+
+                ui.add_space(10.0);
+                ui.heading("Add User");
+                self.render_new_user(ui);
+
+                ui.add_space(20.0);
+                ui.heading("Custom Customer Fields");
+                custom_fields::render_field_definitions(
+                    ui,
+                    custom_fields,
+                    &mut self.new_field,
+                    &self.status,
+                );
+
+                ui.add_space(20.0);
+                ui.heading("Tax Rates");
+                tax::render_rate_definitions(ui, tax_rates, &self.status);
+
+                ui.add_space(10.0);
+                ui.heading("Product Tax Rates");
+                ui.label("Products without a rate use the default rate.");
+                self.render_product_rates(ui, tax_rates);
+
+                ui.add_space(20.0);
+                ui.heading("Currencies");
+                currency::render_currency_settings(ui, currencies, &self.status);
+
+                ui.add_space(20.0);
+                ui.heading("Company");
+                ui.label("Seller details of e-invoices.");
+                self.render_company(ui);
+
+                ui.add_space(20.0);
+                ui.heading("Number Sequences");
+                ui.label("Documents saved without a number get the next one: {YYYY}, {YY} and {MM} are the year and month of the document, {0000} the counter with that many digits.");
+                self.render_sequences(ui);
+            });
+        });
+    }
+
+    fn render_users(&mut self, ui: &mut egui::Ui) {
+        let mut users = self.users.lock().unwrap().clone();
+        let mut save = None;
+        egui::Grid::new("users_grid").striped(true).show(ui, |ui| {
+            ui.strong("Username");
+            ui.strong("Display Name");
+            ui.strong("Role");
+            ui.strong("Active");
+            ui.strong("New Password");
+            ui.end_row();
+
+            for user in users.iter_mut() {
+                ui.label(&user.username);
+                ui.text_edit_singleline(&mut user.display_name);
+                role_combo(ui, ("user_role", user.user_id), &mut user.role);
+                ui.checkbox(&mut user.active, "");
+                let password = self.new_passwords.entry(user.user_id).or_default();
+                ui.add(egui::TextEdit::singleline(password).password(true));
+                if ui.button("Save").clicked() {
+                    save = Some(user.clone());
+                }
+                ui.end_row();
+            }
+        });
+        *self.users.lock().unwrap() = users;
+
+        if let Some(user) = save {
+            let password = self.new_passwords.remove(&user.user_id).unwrap_or_default();
+            self.save_user(user, password);
+        }
+    }
+
+    fn render_new_user(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("new_user_grid").show(ui, |ui| {
+            ui.label("Username:");
+            ui.text_edit_singleline(&mut self.new_user.username);
+            ui.end_row();
+            ui.label("Display Name:");
+            ui.text_edit_singleline(&mut self.new_user.display_name);
+            ui.end_row();
+            ui.label("Role:");
+            role_combo(ui, "new_user_role", &mut self.new_user.role);
+            ui.end_row();
+            ui.label("Password:");
+            ui.add(egui::TextEdit::singleline(&mut self.new_user_password).password(true));
+            ui.end_row();
+        });
+
+        if ui.button("Add User").clicked() {
+            let user = std::mem::replace(&mut self.new_user, empty_user());
+            let password = std::mem::take(&mut self.new_user_password);
+            self.save_user(user, password);
+        }
+    }
+
+    fn render_product_rates(&mut self, ui: &mut egui::Ui, tax_rates: &TaxRateStore) {
+        let rates = tax_rates.all_rates();
+        let products = self.products.lock().unwrap().clone();
+        let mut changed = None;
+        egui::Grid::new("product_tax_rates_grid").striped(true).show(ui, |ui| {
+            for product in &products {
+                ui.label(&product.product_name);
+                let selected = product
+                    .tax_rate_id
+                    .and_then(|id| rates.iter().find(|r| r.tax_rate_id == id))
+                    .map_or("Default".to_string(), |r| r.name.clone());
+                egui::ComboBox::from_id_source(("product_tax_rate", product.product_id))
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        if ui.selectable_label(product.tax_rate_id.is_none(), "Default").clicked() {
+                            changed = Some((product.product_id, None));
+                        }
+                        for rate in rates.iter().filter(|r| r.active) {
+                            let selected = product.tax_rate_id == Some(rate.tax_rate_id);
+                            if ui.selectable_label(selected, &rate.name).clicked() {
+                                changed = Some((product.product_id, Some(rate.tax_rate_id)));
+                            }
+                        }
+                    });
+                ui.end_row();
+            }
+        });
+
+        if let Some((product_id, tax_rate_id)) = changed {
+            self.set_product_rate(product_id, tax_rate_id);
+        }
+    }
+
+    fn render_company(&mut self, ui: &mut egui::Ui) {
+        let mut profile = self.company.lock().unwrap().clone();
+        egui::Grid::new("company_profile_grid").show(ui, |ui| {
+            for (label, value) in [
+                ("Company Name:", &mut profile.company_name),
+                ("Street:", &mut profile.street),
+                ("Postal Code:", &mut profile.postal_code),
+                ("City:", &mut profile.city),
+                ("Country Code:", &mut profile.country_code),
+                ("VAT ID:", &mut profile.vat_id),
+                ("Tax Number:", &mut profile.tax_number),
+                ("Contact Name:", &mut profile.contact_name),
+                ("Phone:", &mut profile.phone),
+                ("Email:", &mut profile.email),
+                ("IBAN:", &mut profile.iban),
+                ("BIC:", &mut profile.bic),
+                ("Creditor ID:", &mut profile.creditor_id),
+            ] {
+                ui.label(label);
+                ui.text_edit_singleline(value);
+                ui.end_row();
+            }
+        });
+
+        ui.add_space(10.0);
+        ui.strong("DATEV Export");
+        ui.label("Revenue accounts are set per tax rate; debtor accounts are 10000 plus the customer id.");
+        egui::Grid::new("datev_settings_grid").show(ui, |ui| {
+            ui.label("Consultant Number:");
+            ui::parsed_field(ui, "datev_consultant_number", &mut profile.datev_consultant_number);
+            ui.end_row();
+            ui.label("Client Number:");
+            ui::parsed_field(ui, "datev_client_number", &mut profile.datev_client_number);
+            ui.end_row();
+            ui.label("Fiscal Year Starts In Month:");
+            ui::parsed_field(ui, "datev_fiscal_year_start", &mut profile.datev_fiscal_year_start);
+            ui.end_row();
+            ui.label("Bank Account:");
+            ui.text_edit_singleline(&mut profile.datev_bank_account);
+            ui.end_row();
+        });
+        let save = ui.button("Save Company").clicked();
+        *self.company.lock().unwrap() = profile.clone();
+        if save {
+            self.save_company(profile);
+        }
+    }
+
+    fn render_sequences(&mut self, ui: &mut egui::Ui) {
+        let mut sequences = self.sequences.lock().unwrap().clone();
+        let today = Utc::now().date_naive();
+        let mut save = None;
+        egui::Grid::new("number_sequences_grid").striped(true).show(ui, |ui| {
+            ui.strong("Documents");
+            ui.strong("Pattern");
+            ui.strong("Yearly Reset");
+            ui.strong("Next Number");
+            ui.strong("Example");
+            ui.end_row();
+            for sequence in &mut sequences {
+                let label = numbering::SEQUENCE_TYPES
+                    .iter()
+                    .find(|(document_type, _)| *document_type == sequence.document_type)
+                    .map_or(sequence.document_type.as_str(), |(_, label)| label);
+                ui.label(label);
+                ui.text_edit_singleline(&mut sequence.pattern);
+                ui.checkbox(&mut sequence.yearly_reset, "");
+                ui.add(egui::DragValue::new(&mut sequence.next_number).clamp_range(1..=i32::MAX));
+                match numbering::pattern_error(&sequence.pattern) {
+                    Some(error) => {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    None => {
+                        ui.label(numbering::format_number(&sequence.pattern, today, sequence.next_number));
+                        if ui.button("Save").clicked() {
+                            save = Some(sequence.clone());
+                        }
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        *self.sequences.lock().unwrap() = sequences;
+        if let Some(sequence) = save {
+            self.save_sequence(sequence);
+        }
+    }
+
+    fn load_sequences(&self) {
+        let sequences = Arc::clone(&self.sequences);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_number_sequences(&config).await {
+                    Ok(loaded) => *sequences.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching number sequences: {}", e),
+                }
+            }
+        });
+    }
+
+    fn save_sequence(&self, mut sequence: NumberSequence) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        sequence.pattern = sequence.pattern.trim().to_string();
+        let sequences = Arc::clone(&self.sequences);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let replace = |saved: NumberSequence| {
+                let mut sequences = sequences.lock().unwrap();
+                if let Some(existing) = sequences.iter_mut().find(|s| s.sequence_id == saved.sequence_id) {
+                    *existing = saved;
+                }
+            };
+            match db::save_number_sequence(&config, &sequence).await {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = "Number sequence saved".to_string();
+                    replace(saved);
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() =
+                        "The number sequence was changed or used meanwhile and has been reloaded".to_string();
+                    replace(current);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error saving number sequence: {}", e),
+            }
+        });
+    }
+
+    fn load_company(&self) {
+        let company = Arc::clone(&self.company);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_company_profile(&config).await {
+                    Ok(loaded) => *company.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching company profile: {}", e),
+                }
+            }
+        });
+    }
+
+    fn save_company(&self, mut profile: CompanyProfile) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        profile.country_code = profile.country_code.trim().to_uppercase();
+        profile.datev_bank_account = profile.datev_bank_account.trim().to_string();
+        profile.datev_fiscal_year_start = profile.datev_fiscal_year_start.clamp(1, 12);
+        let company = Arc::clone(&self.company);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::save_company_profile(&config, &profile).await {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = "Company profile saved".to_string();
+                    *company.lock().unwrap() = saved;
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() =
+                        "The company profile was changed by someone else and has been reloaded".to_string();
+                    *company.lock().unwrap() = current;
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error saving company profile: {}", e),
+            }
+        });
+    }
+
+    fn load_products(&self) {
+        let products = Arc::clone(&self.products);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_products(&config).await {
+                    Ok(loaded) => *products.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching products: {}", e),
+                }
+            }
+        });
+    }
+
+    fn set_product_rate(&self, product_id: i32, tax_rate_id: Option<i32>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let products = Arc::clone(&self.products);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::set_product_tax_rate(&config, product_id, tax_rate_id).await {
+                Ok(()) => {
+                    let mut products = products.lock().unwrap();
+                    if let Some(product) = products.iter_mut().find(|p| p.product_id == product_id) {
+                        product.tax_rate_id = tax_rate_id;
+                        *status.lock().unwrap() = format!("Tax rate of {} saved", product.product_name);
+                    }
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error saving tax rate: {}", e),
+            }
+        });
+    }
+
+    fn load_users(&self) {
+        let users = Arc::clone(&self.users);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_users(&config).await {
+                    Ok(loaded) => *users.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching users: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Adds `user` if it has no id yet, otherwise updates it. An empty
+    /// `password` keeps the existing one.
+    fn save_user(&self, user: User, password: String) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let is_new = user.user_id == 0;
+        if is_new && (user.username.trim().is_empty() || password.len() < 8) {
+            *self.status.lock().unwrap() =
+                "Please enter a username and a password of at least 8 characters".to_string();
+            return;
+        }
+        let is_self = auth::current_user().map(|u| u.user_id) == Some(user.user_id);
+        if is_self && (user.role != Role::Admin || !user.active) {
+            *self.status.lock().unwrap() =
+                "You cannot remove your own administrator access".to_string();
+            return;
+        }
+
+        let users = Arc::clone(&self.users);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let hash = if password.is_empty() {
+                None
+            } else {
+                match auth::hash_password(&password) {
+                    Ok(hash) => Some(hash),
+                    Err(e) => {
+                        *status.lock().unwrap() = format!("Could not hash password: {}", e);
+                        return;
+                    }
+                }
+            };
+            let result = if is_new {
+                db::add_user(&config, &user, hash.as_deref().unwrap_or_default()).await
+            } else {
+                db::update_user(&config, &user, hash.as_deref()).await
+            };
+            match result {
+                Ok(saved) => {
+                    *status.lock().unwrap() = format!("User {} saved", saved.username);
+                    let mut users = users.lock().unwrap();
+                    match users.iter_mut().find(|u| u.user_id == saved.user_id) {
+                        Some(existing) => *existing = saved,
+                        None => users.push(saved),
+                    }
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error saving user: {}", e),
+            }
+        });
+    }
+}
+
+fn role_combo(ui: &mut egui::Ui, id_source: impl std::hash::Hash, role: &mut Role) {
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(role.label())
+        .show_ui(ui, |ui| {
+            for option in ROLES {
+                ui.selectable_value(role, option, option.label());
+            }
+        });
+}
@@ -0,0 +1,291 @@
+// stock.rs
+use crate::auth;
+use crate::db::{self, Invoice, LineItem, Product, StockMovement};
+use eframe::egui;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Quantities of each product on `items`.
+fn quantities(items: &[LineItem]) -> HashMap<i32, i32> {
+    let mut quantities = HashMap::new();
+    for item in items {
+        if let Some(product_id) = item.product_id {
+            *quantities.entry(product_id).or_insert(0) += item.quantity;
+        }
+    }
+    quantities
+}
+
+/// Whether the goods of an invoice with this status are out of stock.
+fn takes_stock(status: &str) -> bool {
+    matches!(status, "sent" | "paid" | "credited")
+}
+
+/// Products `invoice` asks for more of than there is in stock. `saved` is
+/// the invoice as stored, whose goods may already be booked out.
+pub fn stock_warnings(invoice: &Invoice, saved: Option<&Invoice>, products: &[Product]) -> Vec<String> {
+    if invoice.status == "cancelled" {
+        return Vec::new();
+    }
+    let booked = saved
+        .filter(|s| takes_stock(&s.status))
+        .map(|s| quantities(&s.items))
+        .unwrap_or_default();
+    let mut warnings: Vec<String> = quantities(&invoice.items)
+        .into_iter()
+        .filter_map(|(product_id, quantity)| {
+            let product = products.iter().find(|p| p.product_id == product_id)?;
+            let available = product.stock_quantity + booked.get(&product_id).copied().unwrap_or(0);
+            (quantity > available).then(|| {
+                format!(
+                    "{}: {} requested, {} in stock",
+                    product.product_name,
+                    quantity,
+                    available.max(0)
+                )
+            })
+        })
+        .collect();
+    warnings.sort();
+    warnings
+}
+
+/// Stock change of a movement entered as a positive number of goods.
+fn signed_quantity(movement_type: &str, quantity: i32) -> i32 {
+    match movement_type {
+        "sale" => -quantity,
+        // Corrections are entered as the difference to the counted stock.
+        _ => quantity,
+    }
+}
+
+/// Stock of the products, their movements and new goods receipts,
+/// corrections and returns.
+pub struct InventoryView {
+    products: Arc<Mutex<Vec<Product>>>,
+    movements: Arc<Mutex<Vec<StockMovement>>>,
+    /// Set whenever the products must be (re)loaded.
+    pub stale: Arc<AtomicBool>,
+    status: Arc<Mutex<String>>,
+    selected: Option<i32>,
+    new_movement: StockMovement,
+    reorder_level: i32,
+}
+
+impl Default for InventoryView {
+    fn default() -> Self {
+        Self {
+            products: Arc::new(Mutex::new(Vec::new())),
+            movements: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            status: Arc::new(Mutex::new(String::new())),
+            selected: None,
+            new_movement: StockMovement::default(),
+            reorder_level: 0,
+        }
+    }
+}
+
+impl InventoryView {
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load_products();
+            if let Some(product_id) = self.selected {
+                self.load_movements(product_id);
+            }
+        }
+        let products = self.products.lock().unwrap().clone();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Inventory");
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            let low: Vec<&Product> = products.iter().filter(|p| p.is_low_on_stock()).collect();
+            if !low.is_empty() {
+                ui.strong(format!("Low stock ({})", low.len()));
+                for product in low {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(200, 120, 0),
+                        format!(
+                            "{}: {} in stock, reorder level {}",
+                            product.product_name, product.stock_quantity, product.reorder_level
+                        ),
+                    );
+                }
+                ui.add_space(10.0);
+            }
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("inventory_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Product");
+                    ui.strong("In Stock");
+                    ui.strong("Reorder Level");
+                    ui.end_row();
+                    for product in &products {
+                        let selected = self.selected == Some(product.product_id);
+                        if ui.selectable_label(selected, &product.product_name).clicked() && !selected {
+                            self.select(product);
+                        }
+                        if product.is_low_on_stock() {
+                            ui.colored_label(egui::Color32::from_rgb(200, 120, 0), product.stock_quantity.to_string());
+                        } else {
+                            ui.label(product.stock_quantity.to_string());
+                        }
+                        ui.label(product.reorder_level.to_string());
+                        ui.end_row();
+                    }
+                });
+
+                if let Some(product) = self
+                    .selected
+                    .and_then(|id| products.iter().find(|p| p.product_id == id))
+                {
+                    ui.add_space(20.0);
+                    self.render_product(ui, product);
+                }
+            });
+        });
+    }
+
+    fn render_product(&mut self, ui: &mut egui::Ui, product: &Product) {
+        ui.strong(&product.product_name);
+        if auth::can_edit() {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("movement_type")
+                    .selected_text(self.new_movement.movement_type.clone())
+                    .show_ui(ui, |ui| {
+                        for movement_type in db::MOVEMENT_TYPES {
+                            ui.selectable_value(
+                                &mut self.new_movement.movement_type,
+                                movement_type.to_string(),
+                                movement_type,
+                            );
+                        }
+                    });
+                ui.label("Quantity:");
+                ui.add(egui::DragValue::new(&mut self.new_movement.quantity));
+                ui.label("Notes:");
+                ui.text_edit_singleline(&mut self.new_movement.notes);
+                if ui.button("Record").clicked() {
+                    self.record_movement(product.product_id);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Reorder level:");
+                ui.add(egui::DragValue::new(&mut self.reorder_level).clamp_range(0..=1_000_000));
+                if self.reorder_level != product.reorder_level && ui.button("Save").clicked() {
+                    self.save_reorder_level(product.product_id);
+                }
+            });
+        }
+
+        let movements = self.movements.lock().unwrap().clone();
+        egui::Grid::new("stock_movements_grid").striped(true).show(ui, |ui| {
+            ui.strong("Date");
+            ui.strong("Type");
+            ui.strong("Quantity");
+            ui.strong("Invoice");
+            ui.strong("Notes");
+            ui.strong("By");
+            ui.end_row();
+            for movement in movements.iter().filter(|m| m.product_id == product.product_id) {
+                ui.label(movement.created_at.format("%d.%m.%Y %H:%M").to_string());
+                ui.label(&movement.movement_type);
+                ui.label(format!("{:+}", movement.quantity));
+                ui.label(movement.invoice_number.as_deref().unwrap_or(""));
+                ui.label(&movement.notes);
+                ui.label(&movement.created_by);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn select(&mut self, product: &Product) {
+        self.selected = Some(product.product_id);
+        self.reorder_level = product.reorder_level;
+        self.new_movement = StockMovement::default();
+        self.movements.lock().unwrap().clear();
+        self.load_movements(product.product_id);
+    }
+
+    fn load_products(&self) {
+        let products = Arc::clone(&self.products);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_products(&config).await {
+                    Ok(loaded) => *products.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching products: {}", e),
+                }
+            }
+        });
+    }
+
+    fn load_movements(&self, product_id: i32) {
+        let movements = Arc::clone(&self.movements);
+        tokio::spawn(async move {
+            if let Some(config) = db::get_config() {
+                match db::get_stock_movements(&config, product_id).await {
+                    Ok(loaded) => *movements.lock().unwrap() = loaded,
+                    Err(e) => eprintln!("Error fetching stock movements: {}", e),
+                }
+            }
+        });
+    }
+
+    fn record_movement(&mut self, product_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let quantity = self.new_movement.quantity;
+        if quantity == 0 || (quantity < 0 && self.new_movement.movement_type != "correction") {
+            *self.status.lock().unwrap() =
+                "Please enter a positive quantity; only corrections can be negative".to_string();
+            return;
+        }
+        let movement = StockMovement {
+            product_id,
+            quantity: signed_quantity(&self.new_movement.movement_type, quantity),
+            ..self.new_movement.clone()
+        };
+        self.new_movement = StockMovement {
+            movement_type: movement.movement_type.clone(),
+            ..StockMovement::default()
+        };
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::add_stock_movement(&config, &movement).await {
+                Ok(added) => {
+                    *status.lock().unwrap() = format!("Recorded {} of {:+}", added.movement_type, added.quantity);
+                    stale.store(true, Ordering::SeqCst);
+                }
+                Err(e) => *status.lock().unwrap() = format!("Error recording stock movement: {}", e),
+            }
+        });
+    }
+
+    fn save_reorder_level(&self, product_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let reorder_level = self.reorder_level;
+        let status = Arc::clone(&self.status);
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::set_reorder_level(&config, product_id, reorder_level).await {
+                Ok(()) => stale.store(true, Ordering::SeqCst),
+                Err(e) => *status.lock().unwrap() = format!("Error saving reorder level: {}", e),
+            }
+        });
+    }
+}
@@ -0,0 +1,224 @@
+// tags.rs
+use crate::db::{self, Tag};
+use eframe::egui;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Tags and their assignment to customers, shared by every view that shows
+/// or filters by tags.
+#[derive(Clone)]
+pub struct TagStore {
+    tags: Arc<Mutex<Vec<Tag>>>,
+    links: Arc<Mutex<HashMap<i32, BTreeSet<i32>>>>,
+    /// Set whenever tags must be reloaded, e.g. by the change listener.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl Default for TagStore {
+    fn default() -> Self {
+        Self {
+            tags: Arc::new(Mutex::new(Vec::new())),
+            links: Arc::new(Mutex::new(HashMap::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl TagStore {
+    pub fn refresh_if_stale(&self) {
+        if !self.stale.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let store = self.clone();
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_tags(&config).await {
+                Ok(tags) => *store.tags.lock().unwrap() = tags,
+                Err(e) => eprintln!("Error fetching tags: {}", e),
+            }
+            match db::get_customer_tag_links(&config).await {
+                Ok(pairs) => {
+                    let mut links: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+                    for (customer_id, tag_id) in pairs {
+                        links.entry(customer_id).or_default().insert(tag_id);
+                    }
+                    *store.links.lock().unwrap() = links;
+                }
+                Err(e) => eprintln!("Error fetching customer tags: {}", e),
+            }
+        });
+    }
+
+    pub fn tags(&self) -> Vec<Tag> {
+        self.tags.lock().unwrap().clone()
+    }
+
+    pub fn tags_of(&self, customer_id: i32) -> Vec<Tag> {
+        let links = self.links.lock().unwrap();
+        let Some(tag_ids) = links.get(&customer_id) else {
+            return Vec::new();
+        };
+        self.tags
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tag| tag_ids.contains(&tag.tag_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the customer carries every tag in `tag_ids`.
+    pub fn has_all(&self, customer_id: i32, tag_ids: &BTreeSet<i32>) -> bool {
+        if tag_ids.is_empty() {
+            return true;
+        }
+        self.links
+            .lock()
+            .unwrap()
+            .get(&customer_id)
+            .is_some_and(|assigned| tag_ids.is_subset(assigned))
+    }
+
+    /// Assigns or removes `tag_id` for all `customer_ids`.
+    pub fn set_tag(&self, customer_ids: Vec<i32>, tag_id: i32, assign: bool) {
+        self.run(move |config| async move {
+            db::set_customer_tag(&config, &customer_ids, tag_id, assign)
+                .await
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    pub fn create(&self, name: String, color: String) {
+        self.run(move |config| async move {
+            db::add_tag(&config, &name, &color)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    pub fn delete(&self, tag_id: i32) {
+        self.run(move |config| async move {
+            db::delete_tag(&config, tag_id)
+                .await
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    /// Runs a database change and reloads the tags afterwards.
+    fn run<F, Fut>(&self, change: F)
+    where
+        F: FnOnce(crate::config::DbConfig) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Result<(), String>> + Send,
+    {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            if let Err(e) = change(config).await {
+                eprintln!("Error changing tags: {}", e);
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+pub fn tag_color(tag: &Tag) -> egui::Color32 {
+    let hex = tag.color.trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .unwrap_or(128)
+    };
+    egui::Color32::from_rgb(channel(0), channel(2), channel(4))
+}
+
+/// A tag drawn as a small colored button. Unselected chips in a filter are
+/// drawn faded.
+pub fn tag_chip(ui: &mut egui::Ui, tag: &Tag, selected: bool) -> egui::Response {
+    let fill = tag_color(tag);
+    let fill = if selected {
+        fill
+    } else {
+        fill.linear_multiply(0.35)
+    };
+    // Pick black or white text depending on the brightness of the fill.
+    let [r, g, b, _] = fill.to_array();
+    let luminance = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+    let text_color = if luminance > 140.0 {
+        egui::Color32::BLACK
+    } else {
+        egui::Color32::WHITE
+    };
+    ui.add(
+        egui::Button::new(egui::RichText::new(&tag.name).color(text_color).small())
+            .fill(fill)
+            .rounding(8.0),
+    )
+}
+
+/// Chips for all tags of a customer; with `editable`, each chip removes its
+/// tag on click and a combo box offers the remaining tags.
+pub fn render_customer_tags(ui: &mut egui::Ui, store: &TagStore, customer_id: i32, editable: bool) {
+    ui.horizontal_wrapped(|ui| {
+        let assigned = store.tags_of(customer_id);
+        for tag in &assigned {
+            let chip = tag_chip(ui, tag, true);
+            if editable && chip.on_hover_text("Click to remove").clicked() {
+                store.set_tag(vec![customer_id], tag.tag_id, false);
+            }
+        }
+        if !editable {
+            return;
+        }
+        let available: Vec<Tag> = store
+            .tags()
+            .into_iter()
+            .filter(|tag| !assigned.iter().any(|a| a.tag_id == tag.tag_id))
+            .collect();
+        if available.is_empty() {
+            return;
+        }
+        egui::ComboBox::from_id_source(("add_tag", customer_id))
+            .selected_text("Add tag...")
+            .show_ui(ui, |ui| {
+                for tag in available {
+                    if ui.selectable_label(false, &tag.name).clicked() {
+                        store.set_tag(vec![customer_id], tag.tag_id, true);
+                    }
+                }
+            });
+    });
+}
+
+/// Create and delete tags.
+pub fn render_tag_manager(ui: &mut egui::Ui, store: &TagStore) {
+    let id = egui::Id::new("new_tag");
+    let (mut name, mut color): (String, [u8; 3]) =
+        ui.data_mut(|d| d.get_temp(id)).unwrap_or((String::new(), [70, 130, 180]));
+
+    ui.horizontal_wrapped(|ui| {
+        for tag in store.tags() {
+            tag_chip(ui, &tag, true);
+            if ui.small_button("x").on_hover_text("Delete tag").clicked() {
+                store.delete(tag.tag_id);
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("New tag:");
+        ui.text_edit_singleline(&mut name);
+        ui.color_edit_button_srgb(&mut color);
+        if ui.button("Create").clicked() && !name.trim().is_empty() {
+            let hex = format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2]);
+            store.create(name.trim().to_string(), hex);
+            name.clear();
+        }
+    });
+
+    ui.data_mut(|d| d.insert_temp(id, (name, color)));
+}
@@ -0,0 +1,418 @@
+// tasks.rs
+use crate::audit::AuditPanel;
+use crate::auth;
+use crate::db::{self, ContactHistory, Customer, SaveResult, Task, User};
+use crate::merge::{MergeAction, MergeDialog};
+use crate::ui;
+use chrono::Utc;
+use eframe::egui;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const TASK_STATUSES: [&str; 3] = ["open", "in_progress", "done"];
+pub const TASK_PRIORITIES: [&str; 3] = ["low", "normal", "high"];
+
+/// Task following up a contact, due on its follow-up date and assigned to
+/// the logged-in user.
+pub fn follow_up_task(entry: &ContactHistory) -> Task {
+    let subject = if entry.contact_outcome.trim().is_empty() {
+        &entry.contact_type
+    } else {
+        &entry.contact_outcome
+    };
+    Task {
+        title: format!("Follow up: {}", subject),
+        description: entry.notes.clone(),
+        customer_id: Some(entry.customer_id),
+        history_id: Some(entry.history_id),
+        assignee: auth::current_user().map(|user| user.username),
+        due_date: entry.follow_up_date,
+        ..Task::default()
+    }
+}
+
+/// Task list, by default the logged-in user's unfinished tasks, with the
+/// task editor.
+pub struct TasksView {
+    tasks: Arc<Mutex<Vec<Task>>>,
+    users: Arc<Mutex<Vec<User>>>,
+    /// Set whenever the tasks must be (re)loaded from the database.
+    pub stale: Arc<AtomicBool>,
+    editor: Arc<Mutex<Option<Task>>>,
+    /// The stored task the editor started from, the base of a merge.
+    loaded: Option<Task>,
+    merge: Arc<Mutex<Option<MergeDialog<Task>>>>,
+    status: Arc<Mutex<String>>,
+    audit: AuditPanel,
+    /// Only tasks assigned to the logged-in user.
+    mine_only: bool,
+    show_done: bool,
+}
+
+impl Default for TasksView {
+    fn default() -> Self {
+        Self {
+            tasks: Arc::new(Mutex::new(Vec::new())),
+            users: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+            editor: Arc::new(Mutex::new(None)),
+            loaded: None,
+            merge: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(String::new())),
+            audit: AuditPanel::default(),
+            mine_only: true,
+            show_done: false,
+        }
+    }
+}
+
+impl TasksView {
+    /// Opens `task` in the editor.
+    pub fn open(&mut self, task: Task) {
+        self.loaded = Some(task.clone());
+        *self.editor.lock().unwrap() = Some(task);
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, customers: &Arc<Mutex<Vec<Customer>>>) {
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.load();
+        }
+
+        let customer_names: HashMap<i32, String> = customers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| (c.customer_id, c.company_name.clone()))
+            .collect();
+        let users = self.users.lock().unwrap().clone();
+        let username = auth::current_user().map(|user| user.username);
+        let tasks: Vec<Task> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| !self.mine_only || t.assignee == username)
+            .filter(|t| self.show_done || t.status != "done")
+            .cloned()
+            .collect();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(if self.mine_only { "My Tasks" } else { "All Tasks" });
+
+            if db::get_config().is_none() {
+                ui.label("No database configuration found. Please run the Setup Wizard first.");
+                return;
+            }
+
+            let status = self.status.lock().unwrap().clone();
+            if !status.is_empty() {
+                ui.label(status);
+            }
+
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.mine_only, true, "My Tasks");
+                ui.selectable_value(&mut self.mine_only, false, "All Tasks");
+                ui.separator();
+                ui.checkbox(&mut self.show_done, "Show done");
+                if auth::can_edit() && ui.button("Create New Task").clicked() {
+                    self.open(Task {
+                        assignee: username.clone(),
+                        ..Task::default()
+                    });
+                }
+            });
+            ui.add_space(10.0);
+
+            if tasks.is_empty() {
+                ui.label("No tasks.");
+                return;
+            }
+            let today = Utc::now().date_naive();
+            let mut done = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("task_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Due");
+                    ui.strong("Priority");
+                    ui.strong("Title");
+                    ui.strong("Customer");
+                    ui.strong("Assignee");
+                    ui.strong("Status");
+                    ui.end_row();
+
+                    for task in &tasks {
+                        let due = task.due_date.map(|d| d.to_string()).unwrap_or_else(|| "-".to_string());
+                        if task.status != "done" && task.due_date.is_some_and(|d| d < today) {
+                            ui.colored_label(egui::Color32::RED, due);
+                        } else {
+                            ui.label(due);
+                        }
+                        ui.label(&task.priority);
+                        ui.label(&task.title);
+                        ui.label(task.customer_id.and_then(|id| customer_names.get(&id)).map_or("-", String::as_str));
+                        ui.label(user_label(&users, task.assignee.as_deref()));
+                        ui.label(&task.status);
+                        if ui.button("Open").clicked() {
+                            self.open(task.clone());
+                        }
+                        if auth::can_edit() && task.status != "done" && ui.button("Done").clicked() {
+                            let finished = Task {
+                                status: "done".to_string(),
+                                ..task.clone()
+                            };
+                            done = Some((task.clone(), finished));
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+            if let Some((base, task)) = done {
+                self.save_task(Some(base), task);
+            }
+        });
+
+        self.render_editor(ctx, &customer_names, &users);
+        self.render_merge_dialog(ctx);
+    }
+
+    fn render_editor(&mut self, ctx: &egui::Context, customer_names: &HashMap<i32, String>, users: &[User]) {
+        let Some(mut task) = self.editor.lock().unwrap().clone() else {
+            return;
+        };
+
+        let mut open = true;
+        let mut save = false;
+        let mut delete = false;
+        egui::Window::new("Task")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("task_editor_grid").show(ui, |ui| {
+                    ui.label("Title:");
+                    ui.text_edit_singleline(&mut task.title);
+                    ui.end_row();
+
+                    ui.label("Description:");
+                    ui.text_edit_multiline(&mut task.description);
+                    ui.end_row();
+
+                    ui.label("Customer:");
+                    let selected = task.customer_id.and_then(|id| customer_names.get(&id)).map_or("-", String::as_str);
+                    egui::ComboBox::from_id_source("task_customer")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut task.customer_id, None, "-");
+                            let mut names: Vec<_> = customer_names.iter().collect();
+                            names.sort_by(|a, b| a.1.cmp(b.1));
+                            for (id, name) in names {
+                                ui.selectable_value(&mut task.customer_id, Some(*id), name);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Assignee:");
+                    egui::ComboBox::from_id_source("task_assignee")
+                        .selected_text(user_label(users, task.assignee.as_deref()))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut task.assignee, None, "-");
+                            for user in users.iter().filter(|u| u.active) {
+                                ui.selectable_value(&mut task.assignee, Some(user.username.clone()), &user.display_name);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Due Date:");
+                    ui.horizontal(|ui| {
+                        let mut has_due = task.due_date.is_some();
+                        ui.checkbox(&mut has_due, "");
+                        if has_due {
+                            let mut date = task.due_date.unwrap_or_else(|| Utc::now().date_naive());
+                            ui::parsed_field(ui, "task_due_date", &mut date);
+                            task.due_date = Some(date);
+                        } else {
+                            task.due_date = None;
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Priority:");
+                    egui::ComboBox::from_id_source("task_priority")
+                        .selected_text(task.priority.clone())
+                        .show_ui(ui, |ui| {
+                            for priority in TASK_PRIORITIES {
+                                ui.selectable_value(&mut task.priority, priority.to_string(), priority);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Status:");
+                    egui::ComboBox::from_id_source("task_status")
+                        .selected_text(task.status.clone())
+                        .show_ui(ui, |ui| {
+                            for status in TASK_STATUSES {
+                                ui.selectable_value(&mut task.status, status.to_string(), status);
+                            }
+                        });
+                    ui.end_row();
+                });
+                if task.history_id.is_some() {
+                    ui.small("Follows up an entry of the contact history.");
+                }
+
+                if auth::can_edit() {
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            save = true;
+                        }
+                        if task.task_id != 0 && ui.button("Delete").clicked() {
+                            delete = true;
+                        }
+                    });
+                }
+
+                if task.task_id != 0 {
+                    self.audit.show(ui, "tasks", task.task_id, task.version);
+                }
+            });
+
+        if !open {
+            *self.editor.lock().unwrap() = None;
+            return;
+        }
+        *self.editor.lock().unwrap() = Some(task.clone());
+        if delete {
+            *self.editor.lock().unwrap() = None;
+            self.delete_task(task.task_id);
+        } else if save {
+            if task.title.trim().is_empty() {
+                *self.status.lock().unwrap() = "Please enter a title".to_string();
+            } else {
+                task.title = task.title.trim().to_string();
+                let base = self.loaded.clone().filter(|t| t.task_id == task.task_id);
+                self.save_task(base, task);
+            }
+        }
+    }
+
+    fn render_merge_dialog(&mut self, ctx: &egui::Context) {
+        let mut merge = self.merge.lock().unwrap();
+        let Some(dialog) = merge.as_mut() else {
+            return;
+        };
+        let Some(action) = dialog.show(ctx, "Task changed by someone else") else {
+            return;
+        };
+
+        let dialog = merge.take().unwrap();
+        drop(merge);
+        match action {
+            MergeAction::SaveMerged => {
+                let merged = dialog.merged();
+                if self.loaded.as_ref().is_some_and(|t| t.task_id == merged.task_id) {
+                    self.loaded = Some(dialog.theirs.clone());
+                }
+                self.save_task(Some(dialog.theirs), merged);
+            }
+            MergeAction::DiscardMine => {
+                let mut editor = self.editor.lock().unwrap();
+                if editor.as_ref().is_some_and(|t| t.task_id == dialog.theirs.task_id) {
+                    self.loaded = Some(dialog.theirs.clone());
+                    *editor = Some(dialog.theirs);
+                }
+            }
+            MergeAction::Cancel => {}
+        }
+    }
+
+    fn load(&self) {
+        let tasks = Arc::clone(&self.tasks);
+        let users = Arc::clone(&self.users);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_users(&config).await {
+                Ok(loaded) => *users.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching users: {}", e),
+            }
+            match db::get_tasks(&config).await {
+                Ok(loaded) => *tasks.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching tasks: {}", e),
+            }
+        });
+    }
+
+    /// Saves `task`, edited from `base`. On a conflict the merge dialog
+    /// compares it with the stored task.
+    fn save_task(&self, base: Option<Task>, task: Task) {
+        let Some(config) = db::get_config() else {
+            *self.status.lock().unwrap() = "No database configuration found!".to_string();
+            return;
+        };
+        let tasks = Arc::clone(&self.tasks);
+        let editor = Arc::clone(&self.editor);
+        let merge = Arc::clone(&self.merge);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let result = if task.task_id == 0 {
+                db::add_task(&config, &task).await.map(SaveResult::Saved)
+            } else {
+                db::update_task(&config, &task).await
+            };
+            match result {
+                Ok(SaveResult::Saved(saved)) => {
+                    *status.lock().unwrap() = format!("Task {} saved", saved.title);
+                    // Only close the editor if it shows the task just saved,
+                    // not when another task was marked done from the list.
+                    let mut editor = editor.lock().unwrap();
+                    if editor.as_ref().is_some_and(|t| t.task_id == task.task_id) {
+                        *editor = None;
+                    }
+                    let mut tasks = tasks.lock().unwrap();
+                    match tasks.iter_mut().find(|t| t.task_id == saved.task_id) {
+                        Some(existing) => *existing = saved,
+                        None => tasks.push(saved),
+                    }
+                }
+                Ok(SaveResult::Conflict(current)) => {
+                    *status.lock().unwrap() = format!("Task {} was changed by someone else", current.title);
+                    let mut tasks = tasks.lock().unwrap();
+                    if let Some(existing) = tasks.iter_mut().find(|t| t.task_id == current.task_id) {
+                        *existing = current.clone();
+                    }
+                    let base = base.unwrap_or_else(|| current.clone());
+                    *merge.lock().unwrap() = Some(MergeDialog::new(&base, task, current));
+                }
+                Err(e) => {
+                    eprintln!("Error saving task: {}", e);
+                    *status.lock().unwrap() = format!("Error saving task: {}", e);
+                }
+            }
+        });
+    }
+
+    fn delete_task(&self, task_id: i32) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let tasks = Arc::clone(&self.tasks);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            match db::delete_task(&config, task_id).await {
+                Ok(()) => tasks.lock().unwrap().retain(|t| t.task_id != task_id),
+                Err(e) => *status.lock().unwrap() = format!("Error deleting task: {}", e),
+            }
+        });
+    }
+}
+
+/// Display name of the user `username`, or the username of a user not
+/// loaded.
+fn user_label(users: &[User], username: Option<&str>) -> String {
+    let Some(username) = username else {
+        return "-".to_string();
+    };
+    users
+        .iter()
+        .find(|u| u.username == username)
+        .map_or_else(|| username.to_string(), |u| u.display_name.clone())
+}
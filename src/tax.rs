@@ -0,0 +1,354 @@
+// tax.rs
+use crate::db::{self, LineItem, TaxRate};
+use crate::ui;
+use eframe::egui;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Rounds to cents, halves away from zero as customary in commerce
+/// (`round_dp` would round halves to even).
+pub fn round_money(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// Quantity times unit price of one line, before its discount.
+pub fn line_amount(item: &LineItem) -> Decimal {
+    round_money(item.unit_price * Decimal::from(item.quantity))
+}
+
+/// Net amount of one line after its discount, rounded to cents.
+pub fn line_net(item: &LineItem) -> Decimal {
+    if item.discount_percent.is_zero() {
+        return line_amount(item);
+    }
+    let hundred = Decimal::from(100);
+    round_money(item.unit_price * Decimal::from(item.quantity) * (hundred - item.discount_percent) / hundred)
+}
+
+/// Amount the discount of a line takes off.
+pub fn line_discount(item: &LineItem) -> Decimal {
+    line_amount(item) - line_net(item)
+}
+
+/// Net and tax of all lines sharing one tax rate.
+#[derive(Clone, Debug)]
+pub struct TaxGroup {
+    pub tax_rate_id: Option<i32>,
+    pub rate: Decimal,
+    pub net: Decimal,
+    pub tax: Decimal,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Totals {
+    pub net: Decimal,
+    pub tax: Decimal,
+    pub gross: Decimal,
+    pub groups: Vec<TaxGroup>,
+}
+
+/// Totals of a document. Tax is computed once per rate on the sum of the
+/// rounded line amounts, not per line, so it cannot drift by a cent per line.
+pub fn totals(items: &[LineItem]) -> Totals {
+    let mut groups: Vec<TaxGroup> = Vec::new();
+    for item in items {
+        let net = line_net(item);
+        match groups
+            .iter_mut()
+            .find(|g| g.tax_rate_id == item.tax_rate_id && g.rate == item.tax_rate)
+        {
+            Some(group) => group.net += net,
+            None => groups.push(TaxGroup {
+                tax_rate_id: item.tax_rate_id,
+                rate: item.tax_rate,
+                net,
+                tax: Decimal::ZERO,
+            }),
+        }
+    }
+    for group in &mut groups {
+        group.tax = round_money(group.net * group.rate / Decimal::from(100));
+    }
+    groups.sort_by_key(|g| std::cmp::Reverse(g.rate));
+    let net = groups.iter().map(|g| g.net).sum();
+    let tax = groups.iter().map(|g| g.tax).sum();
+    Totals {
+        net,
+        tax,
+        gross: net + tax,
+        groups,
+    }
+}
+
+/// Label of a breakdown line, such as "19% VAT" or the rate's name for
+/// reverse charge.
+pub fn group_label(group: &TaxGroup, rates: &[TaxRate]) -> String {
+    match rates.iter().find(|r| Some(r.tax_rate_id) == group.tax_rate_id) {
+        Some(rate) if rate.reverse_charge => rate.name.clone(),
+        _ => format!("{}% VAT", group.rate.normalize()),
+    }
+}
+
+/// Notes the rates used by `totals` require on the document, such as the
+/// reverse charge notice.
+pub fn document_notes(totals: &Totals, rates: &[TaxRate]) -> Vec<String> {
+    let mut notes: Vec<String> = Vec::new();
+    for group in &totals.groups {
+        if let Some(rate) = rates.iter().find(|r| Some(r.tax_rate_id) == group.tax_rate_id) {
+            if !rate.note.trim().is_empty() && !notes.contains(&rate.note) {
+                notes.push(rate.note.clone());
+            }
+        }
+    }
+    notes
+}
+
+/// Tax rate definitions shared by all document editors.
+#[derive(Clone)]
+pub struct TaxRateStore {
+    rates: Arc<Mutex<Vec<TaxRate>>>,
+    /// Set whenever the rates must be reloaded.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl Default for TaxRateStore {
+    fn default() -> Self {
+        Self {
+            rates: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl TaxRateStore {
+    pub fn refresh_if_stale(&self) {
+        if !self.stale.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let rates = Arc::clone(&self.rates);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_tax_rates(&config).await {
+                Ok(loaded) => *rates.lock().unwrap() = loaded,
+                Err(e) => eprintln!("Error fetching tax rates: {}", e),
+            }
+        });
+    }
+
+    /// All rates, including inactive ones still used by older documents.
+    pub fn all_rates(&self) -> Vec<TaxRate> {
+        self.rates.lock().unwrap().clone()
+    }
+
+    /// Rate given to new lines without a product.
+    pub fn default_rate(&self) -> Option<TaxRate> {
+        self.rates.lock().unwrap().iter().find(|r| r.is_default && r.active).cloned()
+    }
+
+    pub fn save_rate(&self, rate: TaxRate, status: Arc<Mutex<String>>) {
+        let Some(config) = db::get_config() else {
+            return;
+        };
+        let stale = Arc::clone(&self.stale);
+        tokio::spawn(async move {
+            match db::save_tax_rate(&config, &rate).await {
+                Ok(saved) => *status.lock().unwrap() = format!("Tax rate {} saved", saved.name),
+                Err(e) => *status.lock().unwrap() = format!("Error saving tax rate: {}", e),
+            }
+            stale.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Sets the rate of `item`, keeping a copy of the percentage so later
+/// changes to the rate do not alter existing documents.
+pub fn apply_rate(item: &mut LineItem, rate: Option<&TaxRate>) {
+    item.tax_rate_id = rate.map(|r| r.tax_rate_id);
+    item.tax_rate = rate.map_or(Decimal::ZERO, |r| r.rate);
+}
+
+/// Combo box choosing the tax rate of a line.
+pub fn rate_combo(ui: &mut egui::Ui, id_source: impl std::hash::Hash, item: &mut LineItem, rates: &[TaxRate]) {
+    let selected = rates
+        .iter()
+        .find(|r| Some(r.tax_rate_id) == item.tax_rate_id)
+        .map_or_else(|| format!("{}%", item.tax_rate.normalize()), |r| r.name.clone());
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            for rate in rates.iter().filter(|r| r.active) {
+                if ui
+                    .selectable_label(item.tax_rate_id == Some(rate.tax_rate_id), &rate.name)
+                    .clicked()
+                {
+                    apply_rate(item, Some(rate));
+                }
+            }
+        });
+}
+
+/// Net, tax per rate and gross of a document below its item table.
+pub fn render_totals(ui: &mut egui::Ui, items: &[LineItem], rates: &[TaxRate]) {
+    let totals = totals(items);
+    egui::Grid::new(ui.next_auto_id()).show(ui, |ui| {
+        ui.label("Net:");
+        ui.label(format!("{:.2}", totals.net));
+        ui.end_row();
+        for group in &totals.groups {
+            ui.label(format!("{} on {:.2}:", group_label(group, rates), group.net));
+            ui.label(format!("{:.2}", group.tax));
+            ui.end_row();
+        }
+        ui.strong("Gross:");
+        ui.strong(format!("{:.2}", totals.gross));
+        ui.end_row();
+    });
+    for note in document_notes(&totals, rates) {
+        ui.label(note);
+    }
+}
+
+fn empty_rate() -> TaxRate {
+    TaxRate {
+        tax_rate_id: 0,
+        name: String::new(),
+        rate: Decimal::ZERO,
+        reverse_charge: false,
+        note: String::new(),
+        is_default: false,
+        active: true,
+        revenue_account: String::new(),
+    }
+}
+
+/// Admin section of the settings view for defining tax rates.
+pub fn render_rate_definitions(ui: &mut egui::Ui, store: &TaxRateStore, status: &Arc<Mutex<String>>) {
+    let id = egui::Id::new("tax_rate_edits");
+    let mut edits: HashMap<i32, TaxRate> = ui.data_mut(|d| d.get_temp(id)).unwrap_or_default();
+
+    egui::Grid::new("tax_rates_grid").striped(true).show(ui, |ui| {
+        ui.strong("Name");
+        ui.strong("Rate %");
+        ui.strong("Reverse charge");
+        ui.strong("Note on documents");
+        ui.strong("Default");
+        ui.strong("Active");
+        ui.strong("Revenue Account");
+        ui.end_row();
+
+        let rows = store.all_rates().into_iter().chain(std::iter::once(empty_rate()));
+        for stored in rows {
+            let rate = edits.entry(stored.tax_rate_id).or_insert(stored);
+            ui.text_edit_singleline(&mut rate.name);
+            ui::parsed_field(ui, ("tax_rate_percent", rate.tax_rate_id), &mut rate.rate);
+            if ui.checkbox(&mut rate.reverse_charge, "").changed() && rate.reverse_charge {
+                rate.rate = Decimal::ZERO;
+            }
+            ui.text_edit_singleline(&mut rate.note);
+            ui.checkbox(&mut rate.is_default, "");
+            ui.checkbox(&mut rate.active, "");
+            ui.add(egui::TextEdit::singleline(&mut rate.revenue_account).desired_width(60.0));
+            let label = if rate.tax_rate_id == 0 { "Add" } else { "Save" };
+            if ui.button(label).clicked() && !rate.name.trim().is_empty() {
+                let mut rate = rate.clone();
+                rate.name = rate.name.trim().to_string();
+                rate.revenue_account = rate.revenue_account.trim().to_string();
+                if rate.reverse_charge {
+                    rate.rate = Decimal::ZERO;
+                }
+                edits.remove(&rate.tax_rate_id);
+                store.save_rate(rate, Arc::clone(status));
+            }
+            ui.end_row();
+        }
+    });
+
+    ui.data_mut(|d| d.insert_temp(id, edits));
+}
+
+/// Builders for the invoice amounts used by tests across modules.
+#[cfg(test)]
+pub(crate) mod test_support {
+    use crate::db::LineItem;
+    use rust_decimal::Decimal;
+
+    pub(crate) fn item(quantity: i32, unit_price: &str, tax_rate_id: i32, tax_rate: i64) -> LineItem {
+        LineItem {
+            item_id: 0,
+            product_id: None,
+            description: String::new(),
+            quantity,
+            unit_price: money(unit_price),
+            total_price: Decimal::ZERO,
+            tax_rate_id: Some(tax_rate_id),
+            tax_rate: Decimal::from(tax_rate),
+            discount_percent: Decimal::ZERO,
+        }
+    }
+
+    pub(crate) fn money(amount: &str) -> Decimal {
+        amount.parse().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::{item, money};
+    use super::*;
+
+    #[test]
+    fn round_money_rounds_halves_away_from_zero() {
+        assert_eq!(round_money(money("2.345")), money("2.35"));
+        assert_eq!(round_money(money("0.125")), money("0.13"));
+        assert_eq!(round_money(money("-0.125")), money("-0.13"));
+        assert_eq!(round_money(money("2.3449")), money("2.34"));
+    }
+
+    #[test]
+    fn line_net_rounds_after_the_discount() {
+        let line = LineItem {
+            discount_percent: Decimal::from(10),
+            ..item(3, "9.99", 1, 19)
+        };
+        assert_eq!(line_amount(&line), money("29.97"));
+        assert_eq!(line_net(&line), money("26.97"));
+        assert_eq!(line_discount(&line), money("3.00"));
+    }
+
+    #[test]
+    fn totals_round_tax_once_per_rate() {
+        // Per line the tax would be 3 x 0.07 = 0.21.
+        let totals = totals(&[item(1, "1.05", 2, 7), item(1, "1.05", 2, 7), item(1, "1.05", 2, 7)]);
+        assert_eq!(totals.groups.len(), 1);
+        assert_eq!(totals.net, money("3.15"));
+        assert_eq!(totals.tax, money("0.22"));
+        assert_eq!(totals.gross, money("3.37"));
+    }
+
+    #[test]
+    fn totals_round_each_rate_at_its_midpoint() {
+        // 12.50 at 7% is 0.875 exactly.
+        let totals = totals(&[item(1, "12.00", 2, 7), item(2, "5.00", 1, 19), item(1, "0.50", 2, 7)]);
+        let groups: Vec<_> = totals.groups.iter().map(|g| (g.rate, g.net, g.tax)).collect();
+        assert_eq!(
+            groups,
+            [
+                (Decimal::from(19), money("10.00"), money("1.90")),
+                (Decimal::from(7), money("12.50"), money("0.88")),
+            ]
+        );
+        assert_eq!(totals.tax, money("2.78"));
+        assert_eq!(totals.gross, money("25.28"));
+    }
+
+    #[test]
+    fn totals_keep_changed_percentages_of_one_rate_apart() {
+        let totals = totals(&[item(1, "100", 1, 16), item(1, "100", 1, 19)]);
+        assert_eq!(totals.groups.len(), 2);
+        assert_eq!(totals.tax, money("35.00"));
+    }
+}
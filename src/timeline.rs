@@ -0,0 +1,220 @@
+// timeline.rs
+use crate::db::{self, Customer, TimelineEvent};
+use crate::export;
+use crate::invoices::PDF_MARGIN;
+use crate::pdf::{self, Font, PdfDocument};
+use crate::ui;
+use chrono::{Months, NaiveDate, Utc};
+use eframe::egui;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Kinds of timeline events, in the order of the filter.
+pub const EVENT_KINDS: [&str; 5] = ["Contact", "Invoice", "Payment", "Quote", "Change"];
+
+/// Kinds only shown to roles that see invoices.
+const INVOICE_KINDS: [&str; 2] = ["Invoice", "Payment"];
+
+fn event_time(event: &TimelineEvent) -> String {
+    if event.all_day {
+        event.occurred_at.format("%d.%m.%Y").to_string()
+    } else {
+        event.occurred_at.format("%d.%m.%Y %H:%M").to_string()
+    }
+}
+
+/// Chronological list of everything that happened with one customer,
+/// filterable by kind and date range and printable as a PDF report.
+pub struct TimelinePanel {
+    /// Customer, range and invoice visibility the events were loaded for.
+    loaded_for: Option<(i32, NaiveDate, NaiveDate, bool)>,
+    events: Arc<Mutex<Vec<TimelineEvent>>>,
+    status: Arc<Mutex<String>>,
+    from: NaiveDate,
+    to: NaiveDate,
+    hidden_kinds: BTreeSet<&'static str>,
+    pdf_path: String,
+}
+
+impl Default for TimelinePanel {
+    fn default() -> Self {
+        let today = Utc::now().date_naive();
+        Self {
+            loaded_for: None,
+            events: Arc::new(Mutex::new(Vec::new())),
+            status: Arc::new(Mutex::new(String::new())),
+            from: today.checked_sub_months(Months::new(12)).unwrap_or(today),
+            to: today,
+            hidden_kinds: BTreeSet::new(),
+            pdf_path: String::new(),
+        }
+    }
+}
+
+impl TimelinePanel {
+    /// Makes the next `show` load the events again.
+    pub fn reload(&mut self) {
+        self.loaded_for = None;
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, customer: &Customer, with_invoices: bool) {
+        let key = (customer.customer_id, self.from, self.to, with_invoices);
+        if self.loaded_for != Some(key) {
+            if self.loaded_for.is_none_or(|(customer_id, ..)| customer_id != customer.customer_id) {
+                self.pdf_path = export::default_export_path(&format!("activity_{}.pdf", customer.customer_id));
+            }
+            self.loaded_for = Some(key);
+            self.load(key);
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("From");
+            ui::parsed_field(ui, "timeline_from", &mut self.from);
+            ui.label("to");
+            ui::parsed_field(ui, "timeline_to", &mut self.to);
+            ui.separator();
+            for kind in EVENT_KINDS {
+                if !with_invoices && INVOICE_KINDS.contains(&kind) {
+                    continue;
+                }
+                let mut shown = !self.hidden_kinds.contains(kind);
+                if ui.checkbox(&mut shown, kind).changed() {
+                    if shown {
+                        self.hidden_kinds.remove(kind);
+                    } else {
+                        self.hidden_kinds.insert(kind);
+                    }
+                }
+            }
+        });
+
+        let events: Vec<TimelineEvent> = self
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| !self.hidden_kinds.contains(event.kind))
+            .cloned()
+            .collect();
+
+        ui.horizontal(|ui| {
+            ui.label("Report:");
+            ui.text_edit_singleline(&mut self.pdf_path);
+            if ui.button("Create PDF").clicked() {
+                let path = Path::new(&self.pdf_path);
+                *self.status.lock().unwrap() =
+                    match write_timeline_pdf(customer, self.from, self.to, &events, path) {
+                        Ok(()) => format!("Activity report written to {}", self.pdf_path),
+                        Err(e) => format!("Error writing PDF: {}", e),
+                    };
+            }
+        });
+        let status = self.status.lock().unwrap().clone();
+        if !status.is_empty() {
+            ui.label(status);
+        }
+        ui.add_space(5.0);
+
+        if events.is_empty() {
+            ui.label("Nothing happened in this period.");
+            return;
+        }
+        egui::Grid::new("timeline_grid").striped(true).show(ui, |ui| {
+            ui.strong("When");
+            ui.strong("Type");
+            ui.strong("Event");
+            ui.strong("By");
+            ui.end_row();
+            for event in &events {
+                ui.label(event_time(event));
+                ui.label(event.kind);
+                ui.vertical(|ui| {
+                    ui.label(&event.summary);
+                    if !event.details.is_empty() {
+                        ui.small(&event.details);
+                    }
+                });
+                ui.label(&event.user);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn load(&self, (customer_id, from, to, with_invoices): (i32, NaiveDate, NaiveDate, bool)) {
+        let events = Arc::clone(&self.events);
+        let status = Arc::clone(&self.status);
+        tokio::spawn(async move {
+            let Some(config) = db::get_config() else {
+                return;
+            };
+            match db::get_customer_timeline(&config, customer_id, from, to, with_invoices).await {
+                Ok(loaded) => *events.lock().unwrap() = loaded,
+                Err(e) => *status.lock().unwrap() = format!("Error loading timeline: {}", e),
+            }
+        });
+    }
+}
+
+/// Writes `events` as a printable activity report of `customer`.
+pub fn write_timeline_pdf(
+    customer: &Customer,
+    from: NaiveDate,
+    to: NaiveDate,
+    events: &[TimelineEvent],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pdf = PdfDocument::default();
+    let right = pdf::PAGE_WIDTH - PDF_MARGIN;
+    let mut y = pdf::PAGE_HEIGHT - PDF_MARGIN;
+    pdf.text(PDF_MARGIN, y, 18.0, Font::Bold, &format!("Activity of {}", customer.company_name));
+    y -= 20.0;
+    pdf.text(PDF_MARGIN, y, 10.0, Font::Regular, &format!("From {} to {}", from, to));
+    y -= 30.0;
+
+    // Columns: time, kind, event with its details, user.
+    let event_x = PDF_MARGIN + 140.0;
+    let event_width = right - 90.0 - event_x;
+    let header = |pdf: &mut PdfDocument, y: f32| {
+        pdf.text(PDF_MARGIN, y, 10.0, Font::Bold, "When");
+        pdf.text(PDF_MARGIN + 85.0, y, 10.0, Font::Bold, "Type");
+        pdf.text(event_x, y, 10.0, Font::Bold, "Event");
+        pdf.text_right(right, y, 10.0, Font::Bold, "By");
+        pdf.line(PDF_MARGIN, y - 5.0, right, y - 5.0, 0.5);
+    };
+    header(&mut pdf, y);
+    y -= 20.0;
+
+    if events.is_empty() {
+        pdf.text(PDF_MARGIN, y, 10.0, Font::Regular, "Nothing happened in this period.");
+    }
+    for event in events {
+        let summary = pdf.wrap(&event.summary, event_width, 10.0);
+        let details: Vec<String> = event
+            .details
+            .lines()
+            .flat_map(|line| pdf.wrap(line, event_width, 8.0))
+            .collect();
+        let height = 12.0 * summary.len() as f32 + 10.0 * details.len() as f32;
+        if y - height < PDF_MARGIN {
+            pdf.new_page();
+            y = pdf::PAGE_HEIGHT - PDF_MARGIN;
+            header(&mut pdf, y);
+            y -= 20.0;
+        }
+        pdf.text(PDF_MARGIN, y, 10.0, Font::Regular, &event_time(event));
+        pdf.text(PDF_MARGIN + 85.0, y, 10.0, Font::Regular, event.kind);
+        pdf.text_right(right, y, 10.0, Font::Regular, &event.user);
+        for line in summary {
+            pdf.text(event_x, y, 10.0, Font::Regular, &line);
+            y -= 12.0;
+        }
+        for line in details {
+            pdf.text(event_x, y, 8.0, Font::Regular, &line);
+            y -= 10.0;
+        }
+        y -= 6.0;
+    }
+
+    pdf.save(path)
+}
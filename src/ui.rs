@@ -1,44 +1,88 @@
 use crate::app::View;
+use crate::auth;
 use crate::config::DbConfig;
-use crate::db::{self, Customer};
+use crate::currency::{currency_combo, CurrencyStore};
+use crate::custom_fields::{self, CustomFieldStore};
+use crate::db::{self, Customer, User};
+use crate::export;
+use crate::sepa;
+use crate::tags::{render_tag_manager, tag_chip, TagStore};
 use eframe::egui;
 
 use once_cell::sync::Lazy;
-use serde_json;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
 static STEP: Lazy<Mutex<u8>> = Lazy::new(|| Mutex::new(1));
 static DB_CONFIG: Lazy<Mutex<Option<DbConfig>>> = Lazy::new(|| Mutex::new(None));
 
+/// Renders the menu bar with the entries `user` may use. Returns true when
+/// the user chose to log out.
 pub fn render_menu_bar(
     ctx: &egui::Context,
     current_view: &mut View,
     customer_contact_window_open: &mut bool,
-) {
+    user: &User,
+) -> bool {
+    let mut logout = false;
+    let role = user.role;
     egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
         egui::menu::bar(ui, |ui| {
             ui.menu_button("File", |ui| {
-                if ui.button("Setup Wizard").clicked() {
+                if role.can_access(&View::SetupWizard) && ui.button("Setup Wizard").clicked() {
                     *current_view = View::SetupWizard;
                 }
-                if ui.button("Settings").clicked() {
+                if role.can_access(&View::Settings) && ui.button("Settings").clicked() {
                     *current_view = View::Settings;
                 }
 
+                if ui.button("Log out").clicked() {
+                    logout = true;
+                }
                 if ui.button("Quit").clicked() {
                     std::process::exit(0);
                 }
             });
 
             ui.menu_button("View", |ui| {
+                if ui.button("Dashboard").clicked() {
+                    *current_view = View::Main;
+                }
                 if ui.button("Customers").clicked() {
                     *current_view = View::Customers;
                 }
-                if ui.button("Invoices").clicked() {
+                if ui.button("My Tasks").clicked() {
+                    *current_view = View::Tasks;
+                }
+                if role.can_access(&View::Invoices) && ui.button("Invoices").clicked() {
                     *current_view = View::Invoices;
                 }
+                if role.can_access(&View::Dunning) && ui.button("Dunning").clicked() {
+                    *current_view = View::Dunning;
+                }
+                if role.can_access(&View::Recurring) && ui.button("Recurring Invoices").clicked() {
+                    *current_view = View::Recurring;
+                }
+                if role.can_access(&View::Reports) && ui.button("Reports").clicked() {
+                    *current_view = View::Reports;
+                }
+                if role.can_access(&View::Banking) && ui.button("Banking").clicked() {
+                    *current_view = View::Banking;
+                }
+                if ui.button("Sales Pipeline").clicked() {
+                    *current_view = View::Deals;
+                }
+                if ui.button("Inventory").clicked() {
+                    *current_view = View::Inventory;
+                }
+                if role.can_access(&View::PriceLists) && ui.button("Price Lists").clicked() {
+                    *current_view = View::PriceLists;
+                }
+                if ui.button("Quotes").clicked() {
+                    *current_view = View::Quotes;
+                }
                 if ui.button("Customer Contact").clicked() {
                     // Neuer Button
                     *customer_contact_window_open = true;
@@ -47,6 +91,12 @@ pub fn render_menu_bar(
                 if ui.button("Customer Search").clicked() {
                     *current_view = View::CustomerSearch;
                 }
+                if ui.button("Relationship Graph").clicked() {
+                    *current_view = View::RelationGraph;
+                }
+                if ui.button("Duplicate Customers").clicked() {
+                    *current_view = View::Duplicates;
+                }
             });
 
             ui.menu_button("Help", |ui| {
@@ -54,15 +104,13 @@ pub fn render_menu_bar(
                     // TODO: Implement About dialog
                 }
             });
-        });
-    });
-}
 
-pub fn render_main_view(ctx: &egui::Context) {
-    egui::CentralPanel::default().show(ctx, |ui| {
-        ui.heading("Welcome to CRM Application");
-        ui.label("Select an option from the menu to get started.");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.label(format!("{} ({})", user.username, role.label()));
+            });
+        });
     });
+    logout
 }
 
 pub fn render_setup_wizard_view(ctx: &egui::Context) {
@@ -137,12 +185,15 @@ fn render_step_one(ui: &mut egui::Ui) {
         ));
         ui.label(format!("Saving config to: {:?}", config_path));
 
-        if let Err(e) = save_config_to_file(&config, &config_path) {
+        if let Err(e) = save_config_to_file(config, &config_path) {
             ui.label(format!("Error saving configuration: {}", e));
         } else {
             ui.label("Configuration saved successfully.");
         }
 
+        // Make the new settings usable right away, e.g. for the first login.
+        db::set_config(Some(config.clone()));
+
         let config_clone = config.clone();
         tokio::spawn(async move {
             match db::create_database(&config_clone).await {
@@ -184,16 +235,49 @@ fn render_step_two(ui: &mut egui::Ui) {
     }
 }
 
-pub fn render_customer_contact_view(ctx: &egui::Context) {
-    egui::CentralPanel::default().show(ctx, |ui| {
-        ui.heading("Customer Contact");
-        // Hier können Sie den Inhalt für die Customer Contact-Ansicht hinzufügen
-    });
+use std::sync::Arc;
+
+/// Selection, filter and export settings of the customer list.
+pub struct CustomerListState {
+    pub selected: HashSet<i32>,
+    pub filter_tags: BTreeSet<i32>,
+    pub bulk_tag: Option<i32>,
+    /// Custom field to filter by, and the text its value must contain.
+    pub field_filter: Option<i32>,
+    pub field_filter_value: String,
+    pub export_path: String,
+    pub status: String,
+    /// Customer whose detail screen was asked for.
+    pub open_customer: Option<i32>,
 }
 
-use std::sync::Arc;
+impl Default for CustomerListState {
+    fn default() -> Self {
+        Self {
+            selected: HashSet::new(),
+            filter_tags: BTreeSet::new(),
+            bulk_tag: None,
+            field_filter: None,
+            field_filter_value: String::new(),
+            export_path: export::default_export_path("customers.csv"),
+            status: String::new(),
+            open_customer: None,
+        }
+    }
+}
 
-pub fn render_customers_view(ctx: &egui::Context, customers: Arc<Mutex<Vec<Customer>>>) {
+pub fn render_customers_view(
+    ctx: &egui::Context,
+    customers: Arc<Mutex<Vec<Customer>>>,
+    tags: &TagStore,
+    custom_fields: &CustomFieldStore,
+    currencies: &CurrencyStore,
+    list: &mut CustomerListState,
+) {
+    tags.refresh_if_stale();
+    custom_fields.refresh_if_stale();
+    currencies.refresh_if_stale();
+    let fields = custom_fields.active_fields();
     egui::CentralPanel::default().show(ctx, |ui| {
         ui.heading("Customers");
 
@@ -202,19 +286,158 @@ pub fn render_customers_view(ctx: &egui::Context, customers: Arc<Mutex<Vec<Custo
             return;
         }
 
+        // Tag filter
+        let all_tags = tags.tags();
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Filter by tags:");
+            for tag in &all_tags {
+                let selected = list.filter_tags.contains(&tag.tag_id);
+                if tag_chip(ui, tag, selected).clicked() {
+                    if selected {
+                        list.filter_tags.remove(&tag.tag_id);
+                    } else {
+                        list.filter_tags.insert(tag.tag_id);
+                    }
+                }
+            }
+            if !list.filter_tags.is_empty() && ui.small_button("Clear").clicked() {
+                list.filter_tags.clear();
+            }
+        });
+
+        // Custom field filter
+        if !fields.is_empty() {
+            ui.horizontal(|ui| {
+                ui.label("Filter by field:");
+                let selected_name = list
+                    .field_filter
+                    .and_then(|id| fields.iter().find(|f| f.field_id == id))
+                    .map(|f| f.name.clone())
+                    .unwrap_or_else(|| "-".to_string());
+                egui::ComboBox::from_id_source("field_filter")
+                    .selected_text(selected_name)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut list.field_filter, None, "-");
+                        for field in &fields {
+                            ui.selectable_value(&mut list.field_filter, Some(field.field_id), &field.name);
+                        }
+                    });
+                if list.field_filter.is_some() {
+                    ui.label("contains");
+                    ui.text_edit_singleline(&mut list.field_filter_value);
+                }
+            });
+        }
+        let field_filter = list.field_filter.and_then(|id| fields.iter().find(|f| f.field_id == id));
+        let field_filter_value = list.field_filter_value.trim().to_lowercase();
+
+        let filtered: Vec<Customer> = customers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| tags.has_all(c.customer_id, &list.filter_tags))
+            .filter(|c| {
+                field_filter.is_none_or(|field| {
+                    let value = custom_fields.value(c.customer_id, field.field_id);
+                    !value.is_empty()
+                        && custom_fields::display_value(field, &value)
+                            .to_lowercase()
+                            .contains(&field_filter_value)
+                })
+            })
+            .cloned()
+            .collect();
+        let can_edit = auth::can_edit();
+
         // Display existing customers
         ui.heading("Existing Customers");
-        let customers_lock = customers.lock().unwrap();
-        egui::ScrollArea::vertical().show(ui, |ui| {
-            for customer in customers_lock.iter() {
-                ui.horizontal(|ui| {
-                    ui.label(&customer.company_name);
-                    ui.label(&customer.contact_name);
-                    ui.label(&customer.email);
-                });
+        egui::ScrollArea::vertical()
+            .id_source("customer_list")
+            .max_height(300.0)
+            .show(ui, |ui| {
+                for customer in &filtered {
+                    ui.horizontal(|ui| {
+                        if can_edit {
+                            let mut checked = list.selected.contains(&customer.customer_id);
+                            if ui.checkbox(&mut checked, "").changed() {
+                                if checked {
+                                    list.selected.insert(customer.customer_id);
+                                } else {
+                                    list.selected.remove(&customer.customer_id);
+                                }
+                            }
+                        }
+                        ui.label(&customer.company_name);
+                        ui.label(&customer.contact_name);
+                        ui.label(&customer.email);
+                        for tag in tags.tags_of(customer.customer_id) {
+                            tag_chip(ui, &tag, true);
+                        }
+                        if ui.small_button("Details").clicked() {
+                            list.open_customer = Some(customer.customer_id);
+                        }
+                    });
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.label("Export to:");
+            ui.text_edit_singleline(&mut list.export_path);
+            if ui.button("Export CSV").clicked() {
+                list.status = match export_customers(&filtered, tags, custom_fields, &list.export_path) {
+                    Ok(()) => format!("Exported {} customers to {}", filtered.len(), list.export_path),
+                    Err(e) => format!("Export failed: {}", e),
+                };
             }
         });
-        drop(customers_lock);
+
+        if can_edit {
+            // Bulk tag assignment for the checked customers
+            list.selected
+                .retain(|id| filtered.iter().any(|c| c.customer_id == *id));
+            ui.horizontal(|ui| {
+                ui.label(format!("{} selected", list.selected.len()));
+                if ui.small_button("Select all").clicked() {
+                    list.selected = filtered.iter().map(|c| c.customer_id).collect();
+                }
+                if ui.small_button("Select none").clicked() {
+                    list.selected.clear();
+                }
+                let bulk_name = list
+                    .bulk_tag
+                    .and_then(|id| all_tags.iter().find(|t| t.tag_id == id))
+                    .map(|t| t.name.clone())
+                    .unwrap_or_else(|| "Choose tag...".to_string());
+                egui::ComboBox::from_id_source("bulk_tag")
+                    .selected_text(bulk_name)
+                    .show_ui(ui, |ui| {
+                        for tag in &all_tags {
+                            ui.selectable_value(&mut list.bulk_tag, Some(tag.tag_id), &tag.name);
+                        }
+                    });
+                if let Some(tag_id) = list.bulk_tag {
+                    let ids: Vec<i32> = list.selected.iter().copied().collect();
+                    if ui.button("Assign").clicked() && !ids.is_empty() {
+                        tags.set_tag(ids.clone(), tag_id, true);
+                    }
+                    if ui.button("Remove").clicked() && !ids.is_empty() {
+                        tags.set_tag(ids, tag_id, false);
+                    }
+                }
+            });
+
+            egui::CollapsingHeader::new("Manage Tags").show(ui, |ui| {
+                render_tag_manager(ui, tags);
+            });
+        }
+
+        if !list.status.is_empty() {
+            ui.label(&list.status);
+        }
+
+        if !auth::can_edit() {
+            return;
+        }
 
         // Add new customer form
         ui.heading("Add New Customer");
@@ -261,18 +484,52 @@ pub fn render_customers_view(ctx: &egui::Context, customers: Arc<Mutex<Vec<Custo
             ui.label("Website:");
             ui.text_edit_singleline(&mut new_customer.website);
         });
+        if new_customer.currency.is_empty() {
+            new_customer.currency = currencies.base();
+        }
+        ui.horizontal(|ui| {
+            ui.label("Currency:");
+            currency_combo(ui, "new_customer_currency", &mut new_customer.currency, currencies);
+        });
+        ui.horizontal(|ui| {
+            ui.label("VAT ID:");
+            ui.text_edit_singleline(&mut new_customer.vat_id);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Buyer Reference:");
+            ui.text_edit_singleline(&mut new_customer.buyer_reference);
+        });
+        sepa::render_bank_fields(ui, "new_customer", &mut new_customer);
+
+        let values_id = egui::Id::new("new_customer_custom_values");
+        let mut new_values: HashMap<i32, String> =
+            ctx.data(|d| d.get_temp(values_id).unwrap_or_default());
+        custom_fields::render_field_inputs(ui, &fields, &mut new_values, 0);
 
         if ui.button("Save Customer").clicked() {
-            if let Some(config) = db::get_config() {
+            if !custom_fields::all_valid(&fields, &new_values) {
+                list.status = "Please correct the highlighted fields".to_string();
+            } else if let Some(config) = db::get_config() {
                 let config_clone = config.clone();
                 let new_customer_clone = new_customer.clone();
                 let customers_clone = customers.clone();
+                let custom_fields = custom_fields.clone();
+                let values = std::mem::take(&mut new_values);
                 tokio::spawn(async move {
                     match db::add_customer(&config_clone, &new_customer_clone).await {
-                        Ok(_) => {
+                        Ok(customer_id) => {
                             println!("Customer added successfully!");
+                            if !values.is_empty() {
+                                custom_fields.save_values(customer_id, values);
+                            }
+                            let mut new_customer = new_customer_clone;
+                            new_customer.customer_id = customer_id;
+                            new_customer.version = 1;
                             let mut customers = customers_clone.lock().unwrap();
-                            customers.push(new_customer_clone);
+                            // The change listener may have added it already.
+                            if !customers.iter().any(|c| c.customer_id == customer_id) {
+                                customers.push(new_customer);
+                            }
                         }
                         Err(e) => eprintln!("Error adding customer: {}", e),
                     }
@@ -283,34 +540,62 @@ pub fn render_customers_view(ctx: &egui::Context, customers: Arc<Mutex<Vec<Custo
             }
         }
 
-        ctx.data_mut(|d| d.insert_temp(customer_id, new_customer));
-    });
-}
-
-pub fn render_invoices_view(ctx: &egui::Context) {
-    egui::CentralPanel::default().show(ctx, |ui| {
-        ui.heading("Invoices");
-        ui.label("Here you can manage your invoices.");
-        if ui.button("Create New Invoice").clicked() {
-            // TODO: Implement create new invoice functionality
-        }
-        if ui.button("View Invoice List").clicked() {
-            // TODO: Implement view invoice list functionality
-        }
+        ctx.data_mut(|d| {
+            d.insert_temp(customer_id, new_customer);
+            d.insert_temp(values_id, new_values);
+        });
     });
 }
 
-pub fn render_settings_view(ctx: &egui::Context) {
-    egui::CentralPanel::default().show(ctx, |ui| {
-        ui.heading("Settings");
-        ui.label("Configure your CRM application here.");
-        if ui.button("Database Settings").clicked() {
-            // TODO: Implement database settings functionality
-        }
-        if ui.button("User Preferences").clicked() {
-            // TODO: Implement user preferences functionality
-        }
-    });
+fn export_customers(
+    customers: &[Customer],
+    tags: &TagStore,
+    custom_fields: &CustomFieldStore,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fields = custom_fields.active_fields();
+    let mut header = vec![
+        "Company Name", "Contact Name", "Contact Position", "Address", "City",
+        "Postal Code", "Country", "Phone", "Email", "Website", "Currency", "VAT ID", "Buyer Reference",
+        "IBAN", "BIC", "Mandate Reference", "Mandate Date", "Tags",
+    ];
+    header.extend(fields.iter().map(|f| f.name.as_str()));
+    let rows: Vec<Vec<String>> = customers
+        .iter()
+        .map(|c| {
+            let tag_names: Vec<String> = tags
+                .tags_of(c.customer_id)
+                .into_iter()
+                .map(|t| t.name)
+                .collect();
+            vec![
+                c.company_name.clone(),
+                c.contact_name.clone(),
+                c.contact_position.clone(),
+                c.address.clone(),
+                c.city.clone(),
+                c.postal_code.clone(),
+                c.country.clone(),
+                c.phone.clone(),
+                c.email.clone(),
+                c.website.clone(),
+                c.currency.clone(),
+                c.vat_id.clone(),
+                c.buyer_reference.clone(),
+                c.iban.clone(),
+                c.bic.clone(),
+                c.mandate_reference.clone(),
+                c.mandate_date.map(|d| d.to_string()).unwrap_or_default(),
+                tag_names.join(", "),
+            ]
+            .into_iter()
+            .chain(fields.iter().map(|f| {
+                custom_fields::display_value(f, &custom_fields.value(c.customer_id, f.field_id))
+            }))
+            .collect()
+        })
+        .collect();
+    export::write_csv(Path::new(path), ';', &header, &rows)
 }
 
 fn save_config_to_file(
@@ -329,11 +614,28 @@ pub fn load_config_from_file(path: &PathBuf) -> Result<DbConfig, Box<dyn std::er
     Ok(config)
 }
 
-pub fn render_customer_contact_window(ctx: &egui::Context, open: &mut bool) {
-    egui::Window::new("Customer Contact")
-        .open(open)
-        .show(ctx, |ui| {
-            ui.label("This is the Customer Contact window.");
-            // Hier können Sie später weitere Widgets hinzufügen
-        });
+/// Single-line text field bound to any value that can be parsed from and
+/// displayed as text, e.g. dates (`YYYY-MM-DD`) and amounts. While the field
+/// has focus the raw text is kept, so half-typed input survives between frames;
+/// `value` only changes once the text parses.
+pub fn parsed_field<T>(ui: &mut egui::Ui, id_source: impl std::hash::Hash, value: &mut T) -> egui::Response
+where
+    T: std::str::FromStr + std::fmt::Display,
+{
+    let id = ui.make_persistent_id(id_source);
+    let stored: Option<String> = ui.data_mut(|d| d.get_temp(id));
+    let mut text = stored.unwrap_or_else(|| value.to_string());
+
+    let response = ui.add(egui::TextEdit::singleline(&mut text).id(id));
+    if response.changed() {
+        if let Ok(parsed) = text.trim().parse() {
+            *value = parsed;
+        }
+    }
+    if response.has_focus() {
+        ui.data_mut(|d| d.insert_temp(id, text));
+    } else {
+        ui.data_mut(|d| d.remove::<String>(id));
+    }
+    response
 }